@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use lz_fear::framed::FrameSpec;
+
+fuzz_target!(|spec: FrameSpec| {
+    let frame = spec.compress().expect("Could not compress input data");
+    let roundtripped = spec.decompress(&frame).expect("Could not read decompressed data");
+    assert!(roundtripped == spec.content);
+});
@@ -0,0 +1,146 @@
+//! Tools for looking inside LZ4 frames.
+//!
+//! This is mostly useful to make informed decisions about compression settings for a particular kind of data:
+//! Which block size works best? Does a dictionary help? Are dependent blocks worth it?
+//! Analyzing a few representative frames answers these questions much more reliably than guessing.
+
+use std::io::Read;
+use culpa::throws;
+
+use crate::framed::{DecompressionError, LZ4FrameReader};
+use crate::raw::{self, DecodeError, Sequence};
+
+/// A histogram with power-of-two buckets.
+///
+/// Bucket `i` counts the values in `2^i..2^(i+1)` (and bucket 0 also counts zeros).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; 32],
+}
+impl Histogram {
+    pub fn record(&mut self, value: usize) {
+        let bucket = (usize::BITS - value.leading_zeros()).saturating_sub(1);
+        self.buckets[(bucket as usize).min(self.buckets.len() - 1)] += 1;
+    }
+
+    /// The bucket counts, without trailing empty buckets.
+    pub fn buckets(&self) -> &[u64] {
+        let used = self.buckets.iter().rposition(|&x| x != 0).map_or(0, |i| i + 1);
+        &self.buckets[..used]
+    }
+
+    /// Total number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+    }
+}
+
+/// Statistics about a single block.
+#[derive(Clone, Debug, Default)]
+pub struct BlockAnalysis {
+    /// Size of the block inside the frame (excluding the length field and block checksum).
+    pub compressed_len: usize,
+    /// Size of the block after decompression.
+    pub decompressed_len: usize,
+    /// Whether the block was stored uncompressed.
+    pub stored: bool,
+    /// Number of output bytes that were encoded as literals.
+    pub literal_bytes: usize,
+    /// Number of output bytes that were encoded as matches (backreferences).
+    pub match_bytes: usize,
+    /// Distribution of match lengths.
+    pub match_lengths: Histogram,
+    /// Distribution of match offsets.
+    pub match_offsets: Histogram,
+}
+impl BlockAnalysis {
+    /// Compression ratio (decompressed size divided by compressed size), so higher is better.
+    pub fn ratio(&self) -> f64 {
+        self.decompressed_len as f64 / self.compressed_len as f64
+    }
+
+    fn merge(&mut self, other: &BlockAnalysis) {
+        self.compressed_len += other.compressed_len;
+        self.decompressed_len += other.decompressed_len;
+        self.stored &= other.stored;
+        self.literal_bytes += other.literal_bytes;
+        self.match_bytes += other.match_bytes;
+        self.match_lengths.merge(&other.match_lengths);
+        self.match_offsets.merge(&other.match_offsets);
+    }
+}
+
+/// Statistics about an entire frame.
+#[derive(Clone, Debug)]
+pub struct FrameAnalysis {
+    /// The maximum block size declared in the frame header.
+    pub block_size: usize,
+    /// The content size declared in the frame header, if any.
+    pub content_size: Option<u64>,
+    /// Per-block statistics, in order.
+    pub blocks: Vec<BlockAnalysis>,
+}
+impl FrameAnalysis {
+    /// Statistics for all blocks combined.
+    ///
+    /// `stored` is only set if *all* blocks were stored.
+    pub fn total(&self) -> BlockAnalysis {
+        let mut total = BlockAnalysis { stored: !self.blocks.is_empty(), ..Default::default() };
+        for block in &self.blocks {
+            total.merge(block);
+        }
+        total
+    }
+}
+
+/// Decode an entire frame and gather statistics about each block.
+///
+/// If the frame was compressed with a dictionary, you need to pass it here.
+#[throws(DecompressionError)]
+pub fn analyze_frame<R: Read>(reader: R, dictionary: &[u8]) -> FrameAnalysis {
+    let mut frame_reader = LZ4FrameReader::new(reader)?;
+    let mut analysis = FrameAnalysis {
+        block_size: frame_reader.block_size(),
+        content_size: frame_reader.frame_size(),
+        blocks: Vec::new(),
+    };
+
+    let mut output = Vec::with_capacity(frame_reader.block_size());
+    while let Some(is_compressed) = frame_reader.decode_block_internal(&mut output, dictionary)? {
+        let raw_block = frame_reader.raw_block();
+        let mut block = BlockAnalysis {
+            compressed_len: if is_compressed { raw_block.len() } else { output.len() },
+            decompressed_len: output.len(),
+            stored: !is_compressed,
+            ..Default::default()
+        };
+        if is_compressed {
+            analyze_sequences(raw_block, &mut block)?;
+        } else {
+            block.literal_bytes = output.len();
+        }
+        analysis.blocks.push(block);
+        output.clear();
+    }
+    analysis
+}
+
+#[throws(DecodeError)]
+fn analyze_sequences(input: &[u8], block: &mut BlockAnalysis) {
+    for sequence in raw::sequences(input) {
+        match sequence? {
+            Sequence::Literal { len } => block.literal_bytes += len,
+            Sequence::Match { offset, len } => {
+                block.match_bytes += len;
+                block.match_lengths.record(len);
+                block.match_offsets.record(offset.into());
+            }
+        }
+    }
+}
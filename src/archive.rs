@@ -0,0 +1,245 @@
+//! A minimal container for bundling several files into one LZ4 file.
+//!
+//! Every member is stored as its own LZ4 frame, one after the other.
+//! The archive ends with a skippable frame that holds the table of contents (names, sizes and offsets).
+//! This means that any ordinary LZ4 decompressor (e.g. `lz4 -d`) can still decompress an archive,
+//! it just sees the concatenated contents of all members.
+//!
+//! Layout of the table of contents (all integers little endian):
+//!
+//! ```text
+//! u32 skippable magic (0x184D2A5E)    u32 payload length
+//! u32 member count
+//! per member: u16 name length, name (UTF-8), u64 offset, u64 compressed size, u64 uncompressed size
+//! u32 payload length (again, so we can find the table from the end)    b"LZFA"
+//! ```
+
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take, Write};
+use std::path::Path;
+use thiserror::Error;
+use culpa::{throw, throws};
+
+use crate::framed::{Counting, CompressionError, CompressionSettings, DecompressionError, LZ4FrameIoReader, LZ4FrameReader, SKIPPABLE_MAGIC};
+
+/// The skippable frame magic number we use for the table of contents.
+const TOC_MAGIC: u32 = SKIPPABLE_MAGIC | 0xE;
+/// Marks the end of an archive.
+const TOC_TAG: &[u8; 4] = b"LZFA";
+/// Size of the payload length and tag at the very end.
+const TRAILER_LEN: u64 = 8;
+
+/// Errors when reading or writing an archive.
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+    #[error("error compressing a member")]
+    Compression(#[from] CompressionError),
+    #[error("error decompressing a member")]
+    Decompression(#[from] DecompressionError),
+    #[error("member names must be at most 65535 bytes long")]
+    NameTooLong,
+    #[error("this is not an archive (no table of contents at the end)")]
+    NoTableOfContents,
+    #[error("the table of contents is corrupted")]
+    CorruptTableOfContents,
+    #[error("no member named {0:?}")]
+    NotFound(String),
+    #[error("an earlier member was only partially written, so the archive is broken")]
+    Broken,
+}
+type Error = ArchiveError; // do it this way for better docs
+
+/// An entry in the table of contents.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    /// Offset of the member's frame from the start of the archive.
+    pub offset: u64,
+    /// Size of the member's frame.
+    pub compressed_size: u64,
+    /// Size of the member after decompression.
+    pub uncompressed_size: u64,
+}
+
+/// Writes an archive. Call `finish` when you're done, otherwise there won't be a table of contents.
+pub struct ArchiveWriter<'a, W: Write> {
+    writer: W,
+    settings: CompressionSettings<'a>,
+    offset: u64,
+    members: Vec<Member>,
+    /// A member failed halfway, so there's a torn frame in the output and every offset after it would be wrong.
+    broken: bool,
+}
+
+impl<'a, W: Write> ArchiveWriter<'a, W> {
+    /// Start a new archive. All members are compressed with the given settings.
+    pub fn new(writer: W, settings: CompressionSettings<'a>) -> Self {
+        ArchiveWriter { writer, settings, offset: 0, members: Vec::new(), broken: false }
+    }
+
+    /// Compress everything from `reader` into a new member.
+    ///
+    /// If this fails after part of the member was written, the archive can't be saved anymore:
+    /// all further calls to `add` and `finish` fail with `ArchiveError::Broken`.
+    #[throws]
+    pub fn add<R: Read>(&mut self, name: &str, reader: R) -> &Member {
+        if self.broken {
+            throw!(Error::Broken);
+        }
+        if name.len() > usize::from(u16::MAX) {
+            throw!(Error::NameTooLong);
+        }
+
+        let mut reader = Counting::new(reader);
+        let mut writer = Counting::new(&mut self.writer);
+        if let Err(e) = self.settings.compress(&mut reader, &mut writer) {
+            self.broken = writer.count > 0;
+            throw!(e);
+        }
+
+        self.members.push(Member {
+            name: name.to_owned(),
+            offset: self.offset,
+            compressed_size: writer.count,
+            uncompressed_size: reader.count,
+        });
+        self.offset += writer.count;
+        self.members.last().unwrap()
+    }
+
+    /// Add the file at `path` as a new member.
+    #[throws]
+    pub fn add_file<P: AsRef<Path>>(&mut self, name: &str, path: P) -> &Member {
+        self.add(name, BufReader::new(File::open(path)?))?
+    }
+
+    /// The members that have been added so far.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Write the table of contents and return the underlying writer.
+    #[throws]
+    pub fn finish(mut self) -> W {
+        if self.broken {
+            throw!(Error::Broken);
+        }
+        let mut payload = Vec::new();
+        payload.write_u32::<LE>(self.members.len() as u32)?;
+        for member in &self.members {
+            payload.write_u16::<LE>(member.name.len() as u16)?;
+            payload.write_all(member.name.as_bytes())?;
+            payload.write_u64::<LE>(member.offset)?;
+            payload.write_u64::<LE>(member.compressed_size)?;
+            payload.write_u64::<LE>(member.uncompressed_size)?;
+        }
+        let payload_len = payload.len() as u32 + TRAILER_LEN as u32;
+        payload.write_u32::<LE>(payload_len)?;
+        payload.write_all(TOC_TAG)?;
+
+        self.writer.write_u32::<LE>(TOC_MAGIC)?;
+        self.writer.write_u32::<LE>(payload_len)?;
+        self.writer.write_all(&payload)?;
+        self.writer.flush()?;
+        self.writer
+    }
+}
+
+/// Reads an archive, giving access to individual members by name.
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    members: Vec<Member>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Open an archive and read its table of contents.
+    #[throws]
+    pub fn new(mut reader: R) -> Self {
+        let end = reader.seek(SeekFrom::End(0))?;
+        if end < 8 + TRAILER_LEN {
+            throw!(Error::NoTableOfContents);
+        }
+        reader.seek(SeekFrom::Start(end - TRAILER_LEN))?;
+        let payload_len = u64::from(reader.read_u32::<LE>()?);
+        let mut tag = [0; 4];
+        reader.read_exact(&mut tag)?;
+        if &tag != TOC_TAG || payload_len < TRAILER_LEN || payload_len + 8 > end {
+            throw!(Error::NoTableOfContents);
+        }
+
+        let toc_start = end - payload_len - 8;
+        reader.seek(SeekFrom::Start(toc_start))?;
+        if reader.read_u32::<LE>()? != TOC_MAGIC || u64::from(reader.read_u32::<LE>()?) != payload_len {
+            throw!(Error::NoTableOfContents);
+        }
+        let mut payload = vec![0; (payload_len - TRAILER_LEN) as usize];
+        reader.read_exact(&mut payload)?;
+
+        ArchiveReader { members: parse_toc(&payload, toc_start)?, reader }
+    }
+
+    /// All members, in the order they were added.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Look up a member by name.
+    pub fn find(&self, name: &str) -> Option<&Member> {
+        self.members.iter().find(|m| m.name == name)
+    }
+
+    /// Decompress a member as a stream.
+    #[throws]
+    pub fn open(&mut self, name: &str) -> LZ4FrameIoReader<'static, Take<&mut R>> {
+        let member = self.find(name).ok_or_else(|| Error::NotFound(name.to_owned()))?.clone();
+        self.reader.seek(SeekFrom::Start(member.offset))?;
+        LZ4FrameReader::new(self.reader.by_ref().take(member.compressed_size))?.into_read()
+    }
+
+    /// Decompress a member into a vector.
+    #[throws]
+    pub fn read(&mut self, name: &str) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.open(name)?.read_to_end(&mut output)?;
+        output
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[throws]
+fn parse_toc(mut payload: &[u8], toc_start: u64) -> Vec<Member> {
+    let corrupt = |_| Error::CorruptTableOfContents;
+    let count = payload.read_u32::<LE>().map_err(corrupt)?;
+    let mut members = Vec::new();
+    for _ in 0..count {
+        let name_len = usize::from(payload.read_u16::<LE>().map_err(corrupt)?);
+        if name_len > payload.len() {
+            throw!(Error::CorruptTableOfContents);
+        }
+        let (name, rest) = payload.split_at(name_len);
+        payload = rest;
+        let name = String::from_utf8(name.to_vec()).map_err(|_| Error::CorruptTableOfContents)?;
+
+        let member = Member {
+            name,
+            offset: payload.read_u64::<LE>().map_err(corrupt)?,
+            compressed_size: payload.read_u64::<LE>().map_err(corrupt)?,
+            uncompressed_size: payload.read_u64::<LE>().map_err(corrupt)?,
+        };
+        if member.offset.checked_add(member.compressed_size).is_none_or(|end| end > toc_start) {
+            throw!(Error::CorruptTableOfContents);
+        }
+        members.push(member);
+    }
+    if !payload.is_empty() {
+        throw!(Error::CorruptTableOfContents);
+    }
+    members
+}
@@ -0,0 +1,269 @@
+use clap::{Args, Parser, Subcommand};
+use lz_fear::analysis::analyze_frame;
+use byteorder::{ReadBytesExt, LE};
+use lz_fear::framed::{decompress_file, CompressionSettings, DictionaryInfo, LZ4FrameReader, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use culpa::{throw, throws};
+
+/// Compress and decompress LZ4 frames.
+#[derive(Parser)]
+#[command(name = "lz-fear", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress INPUT into OUTPUT (stdin/stdout if omitted or "-")
+    Compress {
+        #[command(flatten)]
+        io: InputOutput,
+        #[command(flatten)]
+        options: CompressOptions,
+    },
+    /// Decompress INPUT into OUTPUT (stdin/stdout if omitted or "-")
+    Decompress {
+        #[command(flatten)]
+        io: InputOutput,
+        #[command(flatten)]
+        dictionary: DictionaryOption,
+    },
+    /// Decompress the given files (or stdin) without writing the output anywhere
+    Test {
+        files: Vec<PathBuf>,
+        #[command(flatten)]
+        dictionary: DictionaryOption,
+    },
+    /// Print information about the frames in the given files (or stdin)
+    List {
+        files: Vec<PathBuf>,
+        #[command(flatten)]
+        dictionary: DictionaryOption,
+    },
+}
+
+#[derive(Args)]
+struct InputOutput {
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct DictionaryOption {
+    /// Use FILE as dictionary
+    #[arg(short = 'D', value_name = "FILE")]
+    dictionary: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct CompressOptions {
+    /// Block size (-B4: 64 KiB, -B5: 256 KiB, -B6: 1 MiB, -B7: 4 MiB), dependent blocks (-BD) or block checksums (-BX)
+    #[arg(short = 'B', value_name = "4|5|6|7|D|X")]
+    block: Vec<String>,
+    /// Write the uncompressed size into the frame header (requires a file as input)
+    #[arg(long)]
+    content_size: bool,
+    /// Don't write a content checksum
+    #[arg(long)]
+    no_frame_crc: bool,
+    /// Use FILE as dictionary
+    #[arg(short = 'D', value_name = "FILE")]
+    dictionary: Option<PathBuf>,
+    /// Write this dictionary id into the frame header (the lz4 CLI never does this)
+    #[arg(long, requires = "dictionary")]
+    dictionary_id: Option<u32>,
+    /// Use a hash table with 2^N entries (bigger tables can find more matches)
+    #[arg(long, value_name = "N")]
+    hash_log: Option<usize>,
+}
+
+fn is_stdio(path: &Option<PathBuf>) -> bool {
+    path.as_deref().is_none_or(|p| p == Path::new("-"))
+}
+
+fn open_input(path: &Option<PathBuf>) -> io::Result<Box<dyn BufRead>> {
+    Ok(if is_stdio(path) {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(path.as_ref().unwrap())?))
+    })
+}
+
+fn open_output(path: &Option<PathBuf>) -> io::Result<Box<dyn Write>> {
+    Ok(if is_stdio(path) {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(BufWriter::new(File::create(path.as_ref().unwrap())?))
+    })
+}
+
+#[throws(io::Error)]
+fn read_dictionary(path: &Option<PathBuf>) -> Vec<u8> {
+    match path {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    }
+}
+
+#[throws(io::Error)]
+fn compress(io: &InputOutput, options: &CompressOptions) {
+    let dictionary = read_dictionary(&options.dictionary)?;
+
+    let mut settings = CompressionSettings::default();
+    for b in &options.block {
+        match b.as_str() {
+            "4" => settings.block_size(64 * 1024),
+            "5" => settings.block_size(256 * 1024),
+            "6" => settings.block_size(1024 * 1024),
+            "7" => settings.block_size(4 * 1024 * 1024),
+            "D" => settings.independent_blocks(false),
+            "X" => settings.block_checksums(true),
+            other => throw!(io::Error::new(ErrorKind::InvalidInput, format!("unknown block option -B{}", other))),
+        };
+    }
+    settings.content_checksum(!options.no_frame_crc);
+    if let Some(hash_log) = options.hash_log {
+        settings.hash_log(hash_log);
+    }
+    if options.dictionary.is_some() {
+        settings.dictionary(0, &dictionary).dictionary_id_nonsense_override(options.dictionary_id);
+        if let Some(info) = settings.dictionary_info().filter(DictionaryInfo::truncated) {
+            eprintln!("warning: only the last {} of {} dictionary bytes are used", info.effective_size, info.original_size);
+        }
+    }
+
+    if !options.content_size && !is_stdio(&io.input) && !is_stdio(&io.output) {
+        settings.compress_file(io.input.as_ref().unwrap(), io.output.as_ref().unwrap())?;
+        return;
+    }
+
+    let mut output = open_output(&io.output)?;
+    if options.content_size {
+        if is_stdio(&io.input) {
+            throw!(io::Error::new(ErrorKind::InvalidInput, "--content-size requires a file as input"));
+        }
+        settings.compress_path_with_size(io.input.as_ref().unwrap(), &mut output)?;
+    } else {
+        settings.compress(open_input(&io.input)?, &mut output)?;
+    }
+    output.flush()?;
+}
+
+/// Calls `f` for each frame in `input` until the input is exhausted. Skippable frames are skipped.
+#[throws(io::Error)]
+fn for_each_frame(mut input: Box<dyn BufRead>, mut f: impl FnMut(&mut dyn BufRead) -> io::Result<()>) {
+    while !input.fill_buf()?.is_empty() {
+        let magic = input.read_u32::<LE>()?;
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            let len = input.read_u32::<LE>()?;
+            if io::copy(&mut input.by_ref().take(len.into()), &mut io::sink())? != u64::from(len) {
+                throw!(io::Error::from(ErrorKind::UnexpectedEof));
+            }
+        } else {
+            // the frame reader wants to see the magic number for itself
+            let magic = magic.to_le_bytes();
+            f(&mut (&magic[..]).chain(&mut input))?;
+        }
+    }
+}
+
+#[throws(io::Error)]
+fn decompress_into(input: Box<dyn BufRead>, output: &mut dyn Write, dictionary: &[u8]) {
+    for_each_frame(input, |input| {
+        let mut reader = LZ4FrameReader::new(input)?.into_read_with_dictionary(dictionary);
+        loop {
+            let buf = reader.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let len = buf.len();
+            output.write_all(buf)?;
+            reader.consume(len);
+        }
+        Ok(())
+    })?;
+}
+
+#[throws(io::Error)]
+fn decompress(io: &InputOutput, dictionary: &DictionaryOption) {
+    let dictionary = read_dictionary(&dictionary.dictionary)?;
+    if !is_stdio(&io.input) && !is_stdio(&io.output) {
+        decompress_file(io.input.as_ref().unwrap(), io.output.as_ref().unwrap(), &dictionary)?;
+        return;
+    }
+
+    let mut output = open_output(&io.output)?;
+    decompress_into(open_input(&io.input)?, &mut output, &dictionary)?;
+    output.flush()?;
+}
+
+fn inputs(files: &[PathBuf]) -> Vec<Option<PathBuf>> {
+    if files.is_empty() {
+        vec![None]
+    } else {
+        files.iter().cloned().map(Some).collect()
+    }
+}
+
+fn display_name(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(p) if !is_stdio(path) => p.display().to_string(),
+        _ => "stdin".to_owned(),
+    }
+}
+
+#[throws(io::Error)]
+fn test(files: &[PathBuf], dictionary: &DictionaryOption) {
+    let dictionary = read_dictionary(&dictionary.dictionary)?;
+    for path in inputs(files) {
+        decompress_into(open_input(&path)?, &mut io::sink(), &dictionary)?;
+        eprintln!("{}: OK", display_name(&path));
+    }
+}
+
+/// Counts the bytes passing through so we know how large each frame is.
+struct CountingReader<'a> {
+    inner: &'a mut dyn BufRead,
+    count: u64,
+}
+impl Read for CountingReader<'_> {
+    #[throws(io::Error)]
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        n
+    }
+}
+
+#[throws(io::Error)]
+fn list(files: &[PathBuf], dictionary: &DictionaryOption) {
+    let dictionary = read_dictionary(&dictionary.dictionary)?;
+    println!("{:>6} {:>9} {:>7} {:>14} {:>14} {:>7}  Filename", "Frame", "Block", "Blocks", "Compressed", "Uncompressed", "Ratio");
+    for path in inputs(files) {
+        let name = display_name(&path);
+        let mut frame_number = 0;
+        for_each_frame(open_input(&path)?, |input| {
+            let mut counter = CountingReader { inner: input, count: 0 };
+            let analysis = analyze_frame(&mut counter, &dictionary)?;
+            let total = analysis.total();
+            frame_number += 1;
+            println!("{:>6} {:>8}K {:>7} {:>14} {:>14} {:>6.2}%  {}",
+                frame_number, analysis.block_size / 1024, analysis.blocks.len(), counter.count,
+                total.decompressed_len, 100. * counter.count as f64 / total.decompressed_len.max(1) as f64, name);
+            Ok(())
+        })?;
+    }
+}
+
+#[throws(io::Error)]
+fn main() {
+    match Cli::parse().command {
+        Command::Compress { io, options } => compress(&io, &options)?,
+        Command::Decompress { io, dictionary } => decompress(&io, &dictionary)?,
+        Command::Test { files, dictionary } => test(&files, &dictionary)?,
+        Command::List { files, dictionary } => list(&files, &dictionary)?,
+    }
+}
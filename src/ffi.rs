@@ -0,0 +1,295 @@
+//! C bindings that mimic the liblz4 frame API (`lz4frame.h`).
+//!
+//! This allows C and C++ projects to swap in a memory-safe LZ4 implementation without changing call sites.
+//! Only the most commonly used subset of the API is provided: one-shot frame compression and streaming decompression.
+//!
+//! Apart from mapping files in `CompressionSettings::compress_mmap` (with the `mmap` feature), this is the only place
+//! in the crate that contains `unsafe` code, and it is only compiled with the `ffi` feature.
+//! To get a library that C code can link against, build with
+//! `cargo rustc --release --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! Differences to liblz4:
+//! * There is only one compression level, so `compressionLevel` is ignored.
+//! * Dictionaries are not supported through this interface.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use std::ffi::c_void;
+use std::io::ErrorKind;
+use std::os::raw::{c_char, c_int, c_uint, c_ulonglong};
+use std::slice;
+
+use crate::framed::{CompressionError, CompressionSettings, DecoderProgress, DecoderStatus, DecompressionError, FrameDecoder};
+
+/// The API version this module implements, as in `LZ4F_VERSION`.
+pub const LZ4F_VERSION: c_uint = 100;
+
+/// Error codes, numbered exactly like `LZ4F_errorCodes` in liblz4.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ErrorCode {
+    Generic = 1,
+    MaxBlockSizeInvalid = 2,
+    BlockModeInvalid = 3,
+    HeaderVersionWrong = 6,
+    BlockChecksumInvalid = 7,
+    ReservedFlagSet = 8,
+    DstMaxSizeTooSmall = 11,
+    FrameTypeUnknown = 13,
+    DecompressionFailed = 16,
+    HeaderChecksumInvalid = 17,
+    ContentChecksumInvalid = 18,
+    ParameterNull = 21,
+    MaxCode = 22,
+}
+impl ErrorCode {
+    fn to_result(self) -> usize {
+        (self as usize).wrapping_neg()
+    }
+}
+
+impl From<&DecompressionError> for ErrorCode {
+    fn from(e: &DecompressionError) -> Self {
+        use crate::framed::DecompressionError::*;
+        match e {
+            CodecError(_) | BlockLengthOverflow | BlockSizeOverflow => ErrorCode::DecompressionFailed,
+            WrongMagic(_) => ErrorCode::FrameTypeUnknown,
+            HeaderChecksumFail => ErrorCode::HeaderChecksumInvalid,
+            BlockChecksumFail => ErrorCode::BlockChecksumInvalid,
+            FrameChecksumFail => ErrorCode::ContentChecksumInvalid,
+            HeaderParseError(crate::framed::header::ParseError::UnsupportedVersion(_)) => ErrorCode::HeaderVersionWrong,
+            HeaderParseError(crate::framed::header::ParseError::UnimplementedBlocksize(_)) => ErrorCode::MaxBlockSizeInvalid,
+            HeaderParseError(_) => ErrorCode::ReservedFlagSet,
+            _ => ErrorCode::Generic,
+        }
+    }
+}
+
+/// Equivalent of `LZ4F_frameInfo_t`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LZ4F_frameInfo_t {
+    pub blockSizeID: c_int,
+    pub blockMode: c_int,
+    pub contentChecksumFlag: c_int,
+    pub frameType: c_int,
+    pub contentSize: c_ulonglong,
+    pub dictID: c_uint,
+    pub blockChecksumFlag: c_int,
+}
+
+/// Equivalent of `LZ4F_preferences_t`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LZ4F_preferences_t {
+    pub frameInfo: LZ4F_frameInfo_t,
+    pub compressionLevel: c_int,
+    pub autoFlush: c_uint,
+    pub favorDecSpeed: c_uint,
+    pub reserved: [c_uint; 3],
+}
+
+/// Equivalent of `LZ4F_decompressOptions_t`. None of the options have any effect.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LZ4F_decompressOptions_t {
+    pub stableDst: c_uint,
+    pub skipChecksums: c_uint,
+    pub reserved1: c_uint,
+    pub reserved0: c_uint,
+}
+
+fn block_size_of(prefs: &LZ4F_preferences_t) -> Option<usize> {
+    match prefs.frameInfo.blockSizeID {
+        0 | 4 => Some(64 * 1024), // liblz4 defaults to 64 KiB
+        5 => Some(256 * 1024),
+        6 => Some(1024 * 1024),
+        7 => Some(4 * 1024 * 1024),
+        _ => None,
+    }
+}
+
+/// Returns whether `code` (as returned by any other function in this module) indicates an error.
+#[no_mangle]
+pub extern "C" fn LZ4F_isError(code: usize) -> c_uint {
+    (code > ErrorCode::MaxCode.to_result()) as c_uint
+}
+
+/// Returns a static, NUL-terminated description of an error code.
+#[no_mangle]
+pub extern "C" fn LZ4F_getErrorName(code: usize) -> *const c_char {
+    let name: &'static [u8] = if LZ4F_isError(code) == 0 {
+        b"Unspecified error code\0"
+    } else {
+        match code.wrapping_neg() {
+            2 => b"ERROR_maxBlockSize_invalid\0",
+            3 => b"ERROR_blockMode_invalid\0",
+            6 => b"ERROR_headerVersion_wrong\0",
+            7 => b"ERROR_blockChecksum_invalid\0",
+            8 => b"ERROR_reservedFlag_set\0",
+            11 => b"ERROR_dstMaxSize_tooSmall\0",
+            13 => b"ERROR_frameType_unknown\0",
+            16 => b"ERROR_decompressionFailed\0",
+            17 => b"ERROR_headerChecksum_invalid\0",
+            18 => b"ERROR_contentChecksum_invalid\0",
+            21 => b"ERROR_parameter_null\0",
+            _ => b"ERROR_GENERIC\0",
+        }
+    };
+    name.as_ptr() as *const c_char
+}
+
+#[no_mangle]
+pub extern "C" fn LZ4F_getVersion() -> c_uint {
+    LZ4F_VERSION
+}
+
+/// Returns the maximum size of a frame produced by `LZ4F_compressFrame` for `src_size` bytes of input.
+///
+/// # Safety
+/// `prefs` must either be null or point to a valid `LZ4F_preferences_t`.
+#[no_mangle]
+pub unsafe extern "C" fn LZ4F_compressFrameBound(src_size: usize, prefs: *const LZ4F_preferences_t) -> usize {
+    let prefs = prefs.as_ref().copied().unwrap_or_default();
+    let block_size = block_size_of(&prefs).unwrap_or(4 * 1024 * 1024);
+    let blocks = src_size.div_ceil(block_size);
+    let block_overhead = if prefs.frameInfo.blockChecksumFlag != 0 { 8 } else { 4 };
+    let trailer = if prefs.frameInfo.contentChecksumFlag != 0 { 8 } else { 4 };
+    19 + src_size + blocks * block_overhead + trailer
+}
+
+/// Compress `src` into a single frame in `dst` and return the size of the frame (or an error code).
+///
+/// # Safety
+/// `dst` and `src` must be valid for `dst_capacity` and `src_size` bytes respectively.
+/// `prefs` must either be null or point to a valid `LZ4F_preferences_t`.
+#[no_mangle]
+pub unsafe extern "C" fn LZ4F_compressFrame(dst: *mut c_void, dst_capacity: usize, src: *const c_void, src_size: usize,
+                                           prefs: *const LZ4F_preferences_t) -> usize {
+    if dst.is_null() || (src.is_null() && src_size != 0) {
+        return ErrorCode::ParameterNull.to_result();
+    }
+    let prefs = prefs.as_ref().copied().unwrap_or_default();
+    let src = if src_size == 0 { &[][..] } else { slice::from_raw_parts(src as *const u8, src_size) };
+    let mut dst = slice::from_raw_parts_mut(dst as *mut u8, dst_capacity);
+
+    let mut settings = CompressionSettings::default();
+    match block_size_of(&prefs) {
+        Some(size) => settings.block_size(size),
+        None => return ErrorCode::MaxBlockSizeInvalid.to_result(),
+    };
+    match prefs.frameInfo.blockMode {
+        0 => settings.independent_blocks(false),
+        1 => settings.independent_blocks(true),
+        _ => return ErrorCode::BlockModeInvalid.to_result(),
+    };
+    settings.content_checksum(prefs.frameInfo.contentChecksumFlag != 0);
+    settings.block_checksums(prefs.frameInfo.blockChecksumFlag != 0);
+    if prefs.frameInfo.dictID != 0 {
+        settings.dictionary_id_nonsense_override(Some(prefs.frameInfo.dictID));
+    }
+
+    let capacity = dst.len();
+    let result = if prefs.frameInfo.contentSize != 0 {
+        // just like liblz4, we replace whatever size was given with the actual size
+        settings.compress_with_size_unchecked(src, &mut dst, src_size as u64)
+    } else {
+        settings.compress(src, &mut dst)
+    };
+    match result {
+        Ok(()) => capacity - dst.len(),
+        Err(CompressionError::WriteError(e)) if e.kind() == ErrorKind::WriteZero => ErrorCode::DstMaxSizeTooSmall.to_result(),
+        Err(_) => ErrorCode::Generic.to_result(),
+    }
+}
+
+/// Equivalent of `LZ4F_dctx`. This type is opaque to C code.
+pub struct LZ4F_dctx(FrameDecoder<'static>);
+
+impl LZ4F_dctx {
+    fn new() -> Self {
+        LZ4F_dctx(FrameDecoder::new())
+    }
+
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+
+    /// The actual implementation of `LZ4F_decompress`, returning (hint or error, consumed, written).
+    fn decompress(&mut self, src: &[u8], dst: &mut [u8]) -> (usize, usize, usize) {
+        match self.0.decode(src, dst) {
+            Ok(DecoderProgress { consumed, written, status }) => {
+                let hint = match status {
+                    DecoderStatus::NeedInput(hint) => hint,
+                    DecoderStatus::OutputFull => 1,
+                    DecoderStatus::FrameEnd => 0,
+                };
+                (hint, consumed, written)
+            }
+            // the decoder doesn't tell us how far it got, but C code has no business continuing after an error anyway
+            Err(e) => (ErrorCode::from(&e).to_result(), src.len(), 0),
+        }
+    }
+}
+
+/// Allocate a new decompression context.
+///
+/// # Safety
+/// `dctx_ptr` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn LZ4F_createDecompressionContext(dctx_ptr: *mut *mut LZ4F_dctx, _version: c_uint) -> usize {
+    if dctx_ptr.is_null() {
+        return ErrorCode::ParameterNull.to_result();
+    }
+    *dctx_ptr = Box::into_raw(Box::new(LZ4F_dctx::new()));
+    0
+}
+
+/// Free a decompression context.
+///
+/// # Safety
+/// `dctx` must either be null or have been created by `LZ4F_createDecompressionContext` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn LZ4F_freeDecompressionContext(dctx: *mut LZ4F_dctx) -> usize {
+    if !dctx.is_null() {
+        drop(Box::from_raw(dctx));
+    }
+    0
+}
+
+/// Return a decompression context to its initial state so it can be used for a new frame.
+///
+/// # Safety
+/// `dctx` must be a valid decompression context.
+#[no_mangle]
+pub unsafe extern "C" fn LZ4F_resetDecompressionContext(dctx: *mut LZ4F_dctx) {
+    if let Some(dctx) = dctx.as_mut() {
+        dctx.reset();
+    }
+}
+
+/// Decompress a frame incrementally.
+///
+/// On return, `*src_size_ptr` holds the number of bytes consumed from `src` and `*dst_size_ptr` the number
+/// of bytes written to `dst`. The return value is `0` once a frame has been decoded completely,
+/// an error code (check with `LZ4F_isError`) or a hint how many more input bytes are required.
+///
+/// # Safety
+/// `dctx` must be a valid decompression context, `dst_size_ptr` and `src_size_ptr` must be valid pointers,
+/// and `dst`/`src` must be valid for `*dst_size_ptr`/`*src_size_ptr` bytes respectively.
+#[no_mangle]
+pub unsafe extern "C" fn LZ4F_decompress(dctx: *mut LZ4F_dctx, dst: *mut c_void, dst_size_ptr: *mut usize,
+                                         src: *const c_void, src_size_ptr: *mut usize,
+                                         _options: *const LZ4F_decompressOptions_t) -> usize {
+    let (dctx, dst_size, src_size) = match (dctx.as_mut(), dst_size_ptr.as_mut(), src_size_ptr.as_mut()) {
+        (Some(a), Some(b), Some(c)) => (a, b, c),
+        _ => return ErrorCode::ParameterNull.to_result(),
+    };
+    let src = if *src_size == 0 || src.is_null() { &[][..] } else { slice::from_raw_parts(src as *const u8, *src_size) };
+    let dst = if *dst_size == 0 || dst.is_null() { &mut [][..] } else { slice::from_raw_parts_mut(dst as *mut u8, *dst_size) };
+
+    let (result, consumed, written) = dctx.decompress(src, dst);
+    *src_size = consumed;
+    *dst_size = written;
+    result
+}
@@ -0,0 +1,476 @@
+use byteorder::{ByteOrder, LE};
+use futures_io::{AsyncRead, AsyncSeek, AsyncWrite};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, SeekFrom};
+use std::mem;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use culpa::throws;
+
+use super::{Checkpoint, CompressionError, CompressionSettings, DecoderStatus, DecompressionError, FrameDecoder, LZ4FrameReader, MAGIC, Spawn, YieldHook};
+use super::async_parallel::ParallelBlocks;
+use super::buffered::{FrameInput, FrameOutput, READ_BUFFER_SIZE};
+use super::header::Flags;
+
+/// Decompresses a single LZ4 frame from an `AsyncRead`.
+///
+/// This is the async counterpart to `LZ4FrameIoReader`. It only depends on `futures-io`,
+/// so it works with async-std, smol and anything else that speaks `futures::io`.
+///
+/// We read ahead in chunks, so the underlying reader may have been advanced past the end of the frame.
+pub struct AsyncLZ4FrameReader<'a, R> {
+    reader: R,
+    input: FrameInput<'a>,
+    dictionary: &'a [u8],
+    mode: Mode,
+    spawner: Option<Arc<dyn Spawn>>,
+    reorder_window: Option<usize>,
+}
+
+/// How we decode the blocks, see `AsyncLZ4FrameReader::set_parallel_blocks`.
+enum Mode {
+    /// One after the other, right on the task (with `input.decoder`).
+    Sequential,
+    /// We're still reading the header to find out whether the blocks are independent. This is how far we got,
+    /// and how many blocks we'd like to decode at once.
+    Undecided(Vec<u8>, usize),
+    Parallel(Box<ParallelBlocks>),
+}
+
+impl<R: AsyncRead + Unpin> AsyncLZ4FrameReader<'static, R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_dictionary(reader, &[])
+    }
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncLZ4FrameReader<'a, R> {
+    /// Decompress a frame that was compressed with this dictionary.
+    pub fn with_dictionary(reader: R, dictionary: &'a [u8]) -> Self {
+        AsyncLZ4FrameReader {
+            reader,
+            input: FrameInput::new(dictionary),
+            dictionary,
+            mode: Mode::Sequential,
+            spawner: None,
+            reorder_window: None,
+        }
+    }
+
+    /// Call a hook every so often while decompressing, even in the middle of a block (see `YieldHook`).
+    pub fn set_yield_hook(&mut self, hook: YieldHook) {
+        self.input.decoder.yield_hook(hook);
+    }
+
+    /// Decode up to `blocks` blocks at once on worker threads, instead of one after the other on the task.
+    ///
+    /// Decoding a large block takes a while, and the task can't read from the network in the meantime, so a single
+    /// slow CPU can hold up a fast stream. With this, we keep reading and splitting the input into blocks while
+    /// the workers decode them, and the task only copies out what they decoded. That takes `blocks` threads
+    /// (for as long as this reader lives) and up to `blocks` blocks of memory twice over (what goes in and what comes out),
+    /// unless you allow more blocks in flight with `set_reorder_window`.
+    /// It only works if the blocks are independent, otherwise we decode on the task as usual. The yield hook is never
+    /// called for blocks decoded on the workers, there's no need to yield when nothing takes long.
+    ///
+    /// This must be set before the first read. By default (and with 1), everything happens on the task.
+    pub fn set_parallel_blocks(&mut self, blocks: usize) {
+        self.mode = if blocks > 1 { Mode::Undecided(Vec::new(), blocks) } else { Mode::Sequential };
+    }
+
+    /// Decode the blocks of `set_parallel_blocks` on your threads instead of starting our own (see `Spawn`).
+    ///
+    /// Every block is a task for `spawner`, and we still only have up to `blocks` of them in flight at once
+    /// (or as many as `set_reorder_window` allows).
+    /// Like `set_parallel_blocks`, this must be set before the first read.
+    pub fn set_spawner(&mut self, spawner: Arc<dyn Spawn>) {
+        self.spawner = Some(spawner);
+    }
+
+    /// Let up to `blocks` blocks be in flight with `set_parallel_blocks` (by default, as many as there are threads).
+    ///
+    /// Blocks are done in any order, but we must hand them out in the order of the frame, so a block that's done early
+    /// waits until all blocks before it are out. If there are only as many blocks in flight as there are threads,
+    /// a single slow block leaves all other threads without work until it's done. A larger window keeps them busy
+    /// on the blocks after it, at the cost of up to `blocks` blocks of memory twice over. Zero is treated as one.
+    ///
+    /// Like `set_parallel_blocks`, this must be set before the first read.
+    pub fn set_reorder_window(&mut self, blocks: usize) {
+        self.reorder_window = Some(blocks.max(1));
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncLZ4FrameReader<'_, R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if this.input.is_done() || buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            match &mut this.mode {
+                Mode::Sequential => {
+                    if let Some(n) = this.input.decode(buf)? {
+                        return Poll::Ready(Ok(n));
+                    }
+                }
+                Mode::Undecided(header, blocks) => {
+                    let is_frame = header.len() < 4 || LE::read_u32(header) == MAGIC;
+                    let needed = header_len(header) - header.len();
+                    if is_frame && needed > 0 {
+                        let n = needed.min(this.input.input().len());
+                        header.extend_from_slice(&this.input.input()[..n]);
+                        this.input.consume(n);
+                        if n == needed {
+                            continue;
+                        }
+                    } else {
+                        // anything but a frame with independent blocks (e.g. a skippable frame) goes to the decoder
+                        let header = mem::take(header);
+                        let frame = if is_frame { Some(LZ4FrameReader::new(VecDeque::from(header.clone()))?) } else { None };
+                        this.mode = match frame {
+                            Some(frame) if frame.flags().independent_blocks() => {
+                                let window = this.reorder_window.unwrap_or(*blocks);
+                                Mode::Parallel(Box::new(ParallelBlocks::new(frame, this.dictionary, *blocks, window, this.spawner.clone())))
+                            }
+                            _ => {
+                                this.input.decoder.decode(&header, &mut [])?;
+                                Mode::Sequential
+                            }
+                        };
+                        continue;
+                    }
+                }
+                Mode::Parallel(blocks) => {
+                    match blocks.poll_output(cx, buf)? {
+                        Poll::Ready(0) => {
+                            this.input.set_done();
+                            return Poll::Ready(Ok(0));
+                        }
+                        Poll::Ready(n) => return Poll::Ready(Ok(n)),
+                        Poll::Pending => (),
+                    }
+                    let consumed = blocks.feed(this.input.input())?;
+                    this.input.consume(consumed);
+                    if consumed > 0 {
+                        continue;
+                    }
+                    if !this.input.input().is_empty() || !blocks.wants_input() {
+                        // all workers are busy (or we're at the end), and they wake us up once the next block is done
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            // the decoder wants more input, and it already took everything we had
+            let n = ready!(Pin::new(&mut this.reader).poll_read(cx, this.input.spare()))?;
+            this.input.filled(n)?;
+        }
+    }
+}
+
+/// Random access to the content of a single LZ4 frame in an `AsyncRead + AsyncSeek`.
+///
+/// This implements `AsyncRead` and `AsyncSeek` over the uncompressed content, so you can e.g. serve range requests
+/// straight from a compressed file. Where we can start decoding comes from the checkpoints the frame was written with
+/// (see `CompressionSettings::checkpoint_interval` and `LZ4FrameWriter::finish_with_checkpoints`): a seek decodes
+/// from the last checkpoint before the target and throws away everything up to it. Seeking forward from where we are
+/// just keeps decoding, unless there is a checkpoint in between. Without any checkpoints this still works,
+/// but every seek backwards starts over at the beginning of the frame.
+///
+/// The underlying reader must be positioned at the start of the frame when we first use it. Seeking relative to the end
+/// only works if the frame has a content size. The content checksum is only checked if we decode everything from the
+/// beginning, block checksums are always checked.
+pub struct AsyncLZ4RandomAccessReader<'a, R> {
+    reader: R,
+    dictionary: &'a [u8],
+    /// Sorted by their position in the content.
+    checkpoints: Vec<Checkpoint>,
+    /// Where the frame starts in the reader, once we asked it.
+    frame_start: Option<u64>,
+    /// The frame header, as far as we have read it.
+    header: Vec<u8>,
+    /// Only `Some` once we have the whole header.
+    content_size: Option<Option<u64>>,
+    /// Only `Some` while the reader is where the decoder expects its next input.
+    decoder: Option<FrameDecoder<'a>>,
+    /// How much content the decoder has produced, i.e. where it is in the content.
+    decoded: u64,
+    /// Where the next read starts in the content.
+    pos: u64,
+    buffer: Box<[u8]>,
+    start: usize,
+    end: usize,
+    done: bool,
+    /// Where decoded data goes that we skip over.
+    scratch: Vec<u8>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncLZ4RandomAccessReader<'static, R> {
+    pub fn new(reader: R, checkpoints: Vec<Checkpoint>) -> Self {
+        Self::with_dictionary(reader, checkpoints, &[])
+    }
+}
+
+impl<'a, R: AsyncRead + AsyncSeek + Unpin> AsyncLZ4RandomAccessReader<'a, R> {
+    /// Read a frame that was compressed with this dictionary.
+    pub fn with_dictionary(reader: R, mut checkpoints: Vec<Checkpoint>, dictionary: &'a [u8]) -> Self {
+        checkpoints.sort_by_key(|checkpoint| checkpoint.uncompressed_offset);
+        AsyncLZ4RandomAccessReader {
+            reader,
+            dictionary,
+            checkpoints,
+            frame_start: None,
+            header: Vec::new(),
+            content_size: None,
+            decoder: None,
+            decoded: 0,
+            pos: 0,
+            buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            done: false,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Read the frame header, unless we already did.
+    fn poll_header(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.frame_start.is_none() {
+            self.frame_start = Some(ready!(Pin::new(&mut self.reader).poll_seek(cx, SeekFrom::Current(0)))?);
+        }
+        while self.content_size.is_none() {
+            let len = self.header.len();
+            let needed = header_len(&self.header) - len;
+            if needed == 0 {
+                self.content_size = Some(LZ4FrameReader::new(&self.header[..])?.frame_size());
+                break;
+            }
+            self.header.resize(len + needed, 0);
+            let result = Pin::new(&mut self.reader).poll_read(cx, &mut self.header[len..]);
+            self.header.truncate(len + match result { Poll::Ready(Ok(n)) => n, _ => 0 });
+            if ready!(result)? == 0 {
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Unless the decoder can get to `pos` by just decoding on, move it to the closest checkpoint before it.
+    fn poll_reposition(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let frame_start = Checkpoint { compressed_offset: self.header.len() as u64, uncompressed_offset: 0 };
+        let checkpoint = *self.checkpoints.iter().rev().find(|c| c.uncompressed_offset <= self.pos).unwrap_or(&frame_start);
+        if self.decoder.is_some() && self.decoded <= self.pos && checkpoint.uncompressed_offset <= self.decoded {
+            return Poll::Ready(Ok(()));
+        }
+
+        // from here on, the reader is somewhere the decoder doesn't expect
+        self.decoder = None;
+        let offset = self.frame_start.and_then(|start| start.checked_add(checkpoint.compressed_offset))
+            .ok_or(DecompressionError::InvalidCheckpoint)?;
+        ready!(Pin::new(&mut self.reader).poll_seek(cx, SeekFrom::Start(offset)))?;
+
+        let mut decoder = FrameDecoder::with_dictionary(self.dictionary);
+        decoder.decode(&self.header, &mut [])?;
+        if checkpoint.uncompressed_offset > 0 {
+            decoder.jump_to(&checkpoint)?;
+        }
+        self.decoder = Some(decoder);
+        self.decoded = checkpoint.uncompressed_offset;
+        self.start = 0;
+        self.end = 0;
+        self.done = false;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Decode and throw away everything up to `pos` (or the end of the frame, if that comes first).
+    fn poll_skip(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut scratch = mem::take(&mut self.scratch);
+        let result = loop {
+            if self.decoded >= self.pos || self.done {
+                break Poll::Ready(Ok(()));
+            }
+            scratch.resize((self.pos - self.decoded).min(READ_BUFFER_SIZE as u64) as usize, 0);
+            match self.poll_decode(cx, &mut scratch) {
+                Poll::Ready(Ok(_)) => (),
+                Poll::Ready(Err(e)) => break Poll::Ready(Err(e)),
+                Poll::Pending => break Poll::Pending,
+            }
+        };
+        self.scratch = scratch;
+        result
+    }
+
+    /// Decode the next bit of content into `output`. Returns 0 at the end of the frame.
+    fn poll_decode(&mut self, cx: &mut Context<'_>, output: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            if self.done || output.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+
+            let decoder = self.decoder.as_mut().expect("the decoder is always in place before we decode");
+            let progress = match decoder.decode(&self.buffer[self.start..self.end], output) {
+                Ok(progress) => progress,
+                Err(e) => {
+                    // the decoder reset itself, so we have to start over next time
+                    self.decoder = None;
+                    return Poll::Ready(Err(e.into()));
+                }
+            };
+            self.start += progress.consumed;
+            self.decoded += progress.written as u64;
+            if progress.status == DecoderStatus::FrameEnd {
+                self.done = true;
+            }
+            if progress.written > 0 || self.done {
+                return Poll::Ready(Ok(progress.written));
+            }
+
+            debug_assert_eq!(self.start, self.end);
+            let n = ready!(Pin::new(&mut self.reader).poll_read(cx, &mut self.buffer))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+            self.start = 0;
+            self.end = n;
+        }
+    }
+}
+
+/// How long a frame header is, as far as we can tell from its beginning.
+fn header_len(header: &[u8]) -> usize {
+    match header.get(4) {
+        Some(&flags) => {
+            let flags = Flags::from_bits_truncate(flags);
+            7 + if flags.content_size() { 8 } else { 0 } + if flags.dictionary_id() { 4 } else { 0 }
+        }
+        None => 5,
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncRead for AsyncLZ4RandomAccessReader<'_, R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(this.poll_header(cx))?;
+        ready!(this.poll_reposition(cx))?;
+
+        ready!(this.poll_skip(cx))?;
+        let n = ready!(this.poll_decode(cx, buf))?;
+        this.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncSeek for AsyncLZ4RandomAccessReader<'_, R> {
+    /// Seeking itself is cheap, the decoding happens on the next read. Seeking past the end is fine, reads just return nothing.
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context<'_>, pos: SeekFrom) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        let pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => this.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                ready!(this.poll_header(cx))?;
+                let size = this.content_size.flatten()
+                    .ok_or_else(|| io::Error::new(ErrorKind::Unsupported, "the frame doesn't say how large its content is"))?;
+                size.checked_add_signed(offset)
+            }
+        };
+        this.pos = pos.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "can't seek before the start of the content"))?;
+        Poll::Ready(Ok(this.pos))
+    }
+}
+
+/// Compresses into an `AsyncWrite`.
+///
+/// This is the async counterpart to `LZ4FrameWriter`: compressed blocks are produced in memory
+/// and then written out as the underlying writer becomes ready. Like all `AsyncWrite`s, it must be
+/// closed (e.g. with `AsyncWriteExt::close`, or `poll_shutdown` through tokio-util's compat layer, which is what
+/// `tokio::io::copy` ends with) to write the end of the frame. Unlike `LZ4FrameWriter`, it can't do that on drop
+/// because that would require blocking.
+///
+/// Closing is cancellation-safe: if the future that closes the writer is dropped halfway, closing again
+/// picks up exactly where it left off. If you drop the writer itself before closing has completed (or give up on
+/// purpose with `abort`), the underlying writer is left with a torn frame: everything that was written out so far,
+/// possibly ending in the middle of a block, but never the end of the frame. Every decoder rejects that as truncated,
+/// so it can't be mistaken for a complete frame with less content.
+pub struct AsyncLZ4FrameWriter<'a, W> {
+    writer: W,
+    output: FrameOutput<'a>,
+}
+
+impl<'a, W: AsyncWrite + Unpin> AsyncLZ4FrameWriter<'a, W> {
+    /// Start a new frame. The header is only written on the first write (or flush/close).
+    #[throws(CompressionError)]
+    pub fn new(writer: W, settings: &'a CompressionSettings<'a>) -> Self {
+        AsyncLZ4FrameWriter { writer, output: FrameOutput::new(settings)? }
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Return the underlying writer.
+    ///
+    /// Unless you closed this writer first, the frame is incomplete.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Give up on the frame: throw away everything that wasn't written out yet and return the underlying writer.
+    ///
+    /// This leaves a torn frame behind (see above), just like dropping the writer does,
+    /// except that we don't bother compressing what's still buffered.
+    pub fn abort(self) -> W {
+        self.output.abort();
+        self.writer
+    }
+
+    /// Write out everything we have compressed so far.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.output.pending().is_empty() {
+            match ready!(Pin::new(&mut self.writer).poll_write(cx, self.output.pending()))? {
+                0 => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+                n => self.output.advance(n),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncLZ4FrameWriter<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        // only accept more data once the previous block is out, so we never buffer more than one block
+        ready!(this.poll_drain(cx))?;
+        Poll::Ready(this.output.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        this.output.flush()?;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        // if this fails, so does every retry, so we never close the underlying writer as if the frame was complete
+        this.output.finish()?;
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.writer).poll_close(cx)
+    }
+}
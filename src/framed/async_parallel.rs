@@ -0,0 +1,256 @@
+use byteorder::{ByteOrder, LE};
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use culpa::{throw, throws};
+
+use super::{block_checksum, DecompressionError, LZ4FrameReader, Spawn, INCOMPRESSIBLE, WINDOW_SIZE};
+use crate::raw;
+
+type Error = DecompressionError;
+
+/// What a worker decoded a block to, and the block's length field.
+type Decoded = Result<(Vec<u8>, u32), Error>;
+
+/// Decodes the independent blocks of a frame on a few worker threads, for `AsyncLZ4FrameReader::set_parallel_blocks`.
+///
+/// We split the input into blocks and hand them out to the workers, and hand out what they decoded in order.
+/// The workers finish in any order, so `in_flight` is a reorder buffer: a block that's done early waits there
+/// until all blocks before it are out. The workers wake the task when a block is done, so this works with any executor.
+/// Everything else (content size, content checksum) is up to the frame, which sees the decoded blocks as if it had decoded them itself.
+pub(crate) struct ParallelBlocks {
+    frame: LZ4FrameReader<VecDeque<u8>>,
+    /// input bytes of the current (incomplete) block, or the end of the frame
+    pending: Vec<u8>,
+    workers: Workers,
+    shared: Arc<Shared>,
+    /// What the workers decoded (or will decode), in the order of the frame.
+    in_flight: VecDeque<Receiver<Decoded>>,
+    /// How many blocks may be in flight at once (decoding, or done and waiting for the ones before them).
+    window: usize,
+    /// Who to wake when a block is done.
+    waker: Arc<Mutex<Option<Waker>>>,
+    /// The block we're handing out, and how far we got.
+    output: Vec<u8>,
+    output_pos: usize,
+    /// We have the end mark (and the content checksum) in `pending`.
+    end: bool,
+}
+
+/// Who decodes the blocks.
+enum Workers {
+    /// Threads of our own, which take jobs from this channel.
+    Own(Sender<Job>),
+    /// Somebody else's threads, which get a task for every block.
+    Spawner(Arc<dyn Spawn>),
+}
+
+/// A block for a worker to decode, exactly as it was in the frame (length field and block checksum included).
+struct Job {
+    block: Vec<u8>,
+    result: Sender<Decoded>,
+}
+
+/// What every worker needs to know about the frame.
+struct Shared {
+    dictionary: Vec<u8>,
+    block_maxsize: usize,
+    block_checksums: bool,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl ParallelBlocks {
+    /// Start `threads` workers for the blocks of `frame`, which has independent blocks and whose header was already parsed,
+    /// and keep up to `window` blocks in flight.
+    ///
+    /// With a `spawner`, we don't start any threads, and every block is a task for the spawner instead.
+    pub(crate) fn new(frame: LZ4FrameReader<VecDeque<u8>>, dictionary: &[u8], threads: usize, window: usize,
+                      spawner: Option<Arc<dyn Spawn>>) -> Self {
+        let waker = Arc::new(Mutex::new(None));
+        let shared = Arc::new(Shared {
+            // independent blocks can only see the end of it
+            dictionary: dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..].to_vec(),
+            block_maxsize: frame.block_size(),
+            block_checksums: frame.flags().block_checksums(),
+            waker: waker.clone(),
+        });
+        let workers = match spawner {
+            Some(spawner) => Workers::Spawner(spawner),
+            None => {
+                let (jobs, job_receiver) = mpsc::channel();
+                let job_receiver = Arc::new(Mutex::new(job_receiver));
+                for _ in 0..threads {
+                    let (shared, job_receiver) = (shared.clone(), job_receiver.clone());
+                    // the workers stop once we're dropped and there are no more jobs
+                    thread::spawn(move || work(&shared, &job_receiver));
+                }
+                Workers::Own(jobs)
+            }
+        };
+        ParallelBlocks { frame, pending: Vec::new(), workers, shared, in_flight: VecDeque::new(), window, waker, output: Vec::new(), output_pos: 0, end: false }
+    }
+
+    /// How many bytes the next unit (block or end of the frame) occupies, as far as we can tell from `pending`.
+    #[throws]
+    fn unit_len(&self) -> usize {
+        if self.pending.len() < 4 {
+            return 4;
+        }
+        let flags = self.frame.flags();
+        match LE::read_u32(&self.pending) {
+            0 => if flags.content_checksum() { 8 } else { 4 },
+            length => {
+                let len = (length & !INCOMPRESSIBLE) as usize;
+                if len > self.frame.block_size() {
+                    throw!(Error::BlockSizeOverflow);
+                }
+                4 + len + if flags.block_checksums() { 4 } else { 0 }
+            }
+        }
+    }
+
+    /// Hand out as many blocks from `input` to the workers as there is room for, and return how much of `input` that took.
+    #[throws]
+    pub(crate) fn feed(&mut self, mut input: &[u8]) -> usize {
+        let input_len = input.len();
+        'feed: while self.wants_input() {
+            // the length of a unit is only known after reading its first few bytes, so this may take several rounds
+            loop {
+                let needed = self.unit_len()? - self.pending.len();
+                if needed == 0 {
+                    break;
+                }
+                let (take, rest) = input.split_at(needed.min(input.len()));
+                self.pending.extend_from_slice(take);
+                input = rest;
+                if take.len() < needed {
+                    break 'feed;
+                }
+            }
+            if LE::read_u32(&self.pending) == 0 {
+                // the end mark (and the content checksum) stay in `pending` until all blocks are out
+                self.end = true;
+            } else {
+                let (result, receiver) = mpsc::channel();
+                let job = Job { block: mem::take(&mut self.pending), result };
+                match &self.workers {
+                    Workers::Own(jobs) => if jobs.send(job).is_err() {
+                        throw!(Error::InputError(io::Error::other("the decoding threads died")));
+                    },
+                    Workers::Spawner(spawner) => {
+                        let shared = self.shared.clone();
+                        spawner.spawn(Box::new(move || run(&shared, job)));
+                    }
+                }
+                self.in_flight.push_back(receiver);
+            }
+        }
+        input_len - input.len()
+    }
+
+    /// Whether `feed` would take any more input right now.
+    pub(crate) fn wants_input(&self) -> bool {
+        !self.end && self.in_flight.len() < self.window
+    }
+
+    /// Copy decoded data to `output`. Returns 0 at the end of the frame, and `Pending` if the next block isn't done yet
+    /// or we need more input first (in which case `feed` will make progress).
+    pub(crate) fn poll_output(&mut self, cx: &mut Context<'_>, output: &mut [u8]) -> Poll<Result<usize, Error>> {
+        loop {
+            let available = &self.output[self.output_pos..];
+            if !available.is_empty() {
+                let n = available.len().min(output.len());
+                output[..n].copy_from_slice(&available[..n]);
+                self.output_pos += n;
+                return Poll::Ready(Ok(n));
+            }
+
+            let Some(next) = self.in_flight.front() else {
+                if !self.end {
+                    return Poll::Pending;
+                }
+                return Poll::Ready(self.finish().map(|()| 0));
+            };
+            // register first, so we can't miss the wakeup if the block is done right after we looked
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            let block = match next.try_recv() {
+                Ok(block) => block,
+                Err(TryRecvError::Empty) => return Poll::Pending,
+                Err(TryRecvError::Disconnected) => Err(Error::InputError(io::Error::other("a decoding thread died"))),
+            };
+            self.in_flight.pop_front();
+            let block = match block.and_then(|block| self.accept(block)) {
+                Ok(block) => block,
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            self.output = block;
+            self.output_pos = 0;
+        }
+    }
+
+    /// Let the frame know about a block the workers decoded.
+    #[throws]
+    fn accept(&mut self, (block, length): (Vec<u8>, u32)) -> Vec<u8> {
+        self.frame.finish_block(&block, &[], length & !INCOMPRESSIBLE, length & INCOMPRESSIBLE == 0)?;
+        block
+    }
+
+    /// All blocks are out, so let the frame check the end of it.
+    #[throws]
+    fn finish(&mut self) {
+        self.frame.reader_mut().extend(self.pending.drain(..));
+        let mut output = Vec::new();
+        if self.frame.decode_block_internal(&mut output, &[])?.is_some() {
+            unreachable!("we only passed on the end of the frame");
+        }
+    }
+}
+
+/// Decode blocks from `jobs` until there are no more, and wake the task whenever one is done.
+fn work(shared: &Shared, jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+        run(shared, job);
+    }
+}
+
+/// Decode a single block and wake the task.
+fn run(shared: &Shared, job: Job) {
+    // if decoding panics, the task still has to find out
+    let _wake = WakeOnDrop(shared);
+    let _ = job.result.send(decode(shared, &job.block));
+}
+
+struct WakeOnDrop<'a>(&'a Shared);
+
+impl Drop for WakeOnDrop<'_> {
+    fn drop(&mut self) {
+        if let Some(waker) = self.0.waker.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Check and decode a single block. Returns what it decoded to, and its length field.
+#[throws]
+fn decode(shared: &Shared, block: &[u8]) -> (Vec<u8>, u32) {
+    let length = LE::read_u32(block);
+    let (data, checksum) = block[4..].split_at((length & !INCOMPRESSIBLE) as usize);
+    if shared.block_checksums && block_checksum(data) != LE::read_u32(checksum) {
+        throw!(Error::BlockChecksumFail);
+    }
+    let mut output = Vec::new();
+    if length & INCOMPRESSIBLE == 0 {
+        raw::decompress_raw(data, &shared.dictionary, &mut output, shared.block_maxsize)?;
+    } else {
+        output.extend_from_slice(data);
+    }
+    (output, length)
+}
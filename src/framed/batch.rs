@@ -0,0 +1,54 @@
+use std::io::Read;
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings, DecompressionError, FrameBuffers, LZ4FrameReader, LZ4FrameWriter};
+
+/// Compress every item into a frame of its own.
+///
+/// That's the same as calling `compress_slice` for every item, but all frames share one writer, so the hash table
+/// (with the dictionary already loaded, if there is one) is only set up once and then copied, and the buffers
+/// are only allocated once. For lots of small items (e.g. the pages of a columnar store), that setup is most of the work.
+/// Like with `compress_many`, the frames don't record their content size.
+#[throws(CompressionError)]
+pub fn compress_batch(items: &[&[u8]], settings: &CompressionSettings<'_>) -> Vec<Vec<u8>> {
+    let (first, rest) = match items.split_first() {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    let mut frames = Vec::with_capacity(items.len());
+    let mut frame = LZ4FrameWriter::new(Vec::new(), settings)?;
+    let mut result = frame.write_all_internal(first);
+    for item in rest {
+        if result.is_err() {
+            break;
+        }
+        result = frame.reset(Vec::new()).and_then(|previous| {
+            frames.push(previous);
+            frame.write_all_internal(item)
+        });
+    }
+    if let Err(e) = result {
+        frame.abort();
+        throw!(e);
+    }
+    frames.push(frame.finish()?);
+    frames
+}
+
+/// Decompress every frame on its own, e.g. the output of `compress_batch`.
+///
+/// Like `decompress_slice`, but every frame gets a vector of its own (and only the first frame of every item is decoded).
+/// All readers share their buffers, so this doesn't allocate anything except the output once they have grown to the largest block.
+#[throws(DecompressionError)]
+pub fn decompress_batch(frames: &[&[u8]], dictionary: &[u8]) -> Vec<Vec<u8>> {
+    let mut buffers = FrameBuffers::new();
+    let mut outputs = Vec::with_capacity(frames.len());
+    for &frame in frames {
+        let mut output = Vec::new();
+        let mut reader = LZ4FrameReader::with_buffers(frame, &mut buffers)?.into_read_with_dictionary(dictionary);
+        reader.read_to_end(&mut output)?;
+        reader.return_buffers(&mut buffers);
+        outputs.push(output);
+    }
+    outputs
+}
@@ -0,0 +1,193 @@
+use std::io::{self, ErrorKind, Write};
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings, DecoderStatus, DecompressionError, FrameDecoder, LZ4FrameWriter};
+
+/// How much we read from the underlying reader at once.
+pub(crate) const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Everything a streaming reader needs, except for the reader: a `FrameDecoder`, and the input we read ahead.
+///
+/// `NonBlockingFrameReader` and `AsyncLZ4FrameReader` only differ in how they get more input,
+/// so that's all they do themselves: whenever `decode` says so, they read into `spare` and tell us with `filled`.
+pub(crate) struct FrameInput<'a> {
+    pub(crate) decoder: FrameDecoder<'a>,
+    buffer: Box<[u8]>,
+    start: usize,
+    end: usize,
+    done: bool,
+}
+
+impl<'a> FrameInput<'a> {
+    pub(crate) fn new(dictionary: &'a [u8]) -> Self {
+        FrameInput {
+            decoder: FrameDecoder::with_dictionary(dictionary),
+            buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            done: false,
+        }
+    }
+
+    /// Whether we have reached the end of the frame.
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// For when someone else decoded the frame to its end.
+    #[cfg(feature = "async-futures")]
+    pub(crate) fn set_done(&mut self) {
+        self.done = true;
+    }
+
+    /// What we read ahead, but nobody took yet.
+    #[cfg(feature = "async-futures")]
+    pub(crate) fn input(&self) -> &[u8] {
+        &self.buffer[self.start..self.end]
+    }
+
+    /// Someone else took `n` bytes of `input`.
+    #[cfg(feature = "async-futures")]
+    pub(crate) fn consume(&mut self, n: usize) {
+        self.start += n;
+    }
+
+    /// Decode what we have into `buf`, and return how much we wrote (0 at the end of the frame).
+    ///
+    /// `None` means that the decoder took all of our input and wants more.
+    #[throws(DecompressionError)]
+    pub(crate) fn decode(&mut self, buf: &mut [u8]) -> Option<usize> {
+        loop {
+            if self.done || buf.is_empty() {
+                break Some(0);
+            }
+
+            let progress = self.decoder.decode(&self.buffer[self.start..self.end], buf)?;
+            self.start += progress.consumed;
+            let frame_end = progress.status == DecoderStatus::FrameEnd;
+            if frame_end && !self.decoder.skipped() {
+                self.done = true;
+            }
+            if progress.written > 0 || self.done {
+                break Some(progress.written);
+            }
+            if !frame_end {
+                break None;
+            }
+            // that was a skippable frame, our frame comes after it
+        }
+    }
+
+    /// Where the next read from the underlying reader goes. Only once all of our input was taken.
+    pub(crate) fn spare(&mut self) -> &mut [u8] {
+        debug_assert_eq!(self.start, self.end);
+        &mut self.buffer
+    }
+
+    /// We read `n` bytes into `spare`.
+    #[throws(io::Error)]
+    pub(crate) fn filled(&mut self, n: usize) {
+        if n == 0 {
+            throw!(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        self.start = 0;
+        self.end = n;
+    }
+}
+
+/// Everything a streaming writer needs, except for the writer: the frame is compressed into memory, and sits there
+/// until it's written out.
+///
+/// `NonBlockingFrameWriter` and `AsyncLZ4FrameWriter` only differ in how they write out what's `pending`,
+/// so that's all they do themselves.
+pub(crate) struct FrameOutput<'a> {
+    /// Only `None` once we started closing.
+    frame: Option<LZ4FrameWriter<'a, Vec<u8>>>,
+    /// The end of the frame, once we started closing.
+    tail: Vec<u8>,
+    /// How much of the compressed data has already been written out.
+    pos: usize,
+    /// Finishing the frame failed, so it can never be closed properly.
+    failed: bool,
+}
+
+impl<'a> FrameOutput<'a> {
+    #[throws(CompressionError)]
+    pub(crate) fn new(settings: &'a CompressionSettings<'a>) -> Self {
+        FrameOutput { frame: Some(LZ4FrameWriter::new(Vec::new(), settings)?), tail: Vec::new(), pos: 0, failed: false }
+    }
+
+    fn buffer(&self) -> &Vec<u8> {
+        match &self.frame {
+            Some(frame) => frame.get_ref(),
+            None => &self.tail,
+        }
+    }
+
+    /// The compressed data that still has to be written out.
+    pub(crate) fn pending(&self) -> &[u8] {
+        &self.buffer()[self.pos..]
+    }
+
+    /// `n` bytes of `pending` were written out.
+    pub(crate) fn advance(&mut self, n: usize) {
+        self.pos += n;
+        if self.pos == self.buffer().len() {
+            match self.frame.as_mut() {
+                Some(frame) => frame.get_mut().clear(),
+                None => self.tail.clear(),
+            }
+            self.pos = 0;
+        }
+    }
+
+    /// Compress `buf`, or as much of it as fits in the current block.
+    ///
+    /// Only do this once nothing is `pending`, so we never buffer more than one block
+    /// and the caller never has to give up on any of `buf` because it can't write it out.
+    #[throws(io::Error)]
+    pub(crate) fn write(&mut self, buf: &[u8]) -> usize {
+        debug_assert!(self.pending().is_empty());
+        match self.frame.as_mut() {
+            Some(frame) => frame.write(buf)?,
+            None => throw!(io::Error::other("writing to a closed frame")),
+        }
+    }
+
+    /// Compress what's buffered in the current block, so it becomes `pending`.
+    #[throws(io::Error)]
+    pub(crate) fn flush(&mut self) {
+        if let Some(frame) = self.frame.as_mut() {
+            // this is a no-op if there is nothing buffered, so it's fine to do this again after the writer wasn't ready
+            frame.flush()?;
+        }
+    }
+
+    /// End the frame, so its end becomes `pending`. Only do this once nothing else is.
+    ///
+    /// If the frame can't be finished, this keeps failing, so a retry never passes for a complete frame.
+    #[throws(io::Error)]
+    pub(crate) fn finish(&mut self) {
+        debug_assert!(self.pending().is_empty());
+        if let Some(frame) = self.frame.take() {
+            match frame.finish() {
+                Ok(tail) => self.tail = tail,
+                Err(e) => {
+                    self.failed = true;
+                    throw!(e);
+                }
+            }
+        }
+        if self.failed {
+            throw!(io::Error::other("the frame could not be finished"));
+        }
+    }
+
+    /// Throw away everything that wasn't written out yet, without compressing what's still buffered.
+    #[cfg(feature = "async-futures")]
+    pub(crate) fn abort(self) {
+        if let Some(frame) = self.frame {
+            frame.abort();
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A cheap handle that allows you to abort a running compression or decompression from somewhere else
+/// (e.g. another thread that noticed the client went away).
+///
+/// Clone it, hand one copy to the compressor/decompressor and keep the other one around.
+/// The token is only checked at block boundaries, so cancellation takes effect after at most one more block.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation. This can not be undone.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+impl From<Arc<AtomicBool>> for CancellationToken {
+    /// Use an existing flag as a token. Setting it to `true` cancels the operation.
+    fn from(flag: Arc<AtomicBool>) -> Self {
+        CancellationToken(flag)
+    }
+}
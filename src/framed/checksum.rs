@@ -0,0 +1,48 @@
+use std::hash::Hasher;
+use twox_hash::XxHash32;
+
+/// The header checksum for a frame descriptor, i.e. the header without the magic number and without the checksum itself.
+///
+/// The format uses the second byte of the xxHash32 (with seed 0), not the first one as you might expect.
+pub fn header_checksum(descriptor: &[u8]) -> u8 {
+    let mut hasher = XxHash32::with_seed(0);
+    hasher.write(descriptor);
+    (hasher.finish() >> 8) as u8
+}
+
+/// The block checksum for a block exactly as it's stored in the frame (i.e. still compressed, unless it's a stored block),
+/// without the length field.
+pub fn block_checksum(block: &[u8]) -> u32 {
+    let mut hasher = XxHash32::with_seed(0);
+    hasher.write(block);
+    hasher.finish() as u32
+}
+
+/// The content checksum at the end of a frame, computed incrementally over the decompressed content.
+///
+/// This is what you need to patch a frame without knowing all of its content at once,
+/// e.g. to recompute the checksum after appending blocks.
+#[derive(Clone, Debug)]
+pub struct ContentChecksum(XxHash32);
+
+impl ContentChecksum {
+    pub fn new() -> Self {
+        ContentChecksum(XxHash32::with_seed(0))
+    }
+
+    /// Add the next part of the content.
+    pub fn update(&mut self, content: &[u8]) {
+        self.0.write(content);
+    }
+
+    /// The checksum of everything so far (you can keep going afterwards).
+    pub fn finish(&self) -> u32 {
+        self.0.finish() as u32
+    }
+}
+
+impl Default for ContentChecksum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
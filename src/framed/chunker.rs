@@ -0,0 +1,175 @@
+use std::hash::Hasher;
+use std::io::Read;
+use culpa::throws;
+use twox_hash::{XxHash32, XxHash64};
+
+use super::compress::{end_len, LoneBlocks};
+use super::gear::Gear;
+use super::header::Flags;
+use super::{CompressionError, CompressionSettings};
+
+type Error = CompressionError;
+
+/// How `Chunker` packages every chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChunkFormat {
+    /// A complete frame (with its content size), which decompresses on its own.
+    Frame,
+    /// A single block. Put them together with `Chunker::header` in front and an end mark (four zero bytes) at the end
+    /// to get a frame of independent blocks.
+    Block,
+}
+
+/// A piece of the input that `Chunker` cut off and compressed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Chunk {
+    /// Where the chunk starts in the input.
+    pub offset: u64,
+    /// How long the chunk is (uncompressed).
+    pub len: usize,
+    /// The xxHash64 of the uncompressed chunk, which is what you look for duplicates by.
+    ///
+    /// That's fast, but not a cryptographic hash: if a collision would hurt, compare the contents when the hashes match.
+    pub hash: u64,
+    /// The compressed chunk, as a frame or a block (see `ChunkFormat`).
+    pub data: Vec<u8>,
+}
+
+/// Splits the input at content-defined boundaries and compresses every chunk on its own, for deduplicating storage.
+///
+/// A gear hash over the last 64 bytes decides where a chunk ends (see also `CompressionSettings::rsyncable`), so the same
+/// content is cut into the same chunks, no matter where it is in the input or what comes before it. An edit only changes
+/// the chunks around it. Chunks are between a quarter and four times the average length, but never longer than a block.
+///
+/// This is an iterator over the chunks. The compressed chunks only depend on their content (and the settings),
+/// except that with `DictionaryScope::FirstBlock`, only the first block may use the dictionary.
+pub struct Chunker<'a, R: Read> {
+    reader: R,
+    settings: &'a CompressionSettings<'a>,
+    format: ChunkFormat,
+    flags: Flags,
+    blocks: LoneBlocks<'a>,
+    boundary_bits: u32,
+    min_len: usize,
+    max_len: usize,
+    /// Input that we read, but didn't cut off yet.
+    buffer: Vec<u8>,
+    offset: u64,
+    /// We hit the end of the input (or an error), so there's nothing more to read.
+    done: bool,
+}
+
+impl<'a, R: Read> Chunker<'a, R> {
+    /// Chunks are 64 KiB on average (or the block size, if that's smaller).
+    #[throws]
+    pub fn new(reader: R, settings: &'a CompressionSettings<'a>, format: ChunkFormat) -> Self {
+        let mut flags = settings.flags(None) | Flags::IndependentBlocks;
+        if format == ChunkFormat::Block {
+            // the frame that the blocks end up in doesn't have a content checksum, because we can't know it up front
+            flags -= Flags::ContentChecksum;
+        }
+        let mut chunker = Chunker {
+            reader,
+            settings,
+            format,
+            flags,
+            blocks: LoneBlocks::new(settings, flags)?,
+            boundary_bits: 0,
+            min_len: 0,
+            max_len: 0,
+            buffer: Vec::new(),
+            offset: 0,
+            done: false,
+        };
+        chunker.average_len(64 * 1024);
+        chunker
+    }
+
+    /// Cut chunks of about `len` bytes on average (rounded down to a power of two, and at most the block size).
+    pub fn average_len(&mut self, len: usize) -> &mut Self {
+        let len = len.clamp(64, self.settings.block_size.max(64));
+        self.boundary_bits = len.ilog2();
+        self.min_len = (1 << self.boundary_bits) / 4;
+        self.max_len = (4 << self.boundary_bits).min(self.settings.block_size);
+        self
+    }
+
+    /// The header of a frame that holds chunks in `ChunkFormat::Block`.
+    #[throws]
+    pub fn header(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        self.settings.write_header(self.flags, None, &mut header)?;
+        header
+    }
+
+    /// Read until we have a full chunk (or the input ends), and return where the chunk ends.
+    #[throws]
+    fn fill(&mut self) -> usize {
+        if !self.done && self.buffer.len() < self.max_len {
+            let missing = self.max_len - self.buffer.len();
+            let read = self.reader.by_ref().take(missing as u64).read_to_end(&mut self.buffer).map_err(Error::ReadError)?;
+            self.done = read < missing;
+        }
+        let available = self.buffer.len().min(self.max_len);
+        // start hashing 64 bytes before the shortest chunk, so the hash has seen a full window when it gets there
+        let mut gear = Gear::default();
+        let mut end = self.min_len.saturating_sub(64).min(available);
+        while let Some(cut) = gear.find_boundary(&self.buffer[end..available], self.boundary_bits) {
+            end += cut;
+            if end >= self.min_len {
+                return end;
+            }
+        }
+        available
+    }
+
+    #[throws]
+    fn next_chunk(&mut self) -> Option<Chunk> {
+        let len = self.fill()?;
+        if len == 0 {
+            return None;
+        }
+        let content = &self.buffer[..len];
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(content);
+
+        let settings = self.settings;
+        let mut data = Vec::new();
+        let room = |data: &Vec<u8>| settings.output_limit.map(|limit| limit.saturating_sub(data.len() as u64 + end_len(self.flags)));
+        match self.format {
+            ChunkFormat::Frame => {
+                let flags = self.flags | Flags::ContentSize;
+                settings.write_header(flags, Some(len as u64), &mut data)?;
+                self.blocks.write(true, content, room(&data), &mut data)?;
+                let content_hasher = flags.contains(Flags::ContentChecksum).then(|| {
+                    let mut hasher = XxHash32::with_seed(0);
+                    hasher.write(content);
+                    hasher
+                });
+                settings.write_end(content_hasher.as_ref(), &mut data)?;
+            }
+            ChunkFormat::Block => {
+                self.blocks.write(self.offset == 0, content, None, &mut data)?;
+            }
+        }
+
+        let chunk = Chunk { offset: self.offset, len, hash: hasher.finish(), data };
+        self.buffer.drain(..len);
+        self.offset += len as u64;
+        Some(chunk)
+    }
+}
+
+impl<R: Read> Iterator for Chunker<'_, R> {
+    type Item = Result<Chunk, CompressionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk = self.next_chunk().transpose();
+        if matches!(chunk, Some(Err(_))) {
+            // don't keep going after an error, the chunks wouldn't line up with the input anymore
+            self.buffer.clear();
+            self.done = true;
+        }
+        chunk
+    }
+}
@@ -0,0 +1,48 @@
+use std::cmp;
+use std::io::{self, BufRead, Read};
+use culpa::throws;
+
+use super::LZ4FrameReader;
+
+type Error = io::Error;
+
+/// The outcome of `compare_frames`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FrameComparison {
+    /// Both frames decompress to the same plaintext, which is this many bytes long.
+    Identical(u64),
+    /// The plaintexts differ, starting at this offset.
+    /// If one of them is a prefix of the other, this is where the shorter one ends.
+    Differ(u64),
+}
+
+/// Check whether two frames decompress to the same plaintext, without decompressing either of them into memory.
+///
+/// Both frames are decoded in lockstep, one block at a time, so this needs no more than a block per frame,
+/// no matter how large the frames are. The frames don't need to have been compressed with the same settings
+/// (though they need the same dictionary). We stop at the first difference, so in that case the rest of the frames
+/// (including their content checksums) are not checked.
+#[throws]
+pub fn compare_frames<A: Read, B: Read>(a: A, b: B, dictionary: &[u8]) -> FrameComparison {
+    let mut a = LZ4FrameReader::new(a)?.into_read_with_dictionary(dictionary);
+    let mut b = LZ4FrameReader::new(b)?.into_read_with_dictionary(dictionary);
+    let mut offset = 0;
+    loop {
+        let (chunk_a, chunk_b) = (a.fill_buf()?, b.fill_buf()?);
+        if chunk_a.is_empty() || chunk_b.is_empty() {
+            break if chunk_a.is_empty() && chunk_b.is_empty() {
+                FrameComparison::Identical(offset)
+            } else {
+                FrameComparison::Differ(offset)
+            };
+        }
+
+        let len = cmp::min(chunk_a.len(), chunk_b.len());
+        if let Some(pos) = chunk_a[..len].iter().zip(&chunk_b[..len]).position(|(x, y)| x != y) {
+            break FrameComparison::Differ(offset + pos as u64);
+        }
+        a.consume(len);
+        b.consume(len);
+        offset += len as u64;
+    }
+}
@@ -0,0 +1,147 @@
+use memmap2::Mmap;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::borrow::Cow;
+use std::cmp;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Write;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+use twox_hash::XxHash32;
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings, BlockStats};
+use crate::framed::{DictionaryScope, WINDOW_SIZE};
+use crate::framed::header::Flags;
+use crate::framed::metrics::{time_checksum, time_stage, Stage, Timed};
+use crate::raw::{Bailout, DEFAULT_ACCELERATION};
+
+type Error = CompressionError;
+
+/// What a worker hands back for one block.
+enum ParallelBlock {
+    /// The finished block, ready to be written.
+    Compressed(Vec<u8>, BlockStats),
+    /// The block turned out to be incompressible, so it is written straight from this part of the input.
+    Stored(Range<usize>, Option<Bailout>, Option<Instant>),
+}
+
+impl<'a> CompressionSettings<'a> {
+    /// Compress in parallel on this thread pool instead of rayon's global one.
+    ///
+    /// This is for `compress_mmap`, so it runs on the threads that your application already sized for CPU-bound work.
+    pub fn thread_pool(&mut self, pool: &'a ThreadPool) -> &mut Self {
+        self.thread_pool = Some(pool);
+        self
+    }
+
+    /// Compress a file by memory-mapping it and compressing its blocks in parallel
+    /// (on rayon's global thread pool, unless you set a `thread_pool`).
+    ///
+    /// This avoids copying the input into a buffer first, which is what dominates
+    /// `compress` for files that are already in the page cache.
+    /// The output is a regular frame, but it won't match the C implementation byte for byte
+    /// because blocks can't share their hash tables when they are compressed in parallel.
+    ///
+    /// Note that modifying the file while we're compressing it results in garbage output (but nothing worse).
+    #[throws]
+    pub fn compress_mmap<P: AsRef<Path>, W: Write>(&self, path: P, mut writer: W) {
+        if self.bounded_memory {
+            throw!(Error::Unbounded);
+        }
+        let file = File::open(path).map_err(Error::ReadError)?;
+        // the only unsafe part: someone else could modify the file while we're looking at it
+        #[allow(unsafe_code)]
+        let map = unsafe { Mmap::map(&file) }.map_err(Error::ReadError)?;
+        let input = &map[..];
+
+        let flags = self.flags(None);
+        self.write_header(flags, None, &mut writer)?;
+
+        // the settings aren't Sync (because of the block callback), so the workers only get what they need
+        let block_writer = self.block_writer(flags);
+        let (block_size, dictionary, dictionary_scope, metrics) = (self.block_size, self.dictionary.as_deref(), self.dictionary_scope, self.metrics.as_deref());
+        let tables = self.tables();
+        let template_table = tables.template()?;
+
+        let mut content_hasher = if self.content_checksum { Some(XxHash32::with_seed(0)) } else { None };
+        let block_starts: Vec<_> = (0..input.len()).step_by(self.block_size).collect();
+        // process a few blocks per thread at a time so we don't hold the entire output in memory
+        let threads = self.thread_pool.map_or_else(rayon::current_num_threads, ThreadPool::current_num_threads);
+        for batch in block_starts.chunks(self.blocks_in_flight.unwrap_or(threads * 4)) {
+            if self.is_cancelled() {
+                throw!(Error::Cancelled);
+            }
+
+            let batch_input = &input[batch[0]..cmp::min(batch[batch.len() - 1] + self.block_size, input.len())];
+            let (blocks, ()) = self.in_pool(|| rayon::join(
+                || batch.par_iter().map(|&start| {
+                    let end = cmp::min(start + block_size, input.len());
+                    let fresh_start = start == 0 || flags.contains(Flags::IndependentBlocks);
+                    let use_dictionary = start == 0 || dictionary_scope == DictionaryScope::EveryBlock;
+                    let block_input = if !fresh_start {
+                        // dependent blocks can refer back to the previous 64KiB, which are right there in the map
+                        Cow::Borrowed(&input[start.saturating_sub(WINDOW_SIZE)..end])
+                    } else if let Some(dict) = dictionary.filter(|_| use_dictionary) {
+                        Cow::Owned([dict, &input[start..end]].concat())
+                    } else {
+                        Cow::Borrowed(&input[start..end])
+                    };
+                    let window_offset = block_input.len() - (end - start);
+                    let mut table = if !fresh_start {
+                        let mut table = tables.empty()?;
+                        table.prime(&block_input[..window_offset]);
+                        table
+                    } else if use_dictionary {
+                        tables.copy(&template_table)?
+                    } else {
+                        tables.empty()?
+                    };
+
+                    let block_start = block_writer.start_timer();
+                    let mut out_buffer = Vec::new();
+                    let compressed = time_stage(metrics, Stage::Compress,
+                                                || block_writer.compress_block(&block_input, window_offset, &mut table, DEFAULT_ACCELERATION, None, &mut out_buffer));
+                    Ok(match compressed {
+                        Ok(len) => {
+                            let mut output = Vec::with_capacity(len + 8);
+                            let stats = block_writer.write_compressed(&out_buffer[..len], end - start, block_start, &mut output)?;
+                            ParallelBlock::Compressed(output, stats)
+                        }
+                        // the input is right there in the map, so there's no point in copying it
+                        Err(bailout) => ParallelBlock::Stored(start..end, bailout, block_start),
+                    })
+                }).collect::<Result<Vec<ParallelBlock>, Error>>(),
+                || if let Some(x) = content_hasher.as_mut() {
+                    time_checksum(metrics, || x.write(batch_input));
+                },
+            ));
+
+            let mut writer = Timed::new(&mut writer, metrics, Stage::Write);
+            for block in blocks? {
+                let stats = match block {
+                    ParallelBlock::Compressed(output, stats) => {
+                        writer.write_all(&output)?;
+                        stats
+                    }
+                    ParallelBlock::Stored(range, bailout, block_start) => block_writer.write_stored(&input[range], bailout, block_start, &mut writer)?,
+                };
+                if let Some(callback) = self.block_callback {
+                    callback(&stats);
+                }
+            }
+        }
+
+        self.write_end(content_hasher.as_ref(), &mut writer)?;
+    }
+
+    /// Run `op` on our thread pool, if we have one.
+    fn in_pool<R: Send>(&self, op: impl FnOnce() -> R + Send) -> R {
+        match self.thread_pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
@@ -0,0 +1,1307 @@
+use byteorder::{LE, WriteBytesExt};
+use std::borrow::Cow;
+use std::hash::Hasher;
+use std::io::{self, Read, Write, Seek, SeekFrom, ErrorKind};
+use std::cmp;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use twox_hash::XxHash32;
+use thiserror::Error;
+use culpa::{throw, throws};
+
+use super::{block_checksum, header_checksum, MAGIC, INCOMPRESSIBLE, WINDOW_SIZE, CancellationToken, Counting, DictionaryInfo, DictionaryScope, Metrics, YieldCounter, YieldHook, chain_dictionaries, trim_dictionary};
+use super::metrics::{time_checksum, time_stage, Stage, Timed};
+use super::header::{Flags, BlockDescriptor};
+use crate::raw::{U16Table, U32Table, VarU32Table, compress_to_slice, compressed_len, prime_table, prime_table_thoroughly, Bailout, EncoderTable, DEFAULT_ACCELERATION};
+
+#[cfg(feature = "mmap")]
+mod mmap;
+mod read_ahead;
+mod sniff;
+mod writer;
+pub use writer::*;
+
+
+/// Errors when compressing an LZ4 frame.
+#[derive(Error, Debug)]
+pub enum CompressionError {
+    #[error("error reading from the input you gave me")]
+    ReadError(io::Error),
+    #[error("error writing to the output you gave me")]
+    WriteError(#[from] io::Error),
+    #[error("the block size you asked for is not supported")]
+    InvalidBlockSize,
+    #[error("compression was cancelled")]
+    Cancelled,
+    #[error("the encoder snapshot is invalid or doesn't match the settings")]
+    InvalidSnapshot,
+    #[error("the encoder table can't handle blocks this large")]
+    TableTooSmall,
+    #[error("the hash table size you asked for is not supported")]
+    InvalidHashLog,
+    #[error("these settings can't stay within the memory bound")]
+    Unbounded,
+    #[error("this only works in page mode (see CompressionSettings::page_size)")]
+    NotPaged,
+    #[error("a page of {0} bytes is empty or doesn't fit into the page size")]
+    InvalidPageLength(usize),
+    #[error("the frame doesn't fit into the output limit")]
+    OutputLimit,
+    #[error("an earlier write failed halfway through, so the frame is broken")]
+    Poisoned,
+}
+type Error = CompressionError; // do it this way for better docs
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        io::Error::new(ErrorKind::Other, e)
+    }
+}
+
+/// Information about a single block that was just written.
+#[derive(Clone, Copy, Debug)]
+pub struct BlockStats {
+    /// Number of plaintext bytes in this block.
+    pub uncompressed_len: usize,
+    /// Number of bytes this block occupies in the frame (excluding the length field and block checksum).
+    pub compressed_len: usize,
+    /// Whether the block was stored uncompressed because compression would have made it larger.
+    pub stored: bool,
+    /// Time spent compressing and writing this block.
+    pub elapsed: Duration,
+    /// If the block was stored because it didn't compress, how far compression got before we gave up.
+    ///
+    /// This is `None` for blocks that we stored without even trying (because of `auto_store`, `store_only` or `block_time_budget`)
+    /// and in a dry run (see `CompressionSettings::compressed_size`), where there's no output to look at.
+    pub bailout: Option<Bailout>,
+}
+
+/// The block sizes that the frame format supports.
+///
+/// `CompressionSettings::block_size` takes the number of bytes, so use `bytes()` to get that.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum BlockSize {
+    Max64KB,
+    Max256KB,
+    Max1MB,
+    Max4MB,
+}
+impl BlockSize {
+    pub fn bytes(self) -> usize {
+        match self {
+            BlockSize::Max64KB => 64 * 1024,
+            BlockSize::Max256KB => 256 * 1024,
+            BlockSize::Max1MB => 1024 * 1024,
+            BlockSize::Max4MB => 4 * 1024 * 1024,
+        }
+    }
+}
+
+/// Which version of liblz4 (and the lz4 CLI built on it) to match byte for byte.
+///
+/// We produce exactly the same frames as liblz4 (at its default level), but its output changes a little
+/// from version to version. Pick the version you compare against, and your output stays reproducible
+/// even after the reference moves on. See `CompressionSettings::compat_profile`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum CompatProfile {
+    /// liblz4 1.9.x, which is what we have always matched.
+    #[default]
+    Liblz4_1_9,
+    /// liblz4 1.10, which loads dictionaries more thoroughly: after every third position (like 1.9 does),
+    /// every single position gets a slot in the hash table if nobody took it yet. Only the last 64 KiB of the dictionary are used.
+    Liblz4_1_10,
+}
+
+/// How long the end of a frame with these flags is: the end mark and the content checksum (if there is one).
+pub(crate) fn end_len(flags: Flags) -> u64 {
+    if flags.contains(Flags::ContentChecksum) { 8 } else { 4 }
+}
+
+/// The size of `U32Table`.
+const DEFAULT_HASH_LOG: usize = 12;
+
+/// Creates the hash tables for `CompressionSettings::encoder_table`.
+pub type EncoderTableFactory = dyn Fn() -> Box<dyn EncoderTable + Send + Sync> + Sync;
+
+/// A builder-style struct that configures compression settings.
+/// This is how you compress LZ4 frames.
+/// (An LZ4 file usually consists of a single frame.)
+///
+/// Create it using `Default::default()`.
+pub struct CompressionSettings<'a> {
+    independent_blocks: bool,
+    block_checksums: bool,
+    content_checksum: bool,
+    pub(crate) block_size: usize,
+    /// Every block is exactly one page of `block_size` bytes, see `page_size`.
+    pages: bool,
+    pub(crate) dictionary: Option<Cow<'a, [u8]>>,
+    dictionary_info: Option<DictionaryInfo>,
+    dictionary_id: Option<u32>,
+    pub(crate) dictionary_scope: DictionaryScope,
+    compat_profile: CompatProfile,
+    cancellation_token: Option<CancellationToken>,
+    metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+    pub(crate) block_callback: Option<&'a dyn Fn(&BlockStats)>,
+    encoder_table: Option<&'a EncoderTableFactory>,
+    hash_log: Option<usize>,
+    block_time_budget: Option<Duration>,
+    yield_hook: Option<YieldHook>,
+    auto_store: bool,
+    store_only: bool,
+    pub(crate) checkpoint_interval: Option<u64>,
+    bounded_memory: bool,
+    pub(crate) blocks_in_flight: Option<usize>,
+    pub(crate) output_limit: Option<u64>,
+    pub(crate) rsyncable: bool,
+    #[cfg(feature = "mmap")]
+    thread_pool: Option<&'a rayon::ThreadPool>,
+}
+impl<'a> Default for CompressionSettings<'a> {
+    fn default() -> Self {
+        Self {
+            independent_blocks: true,
+            block_checksums: false,
+            content_checksum: true,
+            block_size: 4 * 1024 * 1024,
+            pages: false,
+            dictionary: None,
+            dictionary_info: None,
+            dictionary_id: None,
+            dictionary_scope: DictionaryScope::EveryBlock,
+            compat_profile: CompatProfile::default(),
+            cancellation_token: None,
+            metrics: None,
+            block_callback: None,
+            encoder_table: None,
+            hash_log: None,
+            block_time_budget: None,
+            yield_hook: None,
+            auto_store: false,
+            store_only: false,
+            checkpoint_interval: None,
+            bounded_memory: false,
+            blocks_in_flight: None,
+            output_limit: None,
+            rsyncable: false,
+            #[cfg(feature = "mmap")]
+            thread_pool: None,
+        }
+    }
+}
+impl fmt::Debug for CompressionSettings<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the dictionary itself is not very interesting, and neither are the callbacks
+        f.debug_struct("CompressionSettings")
+            .field("independent_blocks", &self.independent_blocks)
+            .field("block_checksums", &self.block_checksums)
+            .field("content_checksum", &self.content_checksum)
+            .field("block_size", &self.block_size)
+            .field("pages", &self.pages)
+            .field("dictionary_info", &self.dictionary_info)
+            .field("dictionary_id", &self.dictionary_id)
+            .field("dictionary_scope", &self.dictionary_scope)
+            .field("compat_profile", &self.compat_profile)
+            .field("hash_log", &self.hash_log)
+            .field("block_time_budget", &self.block_time_budget)
+            .field("yield_hook", &self.yield_hook)
+            .field("auto_store", &self.auto_store)
+            .field("store_only", &self.store_only)
+            .field("checkpoint_interval", &self.checkpoint_interval)
+            .field("bounded_memory", &self.bounded_memory)
+            .field("blocks_in_flight", &self.blocks_in_flight)
+            .field("output_limit", &self.output_limit)
+            .field("rsyncable", &self.rsyncable)
+            .finish_non_exhaustive()
+    }
+}
+impl<'a> CompressionSettings<'a> {
+    /// In independent mode, blocks are not allowed to reference data from previous blocks.
+    /// Hence, using dependent blocks yields slightly better compression.
+    /// The downside of dependent blocks is that seeking becomes impossible - the entire frame always has
+    /// to be decompressed from the beginning.
+    ///
+    /// Blocks are independent by default.
+    pub fn independent_blocks(&mut self, v: bool) -> &mut Self {
+        self.independent_blocks = v;
+        self
+    }
+
+    /// Block checksums can help detect data corruption in storage and transit.
+    /// They do not offer error correction though.
+    ///
+    /// In most cases, block checksums are not very helpful because you generally want a lower
+    /// layer to deal with data corruption more comprehensively.
+    ///
+    /// Block checksums are disabled by default.
+    pub fn block_checksums(&mut self, v: bool) -> &mut Self {
+        self.block_checksums = v;
+        self
+    }
+
+    /// The content checksum (also called frame checksum) is calculated over the contents of the entire frame.
+    /// This makes them cheaper than block checksums as their size overhead is constant
+    /// as well as marginally more useful, because they can help protect against incorrect decompression.
+    ///
+    /// Note that the content checksum can only be verified *after* the entire frame has been read
+    /// (and returned!), which is the downside of content checksums.
+    ///
+    /// Frame checksums are enabled by default.
+    pub fn content_checksum(&mut self, v: bool) -> &mut Self {
+        self.content_checksum = v;
+        self
+    }
+
+    /// Only valid values are 4MiB, 1MiB, 256KiB, 64KiB (see `BlockSize`).
+    ///
+    /// The default block size is 4 MiB.
+    pub fn block_size(&mut self, v: usize) -> &mut Self {
+        self.block_size = v;
+        self.pages = false;
+        self
+    }
+
+    /// Page mode: every block holds exactly one page of `size` bytes (only the last one may be shorter), like the pages
+    /// of an embedded database. The page size can be anything up to 4 MiB, the header simply claims the smallest
+    /// block size that fits.
+    ///
+    /// Blocks are always independent in page mode, and every block is a checkpoint, so the checkpoints
+    /// (or `compress_pages`, which returns just their offsets) are your offset table: with it, `PageReader` decodes
+    /// any page on its own, and `compress_page` compresses a page on its own (e.g. to rewrite it).
+    /// This replaces `block_size`, and `block_size` turns page mode off again.
+    pub fn page_size(&mut self, size: usize) -> &mut Self {
+        self.block_size = size;
+        self.pages = true;
+        self
+    }
+
+    /// A dictionary is essentially a constant slice of bytes shared by the compressing and decompressing party.
+    /// Using a dictionary can improve compression ratios, because the compressor can reference data from the dictionary.
+    ///
+    /// The dictionary id is an application-specific identifier which can be used during decompression to determine
+    /// which dictionary to use.
+    ///
+    /// Note that while the size of a dictionary can be arbitrary, dictionaries larger than 64 KiB are not useful as
+    /// the LZ4 algorithm does not support backreferences by more than 64 KiB, i.e. any dictionary content before
+    /// the trailing 64 KiB is ignored. We cut it off right away, check `dictionary_info` to see if that happened.
+    ///
+    /// By default, no dictionary is used and no id is specified.
+    pub fn dictionary(&mut self, id: u32, dict: &'a [u8]) -> &mut Self {
+        let (dict, info) = trim_dictionary(dict);
+        self.dictionary_id = Some(id);
+        self.dictionary = Some(Cow::Borrowed(dict));
+        self.dictionary_info = Some(info);
+        self
+    }
+
+    /// Use several dictionary segments that are logically concatenated, e.g. a global base dictionary
+    /// followed by a per-tenant suffix.
+    ///
+    /// This behaves exactly like passing the concatenation of all segments to `dictionary`,
+    /// except that you don't have to build the concatenation yourself. The decompressing side needs the same chain
+    /// (see `LZ4FrameReader::into_read_with_dictionary_chain` or `chain_dictionaries`).
+    pub fn dictionary_chain(&mut self, id: u32, segments: &[&[u8]]) -> &mut Self {
+        let (dict, info) = chain_dictionaries(segments);
+        self.dictionary_id = Some(id);
+        self.dictionary = Some(Cow::Owned(dict));
+        self.dictionary_info = Some(info);
+        self
+    }
+
+    /// How much of the dictionary is actually used (or `None` if there is no dictionary).
+    pub fn dictionary_info(&self) -> Option<DictionaryInfo> {
+        self.dictionary_info
+    }
+
+    /// Whether every independent block can reference the dictionary, or just the first one.
+    ///
+    /// The frame format doesn't say anything about this, so the decompressing side has to use the same setting
+    /// (see `LZ4FrameReader::set_dictionary_scope`). There is no point in changing this unless you need to
+    /// produce frames for a decompressor that only applies the dictionary to the first block.
+    ///
+    /// By default, every block can reference the dictionary.
+    pub fn dictionary_scope(&mut self, scope: DictionaryScope) -> &mut Self {
+        self.dictionary_scope = scope;
+        self
+    }
+
+    /// The dictionary id header field is quite obviously intended to tell anyone trying to decompress your frame which dictionary to use.
+    /// So it is only natural to assume that the *absence* of a dictionary id indicates that no dictionary was used.
+    ///
+    /// Unfortunately this assumption turns out to be incorrect. The LZ4 CLI simply never writes a dictionary id.
+    /// The major downside is that you can no longer distinguish corrupted data from a missing dictionary
+    /// (unless you write block checksums, which the LZ4 CLI also never does).
+    ///
+    /// Hence, this library is opinionated in the sense that we always want you to specify either neither or both of these things
+    /// (the LZ4 CLI basically just ignores the dictionary id completely and only cares about whether you specify a dictionary parameter or not).
+    ///
+    /// If you think you know better (you probably don't) you may use this method to break this rule.
+    pub fn dictionary_id_nonsense_override(&mut self, id: Option<u32>) -> &mut Self {
+        self.dictionary_id = id;
+        self
+    }
+
+    /// Which version of liblz4 to match byte for byte (see `CompatProfile`).
+    ///
+    /// The versions only differ in how a dictionary is loaded, so without a dictionary this changes nothing.
+    ///
+    /// The default is `CompatProfile::Liblz4_1_9`.
+    pub fn compat_profile(&mut self, profile: CompatProfile) -> &mut Self {
+        self.compat_profile = profile;
+        self
+    }
+
+    /// Allows you to abort compression from the outside.
+    /// When the token is cancelled, compression stops at the next block boundary and returns `CompressionError::Cancelled`.
+    /// Note that the output is left in an incomplete state in this case.
+    ///
+    /// By default, compression can not be cancelled.
+    pub fn cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.cancellation_token = Some(token);
+        self
+    }
+
+    /// Report progress (bytes, blocks, checksum time) to a `Metrics` implementation.
+    ///
+    /// By default, no metrics are collected.
+    /// Note that checksum timing uses `std::time::Instant`, which is not available on `wasm32-unknown-unknown`.
+    pub fn metrics(&mut self, metrics: Arc<dyn Metrics + Send + Sync>) -> &mut Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Invoke a callback after every block with some statistics about it.
+    ///
+    /// This is useful for tuning block sizes and dictionaries for a particular kind of data
+    /// without having to parse the compressed output.
+    /// Note that block timing uses `std::time::Instant`, which is not available on `wasm32-unknown-unknown`.
+    pub fn block_callback(&mut self, callback: &'a dyn Fn(&BlockStats)) -> &mut Self {
+        self.block_callback = Some(callback);
+        self
+    }
+
+    /// Use your own hash table implementation instead of `U32Table`.
+    ///
+    /// The factory is called whenever we need a fresh table (i.e. for every independent block),
+    /// so you can experiment with bigger or smarter tables without forking the framing code.
+    /// The table must be able to handle a full block plus the window in front of it
+    /// (see `EncoderTable::payload_size_limit`), otherwise compression fails with `TableTooSmall`.
+    ///
+    /// Note that calls to a custom table can't be inlined, so this is a bit slower than the built-in table
+    /// even if you plug in an identical implementation. By default, `U32Table` is used.
+    pub fn encoder_table(&mut self, factory: &'a EncoderTableFactory) -> &mut Self {
+        self.encoder_table = Some(factory);
+        self
+    }
+
+    /// The size of the hash table that finds matches: it has `1 << hash_log` entries of 4 bytes each.
+    ///
+    /// A bigger table finds more matches on big blocks (at the cost of memory and cache misses),
+    /// a smaller one saves memory on embedded systems. Valid values are
+    /// `VarU32Table::MIN_HASH_LOG..=VarU32Table::MAX_HASH_LOG`, anything else fails with `InvalidHashLog`.
+    /// Note that you need one table per frame that is being compressed (or per thread with `compress_mmap`).
+    /// This is ignored if you use a custom `encoder_table`.
+    ///
+    /// The default is 12 (a 16 KiB table), which is what the C implementation uses as well.
+    pub fn hash_log(&mut self, v: usize) -> &mut Self {
+        self.hash_log = Some(v);
+        self
+    }
+
+    /// How long compressing a single block may take. Once a block takes longer than this, we compress
+    /// the following blocks faster (and worse), and if even that is too slow, we store them uncompressed.
+    /// As soon as blocks are fast again, we gradually go back to normal compression.
+    ///
+    /// This is for real-time pipelines (e.g. capturing) that would rather ship bigger data than stall
+    /// when the machine is busy. Note that it's a reaction to blocks that were too slow, not a guarantee:
+    /// each block is still compressed in one go. Only the time spent compressing counts, not writing the output.
+    /// This applies to `LZ4FrameWriter` (and everything built on it), but not to `compress_mmap`.
+    ///
+    /// Like block timing, this uses `std::time::Instant`, which is not available on `wasm32-unknown-unknown`.
+    /// By default, there is no budget.
+    pub fn block_time_budget(&mut self, budget: Duration) -> &mut Self {
+        self.block_time_budget = Some(budget);
+        self
+    }
+
+    /// Call a hook every so often while compressing, even in the middle of a block (see `YieldHook`).
+    ///
+    /// This applies to `LZ4FrameWriter` (and everything built on it, like `AsyncLZ4FrameWriter`), but not to `compress_mmap`.
+    /// By default, there is no hook.
+    pub fn yield_hook(&mut self, hook: YieldHook) -> &mut Self {
+        self.yield_hook = Some(hook);
+        self
+    }
+
+    /// Make every `blocks`th block a checkpoint, where a reader can start decoding (see `LZ4FrameReader::from_checkpoint`).
+    ///
+    /// With dependent blocks, a checkpoint block doesn't refer back to anything before it, so this gives you most of
+    /// the compression ratio of dependent blocks while still allowing coarse random access, or decoding the parts
+    /// between checkpoints in parallel. `LZ4FrameWriter::finish_with_checkpoints` tells you where the checkpoints are.
+    /// With independent blocks, every block could be a checkpoint anyway, so this only decides which ones are recorded.
+    ///
+    /// This applies to `LZ4FrameWriter` (and everything built on it), but not to `compress_mmap`.
+    /// By default, there are no checkpoints.
+    pub fn checkpoint_interval(&mut self, blocks: u64) -> &mut Self {
+        self.checkpoint_interval = Some(blocks.max(1));
+        self
+    }
+
+    /// Whether the `block`th block of a frame (counting from zero) is a checkpoint.
+    pub(crate) fn is_checkpoint(&self, block: u64) -> bool {
+        self.pages || self.checkpoint_interval.is_some_and(|interval| block.is_multiple_of(interval))
+    }
+
+    /// End blocks where the content says so (like `gzip --rsyncable`), not only when they are full.
+    ///
+    /// A rolling hash over the last 64 bytes of input decides where a block ends (about every 64 KiB), and the next block
+    /// doesn't refer back to anything before that (not even with dependent blocks). So an edit in the middle of the input
+    /// only changes the blocks around it, and everything after the next boundary compresses exactly like before.
+    /// That's what delta-sync tools like rsync or casync need to transfer only what changed.
+    /// The price is a somewhat worse compression ratio, because blocks are smaller and don't share any history.
+    ///
+    /// This applies to `LZ4FrameWriter` (and everything built on it), but not to `compress_mmap`.
+    /// It's off by default.
+    pub fn rsyncable(&mut self, v: bool) -> &mut Self {
+        self.rsyncable = v;
+        self
+    }
+
+    /// Store blocks that look like they're already compressed without even trying to compress them.
+    ///
+    /// We check whether a block starts with the magic number of a compressed format (gzip, zstd, zip, JPEG, PNG, ...)
+    /// and whether a few samples of it look random. If you're archiving lots of media files, this saves most of the time
+    /// spent on blocks that would have been stored anyway. The check is a guess, so once in a while a block is stored
+    /// that would have compressed a bit (the frame is still perfectly valid, of course).
+    ///
+    /// Auto-store is disabled by default.
+    pub fn auto_store(&mut self, v: bool) -> &mut Self {
+        self.auto_store = v;
+        self
+    }
+
+    /// Store every block as it is, without even trying to compress it.
+    ///
+    /// This still produces a perfectly valid frame (with all the checksums you asked for), it just isn't any smaller
+    /// than the input. That's for pipelines that must emit LZ4 files for compatibility, but carry data that never
+    /// compresses anyway (e.g. encrypted payloads): we don't waste any time looking for matches that aren't there.
+    ///
+    /// Storing is disabled by default.
+    pub fn store_only(&mut self, v: bool) -> &mut Self {
+        self.store_only = v;
+        self
+    }
+
+    /// Promise to never use more than `memory_bound(block_size)` bytes of memory while compressing a stream.
+    ///
+    /// That's what you want if you run in a tight memory limit (e.g. a cgroup-limited sidecar) and would rather
+    /// get an error up front than be killed halfway. All buffers are allocated at their final size right away,
+    /// and settings that could make us hold on to more fail with `Unbounded` when the frame is started:
+    /// a custom `encoder_table`, a `hash_log` above the default, a dictionary larger than the 64 KiB window
+    /// and `checkpoint_interval` or `page_size` (the list of checkpoints grows with the frame). So do `compress_mmap`
+    /// and `compress_read_ahead`, which need the entire input or more blocks.
+    ///
+    /// This covers `LZ4FrameWriter` and everything built on it, but not whatever your reader and writer allocate
+    /// (nor the output of `compress_slice`, of course). It's off by default.
+    pub fn bounded_memory(&mut self, v: bool) -> &mut Self {
+        self.bounded_memory = v;
+        self
+    }
+
+    /// How many blocks the threaded modes may have in flight at once, which bounds their memory to about that many blocks.
+    ///
+    /// For `compress_read_ahead`, that's how many blocks the reading thread may get ahead of us (1 by default,
+    /// which is enough if reading a block takes about as long as compressing one). For `compress_mmap`, it's how many
+    /// blocks are compressed in parallel before we write them out (4 per thread by default). More blocks smooth out
+    /// hiccups and keep all threads busy, fewer blocks take less memory. Zero is treated as one.
+    /// `LZ4FrameReader::set_blocks_in_flight` does the same when decompressing.
+    pub fn blocks_in_flight(&mut self, blocks: usize) -> &mut Self {
+        self.blocks_in_flight = Some(blocks.max(1));
+        self
+    }
+
+    /// Fail with `OutputLimit` as soon as it's clear that the frame won't fit into `bytes` (e.g. one packet or one flash sector).
+    ///
+    /// We check every block before writing it (leaving room for the end of the frame), so nothing that would go over
+    /// the limit is ever written, and you don't have to compress everything to find out that it's too large.
+    /// Whatever was written up to then is an unfinished frame, of course. For `RecordWriter`, the limit applies to every record.
+    ///
+    /// This applies to `LZ4FrameWriter` (and everything built on it), but not to `compress_mmap`.
+    /// By default, there is no limit.
+    pub fn output_limit(&mut self, bytes: u64) -> &mut Self {
+        self.output_limit = Some(bytes);
+        self
+    }
+
+    /// Work out how large the frame for everything in `reader` would be, without writing it anywhere.
+    ///
+    /// This does exactly the same work as `compress` to find matches (so it's not much faster), but compressed blocks
+    /// are only counted, never written or even buffered, so you can size things up (plan storage, decide whether
+    /// compression is worth it at all) without paying for the output. The result is exactly the length of what `compress`
+    /// would produce with these settings, except if `block_time_budget` makes it store different blocks.
+    /// Metrics are not updated in a dry run, but the block callback is called as usual.
+    #[throws]
+    pub fn compressed_size<R: Read>(&self, reader: R) -> u64 {
+        writer::compressed_size(self, reader)?
+    }
+
+    // TODO: these interfaces need to go away in favor of something that can handle individual blocks rather than always compressing full frames at once
+
+    #[throws]
+    pub fn compress<R: Read, W: Write>(&self, reader: R, writer: W) {
+        self.compress_internal(reader, writer, None)?;
+    }
+
+    /// Compress a slice into a new vector.
+    ///
+    /// This is the easiest way to produce an LZ4 frame when everything is in memory anyway
+    /// (e.g. in a browser via wasm-bindgen, where there are no files to stream from).
+    #[throws]
+    pub fn compress_slice(&self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.compress_internal(input, &mut output, None)?;
+        output
+    }
+
+    #[throws]
+    pub fn compress_with_size_unchecked<R: Read, W: Write>(&self, reader: R, writer: W, content_size: u64) {
+        self.compress_internal(reader, writer, Some(content_size))?;
+    }
+
+    #[throws]
+    pub fn compress_with_size<R: Read + Seek, W: Write>(&self, mut reader: R, writer: W) {
+        // maybe one day we can just use reader.stream_len() here: https://github.com/rust-lang/rust/issues/59359
+        // then again, we implement this to ignore the all bytes before the cursor which stream_len() does not
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        let length = end - start;
+        self.compress_internal(reader, writer, Some(length))?;
+    }
+
+    /// Compress data that is already split into chunks (e.g. received from a channel).
+    ///
+    /// This saves you from writing a `Read` adapter that chains the chunks together.
+    /// The chunk boundaries have no influence on the output.
+    #[throws]
+    pub fn compress_chunks<I: IntoIterator, W: Write>(&self, chunks: I, writer: W) where I::Item: AsRef<[u8]> {
+        let mut frame = LZ4FrameWriter::new(writer, self)?;
+        for chunk in chunks {
+            if let Err(e) = frame.write_all_internal(chunk.as_ref()) {
+                frame.abort();
+                throw!(e);
+            }
+        }
+        frame.finish()?;
+    }
+
+    /// Compress `input` into a frame of pages (see `page_size`) and return the offset table:
+    /// where every page's block starts, relative to the start of the frame.
+    ///
+    /// Keep the offset table wherever you like (next to the frame, or in your database's metadata)
+    /// and hand it to `PageReader` to read the pages back one at a time. The header records the content size.
+    #[throws]
+    pub fn compress_pages<W: Write>(&self, input: &[u8], writer: W) -> Vec<u64> {
+        if !self.pages {
+            throw!(Error::NotPaged);
+        }
+        let mut frame = LZ4FrameWriter::with_content_size(writer, self, Some(input.len() as u64))?;
+        if let Err(e) = frame.write_all_internal(input) {
+            frame.abort();
+            throw!(e);
+        }
+        let (_, checkpoints) = frame.finish_with_checkpoints()?;
+        checkpoints.into_iter().map(|checkpoint| checkpoint.compressed_offset).collect()
+    }
+
+    /// Compress the `index`th page on its own into a block, exactly as it would be in a frame of pages,
+    /// and return how many bytes that took (including the length field and block checksum).
+    ///
+    /// This is how you replace a page without rewriting the whole frame: put the block wherever there's room and
+    /// point the offset table at it. `PageReader` only ever looks at the header and the blocks you ask for, so it doesn't
+    /// mind, but the frame as a whole won't decode anymore (the content checksum doesn't match, for one).
+    /// The index only matters for `DictionaryScope::FirstBlock`, where the dictionary only applies to the first page.
+    #[throws]
+    pub fn compress_page<W: Write>(&self, index: usize, page: &[u8], writer: W) -> u64 {
+        if !self.pages {
+            throw!(Error::NotPaged);
+        }
+        if page.is_empty() || page.len() > self.block_size {
+            throw!(Error::InvalidPageLength(page.len()));
+        }
+        let mut writer = Counting::new(writer);
+        LoneBlocks::new(self, self.flags(None))?.write(index == 0, page, None, &mut writer)?;
+        writer.count
+    }
+
+    /// Compress every input into its own frame, all written back to back (like `lz4 -m`, but into a single output).
+    ///
+    /// This reuses all buffers and tables from one frame to the next (see `LZ4FrameWriter::next_frame`),
+    /// so it's much cheaper than calling `compress` for every input. To get the inputs back separately, create
+    /// one `LZ4FrameReader` after another on the same reader (`decompress_slice` concatenates them).
+    /// If there are no inputs, nothing is written at all.
+    #[throws]
+    pub fn compress_many<I: IntoIterator, W: Write>(&self, inputs: I, writer: W) where I::Item: Read {
+        let mut inputs = inputs.into_iter();
+        let first = match inputs.next() {
+            Some(input) => input,
+            None => return,
+        };
+
+        let mut frame = LZ4FrameWriter::new(writer, self)?;
+        let mut result = frame.write_from(first);
+        for input in inputs {
+            if result.is_err() {
+                break;
+            }
+            result = frame.next_frame().and_then(|()| frame.write_from(input));
+        }
+        if let Err(e) = result {
+            // don't finish the frame on drop, the caller should know that something went wrong
+            frame.abort();
+            throw!(e);
+        }
+        frame.finish()?;
+    }
+
+    /// Compress a stream of unknown length into a seekable output, and still record the content size.
+    ///
+    /// This reserves space for the content size in the header, compresses everything
+    /// and then seeks back to fill in the actual size (and fix the header checksum).
+    /// So unlike `compress_with_size`, only the output needs to be seekable, not the input.
+    /// The frame starts at the writer's current position and the writer is left at the end of the frame.
+    /// Returns the content size.
+    #[throws]
+    pub fn compress_patch_size<R: Read, W: Write + Seek>(&self, reader: R, mut writer: W) -> u64 {
+        let start = writer.stream_position()?;
+        let mut reader = Counting::new(reader);
+        self.compress_internal(&mut reader, &mut writer, Some(0))?;
+        let end = writer.stream_position()?;
+
+        let flags = self.flags(Some(reader.count));
+        writer.seek(SeekFrom::Start(start))?;
+        writer.write_all(&self.header(flags, Some(reader.count))?)?;
+        writer.seek(SeekFrom::Start(end))?;
+        reader.count
+    }
+
+    /// Computes the frame flags for these settings.
+    pub(crate) fn flags(&self, content_size: Option<u64>) -> Flags {
+        let mut flags = Flags::empty();
+        if self.independent_blocks || self.pages {
+            flags |= Flags::IndependentBlocks;
+        }
+        if self.block_checksums {
+            flags |= Flags::BlockChecksums;
+        }
+        if self.content_checksum {
+            flags |= Flags::ContentChecksum;
+        }
+        if self.dictionary_id.is_some() {
+            flags |= Flags::DictionaryId;
+        }
+        if content_size.is_some() {
+            flags |= Flags::ContentSize;
+        }
+        flags
+    }
+
+    /// Writes the frame header.
+    #[throws]
+    pub(crate) fn write_header<W: Write>(&self, flags: Flags, content_size: Option<u64>, writer: W) {
+        let header = self.header(flags, content_size)?;
+        if self.output_limit.is_some_and(|limit| header.len() as u64 + end_len(flags) > limit) {
+            throw!(Error::OutputLimit);
+        }
+        Timed::new(writer, self.metrics.as_deref(), Stage::Write).write_all(&header)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?flags, block_size = self.block_size, content_size, dictionary_id = self.dictionary_id, "writing lz4 frame");
+        if let Some(m) = self.metrics.as_deref() {
+            m.bytes_out(header.len() as u64);
+        }
+    }
+
+    #[throws]
+    fn header(&self, flags: Flags, content_size: Option<u64>) -> Vec<u8> {
+        let version = 1 << 6;
+        let flag_byte = version | flags.bits();
+        let bd = if self.pages { BlockDescriptor::fitting(self.block_size) } else { BlockDescriptor::new(self.block_size) };
+        let bd_byte = bd.ok_or(Error::InvalidBlockSize)?.0;
+
+        let mut header = Vec::new();
+        header.write_u32::<LE>(MAGIC)?;
+        header.write_u8(flag_byte)?;
+        header.write_u8(bd_byte)?;
+        
+        if let Some(content_size) = content_size {
+            header.write_u64::<LE>(content_size)?;
+        }
+        if let Some(id) = self.dictionary_id {
+            header.write_u32::<LE>(id)?;
+        }
+
+        header.write_u8(header_checksum(&header[4..]))?; // skip magic for header checksum
+        header
+    }
+
+    /// Returns a `Tables` that creates hash tables for these settings.
+    pub(crate) fn tables(&self) -> Tables<'_> {
+        Tables {
+            custom: self.encoder_table,
+            hash_log: self.hash_log,
+            dictionary: self.dictionary.as_deref(),
+            compat_profile: self.compat_profile,
+            needed: self.block_size + cmp::max(WINDOW_SIZE, self.dictionary.as_ref().map_or(0, |d| d.len())),
+        }
+    }
+
+    /// How large the input buffer of a `LZ4FrameWriter` gets: a full block plus whatever history it can refer back to.
+    pub(crate) fn in_buffer_capacity(&self) -> usize {
+        let dictionary_len = self.dictionary.as_ref().map_or(0, |d| d.len());
+        let window = if self.independent_blocks { 0 } else { WINDOW_SIZE };
+        self.block_size + cmp::max(dictionary_len, window)
+    }
+
+    /// Fails with `Unbounded` if we promised to stay within `memory_bound` but these settings wouldn't.
+    #[throws]
+    pub(crate) fn check_memory_bound(&self) {
+        let dictionary_len = self.dictionary.as_ref().map_or(0, |d| d.len());
+        if self.bounded_memory && (self.encoder_table.is_some() || self.hash_log.is_some_and(|log| log > DEFAULT_HASH_LOG)
+            || dictionary_len > WINDOW_SIZE || self.checkpoint_interval.is_some() || self.pages) {
+            throw!(Error::Unbounded);
+        }
+    }
+
+    /// The buffer that a `LZ4FrameWriter` compresses blocks into: empty, so it only grows as large as the blocks are,
+    /// unless it must never grow at all (with `bounded_memory`).
+    pub(crate) fn out_buffer(&self) -> Vec<u8> {
+        if self.bounded_memory { Vec::with_capacity(self.block_size) } else { Vec::new() }
+    }
+
+    /// Returns a `BlockWriter` that produces blocks for a frame with the given flags.
+    pub(crate) fn block_writer(&self, flags: Flags) -> BlockWriter<'_> {
+        let timed = self.block_callback.is_some() || self.block_time_budget.is_some();
+        BlockWriter { flags, metrics: self.metrics.as_deref(), timed, auto_store: self.auto_store, store_only: self.store_only, dry_run: false, room: None }
+    }
+
+    /// Returns a fresh `Pacing` for a `LZ4FrameWriter`.
+    pub(crate) fn pacing(&self) -> Pacing {
+        Pacing {
+            throttle: self.block_time_budget.map(|budget| Throttle { budget, acceleration: DEFAULT_ACCELERATION }),
+            yield_counter: self.yield_hook.clone().map(YieldCounter::new),
+        }
+    }
+
+    /// Writes the end mark and the content checksum (if enabled).
+    #[throws]
+    pub(crate) fn write_end<W: Write>(&self, content_hasher: Option<&XxHash32>, writer: W) {
+        let mut writer = Timed::new(writer, self.metrics.as_deref(), Stage::Write);
+        writer.write_u32::<LE>(0)?;
+
+        if let Some(x) = content_hasher {
+            writer.write_u32::<LE>(x.finish() as u32)?;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(content_checksum = content_hasher.map(|x| x.finish() as u32), "finished writing lz4 frame");
+        if let Some(m) = self.metrics.as_deref() {
+            m.bytes_out(if content_hasher.is_some() { 8 } else { 4 });
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation_token.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    #[throws]
+    fn compress_internal<R: Read, W: Write>(&self, reader: R, writer: W, content_size: Option<u64>) {
+        let mut frame = LZ4FrameWriter::with_content_size(writer, self, content_size)?;
+        if let Err(e) = frame.write_from(reader) {
+            // don't finish the frame on drop, the caller should know that something went wrong
+            frame.abort();
+            throw!(e);
+        }
+        frame.finish()?;
+    }
+}
+
+/// Everything in `CompressionSettings` except for the block callback, which is all that keeps them from going to other threads.
+///
+/// Worker threads turn this back into settings of their own (see `CompressionSettings::compress_files`).
+pub(crate) struct ThreadSafeSettings<'a> {
+    independent_blocks: bool,
+    block_checksums: bool,
+    content_checksum: bool,
+    block_size: usize,
+    pages: bool,
+    dictionary: Option<&'a [u8]>,
+    dictionary_info: Option<DictionaryInfo>,
+    dictionary_id: Option<u32>,
+    dictionary_scope: DictionaryScope,
+    compat_profile: CompatProfile,
+    cancellation_token: Option<CancellationToken>,
+    metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+    encoder_table: Option<&'a EncoderTableFactory>,
+    hash_log: Option<usize>,
+    block_time_budget: Option<Duration>,
+    yield_hook: Option<YieldHook>,
+    auto_store: bool,
+    store_only: bool,
+    checkpoint_interval: Option<u64>,
+    bounded_memory: bool,
+    blocks_in_flight: Option<usize>,
+    output_limit: Option<u64>,
+    rsyncable: bool,
+    #[cfg(feature = "mmap")]
+    thread_pool: Option<&'a rayon::ThreadPool>,
+}
+impl<'a> CompressionSettings<'a> {
+    pub(crate) fn thread_safe(&self) -> ThreadSafeSettings<'_> {
+        // no `..`, so that new settings can't be forgotten here
+        let CompressionSettings {
+            independent_blocks,
+            block_checksums,
+            content_checksum,
+            block_size,
+            pages,
+            dictionary,
+            dictionary_info,
+            dictionary_id,
+            dictionary_scope,
+            compat_profile,
+            cancellation_token,
+            metrics,
+            block_callback: _,
+            encoder_table,
+            hash_log,
+            block_time_budget,
+            yield_hook,
+            auto_store,
+            store_only,
+            checkpoint_interval,
+            bounded_memory,
+            blocks_in_flight,
+            output_limit,
+            rsyncable,
+            #[cfg(feature = "mmap")]
+            thread_pool,
+        } = self;
+        ThreadSafeSettings {
+            independent_blocks: *independent_blocks,
+            block_checksums: *block_checksums,
+            content_checksum: *content_checksum,
+            block_size: *block_size,
+            pages: *pages,
+            dictionary: dictionary.as_deref(),
+            dictionary_info: *dictionary_info,
+            dictionary_id: *dictionary_id,
+            dictionary_scope: *dictionary_scope,
+            compat_profile: *compat_profile,
+            cancellation_token: cancellation_token.clone(),
+            metrics: metrics.clone(),
+            encoder_table: *encoder_table,
+            hash_log: *hash_log,
+            block_time_budget: *block_time_budget,
+            yield_hook: yield_hook.clone(),
+            auto_store: *auto_store,
+            store_only: *store_only,
+            checkpoint_interval: *checkpoint_interval,
+            bounded_memory: *bounded_memory,
+            blocks_in_flight: *blocks_in_flight,
+            output_limit: *output_limit,
+            rsyncable: *rsyncable,
+            #[cfg(feature = "mmap")]
+            thread_pool: *thread_pool,
+        }
+    }
+}
+impl<'a> ThreadSafeSettings<'a> {
+    /// The settings again, with a block callback of their own.
+    pub(crate) fn settings(&self, block_callback: Option<&'a dyn Fn(&BlockStats)>) -> CompressionSettings<'a> {
+        CompressionSettings {
+            independent_blocks: self.independent_blocks,
+            block_checksums: self.block_checksums,
+            content_checksum: self.content_checksum,
+            block_size: self.block_size,
+            pages: self.pages,
+            dictionary: self.dictionary.map(Cow::Borrowed),
+            dictionary_info: self.dictionary_info,
+            dictionary_id: self.dictionary_id,
+            dictionary_scope: self.dictionary_scope,
+            compat_profile: self.compat_profile,
+            cancellation_token: self.cancellation_token.clone(),
+            metrics: self.metrics.clone(),
+            block_callback,
+            encoder_table: self.encoder_table,
+            hash_log: self.hash_log,
+            block_time_budget: self.block_time_budget,
+            yield_hook: self.yield_hook.clone(),
+            auto_store: self.auto_store,
+            store_only: self.store_only,
+            checkpoint_interval: self.checkpoint_interval,
+            bounded_memory: self.bounded_memory,
+            blocks_in_flight: self.blocks_in_flight,
+            output_limit: self.output_limit,
+            rsyncable: self.rsyncable,
+            #[cfg(feature = "mmap")]
+            thread_pool: self.thread_pool,
+        }
+    }
+}
+
+/// The parts of `CompressionSettings` that are needed to create hash tables.
+///
+/// Like `BlockWriter`, this can be shared between threads.
+#[derive(Clone, Copy)]
+pub(crate) struct Tables<'a> {
+    custom: Option<&'a EncoderTableFactory>,
+    hash_log: Option<usize>,
+    dictionary: Option<&'a [u8]>,
+    compat_profile: CompatProfile,
+    /// how much input a table needs to be able to handle
+    needed: usize,
+}
+impl Tables<'_> {
+    /// Returns a table that doesn't know about anything yet.
+    #[throws]
+    pub(crate) fn empty(&self) -> Table {
+        match (self.custom, self.hash_log) {
+            // a VarU32Table of the default size would work too, but it's slower
+            (None, None | Some(DEFAULT_HASH_LOG)) => Table::Default(U32Table::default()),
+            (None, Some(hash_log)) => Table::Var(VarU32Table::new(hash_log).ok_or(Error::InvalidHashLog)?),
+            (Some(factory), _) => {
+                let table = factory();
+                if table.payload_size_limit() < self.needed {
+                    throw!(Error::TableTooSmall);
+                }
+                Table::Custom(table)
+            }
+        }
+    }
+
+    /// Returns a table that already knows about all positions in the dictionary (or an empty table if there is none).
+    #[throws]
+    pub(crate) fn template(&self) -> Table {
+        let mut template = self.empty()?;
+        if let Some(dict) = self.dictionary {
+            match self.compat_profile {
+                CompatProfile::Liblz4_1_9 => prime_table(template.as_dyn(), dict),
+                CompatProfile::Liblz4_1_10 => prime_table_thoroughly(template.as_dyn(), dict),
+            }
+        }
+        template
+    }
+
+    /// Returns a copy of `template` (i.e. the result of `template`).
+    #[throws]
+    pub(crate) fn copy(&self, template: &Table) -> Table {
+        match template {
+            Table::Default(template) => Table::Default(template.clone()),
+            Table::Var(template) => Table::Var(template.clone()),
+            // we can't clone these, so we have to do it the hard way
+            Table::Custom(_) => self.template()?,
+        }
+    }
+}
+
+/// The parts of `CompressionSettings` that are needed to write a single block.
+///
+/// Unlike the settings themselves (which may hold a block callback), this can be shared between threads.
+#[derive(Clone, Copy)]
+pub(crate) struct BlockWriter<'a> {
+    flags: Flags,
+    metrics: Option<&'a (dyn Metrics + Send + Sync)>,
+    /// Only measure the elapsed time if someone is going to look at it.
+    timed: bool,
+    /// Store blocks that `sniff::looks_compressed` without trying to compress them.
+    auto_store: bool,
+    /// Store all blocks without trying to compress them.
+    store_only: bool,
+    /// Only work out how large blocks would be, without writing anything (see `CompressionSettings::compressed_size`).
+    dry_run: bool,
+    /// How many bytes the block may take up in the frame without going over `CompressionSettings::output_limit`.
+    room: Option<u64>,
+}
+impl BlockWriter<'_> {
+    /// Compresses `input[window_offset..]` into a single block and writes it (including the length field and block checksum).
+    ///
+    /// Everything before `window_offset` is history that the block may refer back to.
+    /// `out_buffer` is scratch space that grows to the size of the block if needed.
+    /// `pacing` decides how hard we try, and learns how long it took.
+    #[throws]
+    pub(crate) fn write_block<W: Write>(&self, input: &[u8], window_offset: usize, table: &mut Table,
+                                         out_buffer: &mut Vec<u8>, pacing: &mut Pacing, writer: W) -> BlockStats {
+        let block_start = self.start_timer();
+        let compressed = match pacing.throttle {
+            // we're so far behind that even the fastest compression is too slow
+            Some(throttle) if throttle.store() => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("over the time budget, storing the block without trying");
+                if let Some(counter) = pacing.yield_counter.as_mut() {
+                    counter.advance(input.len() - window_offset);
+                }
+                Err(None)
+            }
+            throttle => {
+                let acceleration = throttle.map_or(DEFAULT_ACCELERATION, |t| t.acceleration);
+                let mut progress = pacing.yield_counter.as_mut().map(|counter| counter.progress(window_offset));
+                time_stage(self.metrics, Stage::Compress, || self.compress_block(input, window_offset, table, acceleration,
+                                    progress.as_mut().map(|p| p as &mut dyn FnMut(usize)), out_buffer))
+            }
+        };
+        if let Some(throttle) = pacing.throttle.as_mut() {
+            throttle.update(block_start.map_or(Duration::ZERO, |s| s.elapsed()));
+        }
+        let compressed_len = compressed.unwrap_or(input.len() - window_offset);
+        if self.room.is_some_and(|room| self.frame_len(compressed_len) > room) {
+            throw!(Error::OutputLimit);
+        }
+        if self.dry_run {
+            return BlockStats {
+                uncompressed_len: input.len() - window_offset,
+                compressed_len,
+                stored: compressed.is_err(),
+                elapsed: block_start.map_or(Duration::ZERO, |s| s.elapsed()),
+                bailout: None,
+            };
+        }
+        match compressed {
+            Ok(len) => self.write_compressed(&out_buffer[..len], input.len() - window_offset, block_start, writer)?,
+            // incompressible: the block is stored straight from the input, no need to copy it anywhere first
+            Err(bailout) => self.write_stored(&input[window_offset..], bailout, block_start, writer)?,
+        }
+    }
+
+    /// Returns when the block started, if someone is going to look at the elapsed time.
+    pub(crate) fn start_timer(&self) -> Option<Instant> {
+        // Instant::now() panics on wasm32-unknown-unknown, so only touch the clock if someone asked for timings
+        self.timed.then(Instant::now)
+    }
+
+    /// Compresses `input[window_offset..]` into `out_buffer` and returns the compressed size,
+    /// or an error if the block is incompressible and should be stored instead (with how far we got, if we tried).
+    ///
+    /// In a dry run, `out_buffer` is left alone and we only count.
+    pub(crate) fn compress_block(&self, input: &[u8], window_offset: usize, table: &mut Table, acceleration: usize,
+                                 progress: Option<&mut dyn FnMut(usize)>, out_buffer: &mut Vec<u8>) -> Result<usize, Option<Bailout>> {
+        let read_bytes = input.len() - window_offset;
+        if self.store_only || (self.auto_store && sniff::looks_compressed(&input[window_offset..])) {
+            #[cfg(feature = "tracing")]
+            tracing::trace!(store_only = self.store_only, "storing the block without trying");
+            if let Some(progress) = progress {
+                progress(input.len());
+            }
+            return Err(None);
+        }
+        // limit output by input size so we never have negative compression ratio
+        let output = if self.dry_run {
+            BlockOutput::Count(read_bytes)
+        } else {
+            if out_buffer.len() < read_bytes {
+                out_buffer.resize(read_bytes, 0);
+            }
+            BlockOutput::Slice(&mut out_buffer[..read_bytes])
+        };
+        // dispatch once per block rather than once per table lookup
+        match table {
+            // a small block that doesn't refer to anything else (and won't be referred to) is a perfect fit
+            // for the u16 table: twice as many slots for the same memory (this is what the C implementation does, too)
+            Table::Default(_) if self.flags.contains(Flags::IndependentBlocks) && window_offset == 0
+                && input.len() <= U16Table::default().payload_size_limit() =>
+                output.compress(input, 0, &mut U16Table::default(), acceleration, progress),
+            Table::Default(table) => output.compress(input, window_offset, table, acceleration, progress),
+            Table::Var(table) => output.compress(input, window_offset, table, acceleration, progress),
+            Table::Custom(table) => output.compress(input, window_offset, &mut **table, acceleration, progress),
+        }
+    }
+
+    /// How many bytes a block takes up in the frame, including the length field and block checksum.
+    pub(crate) fn frame_len(&self, compressed_len: usize) -> u64 {
+        let checksum_len = if self.flags.contains(Flags::BlockChecksums) { 4 } else { 0 };
+        (4 + compressed_len + checksum_len) as u64
+    }
+
+    /// Don't write blocks that take up more than `room` bytes in the frame (see `CompressionSettings::output_limit`).
+    pub(crate) fn room(self, room: Option<u64>) -> Self {
+        BlockWriter { room, ..self }
+    }
+
+    /// Writes a block that `compress_block` produced from `uncompressed_len` bytes of input.
+    #[throws]
+    pub(crate) fn write_compressed<W: Write>(&self, block: &[u8], uncompressed_len: usize, block_start: Option<Instant>, mut writer: W) -> BlockStats {
+        writer.write_u32::<LE>(block.len() as u32)?;
+        self.write_payload(block, uncompressed_len, false, block_start, writer)?
+    }
+
+    /// Writes `input` as a stored (uncompressed) block, because compression gave up as described by `bailout`.
+    #[throws]
+    pub(crate) fn write_stored<W: Write>(&self, input: &[u8], bailout: Option<Bailout>, block_start: Option<Instant>, mut writer: W) -> BlockStats {
+        writer.write_u32::<LE>((input.len() as u32) | INCOMPRESSIBLE)?;
+        #[cfg(feature = "tracing")]
+        if let Some(bailout) = bailout {
+            tracing::debug!(len = input.len(), consumed = bailout.consumed, "block doesn't compress, storing it");
+        }
+        BlockStats { bailout, ..self.write_payload(input, input.len(), true, block_start, writer)? }
+    }
+
+    #[throws]
+    fn write_payload<W: Write>(&self, write: &[u8], uncompressed_len: usize, stored: bool, block_start: Option<Instant>, mut writer: W) -> BlockStats {
+        let BlockWriter { flags, metrics, .. } = *self;
+        writer.write_all(write)?;
+        if flags.contains(Flags::BlockChecksums) {
+            writer.write_u32::<LE>(time_checksum(metrics, || block_checksum(write)))?;
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(uncompressed_len, compressed_len = write.len(), stored, "wrote lz4 block");
+
+        if let Some(m) = metrics {
+            m.bytes_in(uncompressed_len as u64);
+            let checksum_len = if flags.contains(Flags::BlockChecksums) { 4 } else { 0 };
+            m.bytes_out((4 + write.len() + checksum_len) as u64);
+            m.block_emitted();
+            if stored {
+                m.block_stored();
+            }
+        }
+
+        BlockStats {
+            uncompressed_len,
+            compressed_len: write.len(),
+            stored,
+            elapsed: block_start.map_or(Duration::ZERO, |s| s.elapsed()),
+            bailout: None,
+        }
+    }
+}
+
+/// Where `BlockWriter::compress_block` puts the compressed block.
+enum BlockOutput<'a> {
+    Slice(&'a mut [u8]),
+    /// Nowhere, we only count up to this many bytes.
+    Count(usize),
+}
+impl BlockOutput<'_> {
+    fn compress<T: EncoderTable + ?Sized>(self, input: &[u8], cursor: usize, table: &mut T, acceleration: usize,
+                                          progress: Option<&mut dyn FnMut(usize)>) -> Result<usize, Option<Bailout>> {
+        match self {
+            BlockOutput::Slice(output) => compress_to_slice(input, cursor, table, acceleration, progress, output).map_err(Some),
+            BlockOutput::Count(limit) => compressed_len(input, cursor, table, acceleration, progress, limit).or(Err(None)),
+        }
+    }
+}
+
+/// Beyond this, we don't bother compressing at all (see `CompressionSettings::block_time_budget`).
+const MAX_ACCELERATION: usize = 64;
+
+/// Compresses blocks one at a time, each on its own as if it was the only block (see `compress_page` and `RecordWriter`).
+pub(crate) struct LoneBlocks<'a> {
+    settings: &'a CompressionSettings<'a>,
+    block_writer: BlockWriter<'a>,
+    template_table: Table,
+    /// The dictionary followed by the block.
+    in_buffer: Vec<u8>,
+    out_buffer: Vec<u8>,
+    pacing: Pacing,
+}
+impl<'a> LoneBlocks<'a> {
+    #[throws]
+    pub(crate) fn new(settings: &'a CompressionSettings<'a>, flags: Flags) -> Self {
+        LoneBlocks {
+            settings,
+            block_writer: settings.block_writer(flags),
+            template_table: settings.tables().template()?,
+            in_buffer: Vec::new(),
+            out_buffer: settings.out_buffer(),
+            pacing: settings.pacing(),
+        }
+    }
+
+    /// Compresses and writes `block`. Only the first block of a frame may use the dictionary with `DictionaryScope::FirstBlock`.
+    ///
+    /// Fails with `OutputLimit` instead if the block would take up more than `room` bytes.
+    #[throws]
+    pub(crate) fn write<W: Write>(&mut self, first: bool, block: &[u8], room: Option<u64>, writer: W) -> BlockStats {
+        let settings = self.settings;
+        let dictionary = match settings.dictionary_scope {
+            DictionaryScope::FirstBlock if !first => &[],
+            _ => settings.dictionary.as_deref().unwrap_or(&[]),
+        };
+        let mut table = if dictionary.is_empty() { settings.tables().empty()? } else { settings.tables().copy(&self.template_table)? };
+        self.in_buffer.clear();
+        self.in_buffer.extend_from_slice(dictionary);
+        self.in_buffer.extend_from_slice(block);
+
+        let writer = Timed::new(writer, settings.metrics.as_deref(), Stage::Write);
+        let stats = self.block_writer.room(room)
+            .write_block(&self.in_buffer, dictionary.len(), &mut table, &mut self.out_buffer, &mut self.pacing, writer)?;
+        if let Some(callback) = settings.block_callback {
+            callback(&stats);
+        }
+        stats
+    }
+}
+
+/// Everything about how fast a `LZ4FrameWriter` goes, which carries over from one block (and frame) to the next.
+#[derive(Clone, Debug)]
+pub(crate) struct Pacing {
+    /// Only if there is a `CompressionSettings::block_time_budget`.
+    throttle: Option<Throttle>,
+    /// Only if there is a `CompressionSettings::yield_hook`.
+    yield_counter: Option<YieldCounter>,
+}
+
+/// Keeps blocks within `CompressionSettings::block_time_budget` by compressing faster when they are too slow.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Throttle {
+    budget: Duration,
+    /// Doubles whenever a block is too slow and halves whenever it is very fast. Above `MAX_ACCELERATION`, we store blocks.
+    acceleration: usize,
+}
+impl Throttle {
+    fn store(&self) -> bool {
+        self.acceleration > MAX_ACCELERATION
+    }
+
+    fn update(&mut self, elapsed: Duration) {
+        if elapsed > self.budget {
+            self.acceleration = cmp::min(self.acceleration * 2, MAX_ACCELERATION * 2);
+        } else if elapsed < self.budget / 2 {
+            // a stored block is always fast, so this is also how we find out whether we can compress again
+            self.acceleration = cmp::max(self.acceleration / 2, DEFAULT_ACCELERATION);
+        }
+    }
+}
+
+/// The hash table of a frame: either our own (which is the fast path) or one from `CompressionSettings::encoder_table`.
+// the default table lives inline on purpose: boxing it would cost an allocation for every table copy
+#[allow(clippy::large_enum_variant)]
+pub(crate) enum Table {
+    Default(U32Table),
+    /// `CompressionSettings::hash_log` asked for a different size.
+    Var(VarU32Table),
+    Custom(Box<dyn EncoderTable + Send + Sync>),
+}
+impl Table {
+    fn as_dyn(&mut self) -> &mut dyn EncoderTable {
+        match self {
+            Table::Default(table) => table,
+            Table::Var(table) => table,
+            Table::Custom(table) => &mut **table,
+        }
+    }
+
+    /// See `EncoderTable::offset`.
+    pub(crate) fn offset(&mut self, offset: usize) {
+        self.as_dyn().offset(offset);
+    }
+
+    /// See `prime_table`.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn prime(&mut self, history: &[u8]) {
+        prime_table(self.as_dyn(), history);
+    }
+}
+
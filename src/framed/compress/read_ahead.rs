@@ -0,0 +1,76 @@
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings, LZ4FrameWriter};
+
+type Error = CompressionError;
+
+impl CompressionSettings<'_> {
+    /// Like `compress`, but the next block is read on another thread while the current one is being compressed.
+    ///
+    /// If reading is slow (network, spinning disks, a pipe from another process), this hides the time we'd otherwise
+    /// spend waiting for the input behind the time spent compressing, without going all the way to compressing
+    /// blocks in parallel. It takes two more blocks of memory than `compress` (or one more than `blocks_in_flight`)
+    /// and a thread for the duration of the call, and the output is exactly the same.
+    /// That's too much for `bounded_memory`, so this fails with `Unbounded` there.
+    #[throws]
+    pub fn compress_read_ahead<R: Read + Send, W: Write>(&self, reader: R, writer: W) {
+        if self.bounded_memory {
+            throw!(Error::Unbounded);
+        }
+        let block_size = self.block_size;
+        let ahead = self.blocks_in_flight.unwrap_or(1);
+        thread::scope(|s| {
+            // buffers are being filled while one of them is being compressed
+            let (full_sender, full) = mpsc::sync_channel(ahead);
+            let (empty, empty_receiver) = mpsc::channel();
+            for _ in 0..ahead + 1 {
+                empty.send(Vec::with_capacity(block_size)).unwrap();
+            }
+            s.spawn(move || read_blocks(reader, block_size, empty_receiver, full_sender));
+            // returning drops our ends of the channels, which stops the reader if we fail halfway
+            self.compress_blocks(full, empty, writer)
+        })?;
+    }
+
+    #[throws]
+    fn compress_blocks<W: Write>(&self, full: Receiver<io::Result<Vec<u8>>>, empty: Sender<Vec<u8>>, writer: W) {
+        let mut frame = LZ4FrameWriter::new(writer, self)?;
+        loop {
+            let block = match full.recv() {
+                Ok(Ok(block)) if block.is_empty() => break,
+                Ok(Ok(block)) => block,
+                Ok(Err(e)) => {
+                    frame.abort();
+                    throw!(Error::ReadError(e));
+                }
+                Err(_) => {
+                    // the reader panicked, so this is not the end of the input
+                    frame.abort();
+                    throw!(Error::ReadError(io::Error::other("the read-ahead thread died")));
+                }
+            };
+            if let Err(e) = frame.write_all_internal(&block) {
+                frame.abort();
+                throw!(e);
+            }
+            // the reader might be gone already if it hit the end of the input
+            let _ = empty.send(block);
+        }
+        frame.finish()?;
+    }
+}
+
+/// Fill the buffers from `empty` with a block each and pass them on to `full`, until an empty one marks the end of the input.
+fn read_blocks<R: Read>(mut reader: R, block_size: usize, empty: Receiver<Vec<u8>>, full: SyncSender<io::Result<Vec<u8>>>) {
+    while let Ok(mut block) = empty.recv() {
+        block.clear();
+        let result = reader.by_ref().take(block_size as u64).read_to_end(&mut block);
+        let done = !matches!(result, Ok(len) if len > 0);
+        if full.send(result.map(|_| block)).is_err() || done {
+            break;
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Guessing whether a block is already compressed (see `CompressionSettings::auto_store`).
+
+/// Magic numbers of formats that don't get any smaller with LZ4.
+const SIGNATURES: &[&[u8]] = &[
+    b"\x1f\x8b",                 // gzip
+    b"\x28\xb5\x2f\xfd",         // zstd
+    b"\x04\x22\x4d\x18",         // lz4 (yes, someone will try)
+    b"PK\x03\x04",               // zip (and everything based on it: jar, docx, epub, ...)
+    b"\xfd7zXZ\x00",             // xz
+    b"BZh",                      // bzip2
+    b"7z\xbc\xaf\x27\x1c",       // 7z
+    b"\xff\xd8\xff",             // JPEG
+    b"\x89PNG\r\n\x1a\n",        // PNG
+];
+
+/// We look at this many bytes at a few places in the block...
+const SAMPLE_LEN: usize = 1024;
+const SAMPLES: usize = 4;
+/// ...and if the bytes are spread out this evenly (in bits per byte, 8 is the maximum), we give up on the block.
+///
+/// Uniformly random bytes come out at about 7.95 for 4 KiB of samples, English text at about 4.5.
+const ENTROPY_THRESHOLD: f64 = 7.8;
+
+/// Whether `block` looks like it's already compressed (or encrypted), so compressing it is a waste of time.
+///
+/// This is only a guess: a block can start with a magic number by accident, and high entropy in a few samples
+/// doesn't rule out long repetitions elsewhere. Either way, the block is simply stored, so we never get it wrong
+/// in a way that breaks anything.
+pub(crate) fn looks_compressed(block: &[u8]) -> bool {
+    SIGNATURES.iter().any(|signature| block.starts_with(signature)) || sample_entropy(block) > ENTROPY_THRESHOLD
+}
+
+/// Shannon entropy of a few samples of `block`, or 0 if the block is too short to tell.
+fn sample_entropy(block: &[u8]) -> f64 {
+    if block.len() < SAMPLE_LEN * SAMPLES {
+        return 0.;
+    }
+
+    let mut histogram = [0u32; 256];
+    let stride = (block.len() - SAMPLE_LEN) / (SAMPLES - 1);
+    for i in 0..SAMPLES {
+        for &byte in &block[i * stride..][..SAMPLE_LEN] {
+            histogram[byte as usize] += 1;
+        }
+    }
+
+    let total = (SAMPLE_LEN * SAMPLES) as f64;
+    histogram.iter().filter(|&&count| count > 0).map(|&count| {
+        let p = count as f64 / total;
+        -p * p.log2()
+    }).sum()
+}
@@ -0,0 +1,580 @@
+use std::hash::Hasher;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
+use twox_hash::XxHash32;
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings};
+use crate::framed::{Counting, DictionaryScope, WINDOW_SIZE};
+use crate::framed::gear::Gear;
+use crate::framed::header::Flags;
+use crate::framed::metrics::{time_checksum, Stage, Timed};
+use super::{end_len, BlockWriter, Pacing, Table};
+use crate::raw::{U32Table, VarU32Table};
+
+type Error = CompressionError;
+
+/// With `CompressionSettings::rsyncable`, there's a boundary about every 64 KiB.
+const RSYNC_BOUNDARY_BITS: u32 = 16;
+
+/// A streaming LZ4 frame compressor that implements `Write`.
+///
+/// Everything you write is buffered until a full block is available, which is then compressed and written
+/// to the underlying writer. Call `flush` to write out a partial block right away
+/// and `finish` to write the final block and the end of the frame.
+///
+/// If you drop the writer without calling `finish`, it finishes the frame for you, ignoring any errors.
+/// Call `abort` if you don't want that. Once writing a block fails, the frame is broken for good:
+/// everything but `abort` fails with `CompressionError::Poisoned`, and dropping the writer leaves the frame alone.
+///
+/// This is the push-based counterpart to `CompressionSettings::compress`, which pulls from a reader.
+pub struct LZ4FrameWriter<'a, W: Write> {
+    /// Only `None` after `finish` or `abort`.
+    writer: Option<W>,
+    settings: &'a CompressionSettings<'a>,
+    flags: Flags,
+    content_hasher: Option<XxHash32>,
+    template_table: Table,
+    table: Table,
+    /// The window (or dictionary) followed by the bytes of the current block.
+    in_buffer: Vec<u8>,
+    /// Where the current block starts in `in_buffer`.
+    window_offset: usize,
+    /// Compressed blocks end up here before they are written. Only grows as large as the largest block we compressed.
+    out_buffer: Vec<u8>,
+    auto_flush_len: Option<usize>,
+    auto_flush_delay: Option<Duration>,
+    last_write: Option<Instant>,
+    /// Uncompressed bytes accepted in the current frame.
+    total_in: u64,
+    /// Compressed bytes written to `writer` for the current frame.
+    total_out: u64,
+    pacing: Pacing,
+    /// Only count how much we would write (see `CompressionSettings::compressed_size`).
+    dry_run: bool,
+    /// Blocks written in the current frame.
+    blocks: u64,
+    checkpoints: Vec<Checkpoint>,
+    /// Finds content-defined boundaries, only with `CompressionSettings::rsyncable`.
+    gear: Option<Gear>,
+    /// The current block ends at a content-defined boundary, so the next one must not refer back to it.
+    boundary: bool,
+    /// Writing a block (or the end of the frame) failed, so part of it may have been written and we can't go on.
+    poisoned: bool,
+}
+
+/// A block that a reader can start decoding at, see `CompressionSettings::checkpoint_interval`.
+///
+/// Both offsets are relative to the start of the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Checkpoint {
+    /// Where the block starts in the frame (the header is included in this).
+    pub compressed_offset: u64,
+    /// How much plaintext comes before the block.
+    pub uncompressed_offset: u64,
+}
+
+/// Everything an `LZ4FrameWriter` needs to continue a frame, see `LZ4FrameWriter::snapshot`.
+///
+/// With the `serde` feature, this can be serialized with any serde format you like.
+/// The settings are not part of the snapshot, you have to supply them again when restoring.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncoderSnapshot {
+    flags: u8,
+    content_hasher: Option<XxHash32>,
+    table: Vec<u32>,
+    table_offset: usize,
+    /// the window (or dictionary) followed by the bytes of the current block
+    in_buffer: Vec<u8>,
+    window_offset: usize,
+    total_in: u64,
+    total_out: u64,
+    // older snapshots don't have this, it's only used for checkpoints
+    #[cfg_attr(feature = "serde", serde(default))]
+    blocks: u64,
+    // nor this, which is only used with `rsyncable`
+    #[cfg_attr(feature = "serde", serde(default))]
+    gear: u64,
+}
+
+impl EncoderSnapshot {
+    /// Number of uncompressed bytes that had been written into the frame when this snapshot was taken.
+    ///
+    /// When resuming from a snapshot, continue writing the input from this position.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of compressed bytes of the frame that had been written to the underlying writer when this snapshot was taken.
+    ///
+    /// When resuming from a snapshot, the output must end exactly here (e.g. truncate the file to this length).
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+impl<'a, W: Write> LZ4FrameWriter<'a, W> {
+    /// Write the frame header and get ready to compress.
+    #[throws]
+    pub fn new(writer: W, settings: &'a CompressionSettings<'a>) -> Self {
+        Self::with_content_size(writer, settings, None)?
+    }
+
+    #[throws]
+    pub(crate) fn with_content_size(mut writer: W, settings: &'a CompressionSettings<'a>, content_size: Option<u64>) -> Self {
+        settings.check_memory_bound()?;
+        let flags = settings.flags(content_size);
+        let mut counting = Counting::new(&mut writer);
+        settings.write_header(flags, content_size, &mut counting)?;
+        let total_out = counting.count;
+
+        let template_table = settings.tables().template()?;
+        let mut in_buffer = Vec::with_capacity(settings.in_buffer_capacity());
+        in_buffer.extend_from_slice(settings.dictionary.as_deref().unwrap_or(&[]));
+        LZ4FrameWriter {
+            writer: Some(writer),
+            settings,
+            flags,
+            content_hasher: if settings.content_checksum { Some(XxHash32::with_seed(0)) } else { None },
+            table: settings.tables().copy(&template_table)?,
+            template_table,
+            window_offset: in_buffer.len(),
+            in_buffer,
+            out_buffer: settings.out_buffer(),
+            auto_flush_len: None,
+            auto_flush_delay: None,
+            last_write: None,
+            total_in: 0,
+            total_out,
+            pacing: settings.pacing(),
+            dry_run: false,
+            blocks: 0,
+            checkpoints: Vec::new(),
+            gear: settings.rsyncable.then(Gear::default),
+            boundary: false,
+            poisoned: false,
+        }
+    }
+
+    /// Continue a frame from a snapshot, writing to `writer`.
+    ///
+    /// The settings must be the same ones the snapshot was taken with, and `writer` must already contain
+    /// the first `EncoderSnapshot::total_out` bytes of the frame (and nothing more). The rest of the frame
+    /// then comes out exactly as if the original writer had never stopped.
+    #[throws]
+    pub fn restore(writer: W, settings: &'a CompressionSettings<'a>, snapshot: EncoderSnapshot) -> Self {
+        settings.check_memory_bound()?;
+        let flags = Flags::from_bits(snapshot.flags).ok_or(Error::InvalidSnapshot)?;
+        let dictionary_len = settings.dictionary.as_ref().map_or(0, |d| d.len());
+        let pending = snapshot.in_buffer.len().checked_sub(snapshot.window_offset).ok_or(Error::InvalidSnapshot)?;
+        if flags.difference(Flags::ContentSize) != settings.flags(None)
+            || snapshot.content_hasher.is_some() != settings.content_checksum
+            || snapshot.window_offset > WINDOW_SIZE.max(dictionary_len)
+            || pending >= settings.block_size {
+            throw!(Error::InvalidSnapshot);
+        }
+        if settings.encoder_table.is_some() {
+            throw!(Error::InvalidSnapshot);
+        }
+        let table = match settings.tables().empty()? {
+            Table::Default(_) => U32Table::from_parts(&snapshot.table, snapshot.table_offset, snapshot.window_offset).map(Table::Default),
+            Table::Var(empty) => VarU32Table::from_parts(&snapshot.table, snapshot.table_offset, snapshot.window_offset)
+                .filter(|table| table.hash_log() == empty.hash_log())
+                .map(Table::Var),
+            Table::Custom(_) => None,
+        }.ok_or(Error::InvalidSnapshot)?;
+
+        let mut in_buffer = snapshot.in_buffer;
+        in_buffer.reserve(settings.in_buffer_capacity().saturating_sub(in_buffer.len()));
+        LZ4FrameWriter {
+            writer: Some(writer),
+            settings,
+            flags,
+            content_hasher: snapshot.content_hasher,
+            template_table: settings.tables().template()?,
+            table,
+            in_buffer,
+            window_offset: snapshot.window_offset,
+            out_buffer: settings.out_buffer(),
+            auto_flush_len: None,
+            auto_flush_delay: None,
+            last_write: None,
+            total_in: snapshot.total_in,
+            total_out: snapshot.total_out,
+            pacing: settings.pacing(),
+            dry_run: false,
+            blocks: snapshot.blocks,
+            checkpoints: Vec::new(),
+            gear: settings.rsyncable.then_some(Gear { hash: snapshot.gear }),
+            boundary: false,
+            poisoned: false,
+        }
+    }
+
+    /// Capture the complete state of the current frame: the window, the hash table,
+    /// the running content checksum and any data that is still buffered for the current block.
+    ///
+    /// Together with `restore`, this allows you to continue a frame after a crash (e.g. for resumable uploads)
+    /// and still end up with exactly the same output. Note that this only covers what the underlying writer
+    /// has been given, so if it does any buffering on its own, flush it before persisting the snapshot.
+    ///
+    /// Returns `None` if you use a custom `CompressionSettings::encoder_table`, because we can't look inside those.
+    pub fn snapshot(&self) -> Option<EncoderSnapshot> {
+        let (table, table_offset) = match &self.table {
+            Table::Default(table) => table.to_parts(),
+            Table::Var(table) => table.to_parts(),
+            Table::Custom(_) => return None,
+        };
+        Some(EncoderSnapshot {
+            flags: self.flags.bits(),
+            content_hasher: self.content_hasher,
+            table,
+            table_offset,
+            in_buffer: self.in_buffer.clone(),
+            window_offset: self.window_offset,
+            total_in: self.total_in,
+            total_out: self.total_out,
+            blocks: self.blocks,
+            gear: self.gear.map_or(0, |gear| gear.hash),
+        })
+    }
+
+    /// The checkpoints in the current frame so far (see `CompressionSettings::checkpoint_interval`).
+    ///
+    /// Save these once the frame is finished (e.g. in a sidecar index) to find them again when reading.
+    /// After `restore`, this only has the checkpoints that were written since.
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Automatically `flush` once at least this many bytes are buffered.
+    ///
+    /// Unlike a smaller block size, this also flushes the underlying writer,
+    /// so the receiving end never has to wait for more than `len` bytes.
+    pub fn auto_flush_len(&mut self, len: usize) -> &mut Self {
+        self.auto_flush_len = Some(len);
+        self
+    }
+
+    /// Automatically `flush` when data has been sitting in the buffer without any further writes for this long.
+    ///
+    /// We don't spawn any timers, so this can only take effect when you call into the writer.
+    /// Either way, the next `write` flushes first if the buffered data is overdue.
+    /// If you want to flush on time even when there are no more writes,
+    /// call `poll_auto_flush` periodically (`time_until_auto_flush` tells you when).
+    pub fn auto_flush_delay(&mut self, delay: Duration) -> &mut Self {
+        self.auto_flush_delay = Some(delay);
+        self
+    }
+
+    /// How long until buffered data is due to be flushed, or `None` if there is nothing to do.
+    pub fn time_until_auto_flush(&self) -> Option<Duration> {
+        match (self.auto_flush_delay, self.last_write) {
+            (Some(delay), Some(last_write)) if self.pending() > 0 => Some(delay.saturating_sub(last_write.elapsed())),
+            _ => None,
+        }
+    }
+
+    /// Flush if the buffered data is due according to `auto_flush_delay`. Returns whether we flushed.
+    #[throws]
+    pub fn poll_auto_flush(&mut self) -> bool {
+        let due = self.time_until_auto_flush() == Some(Duration::ZERO);
+        if due {
+            self.flush_block()?;
+        }
+        due
+    }
+
+    /// Buffer as much of `buf` as fits into the current block, writing out blocks as they fill up.
+    #[throws]
+    fn write_some(&mut self, buf: &[u8]) -> usize {
+        self.check_poisoned()?;
+        self.poll_auto_flush()?;
+
+        let mut len = buf.len().min(self.settings.block_size - self.pending());
+        if let Some(cut) = self.gear.as_mut().and_then(|gear| gear.find_boundary(&buf[..len], RSYNC_BOUNDARY_BITS)) {
+            len = cut;
+            self.boundary = true;
+        }
+        self.in_buffer.extend_from_slice(&buf[..len]);
+        self.total_in += len as u64;
+        if self.pending() == self.settings.block_size || self.boundary {
+            self.write_block()?;
+        }
+
+        if self.auto_flush_len.is_some_and(|max| self.pending() >= max) {
+            self.flush_block()?;
+        }
+        // Instant::now() panics on wasm32-unknown-unknown, so only touch the clock if we need it
+        if self.auto_flush_delay.is_some() {
+            self.last_write = Some(Instant::now());
+        }
+        len
+    }
+
+    /// Like `write_all`, but without turning our errors into `io::Error`s.
+    #[throws]
+    pub(crate) fn write_all_internal(&mut self, mut buf: &[u8]) {
+        while !buf.is_empty() {
+            let len = self.write_some(buf)?;
+            buf = &buf[len..];
+        }
+    }
+
+    #[throws]
+    fn flush_block(&mut self) {
+        self.check_poisoned()?;
+        if self.pending() > 0 {
+            self.write_block()?;
+        }
+        self.writer.as_mut().unwrap().flush()?;
+    }
+
+    /// Number of bytes buffered for the current block.
+    fn pending(&self) -> usize {
+        self.in_buffer.len() - self.window_offset
+    }
+
+    /// Fail if an earlier block (or end of the frame) only made it halfway.
+    #[throws]
+    fn check_poisoned(&self) {
+        if self.poisoned {
+            throw!(Error::Poisoned);
+        }
+    }
+
+    /// Compress and write the current block.
+    ///
+    /// If this fails, the block may be partially written and is already part of the content checksum,
+    /// so there's no way to retry it: we're poisoned.
+    #[throws]
+    fn write_block(&mut self) {
+        self.check_poisoned()?;
+        let result = self.compress_block();
+        self.poisoned = result.is_err();
+        result?
+    }
+
+    #[throws]
+    fn compress_block(&mut self) {
+        if self.settings.is_cancelled() {
+            throw!(Error::Cancelled);
+        }
+
+        let settings = self.settings;
+        let window_offset = self.window_offset;
+        let boundary = mem::take(&mut self.boundary);
+        // in a dry run, the checksum is never written, so we don't need to know it
+        if let Some(x) = self.content_hasher.as_mut().filter(|_| !self.dry_run) {
+            time_checksum(settings.metrics.as_deref(), || x.write(&self.in_buffer[window_offset..]));
+        }
+
+        if settings.is_checkpoint(self.blocks) {
+            self.checkpoints.push(Checkpoint { compressed_offset: self.total_out, uncompressed_offset: self.total_in - self.pending() as u64 });
+        }
+        self.blocks += 1;
+
+        let room = settings.output_limit.map(|limit| limit.saturating_sub(self.total_out + end_len(self.flags)));
+        let block_writer = BlockWriter { dry_run: self.dry_run, ..settings.block_writer(self.flags).room(room) };
+        let mut writer = Counting::new(Timed::new(self.writer.as_mut().unwrap(), settings.metrics.as_deref(), Stage::Write));
+        let stats = block_writer
+            .write_block(&self.in_buffer, window_offset, &mut self.table, &mut self.out_buffer, &mut self.pacing, &mut writer)?;
+        self.total_out += if self.dry_run { block_writer.frame_len(stats.compressed_len) } else { writer.count };
+        if let Some(callback) = settings.block_callback {
+            callback(&stats);
+        }
+
+        if self.flags.contains(Flags::IndependentBlocks) {
+            // clear table
+            self.in_buffer.clear();
+            if settings.dictionary_scope == DictionaryScope::EveryBlock {
+                self.in_buffer.extend_from_slice(settings.dictionary.as_deref().unwrap_or(&[]));
+                self.table = settings.tables().copy(&self.template_table)?;
+            } else {
+                self.table = settings.tables().empty()?;
+            }
+        } else if settings.is_checkpoint(self.blocks) || boundary {
+            // the next block is a checkpoint (or starts at a boundary), so it must not refer to anything before it (not even the dictionary)
+            self.in_buffer.clear();
+            self.table = settings.tables().empty()?;
+        } else if self.in_buffer.len() > WINDOW_SIZE {
+            let how_much_to_forget = self.in_buffer.len() - WINDOW_SIZE;
+            self.table.offset(how_much_to_forget);
+            self.in_buffer.drain(..how_much_to_forget);
+        }
+        self.window_offset = self.in_buffer.len();
+    }
+
+    /// Compress everything from `reader`, reading straight into the block buffer.
+    #[throws]
+    pub(crate) fn write_from<R: Read>(&mut self, mut reader: R) {
+        self.check_poisoned()?;
+        loop {
+            if self.settings.is_cancelled() {
+                throw!(Error::Cancelled);
+            }
+
+            // We basically want read_exact semantics, except at the end.
+            // Sadly read_exact specifies the buffer contents to be undefined
+            // on error, so we have to use this construction instead.
+            let missing = self.settings.block_size - self.pending();
+            let start = self.in_buffer.len();
+            let read_bytes = Timed::new(reader.by_ref().take(missing as u64), self.settings.metrics.as_deref(), Stage::Read)
+                .read_to_end(&mut self.in_buffer).map_err(Error::ReadError)?;
+            if let Some(cut) = self.gear.as_mut().and_then(|gear| gear.find_boundary(&self.in_buffer[start..], RSYNC_BOUNDARY_BITS)) {
+                // the block ends early, and whatever we read past the boundary goes into the next one (or more)
+                let rest = self.in_buffer.split_off(start + cut);
+                self.total_in += cut as u64;
+                self.boundary = true;
+                self.write_block()?;
+                self.write_all_internal(&rest)?;
+                continue;
+            }
+            self.total_in += read_bytes as u64;
+            if self.pending() == self.settings.block_size {
+                self.write_block()?;
+            } else if read_bytes == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Write the final block and the end of the frame (including the content checksum), and return the underlying writer.
+    #[throws]
+    pub fn finish(mut self) -> W {
+        let result = self.write_end();
+        // don't try again on drop
+        let writer = self.writer.take().unwrap();
+        result?;
+        writer
+    }
+
+    /// Like `finish`, but also returns all checkpoints of the frame (see `checkpoints`).
+    #[throws]
+    pub fn finish_with_checkpoints(mut self) -> (W, Vec<Checkpoint>) {
+        let result = self.write_end();
+        let writer = self.writer.take().unwrap();
+        result?;
+        (writer, mem::take(&mut self.checkpoints))
+    }
+
+    #[throws]
+    fn write_end(&mut self) {
+        if self.pending() > 0 {
+            self.write_block()?;
+        }
+        self.check_poisoned()?;
+        let mut writer = Counting::new(self.writer.as_mut().unwrap());
+        let result = self.settings.write_end(self.content_hasher.as_ref(), &mut writer);
+        self.total_out += writer.count;
+        self.poisoned = result.is_err();
+        result?
+    }
+
+    /// Finish the current frame and immediately start a new one on the same writer.
+    ///
+    /// This reuses all buffers and tables, so it's much cheaper than creating a new writer for every frame.
+    #[throws]
+    pub fn next_frame(&mut self) {
+        self.write_end()?;
+        self.start_frame()?;
+    }
+
+    /// Finish the current frame, start a new one on `writer` and return the old writer.
+    ///
+    /// Like `next_frame`, this reuses all buffers and tables.
+    /// This is useful if every frame needs to go somewhere else, e.g. one frame per message.
+    #[throws]
+    pub fn reset(&mut self, writer: W) -> W {
+        self.write_end()?;
+        let previous = self.writer.replace(writer).unwrap();
+        self.start_frame()?;
+        previous
+    }
+
+    /// Reset all state and write the header for a new frame.
+    #[throws]
+    fn start_frame(&mut self) {
+        let settings = self.settings;
+        if settings.content_checksum {
+            self.content_hasher = Some(XxHash32::with_seed(0));
+        }
+        self.in_buffer.clear();
+        self.in_buffer.extend_from_slice(settings.dictionary.as_deref().unwrap_or(&[]));
+        self.window_offset = self.in_buffer.len();
+        self.table = settings.tables().copy(&self.template_table)?;
+        self.last_write = None;
+        self.blocks = 0;
+        self.checkpoints.clear();
+        self.gear = settings.rsyncable.then(Gear::default);
+        self.boundary = false;
+        // content size is only ever known for the first frame
+        self.flags.remove(Flags::ContentSize);
+        self.total_in = 0;
+        let mut writer = Counting::new(self.writer.as_mut().unwrap());
+        settings.write_header(self.flags, None, &mut writer)?;
+        self.total_out = writer.count;
+    }
+
+    /// Throw away everything that is buffered and return the underlying writer, leaving the frame unfinished.
+    ///
+    /// Whatever was written so far is a truncated frame, so decompressing it will fail at the end.
+    pub fn abort(mut self) -> W {
+        self.writer.take().unwrap()
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+
+    /// Get a mutable reference to the underlying writer.
+    ///
+    /// Careful: writing to it directly corrupts the frame.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.writer.as_mut().unwrap()
+    }
+}
+
+impl<W: Write> Write for LZ4FrameWriter<'_, W> {
+    #[throws(io::Error)]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        self.write_some(buf)?
+    }
+
+    /// Writes out everything that is buffered as a (possibly small) block and flushes the underlying writer.
+    ///
+    /// After this, the receiving end can decompress everything that was written so far,
+    /// which is what you want for request/response protocols.
+    /// Every flush ends a block though, so flushing too often hurts the compression ratio
+    /// (especially with independent blocks).
+    #[throws(io::Error)]
+    fn flush(&mut self) {
+        self.flush_block()?;
+    }
+}
+
+impl<W: Write> Drop for LZ4FrameWriter<'_, W> {
+    fn drop(&mut self) {
+        // if we're panicking (or something failed halfway), the frame may be in an inconsistent state, so we better leave it alone
+        if self.writer.is_some() && !self.poisoned && !thread::panicking() {
+            let _ = self.write_end();
+        }
+    }
+}
+
+/// See `CompressionSettings::compressed_size`.
+#[throws]
+pub(crate) fn compressed_size<R: Read>(settings: &CompressionSettings<'_>, reader: R) -> u64 {
+    let mut frame = LZ4FrameWriter::new(io::sink(), settings)?;
+    frame.dry_run = true;
+    frame.write_from(reader)?;
+    frame.write_end()?;
+    let total_out = frame.total_out;
+    frame.abort();
+    total_out
+}
@@ -0,0 +1,429 @@
+use byteorder::{ByteOrder, LE};
+use std::collections::VecDeque;
+use culpa::{throw, throws};
+
+use super::decompress::ReaderState;
+use super::{DecompressionError, DictionaryScope, LZ4FrameReader, MAGIC, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK, INCOMPRESSIBLE, WarningHook, YieldHook};
+#[cfg(feature = "async-futures")]
+use super::Checkpoint;
+use super::header::Flags;
+
+type Error = DecompressionError;
+
+/// What a `FrameDecoder` is waiting for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecoderStatus {
+    /// Give me more input. The number is a hint how many more bytes would complete the current unit
+    /// (header or block), but any amount is fine.
+    NeedInput(usize),
+    /// There is more output ready, but it doesn't fit into the output buffer you gave me.
+    OutputFull,
+    /// A frame (or skippable frame) just ended. Any further input is treated as the start of a new frame.
+    FrameEnd,
+}
+
+/// The result of a single `FrameDecoder::decode` call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecoderProgress {
+    /// Number of bytes consumed from the input.
+    pub consumed: usize,
+    /// Number of bytes written to the output.
+    pub written: usize,
+    pub status: DecoderStatus,
+}
+
+/// What the decoder expects next.
+enum Stage {
+    Header,
+    SkippableFrame(usize),
+    // boxed because the reader is a lot larger than the other variants
+    Blocks(Box<LZ4FrameReader<VecDeque<u8>>>),
+}
+
+/// Everything a `FrameDecoder` needs to pick up where it left off, see `FrameDecoder::snapshot`.
+///
+/// With the `serde` feature, this can be serialized with any serde format you like.
+/// The dictionary is not part of the snapshot, you have to supply it again when restoring.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecoderSnapshot {
+    stage: StageSnapshot,
+    pending: Vec<u8>,
+    flags: u8,
+    /// decoded data that has not been handed out yet
+    output: Vec<u8>,
+    total_in: u64,
+    total_out: u64,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum StageSnapshot {
+    Header,
+    SkippableFrame(usize),
+    Blocks(ReaderState),
+}
+
+impl DecoderSnapshot {
+    /// Total number of input bytes the decoder had consumed when this snapshot was taken.
+    ///
+    /// When resuming from a snapshot, continue feeding input from this position.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Total number of bytes the decoder had written to your output buffers when this snapshot was taken.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+}
+
+/// A push-based ("sans-IO") LZ4 frame decoder.
+///
+/// Instead of pulling data from a reader, you hand it whatever input you have and a buffer for the output,
+/// and it tells you how far it got. It never does any IO on its own, so this is the building block
+/// for non-blocking and async decompression (and for embedding lz-fear into a foreign event loop).
+///
+/// Input is consumed in whole units (the header or one block at a time), which are buffered internally
+/// until they are complete. Decoded blocks are buffered too until they fit into the output you provide.
+pub struct FrameDecoder<'a> {
+    stage: Stage,
+    /// input bytes of the current (incomplete) unit
+    pending: Vec<u8>,
+    flags: Flags,
+    /// The block size of the current frame, so we never buffer more than that.
+    block_maxsize: usize,
+    /// The frame that ended last was a skippable frame.
+    skipped: bool,
+    output: Vec<u8>,
+    output_pos: usize,
+    dictionary: &'a [u8],
+    dictionary_scope: DictionaryScope,
+    lenient_header: bool,
+    yield_hook: Option<YieldHook>,
+    warning_hook: Option<WarningHook>,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl Default for FrameDecoder<'static> {
+    fn default() -> Self {
+        FrameDecoder::with_dictionary(&[])
+    }
+}
+
+impl<'a> FrameDecoder<'a> {
+    pub fn new() -> FrameDecoder<'static> {
+        FrameDecoder::default()
+    }
+
+    /// Create a decoder for frames that were compressed with this dictionary.
+    pub fn with_dictionary(dictionary: &'a [u8]) -> Self {
+        FrameDecoder {
+            stage: Stage::Header,
+            pending: Vec::new(),
+            flags: Flags::empty(),
+            block_maxsize: 0,
+            skipped: false,
+            output: Vec::new(),
+            output_pos: 0,
+            dictionary,
+            dictionary_scope: DictionaryScope::EveryBlock,
+            lenient_header: false,
+            yield_hook: None,
+            warning_hook: None,
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    /// Whether every independent block can reference the dictionary, or just the first one.
+    ///
+    /// See `LZ4FrameReader::set_dictionary_scope`. This is not part of snapshots, so set it again after `restore`.
+    pub fn dictionary_scope(&mut self, scope: DictionaryScope) -> &mut Self {
+        self.dictionary_scope = scope;
+        if let Stage::Blocks(reader) = &mut self.stage {
+            reader.set_dictionary_scope(scope);
+        }
+        self
+    }
+
+    /// Ignore reserved bits in frame headers (see `LZ4FrameReader::new_lenient`). Disabled by default.
+    ///
+    /// This is not part of snapshots either, so set it again after `restore`.
+    pub fn lenient_header(&mut self, v: bool) -> &mut Self {
+        self.lenient_header = v;
+        self
+    }
+
+    /// Call a hook every so often while decompressing, even in the middle of a block (see `YieldHook`).
+    ///
+    /// This is not part of snapshots either, so set it again after `restore`.
+    pub fn yield_hook(&mut self, hook: YieldHook) -> &mut Self {
+        if let Stage::Blocks(reader) = &mut self.stage {
+            reader.set_yield_hook(hook.clone());
+        }
+        self.yield_hook = Some(hook);
+        self
+    }
+
+    /// Tell a hook about anything suspicious in the frames we decode (see `LZ4FrameReader::set_warning_hook`).
+    ///
+    /// This is not part of snapshots either, so set it again after `restore`.
+    pub fn warning_hook(&mut self, hook: WarningHook) -> &mut Self {
+        if let Stage::Blocks(reader) = &mut self.stage {
+            reader.set_warning_hook(hook.clone());
+        }
+        self.warning_hook = Some(hook);
+        self
+    }
+
+    /// Forget about the current frame (if any) and start over.
+    pub fn reset(&mut self) {
+        self.stage = Stage::Header;
+        self.skipped = false;
+        self.pending.clear();
+        self.output.clear();
+        self.output_pos = 0;
+    }
+
+    /// Total number of input bytes consumed so far.
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Total number of bytes written to your output buffers so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Capture the complete state of the decoder: the current frame's flags, the window,
+    /// the running content checksum, partially received input and decoded data that you haven't picked up yet.
+    ///
+    /// Together with `restore`, this allows you to suspend decoding (e.g. across a process restart)
+    /// and continue later on, possibly on a different machine. Just continue feeding input
+    /// at `DecoderSnapshot::total_in`.
+    pub fn snapshot(&self) -> DecoderSnapshot {
+        DecoderSnapshot {
+            stage: match &self.stage {
+                Stage::Header => StageSnapshot::Header,
+                Stage::SkippableFrame(remaining) => StageSnapshot::SkippableFrame(*remaining),
+                Stage::Blocks(reader) => StageSnapshot::Blocks(reader.state()),
+            },
+            pending: self.pending.clone(),
+            flags: self.flags.bits(),
+            output: self.output[self.output_pos..].to_vec(),
+            total_in: self.total_in,
+            total_out: self.total_out,
+        }
+    }
+
+    /// Recreate a decoder from a snapshot. Pass the same dictionary as the one you took the snapshot from.
+    ///
+    /// Snapshots from untrusted sources are fine, we check that they make sense (as far as we can tell).
+    #[throws]
+    pub fn restore(snapshot: DecoderSnapshot, dictionary: &'a [u8]) -> Self {
+        let stage = match snapshot.stage {
+            StageSnapshot::Header => Stage::Header,
+            StageSnapshot::SkippableFrame(remaining) => Stage::SkippableFrame(remaining),
+            StageSnapshot::Blocks(state) => Stage::Blocks(Box::new(LZ4FrameReader::from_state(VecDeque::new(), state)?)),
+        };
+        let block_maxsize = match &stage {
+            Stage::Blocks(reader) => reader.block_size(),
+            _ => 0,
+        };
+        let decoder = FrameDecoder {
+            stage,
+            pending: snapshot.pending,
+            flags: Flags::from_bits(snapshot.flags).ok_or(Error::InvalidSnapshot)?,
+            block_maxsize,
+            skipped: false,
+            output: snapshot.output,
+            output_pos: 0,
+            dictionary,
+            dictionary_scope: DictionaryScope::EveryBlock,
+            lenient_header: false,
+            yield_hook: None,
+            warning_hook: None,
+            total_in: snapshot.total_in,
+            total_out: snapshot.total_out,
+        };
+        // a pending unit is never complete, otherwise we would have processed it already
+        if decoder.pending.len() >= decoder.unit_len()?.max(1) {
+            throw!(Error::InvalidSnapshot);
+        }
+        decoder
+    }
+
+    /// Continue at `checkpoint` of the current frame, right after its header was decoded.
+    ///
+    /// Feed the input from `Checkpoint::compressed_offset` on afterwards.
+    #[cfg(feature = "async-futures")]
+    #[throws]
+    pub(crate) fn jump_to(&mut self, checkpoint: &Checkpoint) {
+        match &mut self.stage {
+            Stage::Blocks(reader) if self.pending.is_empty() => reader.jump_to(checkpoint)?,
+            _ => throw!(Error::InvalidCheckpoint),
+        };
+        self.output.clear();
+        self.output_pos = 0;
+        self.total_in = checkpoint.compressed_offset;
+        self.total_out = checkpoint.uncompressed_offset;
+    }
+
+    /// Whether the frame that just ended (see `DecoderStatus::FrameEnd`) was a skippable frame, i.e. no data.
+    pub(crate) fn skipped(&self) -> bool {
+        self.skipped
+    }
+
+    /// Whether we're in between frames, i.e. there is no partially decoded frame.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.stage, Stage::Header) && self.pending.is_empty() && self.output_pos == self.output.len()
+    }
+
+    /// How many bytes the next unit (header, block, ...) occupies, as far as we can tell from `pending`.
+    ///
+    /// Blocks that are larger than the frame allows fail right here, before we buffer any of them.
+    #[throws]
+    fn unit_len(&self) -> usize {
+        let p = &self.pending;
+        match self.stage {
+            Stage::Header if p.len() < 4 => 4,
+            Stage::Header if LE::read_u32(p) & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC => 8,
+            // let process_unit complain about it
+            Stage::Header if LE::read_u32(p) != MAGIC => p.len(),
+            Stage::Header if p.len() < 5 => 5,
+            Stage::Header => {
+                let flags = Flags::from_bits_truncate(p[4]);
+                let mut len = 7;
+                if flags.content_size() { len += 8; }
+                if flags.dictionary_id() { len += 4; }
+                len
+            }
+            Stage::SkippableFrame(_) => 0, // handled separately because we don't buffer skippable frames
+            Stage::Blocks(_) if p.len() < 4 => 4,
+            Stage::Blocks(_) => match LE::read_u32(p) & !INCOMPRESSIBLE {
+                0 => if self.flags.content_checksum() { 8 } else { 4 },
+                len if len as usize > self.block_maxsize => throw!(Error::BlockSizeOverflow),
+                len => 4 + len as usize + if self.flags.block_checksums() { 4 } else { 0 },
+            }
+        }
+    }
+
+    /// Process a complete unit in `pending`. Returns `true` at the end of a frame.
+    #[throws]
+    fn process_unit(&mut self) -> bool {
+        let mut finished = false;
+        match &mut self.stage {
+            Stage::Header if self.pending.len() == 8 && LE::read_u32(&self.pending) & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC => {
+                let len = LE::read_u32(&self.pending[4..]) as usize;
+                self.stage = Stage::SkippableFrame(len);
+                finished = len == 0;
+                if finished {
+                    self.stage = Stage::Header;
+                    self.skipped = true;
+                }
+            }
+            Stage::Header => {
+                let header = self.pending.drain(..).collect::<VecDeque<u8>>();
+                let mut reader = if self.lenient_header { LZ4FrameReader::new_lenient(header)? } else { LZ4FrameReader::new(header)? };
+                reader.set_dictionary_scope(self.dictionary_scope);
+                if let Some(hook) = &self.yield_hook {
+                    reader.set_yield_hook(hook.clone());
+                }
+                if let Some(hook) = &self.warning_hook {
+                    reader.set_warning_hook(hook.clone());
+                }
+                self.flags = reader.flags();
+                self.block_maxsize = reader.block_size();
+                self.stage = Stage::Blocks(Box::new(reader));
+            }
+            Stage::SkippableFrame(_) => unreachable!(),
+            Stage::Blocks(reader) => {
+                reader.reader_mut().extend(self.pending.drain(..));
+                self.output.clear();
+                self.output_pos = 0;
+                if reader.decode_block_internal(&mut self.output, self.dictionary)?.is_none() {
+                    self.stage = Stage::Header;
+                    self.skipped = false;
+                    finished = true;
+                }
+            }
+        }
+        self.pending.clear();
+        finished
+    }
+
+    /// Decode as much as possible from `input` into `output`.
+    ///
+    /// On error, the decoder resets itself, so you can try to continue with the next frame
+    /// (if you know where it starts).
+    #[throws]
+    pub fn decode(&mut self, input: &[u8], output: &mut [u8]) -> DecoderProgress {
+        let progress = self.decode_units(input, output)?;
+        self.total_in += progress.consumed as u64;
+        self.total_out += progress.written as u64;
+        progress
+    }
+
+    #[throws]
+    fn decode_units(&mut self, mut input: &[u8], output: &mut [u8]) -> DecoderProgress {
+        let input_len = input.len();
+        let mut written = 0;
+        'decode: loop {
+            let available = &self.output[self.output_pos..];
+            let n = available.len().min(output.len() - written);
+            output[written..][..n].copy_from_slice(&available[..n]);
+            written += n;
+            self.output_pos += n;
+            if self.output_pos < self.output.len() {
+                break DecoderProgress { consumed: input_len - input.len(), written, status: DecoderStatus::OutputFull };
+            }
+
+            if let Stage::SkippableFrame(remaining) = &mut self.stage {
+                let n = (*remaining).min(input.len());
+                *remaining -= n;
+                input = &input[n..];
+                let status = match *remaining {
+                    0 => {
+                        self.stage = Stage::Header;
+                        self.skipped = true;
+                        DecoderStatus::FrameEnd
+                    }
+                    remaining => DecoderStatus::NeedInput(remaining),
+                };
+                break DecoderProgress { consumed: input_len - input.len(), written, status };
+            }
+
+            // the length of a unit is only known after reading its first few bytes, so this may take several rounds
+            loop {
+                let needed = match self.unit_len() {
+                    Ok(len) => len - self.pending.len(),
+                    Err(e) => {
+                        self.reset();
+                        throw!(e);
+                    }
+                };
+                if needed == 0 {
+                    break;
+                }
+                let (take, rest) = input.split_at(needed.min(input.len()));
+                self.pending.extend_from_slice(take);
+                input = rest;
+                if take.len() < needed {
+                    let status = DecoderStatus::NeedInput(needed - take.len());
+                    break 'decode DecoderProgress { consumed: input_len - input.len(), written, status };
+                }
+            }
+
+            match self.process_unit() {
+                Ok(true) => break DecoderProgress { consumed: input_len - input.len(), written, status: DecoderStatus::FrameEnd },
+                Ok(false) => (),
+                Err(e) => {
+                    self.reset();
+                    throw!(e);
+                }
+            }
+        }
+    }
+}
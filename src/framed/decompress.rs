@@ -1,16 +1,26 @@
-use byteorder::{LE, ReadBytesExt};
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
 use std::hash::Hasher;
-use std::io::{self, Read, BufRead, ErrorKind};
+use std::io::{self, Read, BufRead, ErrorKind, Seek, SeekFrom, Write};
 use std::cmp;
-use std::convert::TryInto;
+use std::mem;
+use std::convert::{TryFrom, TryInto};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
 use twox_hash::XxHash32;
 use thiserror::Error;
 use culpa::{throw, throws};
 
-use super::{MAGIC, INCOMPRESSIBLE, WINDOW_SIZE};
+use super::{block_checksum, header_checksum, Checkpoint, Counting, MAGIC, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK, INCOMPRESSIBLE, WINDOW_SIZE, CancellationToken, DictionaryProvider, DictionaryScope, Metrics, Warning, WarningHook, YieldCounter, YieldHook, chain_dictionaries, memory_bound};
+use super::metrics::{time_checksum, time_stage, Stage, Timed};
+use super::warnings;
+use super::dictionary::Dictionary;
+use super::lowmem::{self, Hashing, RingWindow};
 use super::header::{self, Flags, BlockDescriptor};
 use crate::raw;
 
+/// Magic number, flags, block descriptor, content size, dictionary id and header checksum.
+const MAX_HEADER_SIZE: usize = 4 + 1 + 1 + 8 + 4 + 1;
 
 /// Errors when decompressing an LZ4 frame.
 #[derive(Error, Debug)]
@@ -33,6 +43,26 @@ pub enum DecompressionError {
     BlockLengthOverflow,
     #[error("a block decompressed to more data than allowed")]
     BlockSizeOverflow,
+    #[error("decompression was cancelled")]
+    Cancelled,
+    #[error("the decoder snapshot is invalid")]
+    InvalidSnapshot,
+    #[error("the frame needs dictionary {0:08x}, which we don't have")]
+    UnknownDictionary(u32),
+    #[error("error writing to the output you gave me")]
+    OutputError(io::Error),
+    #[error("there is data after the end of the frame")]
+    TrailingData,
+    #[error("the checkpoint doesn't belong to this frame")]
+    InvalidCheckpoint,
+    #[error("the frames can't be joined because their settings don't match")]
+    IncompatibleFrames,
+    #[error("decoding this frame takes up to {0} bytes of memory, which is more than allowed")]
+    MemoryLimit(usize),
+    #[error("there is no page {0}")]
+    NoSuchPage(usize),
+    #[error("the blocks of this frame depend on each other, so they can't be read one at a time")]
+    DependentBlocks,
 }
 type Error = DecompressionError; // do it this way for better docs
 
@@ -47,12 +77,75 @@ pub struct LZ4FrameIoReader<'a, R: Read> {
     frame_reader: LZ4FrameReader<R>,
     bytes_taken: usize,
     buffer: Vec<u8>,
-    dictionary: &'a [u8],
+    dictionary: Dictionary<'a>,
+    block_per_read: bool,
+}
+impl<R: Read> LZ4FrameIoReader<'_, R> {
+    /// Make every `read` return exactly one block (instead of as many bytes as fit).
+    ///
+    /// This is for record-oriented formats that put one record into each block (e.g. by flushing
+    /// the `LZ4FrameWriter` after every record), so the boundaries between reads are the boundaries between records.
+    /// If the buffer you pass to `read` is too small for the next block, it fails with `InvalidInput`
+    /// without consuming anything, so you can try again with a larger buffer (`LZ4FrameReader::block_size` is always enough).
+    /// Mixing this with `BufRead` works, but then a `read` after a partial `consume` returns the rest of that block.
+    pub fn block_per_read(&mut self, v: bool) -> &mut Self {
+        self.block_per_read = v;
+        self
+    }
+
+    /// The `LZ4FrameReader` that this is wrapped around, e.g. to look at `LZ4FrameReader::trailing_bytes` at the end.
+    pub fn frame_reader(&self) -> &LZ4FrameReader<R> {
+        &self.frame_reader
+    }
+
+    /// Returns how many more bytes you can read from this frame, if the header specifies its size.
+    ///
+    /// See `LZ4FrameReader::remaining`.
+    pub fn remaining(&self) -> Option<u64> {
+        let buffered = (self.buffer.len() - self.bytes_taken) as u64;
+        self.frame_reader.remaining().map(|r| r + buffered)
+    }
+
+    /// Hand this reader's buffers back for the next frame (see `LZ4FrameReader::with_buffers`).
+    pub fn return_buffers(self, buffers: &mut FrameBuffers) {
+        FrameBuffers::keep(&mut buffers.output, self.buffer);
+        self.frame_reader.return_buffers(buffers);
+    }
+
+    /// Write the rest of the frame into `writer` and return how many bytes that was.
+    ///
+    /// Use this instead of `io::copy`, which copies everything through an extra 8 KiB buffer.
+    /// Here, every block is handed to the writer directly after decompressing it.
+    #[throws(io::Error)]
+    pub fn copy_to<W: Write + ?Sized>(&mut self, writer: &mut W) -> u64 {
+        let metrics = self.frame_reader.metrics.clone();
+        let mut copied = 0;
+        loop {
+            let buf = self.fill_buf()?;
+            if buf.is_empty() {
+                break copied;
+            }
+            time_stage(metrics.as_deref(), Stage::Write, || writer.write_all(buf))?;
+            let len = buf.len();
+            self.consume(len);
+            copied += len as u64;
+        }
+    }
 }
 impl<R: Read> Read for LZ4FrameIoReader<'_, R> {
     #[throws(io::Error)]
     fn read(&mut self, buf: &mut [u8]) -> usize {
+        if self.bytes_taken == self.buffer.len() {
+            // stored blocks can skip our buffer and go straight into yours
+            if let Some(len) = self.frame_reader.read_stored_block(buf, &self.dictionary)? {
+                return len;
+            }
+        }
+        let block_per_read = self.block_per_read;
         let mybuf = self.fill_buf()?;
+        if block_per_read && mybuf.len() > buf.len() {
+            throw!(io::Error::new(ErrorKind::InvalidInput, "the buffer is too small for the next block"));
+        }
         let bytes_to_take = cmp::min(mybuf.len(), buf.len());
         buf[..bytes_to_take].copy_from_slice(&mybuf[..bytes_to_take]);
         self.consume(bytes_to_take);
@@ -62,10 +155,13 @@ impl<R: Read> Read for LZ4FrameIoReader<'_, R> {
 impl<R: Read> BufRead for LZ4FrameIoReader<'_, R> {
     #[throws(io::Error)]
     fn fill_buf(&mut self) -> &[u8] {
-        if self.bytes_taken == self.buffer.len() {
+        // skip blocks that decompress to nothing, otherwise it would look like we reached the end
+        while self.bytes_taken == self.buffer.len() {
             self.buffer.clear();
-            self.frame_reader.decode_block(&mut self.buffer, self.dictionary)?;
             self.bytes_taken = 0;
+            if self.frame_reader.decode_block_internal(&mut self.buffer, &self.dictionary)?.is_none() {
+                break;
+            }
         }
         &self.buffer[self.bytes_taken..]
     }
@@ -76,6 +172,129 @@ impl<R: Read> BufRead for LZ4FrameIoReader<'_, R> {
     }
 }
 
+/// How much memory an `LZ4FrameReader` allocates for its buffers up front (see `LZ4FrameReader::with_capacity`).
+///
+/// These are just initial sizes; every buffer still grows as large as it has to.
+/// Each one is also capped at the most it could ever need, so `usize::MAX` means "as much as is useful".
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferCapacity {
+    /// The buffer that compressed blocks are read into (at most one block). Starts out empty by default.
+    pub read_buf: usize,
+    /// The buffer that `LZ4FrameIoReader` decompresses blocks into (at most one block). Defaults to a full block.
+    pub output: usize,
+    /// The last 64 KiB of output that dependent blocks can refer back to. Defaults to all of it.
+    pub window: usize,
+}
+impl Default for BufferCapacity {
+    fn default() -> Self {
+        BufferCapacity { read_buf: 0, output: usize::MAX, window: usize::MAX }
+    }
+}
+impl BufferCapacity {
+    /// Don't allocate more than `bytes` for any of the buffers up front.
+    pub fn at_most(bytes: usize) -> Self {
+        BufferCapacity { read_buf: 0, output: bytes, window: bytes }
+    }
+}
+
+/// Buffers that are passed from one `LZ4FrameReader` to the next, so reading lots of frames doesn't allocate for every frame.
+///
+/// Start with an empty one, create every reader with `LZ4FrameReader::with_buffers` and hand the buffers back with
+/// `LZ4FrameIoReader::return_buffers` (or `LZ4FrameReader::return_buffers`) once you're done with the frame.
+/// This only ever holds on to the largest buffers it has seen, which is at most a block and a window.
+#[derive(Debug, Default)]
+pub struct FrameBuffers {
+    read_buf: Vec<u8>,
+    output: Vec<u8>,
+    window: Vec<u8>,
+}
+impl FrameBuffers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many bytes we're holding on to.
+    pub fn capacity(&self) -> usize {
+        self.read_buf.capacity() + self.output.capacity() + self.window.capacity()
+    }
+
+    /// Keep whichever buffer is larger.
+    fn keep(slot: &mut Vec<u8>, buffer: Vec<u8>) {
+        if buffer.capacity() > slot.capacity() {
+            *slot = buffer;
+        }
+    }
+}
+
+/// What to do with whatever comes after the end of the frame (see `LZ4FrameReader::set_trailing_data`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrailingData {
+    /// Stop reading at the end of the frame and leave the rest of the input alone. This is the default,
+    /// so you can read whatever follows the frame (e.g. another frame) from the underlying reader.
+    Ignore,
+    /// Fail with `DecompressionError::TrailingData` if there is anything at all after the end of the frame.
+    Reject,
+    /// Read everything after the end of the frame and count it, see `LZ4FrameReader::trailing_bytes`.
+    Count,
+}
+
+/// Everything an `LZ4FrameReader` knows about its frame (except for the reader itself), for `FrameDecoder::snapshot`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct ReaderState {
+    flags: u8,
+    block_maxsize: usize,
+    content_size: Option<u64>,
+    dictionary_id: Option<u32>,
+    content_hasher: Option<XxHash32>,
+    carryover_window: Option<Vec<u8>>,
+    finished: bool,
+    past_first_block: bool,
+    // older snapshots don't have this, but it's only used for `remaining`
+    #[cfg_attr(feature = "serde", serde(default))]
+    decoded: u64,
+    // same here, it's only used for `header_bytes`
+    #[cfg_attr(feature = "serde", serde(default))]
+    header: Vec<u8>,
+}
+
+/// Returned by `LZ4FrameReader::try_new` when the header can't be parsed, together with the reader.
+#[derive(Error, Debug)]
+#[error("{error} (after reading {consumed} bytes)")]
+pub struct HeaderError<R> {
+    #[source]
+    pub error: DecompressionError,
+    pub reader: R,
+    /// How many bytes we read from `reader` before we gave up.
+    pub consumed: u64,
+}
+
+/// The parts of the frame header that `LZ4FrameReader` cares about.
+struct Header {
+    flags: Flags,
+    block_maxsize: usize,
+    content_size: Option<u64>,
+    dictionary_id: Option<u32>,
+    /// The header exactly as we read it, from the magic number up to and including the checksum.
+    bytes: Vec<u8>,
+}
+
+/// The header we would write for these values (with a valid checksum).
+pub(crate) fn canonical_header(flags: Flags, block_maxsize: usize, content_size: Option<u64>, dictionary_id: Option<u32>) -> Vec<u8> {
+    let mut header = Vec::with_capacity(MAX_HEADER_SIZE);
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.push(0b0100_0000 | flags.bits()); // version 1
+    header.push(BlockDescriptor::new(block_maxsize).map_or(0, |bd| bd.0));
+    if let Some(size) = content_size {
+        header.extend_from_slice(&size.to_le_bytes());
+    }
+    if let Some(id) = dictionary_id {
+        header.extend_from_slice(&id.to_le_bytes());
+    }
+    header.push(header_checksum(&header[4..])); // skip magic for header checksum
+    header
+}
+
 /// Read an LZ4-compressed frame.
 ///
 /// This reader reads the blocks inside a frame one by one.
@@ -85,13 +304,65 @@ pub struct LZ4FrameReader<R: Read> {
     block_maxsize: usize,
     read_buf: Vec<u8>,
     content_size: Option<u64>,
+    /// How many bytes we've decompressed so far.
+    decoded: u64,
+    /// The length field of the next block, if we already read it but didn't want the block after all.
+    next_block_length: Option<u32>,
     dictionary_id: Option<u32>,
     content_hasher: Option<XxHash32>,
     carryover_window: Option<Vec<u8>>,
     finished: bool,
+    dictionary_scope: DictionaryScope,
+    past_first_block: bool,
+    cancellation_token: Option<CancellationToken>,
+    metrics: Option<Arc<dyn Metrics + Send + Sync>>,
+    yield_counter: Option<YieldCounter>,
+    warning_hook: Option<WarningHook>,
+    /// The most recent block, if it was short. That's only suspicious if another block follows.
+    short_block: Option<Warning>,
+    output_capacity: usize,
+    trailing_data: TrailingData,
+    trailing_bytes: Option<u64>,
+    header: Vec<u8>,
+    /// The buffer for `LZ4FrameIoReader`, if we got one from `FrameBuffers`.
+    output: Vec<u8>,
+    /// Never let a buffer grow beyond `memory_bound`, see `with_memory_limit`.
+    bounded_memory: bool,
+    /// Don't check checksums, but leave them in `deferred_checksum` (see `decode_with_background_checksums`).
+    defer_checksums: bool,
+    /// The checksum of the block we just read, or the content checksum after the end of the frame.
+    deferred_checksum: Option<u32>,
+    /// How many blocks may wait for the hasher in `decode_with_background_checksums`.
+    blocks_in_flight: usize,
 }
 
 impl<R: Read> LZ4FrameReader<R> {
+    /// Like `new`, but the buffers come from `buffers` (if there are any) instead of being allocated.
+    ///
+    /// If this fails, `buffers` is left alone.
+    #[throws]
+    pub fn with_buffers(reader: R, buffers: &mut FrameBuffers) -> Self {
+        let mut frame = Self::with_capacity(reader, BufferCapacity { read_buf: 0, output: 0, window: 0 })?;
+        frame.read_buf = mem::take(&mut buffers.read_buf);
+        frame.read_buf.clear();
+        frame.output = mem::take(&mut buffers.output);
+        frame.output.clear();
+        if let Some(window) = frame.carryover_window.as_mut() {
+            *window = mem::take(&mut buffers.window);
+            window.clear();
+        }
+        frame
+    }
+
+    /// Hand this reader's buffers back for the next frame (see `with_buffers`).
+    pub fn return_buffers(self, buffers: &mut FrameBuffers) {
+        FrameBuffers::keep(&mut buffers.read_buf, self.read_buf);
+        FrameBuffers::keep(&mut buffers.output, self.output);
+        if let Some(window) = self.carryover_window {
+            FrameBuffers::keep(&mut buffers.window, window);
+        }
+    }
+
     /// Create a new LZ4FrameReader over an underlying reader and parse the header.
     ///
     /// A typical LZ4 file consists of exactly one frame.
@@ -99,23 +370,95 @@ impl<R: Read> LZ4FrameReader<R> {
     /// If you want to read any data following this frame, you should probably
     /// pass in your reader by reference, rather than by value.
     #[throws]
-    pub fn new(mut reader: R) -> Self {
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(reader, BufferCapacity::default())?
+    }
+
+    /// Like `new`, but you decide how large the reader's buffers are initially.
+    ///
+    /// By default, reading a frame allocates a full block for the output right away, which is 4 MiB
+    /// for frames compressed with the default settings, no matter how small the frame actually is.
+    /// That's fine for a handful of streams, but if you're decoding thousands of them concurrently,
+    /// `BufferCapacity::at_most` lets the buffers start small and only grow as large as the blocks really are.
+    #[throws]
+    pub fn with_capacity(reader: R, capacity: BufferCapacity) -> Self {
+        Self::try_with_capacity(reader, capacity).map_err(|e| e.error)?
+    }
+
+    /// Like `new`, but if the header can't be parsed, you get the reader back (inside the error).
+    ///
+    /// This is for code that probes for several formats: if this isn't an LZ4 frame, `HeaderError::consumed`
+    /// tells you how many bytes we already took from the reader, so you can hand it to the next decoder
+    /// (e.g. after seeking back, or by replaying the bytes you peeked at).
+    pub fn try_new(reader: R) -> Result<Self, HeaderError<R>> {
+        Self::try_with_capacity(reader, BufferCapacity::default())
+    }
+
+    /// Like `new`, but decoding this frame never takes more than `limit` bytes of memory, or else it fails right away.
+    ///
+    /// The frame decides how large its blocks are, so this fails with `MemoryLimit` if `memory_bound(block_size)`
+    /// is more than `limit`, before allocating anything for the blocks. Otherwise, all buffers are allocated at their
+    /// final size, and blocks are decoded such that nothing ever grows beyond that (see `raw::decompress_raw_strict`),
+    /// no matter what the input looks like. This covers `into_read` and `decode_to_writer`, but `decode_block`
+    /// decodes into your buffer, so that one is up to you.
+    #[throws]
+    pub fn with_memory_limit(mut reader: R, limit: usize) -> Self {
+        let header = Self::parse_header(&mut reader, false)?;
+        let needed = memory_bound(header.block_maxsize);
+        if needed > limit {
+            throw!(Error::MemoryLimit(needed));
+        }
+        let mut frame = Self::from_header(reader, header, BufferCapacity::default());
+        frame.bounded_memory = true;
+        frame
+    }
+
+    /// Like `try_new`, but with custom buffer capacities (see `with_capacity`).
+    pub fn try_with_capacity(reader: R, capacity: BufferCapacity) -> Result<Self, HeaderError<R>> {
+        Self::try_with_options(reader, capacity, false)
+    }
+
+    /// Like `new`, but reserved bits in the header are ignored instead of being an error.
+    ///
+    /// The spec says that decoders must reject them, so that's what `new` does. But some encoders out there
+    /// set them anyway, and if you have to read their frames, this is for you. Everything else about the header
+    /// (including the checksum, which covers the reserved bits too) is still checked. `header_bytes` still shows the
+    /// reserved bits as they were, while `flags` and `block_size` ignore them.
+    #[throws]
+    pub fn new_lenient(reader: R) -> Self {
+        Self::try_with_options(reader, BufferCapacity::default(), true).map_err(|e| e.error)?
+    }
+
+    fn try_with_options(mut reader: R, capacity: BufferCapacity, lenient: bool) -> Result<Self, HeaderError<R>> {
+        let mut counting = Counting::new(&mut reader);
+        match Self::parse_header(&mut counting, lenient) {
+            Ok(header) => Ok(Self::from_header(reader, header, capacity)),
+            Err(error) => {
+                let consumed = counting.count;
+                Err(HeaderError { error, reader, consumed })
+            }
+        }
+    }
+
+    #[throws]
+    fn parse_header<T: Read>(mut reader: T, lenient: bool) -> Header {
         let magic = reader.read_u32::<LE>()?;
         if magic != MAGIC {
             throw!(Error::WrongMagic(magic));
         }
+        let mut bytes = Vec::with_capacity(MAX_HEADER_SIZE);
+        bytes.write_u32::<LE>(magic)?;
 
         let flags_byte = reader.read_u8()?;
-        let flags = Flags::parse(flags_byte)?;
-        let bd = BlockDescriptor::parse(reader.read_u8()?)?;
-
-        let mut hasher = XxHash32::with_seed(0);
-        hasher.write_u8(flags_byte);
-        hasher.write_u8(bd.0);
+        bytes.push(flags_byte);
+        let flags = if lenient { Flags::parse_lenient(flags_byte)? } else { Flags::parse(flags_byte)? };
+        let bd_byte = reader.read_u8()?;
+        bytes.push(bd_byte);
+        let bd = if lenient { BlockDescriptor::parse_lenient(bd_byte) } else { BlockDescriptor::parse(bd_byte)? };
 
         let content_size = if flags.content_size() {
             let i = reader.read_u64::<LE>()?;
-            hasher.write_u64(i);
+            bytes.write_u64::<LE>(i)?;
             Some(i)
         } else {
             None
@@ -123,18 +466,24 @@ impl<R: Read> LZ4FrameReader<R> {
 
         let dictionary_id = if flags.dictionary_id() {
             let i = reader.read_u32::<LE>()?;
-            hasher.write_u32(i);
+            bytes.write_u32::<LE>(i)?;
             Some(i)
         } else {
             None
         };
 
+        let header_checksum_actual = header_checksum(&bytes[4..]); // skip magic for header checksum
         let header_checksum_desired = reader.read_u8()?;
-        let header_checksum_actual = (hasher.finish() >> 8) as u8;
+        bytes.push(header_checksum_desired);
         if header_checksum_desired != header_checksum_actual {
             throw!(Error::HeaderChecksumFail);
         }
 
+        Header { flags, block_maxsize: bd.block_maxsize()?, content_size, dictionary_id, bytes }
+    }
+
+    fn from_header(reader: R, header: Header, capacity: BufferCapacity) -> Self {
+        let Header { flags, block_maxsize, content_size, dictionary_id, bytes } = header;
         let content_hasher = if flags.content_checksum() {
             Some(XxHash32::with_seed(0))
         } else {
@@ -144,19 +493,39 @@ impl<R: Read> LZ4FrameReader<R> {
         let carryover_window = if flags.independent_blocks() {
             None
         } else {
-            Some(Vec::with_capacity(WINDOW_SIZE))
+            Some(Vec::with_capacity(cmp::min(capacity.window, WINDOW_SIZE)))
         };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(?flags, block_maxsize, content_size, dictionary_id, "reading lz4 frame");
 
         LZ4FrameReader {
             reader,
             flags,
-            block_maxsize: bd.block_maxsize()?,
+            block_maxsize,
             content_size,
+            decoded: 0,
+            next_block_length: None,
             dictionary_id,
             content_hasher,
             carryover_window,
             finished: false,
-            read_buf: Vec::new()
+            dictionary_scope: DictionaryScope::EveryBlock,
+            past_first_block: false,
+            read_buf: Vec::with_capacity(cmp::min(capacity.read_buf, block_maxsize)),
+            cancellation_token: None,
+            metrics: None,
+            yield_counter: None,
+            warning_hook: None,
+            short_block: None,
+            output_capacity: cmp::min(capacity.output, block_maxsize),
+            trailing_data: TrailingData::Ignore,
+            trailing_bytes: None,
+            header: bytes,
+            output: Vec::new(),
+            bounded_memory: false,
+            defer_checksums: false,
+            deferred_checksum: None,
+            blocks_in_flight: 1,
         }
     }
 
@@ -168,24 +537,122 @@ impl<R: Read> LZ4FrameReader<R> {
     /// Returns the number of bytes that this entire frame is supposed to decompress to.
     /// This value is read directly from the file header and may be incorrect for malicious inputs.
     pub fn frame_size(&self) -> Option<u64> { self.content_size }
+    /// Returns how many more bytes this frame is supposed to decompress to (i.e. `frame_size` minus what was decoded so far).
+    ///
+    /// Handy for preallocating buffers or showing progress. The same caveat as for `frame_size` applies:
+    /// this is just what the header claims, so don't trust it with malicious inputs.
+    pub fn remaining(&self) -> Option<u64> { self.content_size.map(|size| size.saturating_sub(self.decoded)) }
     /// Return an identifier for the dictionary that was used to compress this frame.
     ///
     /// Dictionary identifiers are always application-specific. Note that the lz4 command line utility never
     /// specifies a dictionary id, even if a dictionary was used.
     pub fn dictionary_id(&self) -> Option<u32> { self.dictionary_id }
 
+    /// Allows you to abort decompression from the outside.
+    /// Once the token is cancelled, the next attempt to decode a block fails with `DecompressionError::Cancelled`.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation_token = Some(token);
+    }
+
+    /// Whether every independent block can reference the dictionary, or just the first one.
+    ///
+    /// This must match what the frame was compressed with (see `CompressionSettings::dictionary_scope`).
+    /// By default, every block can reference the dictionary.
+    pub fn set_dictionary_scope(&mut self, scope: DictionaryScope) {
+        self.dictionary_scope = scope;
+    }
+
+    /// What to do with data after the end of the frame. By default, we ignore it (see `TrailingData`).
+    ///
+    /// Archives are usually supposed to end right after their (last) frame, so validation tools
+    /// want to know when there's junk after it. Either policy other than `Ignore` reads the underlying reader
+    /// to its end once the end of the frame is reached.
+    pub fn set_trailing_data(&mut self, policy: TrailingData) {
+        self.trailing_data = policy;
+    }
+
+    /// How many bytes there were after the end of the frame, once we got there (with `TrailingData::Count`).
+    pub fn trailing_bytes(&self) -> Option<u64> { self.trailing_bytes }
+
+    /// Report progress (bytes, blocks, checksum time) to a `Metrics` implementation.
+    ///
+    /// Note that the header has already been parsed at this point, so its bytes are not counted.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics + Send + Sync>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Call a hook every so often while decompressing, even in the middle of a block (see `YieldHook`).
+    pub fn set_yield_hook(&mut self, hook: YieldHook) {
+        self.yield_counter = Some(YieldCounter::new(hook));
+    }
+
+    /// How many decoded blocks may wait for the hashing thread of `decode_with_background_checksums` (1 by default).
+    ///
+    /// Every one of them holds on to the block and what it decoded to, so this bounds the extra memory to about twice
+    /// that many blocks. More blocks keep us going if hashing is held up for a moment. Zero is treated as one.
+    pub fn set_blocks_in_flight(&mut self, blocks: usize) {
+        self.blocks_in_flight = blocks.max(1);
+    }
+
+    /// Tell a hook about anything suspicious we come across while decompressing (see `Warning`).
+    ///
+    /// We've already read the header at this point, so warnings about it are reported right away.
+    /// Warnings about blocks come in as we decode them, except that `decode_to_writer` can't tell
+    /// whether a stored block would have compressed, because it never holds an entire block in memory.
+    pub fn set_warning_hook(&mut self, hook: WarningHook) {
+        if self.content_size.is_none() {
+            hook.warn(Warning::NoContentSize);
+        }
+        self.warning_hook = Some(hook);
+    }
+
     /// Convert this `LZ4FrameReader` into something that implements `std::io::BufRead`.
     ///
     /// Note that `io::copy` has a small performance issue: https://github.com/rust-lang/rust/issues/49921
-    pub fn into_read_with_dictionary(self, dictionary: &[u8]) -> LZ4FrameIoReader<R> {
+    pub fn into_read_with_dictionary(self, dictionary: &[u8]) -> LZ4FrameIoReader<'_, R> {
+        self.into_read_internal(Dictionary::Borrowed(dictionary))
+    }
+
+    /// Like `into_read_with_dictionary`, but the reader keeps the dictionary alive on its own.
+    ///
+    /// This gets you a `LZ4FrameIoReader<'static, _>`, so you can e.g. load the dictionary from disk
+    /// in a helper function and return the reader from there. Pass an `Arc<[u8]>` if you share
+    /// the dictionary between many readers, a `Vec<u8>` otherwise.
+    pub fn into_read_with_owned_dictionary(self, dictionary: impl Into<Arc<[u8]>>) -> LZ4FrameIoReader<'static, R> {
+        self.into_read_internal(Dictionary::Shared(dictionary.into()))
+    }
+
+    /// Like `into_read_with_dictionary`, but the dictionary consists of several segments that are logically concatenated
+    /// (see `CompressionSettings::dictionary_chain`).
+    pub fn into_read_with_dictionary_chain(self, segments: &[&[u8]]) -> LZ4FrameIoReader<'static, R> {
+        self.into_read_with_owned_dictionary(chain_dictionaries(segments).0)
+    }
+
+    fn into_read_internal<'a>(mut self, dictionary: Dictionary<'a>) -> LZ4FrameIoReader<'a, R> {
+        let mut buffer = mem::take(&mut self.output);
+        buffer.reserve(self.output_capacity);
         LZ4FrameIoReader {
-            buffer: Vec::with_capacity(self.block_size()),
+            buffer,
             bytes_taken: 0,
             frame_reader: self,
             dictionary,
+            block_per_read: false,
         }
     }
 
+    /// Like `into_read_with_dictionary`, but the dictionary is looked up based on this frame's dictionary id.
+    ///
+    /// Fails with `UnknownDictionary` if the frame specifies a dictionary id that the provider doesn't know.
+    #[throws]
+    pub fn into_read_with_provider<'a, P: DictionaryProvider + ?Sized>(self, provider: &'a P) -> LZ4FrameIoReader<'a, R> {
+        let dictionary = match (provider.dictionary(self.dictionary_id), self.dictionary_id) {
+            (Some(dictionary), _) => dictionary,
+            (None, None) => &[],
+            (None, Some(id)) => throw!(Error::UnknownDictionary(id)),
+        };
+        self.into_read_with_dictionary(dictionary)
+    }
+
     /// Convenience wrapper in case you don't want to specify a dictionary.
     pub fn into_read(self) -> LZ4FrameIoReader<'static, R> {
         self.into_read_with_dictionary(&[])
@@ -196,23 +663,130 @@ impl<R: Read> LZ4FrameReader<R> {
     /// The `output` buffer must be empty upon calling this method.
     #[throws]
     pub fn decode_block(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) {
+        self.decode_block_internal(output, dictionary)?;
+    }
+
+    /// The flags from the frame header.
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// The block descriptor byte from the frame header, which encodes the block size (see `block_size`).
+    pub fn block_descriptor(&self) -> u8 {
+        self.header[5]
+    }
+
+    /// The complete frame header, byte for byte as it was in the input:
+    /// magic number, flags, block descriptor, the optional content size and dictionary id, and the header checksum.
+    ///
+    /// This is meant for debugging tools that want to show exactly what a producer wrote.
+    pub fn header_bytes(&self) -> &[u8] {
+        &self.header
+    }
+
+    pub(crate) fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    pub(crate) fn state(&self) -> ReaderState {
+        // only the IO reader peeks at block lengths, and it never takes snapshots
+        debug_assert!(self.next_block_length.is_none());
+        ReaderState {
+            flags: self.flags.bits(),
+            block_maxsize: self.block_maxsize,
+            content_size: self.content_size,
+            dictionary_id: self.dictionary_id,
+            content_hasher: self.content_hasher,
+            carryover_window: self.carryover_window.clone(),
+            finished: self.finished,
+            past_first_block: self.past_first_block,
+            decoded: self.decoded,
+            header: self.header.clone(),
+        }
+    }
+
+    /// The opposite of `state`. This must not trust `state` because it may have come from anywhere.
+    #[throws]
+    pub(crate) fn from_state(reader: R, state: ReaderState) -> Self {
+        let flags = Flags::from_bits(state.flags).ok_or(Error::InvalidSnapshot)?;
+        let valid_size = (4..=7).any(|i| BlockDescriptor(i << 4).block_maxsize().ok() == Some(state.block_maxsize));
+        if !valid_size
+            || flags.independent_blocks() != state.carryover_window.is_none()
+            || flags.content_checksum() != (state.content_hasher.is_some() || state.finished)
+            || state.carryover_window.as_ref().is_some_and(|w| w.len() > WINDOW_SIZE) {
+            throw!(Error::InvalidSnapshot);
+        }
+
+        let header = if state.header.is_empty() {
+            // older snapshots don't have the header, but it can only have been the canonical one
+            canonical_header(flags, state.block_maxsize, state.content_size, state.dictionary_id)
+        } else {
+            state.header
+        };
+        // the header might have come from a lenient reader
+        let parsed = Self::parse_header(&header[..], true).map_err(|_| Error::InvalidSnapshot)?;
+        if (parsed.flags, parsed.block_maxsize, parsed.content_size, parsed.dictionary_id)
+            != (flags, state.block_maxsize, state.content_size, state.dictionary_id)
+            || parsed.bytes.len() != header.len() {
+            throw!(Error::InvalidSnapshot);
+        }
+
+        LZ4FrameReader {
+            reader,
+            flags,
+            block_maxsize: state.block_maxsize,
+            content_size: state.content_size,
+            decoded: state.decoded,
+            next_block_length: None,
+            dictionary_id: state.dictionary_id,
+            content_hasher: state.content_hasher,
+            carryover_window: state.carryover_window,
+            finished: state.finished,
+            dictionary_scope: DictionaryScope::EveryBlock,
+            past_first_block: state.past_first_block,
+            read_buf: Vec::new(),
+            cancellation_token: None,
+            metrics: None,
+            yield_counter: None,
+            warning_hook: None,
+            short_block: None,
+            output_capacity: state.block_maxsize,
+            trailing_data: TrailingData::Ignore,
+            trailing_bytes: None,
+            header,
+            output: Vec::new(),
+            bounded_memory: false,
+            defer_checksums: false,
+            deferred_checksum: None,
+            blocks_in_flight: 1,
+        }
+    }
+
+    /// The raw contents of the block that was decoded most recently, if it was compressed.
+    ///
+    /// Stored blocks don't go through `read_buf` (unless they have a checksum), so this is only meaningful for compressed blocks.
+    pub(crate) fn raw_block(&self) -> &[u8] {
+        &self.read_buf
+    }
+
+    /// Like `decode_block`, but returns whether the block was compressed (or `None` if there was no block).
+    #[throws]
+    pub(crate) fn decode_block_internal(&mut self, output: &mut Vec<u8>, dictionary: &[u8]) -> Option<bool> {
         assert!(output.is_empty(), "You must pass an empty buffer to this interface.");
-        
-        if self.finished { return; }
 
-        let reader = &mut self.reader;
+        if self.finished { return None; }
+        if self.cancellation_token.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            throw!(Error::Cancelled);
+        }
 
-        let block_length = reader.read_u32::<LE>()?;
+        let dictionary = self.block_dictionary(dictionary);
+        let block_length = self.read_block_length()?;
         if block_length == 0 {
-            if let Some(hasher) = self.content_hasher.take() {
-                let checksum = reader.read_u32::<LE>()?;
-                if hasher.finish() != checksum.into() {
-                    throw!(Error::FrameChecksumFail);
-                }
-            }
-            self.finished = true;
-            return;
+            self.finish_frame()?;
+            return None;
         }
+        let metrics = self.metrics.as_deref();
+        let mut reader = Timed::new(&mut self.reader, metrics, Stage::Read);
 
         let is_compressed = block_length & INCOMPRESSIBLE == 0;
         let block_length = block_length & !INCOMPRESSIBLE;
@@ -220,37 +794,332 @@ impl<R: Read> LZ4FrameReader<R> {
         if block_length > self.block_maxsize as u32 {
             throw!(Error::BlockSizeOverflow);
         }
+        let len = block_length.try_into().or(Err(Error::BlockLengthOverflow))?;
 
-        let buf = &mut self.read_buf;
-        buf.resize(block_length.try_into().or(Err(Error::BlockLengthOverflow))?, 0);
-        reader.read_exact(buf.as_mut_slice())?;
+        if !is_compressed && !self.flags.block_checksums() {
+            // nothing to check and nothing to decompress, so there's no need to take a detour through read_buf
+            output.resize(len, 0);
+            reader.read_exact(output)?;
+        } else {
+            let buf = &mut self.read_buf;
+            if self.bounded_memory {
+                buf.reserve_exact(self.block_maxsize.saturating_sub(buf.len()));
+            }
+            buf.resize(len, 0);
+            reader.read_exact(buf.as_mut_slice())?;
+
+            if self.flags.block_checksums() {
+                let checksum = reader.read_u32::<LE>()?;
+                if self.defer_checksums {
+                    self.deferred_checksum = Some(checksum);
+                } else if !checksum_matches("block", checksum, time_checksum(metrics, || block_checksum(buf))) {
+                    throw!(Error::BlockChecksumFail);
+                }
+            }
 
-        if self.flags.block_checksums() {
-            let checksum = reader.read_u32::<LE>()?;
-            let mut hasher = XxHash32::with_seed(0);
-            hasher.write(buf);
-            if hasher.finish() != checksum.into() {
-                throw!(Error::BlockChecksumFail);
+            // decompress or copy, depending on whether this block is compressed
+            if is_compressed {
+                let dec_prefix = match self.carryover_window.as_mut() {
+                    Some(window) => Self::init_window(window, dictionary),
+                    None => dictionary,
+                };
+                time_stage(metrics, Stage::Decompress, || match self.yield_counter.as_mut() {
+                    Some(counter) => raw::decompress_raw_with_progress(buf, dec_prefix, output, self.block_maxsize, self.bounded_memory,
+                                                                       &mut counter.progress(0)),
+                    None if self.bounded_memory => raw::decompress_raw_strict(buf, dec_prefix, output, self.block_maxsize),
+                    None => raw::decompress_raw(buf, dec_prefix, output, self.block_maxsize),
+                })?;
+            } else {
+                output.extend_from_slice(buf);
             }
         }
 
-        // set up the prefix properly
-        let dec_prefix = if let Some(window) = self.carryover_window.as_mut() {
-            if window.is_empty() {
-                window.extend_from_slice(dictionary);
+        self.finish_block(output, dictionary, block_length, is_compressed)?;
+        Some(is_compressed)
+    }
+
+    /// If the next block is stored and has no checksum, read it straight into `buf`, so it isn't copied around at all.
+    ///
+    /// Returns `None` (without consuming the block) if the next block is anything else or doesn't fit into `buf`,
+    /// in which case you have to use `decode_block_internal`.
+    #[throws]
+    pub(crate) fn read_stored_block(&mut self, buf: &mut [u8], dictionary: &[u8]) -> Option<usize> {
+        if self.finished || self.flags.block_checksums()
+            || self.cancellation_token.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            // let decode_block_internal take care of these
+            return None;
+        }
+
+        let block_length = self.read_block_length()?;
+        let len = usize::try_from(block_length & !INCOMPRESSIBLE).or(Err(Error::BlockLengthOverflow))?;
+        if block_length & INCOMPRESSIBLE == 0 || len == 0 || len > self.block_maxsize || len > buf.len() {
+            self.next_block_length = Some(block_length);
+            return None;
+        }
+
+        let dictionary = self.block_dictionary(dictionary);
+        let output = &mut buf[..len];
+        Timed::new(&mut self.reader, self.metrics.as_deref(), Stage::Read).read_exact(output)?;
+        self.finish_block(output, dictionary, block_length & !INCOMPRESSIBLE, false)?;
+        Some(len)
+    }
+
+    /// Read the next block exactly as it is in the frame (including its block checksum) into `buf`, without checking or decoding it.
+    ///
+    /// Returns the length field, or `None` at the end of the frame. The content checksum is skipped, not checked.
+    #[throws]
+    pub(crate) fn read_raw_block(&mut self, buf: &mut Vec<u8>) -> Option<u32> {
+        if self.finished { return None; }
+        let block_length = self.read_block_length()?;
+        if block_length == 0 {
+            if self.flags.content_checksum() {
+                self.reader.read_u32::<LE>()?;
             }
-            window
-        } else {
-            dictionary
+            self.finished = true;
+            return None;
+        }
+        let len = usize::try_from(block_length & !INCOMPRESSIBLE).or(Err(Error::BlockLengthOverflow))?;
+        if len > self.block_maxsize {
+            throw!(Error::BlockSizeOverflow);
+        }
+        let checksum_len = if self.flags.block_checksums() { 4 } else { 0 };
+        buf.clear();
+        buf.resize(len + checksum_len, 0);
+        self.reader.read_exact(buf)?;
+        Some(block_length)
+    }
+
+    /// Decode the rest of the frame straight into `writer` and return how many bytes that was.
+    ///
+    /// Unlike everything else, this never holds an entire block (compressed or not) in memory:
+    /// literals go straight from the underlying reader to `writer`, and matches are copied out of a ring buffer
+    /// that holds the last 64 KiB of output. So this needs about 64 KiB of memory, no matter how large the blocks are,
+    /// which is great for embedded devices. It is slower than the regular path though, and you should wrap
+    /// both the underlying reader and `writer` in a `BufReader`/`BufWriter` because they see lots of small calls.
+    ///
+    /// Note that block checksums can only be verified after the block has been written.
+    /// If anything goes wrong, whatever was written so far is garbage, and so is the state of this reader.
+    #[throws]
+    pub fn decode_to_writer<W: Write>(&mut self, dictionary: &[u8], mut writer: W) -> u64 {
+        let history = match self.carryover_window.as_deref() {
+            Some(window) if !window.is_empty() => window,
+            _ => self.block_dictionary(dictionary),
         };
-        // decompress or copy, depending on whether this block is compressed
-        if is_compressed {
-            raw::decompress_raw(buf, dec_prefix, output, self.block_maxsize)?;
+        let mut window = RingWindow::new(history);
+        let mut total = 0;
+        loop {
+            if self.finished {
+                break total;
+            }
+            if self.cancellation_token.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                throw!(Error::Cancelled);
+            }
+
+            let block_length = self.read_block_length()?;
+            if block_length == 0 {
+                self.finish_frame()?;
+                continue;
+            }
+            let is_compressed = block_length & INCOMPRESSIBLE == 0;
+            let block_length = block_length & !INCOMPRESSIBLE;
+            if block_length > self.block_maxsize as u32 {
+                throw!(Error::BlockSizeOverflow);
+            }
+            if self.flags.independent_blocks() {
+                window.reset(self.block_dictionary(dictionary));
+            }
+
+            let metrics = self.metrics.as_deref();
+            let content_hasher = &mut self.content_hasher;
+            let yield_counter = &mut self.yield_counter;
+            let sink = |data: &[u8]| {
+                if let Some(hasher) = content_hasher.as_mut() {
+                    time_checksum(metrics, || hasher.write(data));
+                }
+                if let Some(counter) = yield_counter.as_mut() {
+                    counter.advance(data.len());
+                }
+                writer.write_all(data)
+            };
+            let mut input = Hashing { inner: &mut self.reader, hasher: self.flags.block_checksums().then(|| XxHash32::with_seed(0)) };
+            let block = input.by_ref().take(block_length.into());
+            let len = if is_compressed {
+                lowmem::decode_block(block, &mut window, self.block_maxsize, sink)?
+            } else {
+                lowmem::copy_block(block, &mut window, sink)?
+            };
+            if let Some(hasher) = input.hasher {
+                let checksum = self.reader.read_u32::<LE>()?;
+                if !checksum_matches("block", checksum, hasher.finish() as u32) {
+                    throw!(Error::BlockChecksumFail);
+                }
+            }
+
+            self.check_block(self.block_dictionary(dictionary), len, None);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(block_length, decoded = len, stored = !is_compressed, "read lz4 block");
+            self.past_first_block = true;
+            self.decoded += len as u64;
+            total += len as u64;
+            if let Some(m) = self.metrics.as_deref() {
+                let checksum_len = if self.flags.block_checksums() { 4 } else { 0 };
+                m.bytes_in(4 + u64::from(block_length) + checksum_len);
+                m.bytes_out(len as u64);
+                m.block_emitted();
+                if !is_compressed {
+                    m.block_stored();
+                }
+            }
+        }
+    }
+
+    /// Like `decode_to_writer`, but checksums are computed on another thread while we decode the next block.
+    ///
+    /// xxHash is fast, but so is LZ4, so on fast storage the checksums take a good part of the time it takes
+    /// to decode a frame. This gets them out of the way: every block (and what it decoded to) is handed to a thread
+    /// that hashes it while we carry on with the next block. That costs a few more blocks of memory (see `set_blocks_in_flight`)
+    /// and a thread for the duration of the call. Just like with `decode_to_writer`, a bad checksum is only noticed after the block
+    /// was written, so if anything goes wrong, whatever was written so far is garbage, and so is the state of this reader.
+    /// If the frame has no checksums (or we started at a checkpoint and there are no block checksums),
+    /// there's nothing to do in the background, so we don't start a thread.
+    #[throws]
+    pub fn decode_with_background_checksums<W: Write>(&mut self, dictionary: &[u8], mut writer: W) -> u64 {
+        if !self.flags.block_checksums() && self.content_hasher.is_none() {
+            let mut output = Vec::new();
+            let mut total = 0;
+            while self.decode_block_internal(&mut output, dictionary)?.is_some() {
+                time_stage(self.metrics.as_deref(), Stage::Write, || writer.write_all(&output)).map_err(Error::OutputError)?;
+                total += output.len() as u64;
+                output.clear();
+            }
+            return total;
+        }
+
+        let content_hasher = self.content_hasher.take();
+        let metrics = self.metrics.clone();
+        self.defer_checksums = true;
+        let (decoded, hashed) = thread::scope(|s| {
+            // only so many blocks wait for the hasher, so we don't run away from it
+            let (jobs, job_receiver) = mpsc::sync_channel(self.blocks_in_flight);
+            let (done, free) = mpsc::channel();
+            let hasher = s.spawn(move || hash_blocks(job_receiver, done, content_hasher, metrics.as_deref()));
+            let decoded = self.decode_deferred(dictionary, &mut writer, jobs, free);
+            (decoded, hasher.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+        });
+        self.defer_checksums = false;
+        // if the hasher stopped early, a bad block is why we stopped, too
+        let content_hasher = hashed?;
+        let total = decoded?;
+        if let (Some(hasher), Some(checksum)) = (content_hasher, self.deferred_checksum.take()) {
+            if !checksum_matches("content", checksum, hasher.finish() as u32) {
+                throw!(Error::FrameChecksumFail);
+            }
+        }
+        total
+    }
+
+    /// Our part of `decode_with_background_checksums`: decode and write the blocks, then hand them to the hasher.
+    #[throws]
+    fn decode_deferred<W: Write>(&mut self, dictionary: &[u8], mut writer: W, jobs: SyncSender<HashJob>, free: Receiver<Vec<u8>>) -> u64 {
+        let mut total = 0;
+        loop {
+            let mut output = free.try_recv().unwrap_or_default();
+            self.read_buf = free.try_recv().unwrap_or_default();
+            if self.decode_block_internal(&mut output, dictionary)?.is_none() {
+                break total;
+            }
+            time_stage(self.metrics.as_deref(), Stage::Write, || writer.write_all(&output)).map_err(Error::OutputError)?;
+            total += output.len() as u64;
+            let job = HashJob { block: mem::take(&mut self.read_buf), block_checksum: self.deferred_checksum.take(), content: output };
+            if jobs.send(job).is_err() {
+                // the hasher found a bad block and will tell our caller
+                break total;
+            }
+        }
+    }
+
+    /// We just read the end mark, so all that's left is the content checksum.
+    #[throws]
+    fn finish_frame(&mut self) {
+        if let Some(m) = self.metrics.as_deref() {
+            m.bytes_in(if self.flags.content_checksum() { 8 } else { 4 });
+        }
+        if self.flags.content_checksum() {
+            let checksum = self.reader.read_u32::<LE>()?;
+            if self.defer_checksums {
+                self.deferred_checksum = Some(checksum);
+            // if we started at a checkpoint, we didn't see all of the content, so there is nothing to compare against
+            } else if let Some(hasher) = self.content_hasher.take() {
+                if !checksum_matches("content", checksum, hasher.finish() as u32) {
+                    throw!(Error::FrameChecksumFail);
+                }
+            }
+        }
+        match self.trailing_data {
+            TrailingData::Ignore => (),
+            TrailingData::Reject => if self.reader.read(&mut [0])? != 0 {
+                throw!(Error::TrailingData);
+            },
+            TrailingData::Count => self.trailing_bytes = Some(io::copy(&mut self.reader, &mut io::sink())?),
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(decoded = self.decoded, "finished reading lz4 frame");
+        self.finished = true;
+    }
+
+    #[throws(io::Error)]
+    fn read_block_length(&mut self) -> u32 {
+        match self.next_block_length.take() {
+            Some(block_length) => block_length,
+            None => self.reader.read_u32::<LE>()?,
+        }
+    }
+
+    /// The dictionary that the next block may refer to.
+    fn block_dictionary<'d>(&self, dictionary: &'d [u8]) -> &'d [u8] {
+        if self.dictionary_scope == DictionaryScope::FirstBlock && self.past_first_block {
+            &[]
         } else {
-            output.extend_from_slice(buf);
+            dictionary
+        }
+    }
+
+    /// The window starts out with the dictionary (or as much of it as blocks can refer back to).
+    fn init_window<'w>(window: &'w mut Vec<u8>, dictionary: &[u8]) -> &'w [u8] {
+        if window.is_empty() {
+            window.extend_from_slice(&dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..]);
         }
-        // finally, push data back into the window as needed
+        window
+    }
+
+    /// Look for anything suspicious about a block of `len` bytes that we just decoded (and its content, if it was stored).
+    ///
+    /// This must happen before the block is counted in `decoded`.
+    fn check_block(&mut self, dictionary: &[u8], len: usize, stored: Option<&[u8]>) {
+        let Some(hook) = self.warning_hook.clone() else { return };
+        if !self.past_first_block && dictionary.is_empty() {
+            if let Some(id) = self.dictionary_id {
+                hook.warn(Warning::MissingDictionary(id));
+            }
+        }
+        // there's another block, so the previous one wasn't the last after all
+        if let Some(warning) = self.short_block.take() {
+            hook.warn(warning);
+        }
+        if len < self.block_maxsize {
+            self.short_block = Some(Warning::ShortBlock { offset: self.decoded, len });
+        }
+        if let Some(compressed_len) = stored.and_then(warnings::compressed_len) {
+            hook.warn(Warning::CompressibleStoredBlock { offset: self.decoded, len, compressed_len });
+        }
+    }
+
+    /// Everything that happens after a block was decoded into `output`, no matter where it came from.
+    #[throws]
+    pub(crate) fn finish_block(&mut self, output: &[u8], dictionary: &[u8], block_length: u32, is_compressed: bool) {
+        // push data back into the window as needed
         if let Some(window) = self.carryover_window.as_mut() {
+            Self::init_window(window, dictionary);
             let outlen = output.len();
             if outlen < WINDOW_SIZE {
                 let available_bytes = window.len() + outlen;
@@ -272,13 +1141,122 @@ impl<R: Read> LZ4FrameReader<R> {
         if output.len() > self.block_maxsize {
             throw!(Error::BlockSizeOverflow);
         }
+        self.check_block(dictionary, output.len(), Some(output).filter(|_| !is_compressed));
+        #[cfg(feature = "tracing")]
+        tracing::trace!(block_length, decoded = output.len(), stored = !is_compressed, "read lz4 block");
+        self.past_first_block = true;
+        self.decoded += output.len() as u64;
+        // compressed blocks already reported their progress while we decompressed them
+        if let Some(counter) = self.yield_counter.as_mut().filter(|_| !is_compressed) {
+            counter.advance(output.len());
+        }
 
+        let metrics = self.metrics.as_deref();
         if let Some(hasher) = self.content_hasher.as_mut() {
-            hasher.write(output);
+            time_checksum(metrics, || hasher.write(output));
+        }
+
+        if let Some(m) = metrics {
+            let checksum_len = if self.flags.block_checksums() { 4 } else { 0 };
+            m.bytes_in(4 + u64::from(block_length) + checksum_len);
+            m.bytes_out(output.len() as u64);
+            m.block_emitted();
+            if !is_compressed {
+                m.block_stored();
+            }
         }
     }
 }
 
+impl<R: Read + Seek> LZ4FrameReader<R> {
+    /// Start reading a frame at a checkpoint (see `CompressionSettings::checkpoint_interval`) instead of at the beginning.
+    ///
+    /// `reader` must be positioned at the start of the frame: we read the header and then seek ahead to the checkpoint.
+    /// From there on, everything works as usual (with the same dictionary as for the whole frame), except that
+    /// we can't check the content checksum, because we never saw the beginning of the content. To decode a frame
+    /// in parallel, start one reader at every checkpoint and stop each one where the next checkpoint begins.
+    #[throws]
+    pub fn from_checkpoint(reader: R, checkpoint: &Checkpoint) -> Self {
+        let mut frame = Self::new(reader)?;
+        let skip = i64::try_from(frame.jump_to(checkpoint)?).map_err(|_| Error::InvalidCheckpoint)?;
+        frame.reader.seek(SeekFrom::Current(skip))?;
+        frame
+    }
+
+    /// Get ready to decode the block at `offset` in a frame that starts at `frame_start`, as if it was the next one.
+    ///
+    /// This is for independent blocks only (see `PageReader`). Since we skip around, there's no content checksum to check.
+    #[throws]
+    pub(crate) fn seek_to_block(&mut self, frame_start: u64, offset: u64, first: bool) {
+        let position = frame_start.checked_add(offset).filter(|_| offset >= self.header.len() as u64).ok_or(Error::InvalidCheckpoint)?;
+        self.reader.seek(SeekFrom::Start(position))?;
+        self.next_block_length = None;
+        self.content_hasher = None;
+        self.short_block = None;
+        self.past_first_block = !first;
+        self.finished = false;
+    }
+}
+
+impl<R: Read> LZ4FrameReader<R> {
+    /// Continue at `checkpoint` as if we had decoded everything before it, and return how far past the header it is.
+    ///
+    /// This doesn't touch the reader, moving it there is up to the caller.
+    #[throws]
+    pub(crate) fn jump_to(&mut self, checkpoint: &Checkpoint) -> u64 {
+        let skip = checkpoint.compressed_offset.checked_sub(self.header.len() as u64).ok_or(Error::InvalidCheckpoint)?;
+        if self.content_size.is_some_and(|size| checkpoint.uncompressed_offset > size) {
+            throw!(Error::InvalidCheckpoint);
+        }
+        self.decoded = checkpoint.uncompressed_offset;
+        self.content_hasher = None;
+        skip
+    }
+}
+
+/// Whether a checksum from the frame matches what we computed (and tell tracing about it, if enabled).
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn checksum_matches(what: &'static str, expected: u32, actual: u32) -> bool {
+    #[cfg(feature = "tracing")]
+    if expected == actual {
+        tracing::trace!(what, checksum = expected, "checksum verified");
+    } else {
+        tracing::warn!(what, expected, actual, "checksum mismatch");
+    }
+    expected == actual
+}
+
+/// A block for the thread that checks the checksums, see `LZ4FrameReader::decode_with_background_checksums`.
+struct HashJob {
+    /// The block as it was in the frame (if it went through `read_buf`).
+    block: Vec<u8>,
+    /// What the block checksum should be, if there is one.
+    block_checksum: Option<u32>,
+    /// What the block decoded to.
+    content: Vec<u8>,
+}
+
+/// Check the blocks from `jobs` and hash their content, then hand the (empty) buffers back through `done` to be reused.
+///
+/// Stops at the first bad block. Otherwise, returns the content hasher once all blocks are through.
+fn hash_blocks(jobs: Receiver<HashJob>, done: Sender<Vec<u8>>, mut content_hasher: Option<XxHash32>,
+               metrics: Option<&(dyn Metrics + Send + Sync)>) -> Result<Option<XxHash32>, Error> {
+    for HashJob { mut block, block_checksum: checksum, mut content } in jobs {
+        if checksum.is_some_and(|checksum| !checksum_matches("block", checksum, time_checksum(metrics, || block_checksum(&block)))) {
+            return Err(Error::BlockChecksumFail);
+        }
+        if let Some(hasher) = content_hasher.as_mut() {
+            time_checksum(metrics, || hasher.write(&content));
+        }
+        block.clear();
+        content.clear();
+        // once the frame is over, nobody is waiting for them anymore
+        let _ = done.send(block);
+        let _ = done.send(content);
+    }
+    Ok(content_hasher)
+}
+
 /// Convenience wrapper around `LZ4FrameReader` that reads everything into a vector and returns it.
 #[throws]
 pub fn decompress_frame<R: Read>(reader: R) -> Vec<u8> {
@@ -287,3 +1265,29 @@ pub fn decompress_frame<R: Read>(reader: R) -> Vec<u8> {
     plaintext
 }
 
+/// Decompress all LZ4 frames in a slice into a new vector.
+///
+/// Unlike `decompress_frame`, this keeps going until the input is exhausted,
+/// so it handles files that consist of several concatenated frames (as produced by e.g. `cat a.lz4 b.lz4`).
+/// Skippable frames are skipped.
+/// This is the easiest way to decode an LZ4 file that is already in memory (e.g. in a browser via wasm-bindgen).
+#[throws]
+pub fn decompress_slice(mut input: &[u8], dictionary: &[u8]) -> Vec<u8> {
+    let mut plaintext = Vec::new();
+    let mut buffers = FrameBuffers::new();
+    while !input.is_empty() {
+        let magic = (&input[..]).read_u32::<LE>()?;
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            let len = (&input[4..]).read_u32::<LE>()?;
+            // `8 + len` would overflow on 32 bit targets
+            input = usize::try_from(len).ok().and_then(|len| input.get(8..)?.get(len..))
+                .ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+            continue;
+        }
+        let mut frame = LZ4FrameReader::with_buffers(&mut input, &mut buffers)?.into_read_with_dictionary(dictionary);
+        frame.read_to_end(&mut plaintext)?;
+        frame.return_buffers(&mut buffers);
+    }
+    plaintext
+}
+
@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use super::WINDOW_SIZE;
+
+/// Looks up dictionaries by their id.
+///
+/// Passing a dictionary to `LZ4FrameReader::into_read_with_dictionary` means you have to know which one
+/// you need before you can even look at the header. A provider is only asked once the header has been parsed,
+/// so frames can pick their own dictionary via the dictionary id (see `CompressionSettings::dictionary`).
+pub trait DictionaryProvider {
+    /// Return the dictionary for the given id, or `None` if you don't have it.
+    ///
+    /// The id is `None` for frames that don't specify one (the lz4 command line utility never does).
+    /// Returning `None` for those is fine and simply means no dictionary.
+    fn dictionary(&self, id: Option<u32>) -> Option<&[u8]>;
+}
+
+/// A single dictionary that is used for every frame, no matter the id.
+impl DictionaryProvider for [u8] {
+    fn dictionary(&self, _id: Option<u32>) -> Option<&[u8]> {
+        Some(self)
+    }
+}
+
+impl<S: BuildHasher> DictionaryProvider for HashMap<u32, Vec<u8>, S> {
+    fn dictionary(&self, id: Option<u32>) -> Option<&[u8]> {
+        self.get(&id?).map(Vec::as_slice)
+    }
+}
+
+impl DictionaryProvider for BTreeMap<u32, Vec<u8>> {
+    fn dictionary(&self, id: Option<u32>) -> Option<&[u8]> {
+        self.get(&id?).map(Vec::as_slice)
+    }
+}
+
+/// What we made of a dictionary you gave us, see `trim_dictionary`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DictionaryInfo {
+    /// How large the dictionary you passed in was.
+    pub original_size: usize,
+    /// How much of it can actually be used (at most 64 KiB).
+    pub effective_size: usize,
+}
+
+impl DictionaryInfo {
+    /// Whether anything was cut off, i.e. some of the dictionary is useless.
+    pub fn truncated(&self) -> bool {
+        self.effective_size < self.original_size
+    }
+}
+
+/// Cut a dictionary down to the part that is actually useful: the trailing 64 KiB.
+///
+/// LZ4 can't reference anything further back than that, so any dictionary content before that point
+/// is silently ignored. `CompressionSettings::dictionary` does this for you, but if you trained
+/// a 1 MiB dictionary, you probably want to know that most of it is dead weight.
+pub fn trim_dictionary(dictionary: &[u8]) -> (&[u8], DictionaryInfo) {
+    let trimmed = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+    (trimmed, DictionaryInfo { original_size: dictionary.len(), effective_size: trimmed.len() })
+}
+
+/// Concatenate dictionary segments, keeping only the part that is actually useful (the trailing 64 KiB).
+///
+/// This is what `CompressionSettings::dictionary_chain` uses, so you can pass the result
+/// to any decompression function that takes a dictionary.
+pub fn chain_dictionaries(segments: &[&[u8]]) -> (Vec<u8>, DictionaryInfo) {
+    let original_size = segments.iter().map(|s| s.len()).sum();
+    // walk backwards until we have a full window, so we never copy anything that we would throw away anyway
+    let mut missing = WINDOW_SIZE;
+    let mut tails = Vec::new();
+    for segment in segments.iter().rev() {
+        if missing == 0 {
+            break;
+        }
+        let tail = &segment[segment.len().saturating_sub(missing)..];
+        missing -= tail.len();
+        tails.push(tail);
+    }
+    tails.reverse();
+    let dictionary = tails.concat();
+    let info = DictionaryInfo { original_size, effective_size: dictionary.len() };
+    (dictionary, info)
+}
+
+/// Which blocks of a frame start out with the dictionary as their history.
+///
+/// This only makes a difference for frames with independent blocks. With dependent blocks,
+/// the dictionary is always just the history in front of the first block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DictionaryScope {
+    /// Every block can reference the dictionary. This is what lz-fear (and the liblz4 frame API) does.
+    #[default]
+    EveryBlock,
+    /// Only the first block can reference the dictionary, all others start from scratch.
+    /// Some other implementations do it this way, so this is what you need to be compatible with them.
+    FirstBlock,
+}
+
+/// A dictionary that is either borrowed or kept alive by us.
+pub(crate) enum Dictionary<'a> {
+    Borrowed(&'a [u8]),
+    Shared(Arc<[u8]>),
+}
+
+impl Deref for Dictionary<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Dictionary::Borrowed(dictionary) => dictionary,
+            Dictionary::Shared(dictionary) => dictionary,
+        }
+    }
+}
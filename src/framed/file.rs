@@ -0,0 +1,286 @@
+use byteorder::{LE, ReadBytesExt};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use culpa::{throw, throws};
+
+use super::{BlockStats, CompressionError, CompressionSettings, FrameBuffers, LZ4FrameReader, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK};
+
+type Error = io::Error;
+
+/// How many bytes were read and written by `compress_file` or `decompress_file` (or their `_pipe` versions).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FileSizes {
+    pub input: u64,
+    pub output: u64,
+}
+
+impl CompressionSettings<'_> {
+    /// Compress the file at `path` and record its size in the frame header.
+    ///
+    /// The size comes from the file's metadata, so unlike `compress_with_size` this doesn't need
+    /// to seek around in the input, and the output doesn't need to be seekable either.
+    /// If the file changes size while we're compressing it, this fails with `InvalidData`
+    /// (the output is garbage in that case).
+    /// Returns the size of the file.
+    #[throws(CompressionError)]
+    pub fn compress_path_with_size<P: AsRef<Path>, W: Write>(&self, path: P, writer: W) -> u64 {
+        let file = File::open(path).map_err(CompressionError::ReadError)?;
+        let len = file.metadata().map_err(CompressionError::ReadError)?.len();
+        let mut reader = Counting::new(BufReader::new(file));
+        self.compress_with_size_unchecked(&mut reader, writer, len)?;
+        if reader.count != len {
+            throw!(CompressionError::ReadError(io::Error::new(ErrorKind::InvalidData, "the file changed size while compressing it")));
+        }
+        len
+    }
+
+    /// Compress the file at `input` into a new file at `output`.
+    ///
+    /// The output is first written to a temporary file next to `output`, which is only renamed
+    /// to `output` once everything went well. On error, the temporary file is removed again,
+    /// so you never end up with a truncated output file (an existing file at `output` is left alone).
+    #[throws]
+    pub fn compress_file<P: AsRef<Path>, Q: AsRef<Path>>(&self, input: P, output: Q) -> FileSizes {
+        let input = File::open(input)?;
+        write_atomically(output.as_ref(), |file| self.compress_pipe(input, file))?
+    }
+
+    /// Compress everything from `reader` into `writer`: basically `io::copy` with compression in the middle.
+    ///
+    /// Neither side needs to be buffered. We read a block at a time straight into the compressor and write through
+    /// a small buffer of our own, so this needs about two blocks of memory no matter how much data flows through.
+    /// The writer is flushed at the end. This is meant to be run on a thread of its own, e.g. to connect
+    /// the pipes of two child processes (with `decompress_pipe` on the other end).
+    #[throws]
+    pub fn compress_pipe<R: Read, W: Write>(&self, reader: R, writer: W) -> FileSizes {
+        let mut reader = Counting::new(reader);
+        let mut writer = BufWriter::new(Counting::new(writer));
+        self.compress(&mut reader, &mut writer)?;
+        writer.flush()?;
+        FileSizes { input: reader.count, output: writer.get_ref().count }
+    }
+
+    /// Compress many files at once, `input` to `output` for every pair, on up to `threads` threads.
+    ///
+    /// Every file is written atomically with `compress_file`, and one failure doesn't stop the others:
+    /// you get one result per pair, in the same order. Files are handed out one at a time,
+    /// so a few large ones don't hold up all the small ones (as long as there are enough threads).
+    /// The block callback (if any) is called on this thread, for the blocks of all files as they come in.
+    pub fn compress_files<P, Q>(&self, files: &[(P, Q)], threads: usize) -> Vec<io::Result<FileSizes>>
+    where P: AsRef<Path> + Sync, Q: AsRef<Path> + Sync {
+        self.compress_jobs(files.iter().collect(), threads, |settings, (input, output)| settings.compress_file(input, output))
+    }
+
+    /// Like `compress_files`, but for anything you can read from and write to.
+    ///
+    /// Every job opens its own reader and writer when a thread gets around to it (so that you don't
+    /// have thousands of files open at once) and is then run through `compress_pipe`.
+    /// If opening fails, that's the job's result.
+    pub fn compress_streams<J, R, W>(&self, jobs: Vec<J>, threads: usize) -> Vec<io::Result<FileSizes>>
+    where J: FnOnce() -> io::Result<(R, W)> + Send, R: Read, W: Write {
+        self.compress_jobs(jobs, threads, |settings, job| {
+            let (reader, writer) = job()?;
+            settings.compress_pipe(reader, writer)
+        })
+    }
+
+    fn compress_jobs<J: Send>(&self, jobs: Vec<J>, threads: usize,
+                              run: impl Fn(&CompressionSettings<'_>, J) -> io::Result<FileSizes> + Sync) -> Vec<io::Result<FileSizes>> {
+        enum Event {
+            Block(BlockStats),
+            Done(usize, io::Result<FileSizes>),
+        }
+
+        let mut results: Vec<_> = jobs.iter().map(|_| None).collect();
+        let threads = threads.clamp(1, jobs.len().max(1));
+        let jobs = Mutex::new(jobs.into_iter().enumerate());
+        let shared = self.thread_safe();
+        let has_callback = self.block_callback.is_some();
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let sender = sender.clone();
+                let (jobs, shared, run) = (&jobs, &shared, &run);
+                scope.spawn(move || {
+                    let forward = |stats: &BlockStats| drop(sender.send(Event::Block(*stats)));
+                    let settings = shared.settings(if has_callback { Some(&forward) } else { None });
+                    loop {
+                        // don't hold the lock while compressing
+                        let next = jobs.lock().unwrap().next();
+                        let Some((i, job)) = next else { break };
+                        let _ = sender.send(Event::Done(i, run(&settings, job)));
+                    }
+                });
+            }
+            // the workers have their own senders, so this ends once they're all done
+            drop(sender);
+            for event in receiver {
+                match event {
+                    Event::Block(stats) => if let Some(callback) = self.block_callback { callback(&stats) },
+                    Event::Done(i, result) => results[i] = Some(result),
+                }
+            }
+        });
+        results.into_iter().map(|result| result.expect("every job reports back")).collect()
+    }
+}
+
+/// Decompress the file at `input` into a new file at `output`.
+///
+/// Like `decompress_slice`, this handles concatenated and skippable frames.
+/// The output is written atomically, just like `CompressionSettings::compress_file` does it.
+///
+/// If the first frame records its content size, the output file is set to that length before we write anything.
+/// That gives the filesystem a chance to lay the file out in one piece, and on filesystems that allocate
+/// right away, a restore that won't fit fails up front instead of after writing gigabytes.
+/// The blocks are still written front to back, and the file is cut to size at the end in case the frames say otherwise.
+#[throws]
+pub fn decompress_file<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, dictionary: &[u8]) -> FileSizes {
+    let mut input = File::open(input)?;
+    let size = peek_content_size(&mut input)?;
+    write_atomically(output.as_ref(), |file| {
+        if let Some(size) = size {
+            file.set_len(size)?;
+        }
+        let sizes = decompress_pipe(input, &mut *file, dictionary)?;
+        if size.is_some_and(|size| size != sizes.output) {
+            file.set_len(sizes.output)?;
+        }
+        Ok(sizes)
+    })?
+}
+
+/// The content size from the header of the first frame in `file` (if it has one). Rewinds `file` afterwards.
+///
+/// Anything that doesn't parse is left for the actual decompression to complain about.
+#[throws]
+fn peek_content_size(file: &mut File) -> Option<u64> {
+    let size = LZ4FrameReader::new(&mut *file).ok().and_then(|frame| frame.frame_size());
+    file.rewind()?;
+    size
+}
+
+/// Decompress everything from `reader` into `writer`, the other end of `CompressionSettings::compress_pipe`.
+///
+/// Like `decompress_slice`, this handles concatenated and skippable frames, and keeps going until `reader` is exhausted.
+/// Both sides are buffered internally, and blocks are written as soon as they are decompressed,
+/// so this needs about two blocks of memory. The writer is flushed at the end.
+#[throws]
+pub fn decompress_pipe<R: Read, W: Write>(reader: R, writer: W, dictionary: &[u8]) -> FileSizes {
+    let mut reader = Counting::new(BufReader::new(reader));
+    let mut writer = BufWriter::new(writer);
+    let mut written = 0;
+    let mut buffers = FrameBuffers::new();
+    while let Some(magic) = read_magic(&mut reader)? {
+        if magic & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC {
+            let len = reader.read_u32::<LE>()?;
+            if io::copy(&mut reader.by_ref().take(len.into()), &mut io::sink())? != u64::from(len) {
+                throw!(io::Error::from(ErrorKind::UnexpectedEof));
+            }
+        } else {
+            // the frame reader wants to see the magic number for itself
+            let magic = magic.to_le_bytes();
+            let frame = (&magic[..]).chain(&mut reader);
+            let mut frame = LZ4FrameReader::with_buffers(frame, &mut buffers)?.into_read_with_dictionary(dictionary);
+            written += frame.copy_to(&mut writer)?;
+            frame.return_buffers(&mut buffers);
+        }
+    }
+    writer.flush()?;
+    FileSizes { input: reader.count, output: written }
+}
+
+/// Reads the magic number of the next frame, or returns `None` if the input is exhausted.
+#[throws]
+fn read_magic<R: Read>(reader: &mut R) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return None,
+            Ok(0) => throw!(io::Error::from(ErrorKind::UnexpectedEof)),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => throw!(e),
+        }
+    }
+    Some(u32::from_le_bytes(buf))
+}
+
+/// Creates a temporary file next to `path`, lets `f` write to it and then renames it to `path`.
+///
+/// If anything fails, the temporary file is removed and `path` remains untouched.
+#[throws]
+pub(crate) fn write_atomically<T>(path: &Path, f: impl FnOnce(&mut File) -> io::Result<T>) -> T {
+    let (tmp_path, mut file) = create_temporary(path)?;
+    let result = f(&mut file).and_then(|t| {
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, path)?;
+        Ok(t)
+    });
+    match result {
+        Ok(t) => t,
+        Err(e) => {
+            // best effort, the original error is much more interesting than this one
+            let _ = fs::remove_file(&tmp_path);
+            throw!(e);
+        }
+    }
+}
+
+#[throws]
+fn create_temporary(path: &Path) -> (PathBuf, File) {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let name = path.file_name().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "output path has no file name"))?;
+    loop {
+        let mut tmp_name = std::ffi::OsString::from(".");
+        tmp_name.push(name);
+        tmp_name.push(format!(".{}.{}.tmp", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let tmp_path = path.with_file_name(tmp_name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&tmp_path) {
+            Ok(file) => break (tmp_path, file),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => throw!(e),
+        }
+    }
+}
+
+/// Counts the bytes that pass through a reader or writer.
+pub(crate) struct Counting<T> {
+    inner: T,
+    pub(crate) count: u64,
+}
+impl<T> Counting<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Counting { inner, count: 0 }
+    }
+}
+impl<R: Read> Read for Counting<R> {
+    #[throws]
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        n
+    }
+}
+impl<W: Write> Write for Counting<W> {
+    #[throws]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        n
+    }
+
+    #[throws]
+    fn flush(&mut self) {
+        self.inner.flush()?;
+    }
+}
@@ -0,0 +1,75 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+use std::io::{Read, Write};
+
+use super::{BlockSize, CompressionError, CompressionSettings, DecompressionError, DictionaryScope, LZ4FrameReader, LZ4FrameWriter};
+use crate::raw::VarU32Table;
+
+/// Covers everything that changes what a frame looks like.
+///
+/// Cancellation, metrics and callbacks are left alone because they don't.
+impl<'a> Arbitrary<'a> for CompressionSettings<'a> {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut settings = CompressionSettings::default();
+        settings
+            .independent_blocks(u.arbitrary()?)
+            .block_checksums(u.arbitrary()?)
+            .content_checksum(u.arbitrary()?)
+            .block_size(BlockSize::arbitrary(u)?.bytes());
+        if u.arbitrary()? {
+            settings.dictionary(u.arbitrary()?, u.arbitrary()?);
+            if u.arbitrary()? {
+                settings.dictionary_scope(DictionaryScope::FirstBlock);
+            }
+        }
+        if u.arbitrary()? {
+            settings.hash_log(u.int_in_range(VarU32Table::MIN_HASH_LOG..=VarU32Table::MAX_HASH_LOG)?);
+        }
+        Ok(settings)
+    }
+}
+
+/// A synthetic frame for fuzzing: some settings, some content, and the way it is fed to the compressor.
+///
+/// This lets a fuzz target explore dependent blocks, dictionaries, checksums and odd write patterns
+/// instead of always compressing with the default settings:
+///
+/// ```ignore
+/// fuzz_target!(|spec: FrameSpec| {
+///     let frame = spec.compress().unwrap();
+///     assert_eq!(spec.decompress(&frame).unwrap(), spec.content);
+/// });
+/// ```
+#[derive(Arbitrary, Debug)]
+pub struct FrameSpec<'a> {
+    pub settings: CompressionSettings<'a>,
+    pub content: &'a [u8],
+    /// Whether to write the content size into the header.
+    pub content_size: bool,
+    /// The content is written in chunks of these sizes (and whatever is left over in one go).
+    pub writes: Vec<u16>,
+}
+
+impl FrameSpec<'_> {
+    /// Compress the content into a frame.
+    pub fn compress(&self) -> std::result::Result<Vec<u8>, CompressionError> {
+        let content_size = Some(self.content.len() as u64).filter(|_| self.content_size);
+        let mut writer = LZ4FrameWriter::with_content_size(Vec::new(), &self.settings, content_size)?;
+        let mut rest = self.content;
+        for &len in &self.writes {
+            let (chunk, tail) = rest.split_at(rest.len().min(len.into()));
+            writer.write_all(chunk)?;
+            rest = tail;
+        }
+        writer.write_all(rest)?;
+        writer.finish()
+    }
+
+    /// Decompress a frame that was compressed with these settings (i.e. with the same dictionary).
+    pub fn decompress(&self, frame: &[u8]) -> std::result::Result<Vec<u8>, DecompressionError> {
+        let mut reader = LZ4FrameReader::new(frame)?;
+        reader.set_dictionary_scope(self.settings.dictionary_scope);
+        let mut output = Vec::new();
+        reader.into_read_with_dictionary(self.settings.dictionary.as_deref().unwrap_or(&[])).read_to_end(&mut output)?;
+        Ok(output)
+    }
+}
@@ -0,0 +1,43 @@
+/// A gear hash, the rolling hash behind content-defined boundaries (see `CompressionSettings::rsyncable`).
+///
+/// Every byte shifts the hash left by one and adds a random number for that byte, so after 64 bytes, everything before
+/// them has been shifted out. The hash only depends on the last 64 bytes, and there's no need to remember them.
+/// We look at the high bits for boundaries, because those have seen the most bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Gear {
+    pub(crate) hash: u64,
+}
+
+impl Gear {
+    /// Roll over `data` until we hit a boundary (where the top `bits` bits of the hash are all zero),
+    /// and return how many bytes that took, including the one that made the boundary.
+    ///
+    /// Without a boundary, we rolled over all of `data`. On average, there is a boundary every `1 << bits` bytes.
+    pub(crate) fn find_boundary(&mut self, data: &[u8], bits: u32) -> Option<usize> {
+        let mask = !(u64::MAX >> bits);
+        for (i, &byte) in data.iter().enumerate() {
+            self.hash = (self.hash << 1).wrapping_add(GEAR[usize::from(byte)]);
+            if self.hash & mask == 0 {
+                return Some(i + 1);
+            }
+        }
+        None
+    }
+}
+
+/// A random number for every byte, from splitmix64. These are part of the output format (they decide where blocks end),
+/// so they must never change.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state = 0x4C5A_4645_4152_u64; // "LZFEAR"
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
@@ -6,6 +6,8 @@ use culpa::{throw, throws};
 use bitflags::bitflags;
 
 bitflags! {
+    /// The flags in the frame header (the version bits are not part of this).
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct Flags: u8 {
         const IndependentBlocks = 0b00100000;
         const BlockChecksums    = 0b00010000;
@@ -30,13 +32,19 @@ pub enum ParseError {
 impl Flags {
     #[throws(ParseError)]
     pub fn parse(i: u8) -> Self {
+        if (i & 0b10) != 0 {
+            throw!(ParseError::ReservedFlagBitsSet);
+        }
+        Flags::parse_lenient(i)?
+    }
+
+    /// Like `parse`, but reserved bits are simply ignored.
+    #[throws(ParseError)]
+    pub fn parse_lenient(i: u8) -> Self {
         let version = i >> 6;
         if version != 1 {
             throw!(ParseError::UnsupportedVersion(version));
         }
-        if (i & 0b10) != 0 {
-            throw!(ParseError::ReservedFlagBitsSet);
-        }
 
         Flags::from_bits_truncate(i)
     }
@@ -61,6 +69,14 @@ impl BlockDescriptor {
         Some(bd)
     }
 
+    /// The smallest block size that blocks of `len` bytes fit into (`None` if they're empty or larger than 4 MiB).
+    pub fn fitting(len: usize) -> Option<Self> {
+        if len == 0 {
+            return None;
+        }
+        (4..8).map(|size| BlockDescriptor(size << 4)).find(|bd| bd.block_maxsize().is_ok_and(|max| len <= max))
+    }
+
     #[throws(ParseError)]
     pub fn parse(i: u8) -> Self {
         if (i & 0b10001111) != 0 {
@@ -69,6 +85,11 @@ impl BlockDescriptor {
         BlockDescriptor(i)
     }
 
+    /// Like `parse`, but reserved bits are simply ignored (and cleared).
+    pub fn parse_lenient(i: u8) -> Self {
+        BlockDescriptor(i & 0b01110000)
+    }
+
     #[throws(ParseError)]
     pub fn block_maxsize(&self) -> usize {
         let size = (self.0 >> 4) & 0b111;
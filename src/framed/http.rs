@@ -0,0 +1,148 @@
+use std::cmp;
+use std::io::{self, ErrorKind, Read, Write};
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings, LZ4FrameWriter, NonBlockingFrameReader};
+
+type Error = io::Error;
+
+/// The value of the `Content-Encoding` (or `Accept-Encoding`) header for LZ4 frames.
+pub const CONTENT_ENCODING: &str = "lz4";
+
+/// How much we read from the body at once.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compresses a body on the fly: you give it the plain body as a `Read`, and reading from it yields an LZ4 frame.
+///
+/// This is what most HTTP servers want for a response with `Content-Encoding: lz4`,
+/// because they take the body as a reader and pull from it whenever the client is ready.
+/// We only ever hold one block of input and one block of output in memory, no matter how large the body is.
+///
+/// By default, every chunk we get from the body is flushed as its own block (see `sync_flush`),
+/// so streaming responses (server-sent events, long polling, progress output) reach the client right away.
+pub struct CompressingReader<'a, R: Read> {
+    reader: R,
+    /// Only `None` once the body is exhausted.
+    frame: Option<LZ4FrameWriter<'a, Vec<u8>>>,
+    /// The end of the frame, once the body is exhausted.
+    tail: Vec<u8>,
+    /// How much of the compressed data has already been read.
+    pos: usize,
+    buffer: Box<[u8]>,
+    sync_flush: bool,
+}
+
+impl<'a, R: Read> CompressingReader<'a, R> {
+    #[throws(CompressionError)]
+    pub fn new(reader: R, settings: &'a CompressionSettings<'a>) -> Self {
+        CompressingReader {
+            reader,
+            frame: Some(LZ4FrameWriter::new(Vec::new(), settings)?),
+            tail: Vec::new(),
+            pos: 0,
+            buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            sync_flush: true,
+        }
+    }
+
+    /// Whether to end a block whenever a `read` on the body returns, so the client can decompress
+    /// everything we have seen so far. Enabled by default.
+    ///
+    /// Disable this if the body is a file or something similar where the chunks have no meaning,
+    /// because lots of small blocks compress worse than a few large ones.
+    pub fn sync_flush(&mut self, sync_flush: bool) -> &mut Self {
+        self.sync_flush = sync_flush;
+        self
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for CompressingReader<'_, R> {
+    #[throws]
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        loop {
+            let output = match self.frame.as_mut() {
+                Some(frame) => frame.get_mut(),
+                None => &mut self.tail,
+            };
+            if self.pos < output.len() || buf.is_empty() {
+                let len = cmp::min(output.len() - self.pos, buf.len());
+                buf[..len].copy_from_slice(&output[self.pos..self.pos + len]);
+                self.pos += len;
+                break len;
+            }
+            output.clear();
+            self.pos = 0;
+
+            if self.frame.is_none() {
+                break 0;
+            }
+            // errors (including WouldBlock) are passed on to the caller, we haven't touched any state yet
+            match self.reader.read(&mut self.buffer)? {
+                0 => self.tail = self.frame.take().unwrap().finish()?,
+                n => {
+                    let frame = self.frame.as_mut().unwrap();
+                    frame.write_all(&self.buffer[..n])?;
+                    if self.sync_flush {
+                        frame.flush()?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Decompresses a body with `Content-Encoding: lz4`, e.g. an incoming request.
+///
+/// Unlike `LZ4FrameIoReader`, this only parses the header on the first `read`, so you can set it up
+/// before any of the body has arrived, and it survives `WouldBlock` like `NonBlockingFrameReader`.
+/// Memory use is bounded by the frame's block size (at most 4 MiB), but the decompressed body can of course
+/// be much larger than what the client sent, so if you read it into memory, set a `limit`.
+pub struct DecompressingReader<'a, R: Read> {
+    inner: NonBlockingFrameReader<'a, R>,
+    limit: Option<u64>,
+    read: u64,
+}
+
+impl<R: Read> DecompressingReader<'static, R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_dictionary(reader, &[])
+    }
+}
+
+impl<'a, R: Read> DecompressingReader<'a, R> {
+    /// Decompress a body that was compressed with this dictionary.
+    pub fn with_dictionary(reader: R, dictionary: &'a [u8]) -> Self {
+        DecompressingReader { inner: NonBlockingFrameReader::with_dictionary(reader, dictionary), limit: None, read: 0 }
+    }
+
+    /// Fail with `InvalidData` as soon as the body decompresses to more than `limit` bytes.
+    pub fn limit(&mut self, limit: u64) -> &mut Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner.into_inner()
+    }
+}
+
+impl<R: Read> Read for DecompressingReader<'_, R> {
+    #[throws]
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        // ask for one byte more than allowed so we notice when the body is too large
+        let allowed = self.limit.map_or(usize::MAX, |limit| usize::try_from(limit.saturating_sub(self.read).saturating_add(1)).unwrap_or(usize::MAX));
+        let len = cmp::min(buf.len(), allowed);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.read += n as u64;
+        if self.limit.is_some_and(|limit| self.read > limit) {
+            throw!(Error::new(ErrorKind::InvalidData, "the decompressed body is larger than the limit"));
+        }
+        n
+    }
+}
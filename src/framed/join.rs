@@ -0,0 +1,92 @@
+use byteorder::{LE, WriteBytesExt};
+use std::io::{self, Read, Write};
+use culpa::{throw, throws};
+
+use super::{block_checksum, ContentChecksum, Counting, DecompressionError, LZ4FrameReader, INCOMPRESSIBLE};
+use super::decompress::canonical_header;
+use super::header::Flags;
+
+type Error = DecompressionError;
+
+/// Join several frames into a single frame by copying their blocks, and return how many bytes we wrote.
+///
+/// This is much cheaper than decompressing and compressing everything again (e.g. to merge hourly log segments
+/// into daily archives): we only strip the headers and end marks in between. For this to work, all frames need
+/// the same settings (independent or dependent blocks, block checksums and dictionary id), and none of them may have
+/// a larger block size than the first one. Dependent blocks can't refer to a dictionary in the middle of a frame,
+/// so frames with dependent blocks and a dictionary id can't be joined. Frames compressed with `DictionaryScope::FirstBlock`
+/// can't either, but we can't tell from the frames, so that's up to you.
+///
+/// The content checksum covers the entire content, so it can't be stitched together from the checksums of the parts.
+/// With `content_checksum`, we decode every block once to compute it (checking the parts on the way,
+/// which is why we may need the `dictionary`). Otherwise, the joined frame has no content checksum, and nothing is
+/// checked (the content checksums of the parts are simply dropped). The joined frame never has a content size.
+/// If there are no frames, nothing is written at all.
+#[throws]
+pub fn join_frames<I: IntoIterator, W: Write>(frames: I, writer: W, dictionary: &[u8], content_checksum: bool) -> u64 where I::Item: Read {
+    let mut frames = frames.into_iter();
+    let mut frame = match frames.next() {
+        Some(first) => LZ4FrameReader::new(first)?,
+        None => return 0,
+    };
+    let mut flags = frame.flags() - Flags::ContentSize;
+    flags.set(Flags::ContentChecksum, content_checksum);
+    if !flags.independent_blocks() && flags.dictionary_id() {
+        throw!(Error::IncompatibleFrames);
+    }
+    let (block_maxsize, dictionary_id) = (frame.block_size(), frame.dictionary_id());
+    let compatible = |frame: &LZ4FrameReader<_>| frame.flags().independent_blocks() == flags.independent_blocks()
+        && frame.flags().block_checksums() == flags.block_checksums()
+        && frame.dictionary_id() == dictionary_id
+        && frame.block_size() <= block_maxsize;
+
+    let mut writer = Counting::new(writer);
+    writer.write_all(&canonical_header(flags, block_maxsize, None, dictionary_id)).map_err(Error::OutputError)?;
+    let mut content_hasher = ContentChecksum::new();
+    let mut block = Vec::new();
+    loop {
+        if content_checksum {
+            let mut plaintext = Vec::new();
+            while let Some(compressed) = frame.decode_block_internal(&mut plaintext, dictionary)? {
+                content_hasher.update(&plaintext);
+                let (data, length) = if compressed {
+                    (frame.raw_block(), frame.raw_block().len() as u32)
+                } else if flags.block_checksums() {
+                    (frame.raw_block(), frame.raw_block().len() as u32 | INCOMPRESSIBLE)
+                } else {
+                    (&plaintext[..], plaintext.len() as u32 | INCOMPRESSIBLE)
+                };
+                write_block(&mut writer, length, data, flags.block_checksums()).map_err(Error::OutputError)?;
+                plaintext.clear();
+            }
+        } else {
+            // the block checksum (if any) is part of the block here
+            while let Some(length) = frame.read_raw_block(&mut block)? {
+                write_block(&mut writer, length, &block, false).map_err(Error::OutputError)?;
+            }
+        }
+
+        frame = match frames.next() {
+            Some(next) => LZ4FrameReader::new(next)?,
+            None => break,
+        };
+        if !compatible(&frame) {
+            throw!(Error::IncompatibleFrames);
+        }
+    }
+
+    writer.write_u32::<LE>(0).map_err(Error::OutputError)?;
+    if content_checksum {
+        writer.write_u32::<LE>(content_hasher.finish()).map_err(Error::OutputError)?;
+    }
+    writer.count
+}
+
+#[throws(io::Error)]
+fn write_block<W: Write>(mut writer: W, length: u32, data: &[u8], checksum: bool) {
+    writer.write_u32::<LE>(length)?;
+    writer.write_all(data)?;
+    if checksum {
+        writer.write_u32::<LE>(block_checksum(data))?;
+    }
+}
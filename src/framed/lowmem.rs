@@ -0,0 +1,179 @@
+use byteorder::{LE, ReadBytesExt};
+use std::cmp;
+use std::hash::Hasher;
+use std::io::{self, Read, ErrorKind};
+use twox_hash::XxHash32;
+use culpa::{throw, throws};
+
+use super::{DecompressionError, WINDOW_SIZE};
+use crate::raw::DecodeError;
+
+type Error = DecompressionError;
+
+/// How much we copy at once (for literals and matches alike).
+const CHUNK_SIZE: usize = 4 * 1024;
+
+/// The last 64 KiB of output in a ring buffer, which is all that matches can ever refer to.
+pub(crate) struct RingWindow {
+    buf: Box<[u8]>,
+    /// Where the next byte goes.
+    pos: usize,
+    /// How many bytes of history there are (at most `WINDOW_SIZE`).
+    len: usize,
+}
+impl RingWindow {
+    pub(crate) fn new(history: &[u8]) -> Self {
+        let mut window = RingWindow { buf: vec![0; WINDOW_SIZE].into_boxed_slice(), pos: 0, len: 0 };
+        window.reset(history);
+        window
+    }
+
+    /// Forget everything and start over with `history` (only the last 64 KiB of it matter).
+    pub(crate) fn reset(&mut self, history: &[u8]) {
+        self.pos = 0;
+        self.len = 0;
+        self.push(&history[history.len().saturating_sub(WINDOW_SIZE)..]);
+    }
+
+    fn push(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = cmp::min(data.len(), WINDOW_SIZE - self.pos);
+            self.buf[self.pos..self.pos + len].copy_from_slice(&data[..len]);
+            self.pos = (self.pos + len) % WINDOW_SIZE;
+            self.len = cmp::min(self.len + len, WINDOW_SIZE);
+            data = &data[len..];
+        }
+    }
+
+    /// Fill `chunk` with the bytes that start `offset` bytes back (so `chunk` must not be larger than `offset`).
+    fn copy_from_history(&self, offset: usize, chunk: &mut [u8]) {
+        let mut start = (self.pos + WINDOW_SIZE - offset) % WINDOW_SIZE;
+        let mut chunk = chunk;
+        while !chunk.is_empty() {
+            let len = cmp::min(chunk.len(), WINDOW_SIZE - start);
+            chunk[..len].copy_from_slice(&self.buf[start..start + len]);
+            start = (start + len) % WINDOW_SIZE;
+            chunk = &mut chunk[len..];
+        }
+    }
+}
+
+/// Decodes a single compressed block from `input` (which must end exactly where the block ends),
+/// passing the output to `sink` in small pieces as it goes. Returns the decompressed size.
+///
+/// Nothing but `window` and a small buffer on the stack is needed, no matter how large the block is.
+#[throws]
+pub(crate) fn decode_block<R: Read>(mut input: io::Take<R>, window: &mut RingWindow, output_limit: usize,
+                                    mut sink: impl FnMut(&[u8]) -> io::Result<()>) -> usize {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut written = 0;
+    let mut emit = |window: &mut RingWindow, data: &[u8], written: &mut usize| -> Result<(), Error> {
+        *written += data.len();
+        if *written > output_limit {
+            throw!(Error::BlockSizeOverflow);
+        }
+        window.push(data);
+        sink(data).map_err(Error::OutputError)
+    };
+
+    // like decompress_raw, we accept blocks that end with a match
+    while input.limit() > 0 {
+        let token = read_u8(&mut input)?;
+        let literal_len = read_len(&mut input, token >> 4)?;
+        let mut remaining = literal_len;
+        while remaining > 0 {
+            let len = cmp::min(remaining, chunk.len());
+            input.read_exact(&mut chunk[..len]).map_err(codec_error)?;
+            emit(window, &chunk[..len], &mut written)?;
+            remaining -= len;
+        }
+
+        // the last sequence has no match
+        if input.limit() == 0 {
+            break;
+        }
+
+        let offset = usize::from(input.read_u16::<LE>().map_err(codec_error)?);
+        if offset == 0 {
+            throw!(Error::CodecError(DecodeError::ZeroDeduplicationOffset));
+        }
+        if offset > window.len {
+            throw!(Error::CodecError(DecodeError::InvalidDeduplicationOffset));
+        }
+        let mut remaining = read_len(&mut input, token & 0xf)? + 4;
+        while remaining > 0 {
+            // never copy more than `offset` at once, so everything we copy is already in the window
+            let len = cmp::min(remaining, cmp::min(offset, chunk.len()));
+            window.copy_from_history(offset, &mut chunk[..len]);
+            emit(window, &chunk[..len], &mut written)?;
+            remaining -= len;
+        }
+    }
+    written
+}
+
+#[throws]
+fn read_u8<R: Read>(input: &mut io::Take<R>) -> u8 {
+    input.read_u8().map_err(codec_error)?
+}
+
+/// Reads the rest of a literal or match length, whose first four bits came from the token.
+#[throws]
+fn read_len<R: Read>(input: &mut io::Take<R>, initial: u8) -> usize {
+    let mut len = usize::from(initial);
+    if initial == 0xf {
+        loop {
+            let extra = read_u8(input)?;
+            len = len.checked_add(extra.into()).ok_or(Error::CodecError(DecodeError::MemoryLimitExceeded))?;
+            if extra != 0xff {
+                break;
+            }
+        }
+    }
+    len
+}
+
+/// The block ended before it should have, or the underlying reader failed.
+fn codec_error(e: io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::UnexpectedEof => Error::CodecError(DecodeError::UnexpectedEnd),
+        _ => Error::InputError(e),
+    }
+}
+
+/// Copies a stored block from `input` (which must end exactly where the block ends) to `sink`. Returns its size.
+#[throws]
+pub(crate) fn copy_block<R: Read>(mut input: io::Take<R>, window: &mut RingWindow,
+                                  mut sink: impl FnMut(&[u8]) -> io::Result<()>) -> usize {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut written = 0;
+    loop {
+        let len = input.read(&mut chunk).map_err(Error::InputError)?;
+        if len == 0 {
+            break;
+        }
+        window.push(&chunk[..len]);
+        sink(&chunk[..len]).map_err(Error::OutputError)?;
+        written += len;
+    }
+    if input.limit() > 0 {
+        throw!(Error::InputError(ErrorKind::UnexpectedEof.into()));
+    }
+    written
+}
+
+/// Passes everything through while computing a block checksum on the side (if there is one).
+pub(crate) struct Hashing<R> {
+    pub(crate) inner: R,
+    pub(crate) hasher: Option<XxHash32>,
+}
+impl<R: Read> Read for Hashing<R> {
+    #[throws(io::Error)]
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = self.inner.read(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.write(&buf[..len]);
+        }
+        len
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+/// Hooks that the framed compressor and decompressor call into as they make progress.
+///
+/// This is intended for wiring lz-fear into whatever monitoring system you use
+/// (Prometheus, statsd, ...) without writing counting wrappers around every reader and writer.
+/// All methods have empty default implementations, so you only need to implement the ones you care about.
+///
+/// "In" and "out" are always from the perspective of the operation:
+/// When compressing, bytes in are plaintext and bytes out are compressed (including framing overhead).
+/// When decompressing, it's the other way around.
+pub trait Metrics {
+    /// Called whenever bytes are consumed.
+    fn bytes_in(&self, _bytes: u64) {}
+    /// Called whenever bytes are produced.
+    fn bytes_out(&self, _bytes: u64) {}
+    /// Called for every block that was written or read, regardless of whether it was compressed or stored.
+    fn block_emitted(&self) {}
+    /// Called for every block that was stored uncompressed (in addition to `block_emitted`).
+    fn block_stored(&self) {}
+    /// Called with the time spent computing block and content checksums.
+    fn checksum_time(&self, _elapsed: Duration) {}
+    /// Called with the time spent in a stage of the pipeline, see `Stage`.
+    ///
+    /// This is called a lot (several times per block), so keep it cheap.
+    fn stage_time(&self, _stage: Stage, _elapsed: Duration) {}
+}
+
+/// Where the framed compressor and decompressor spend their time (see `Metrics::stage_time`).
+///
+/// If `Read` and `Write` dominate, you're IO-bound (or your reader/writer is unbuffered). If it's `Compress` or
+/// `Decompress`, you're CPU-bound, and more threads or a faster acceleration might help.
+/// Finding matches and serializing them into sequences happen in the same loop, so there's no telling them apart:
+/// both are `Compress`. The low-memory `decode_to_writer` interleaves everything at a much finer grain,
+/// so it only reports `Checksum`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Stage {
+    /// Waiting for the reader you gave us.
+    Read,
+    /// Finding matches and encoding a block.
+    Compress,
+    /// Decoding a block.
+    Decompress,
+    /// Computing block and content checksums (the same time that `Metrics::checksum_time` reports).
+    Checksum,
+    /// Waiting for the writer you gave us.
+    Write,
+}
+
+/// Runs `f` and reports its duration as checksum time, but only if anyone is listening.
+pub(crate) fn time_checksum<M: Metrics + ?Sized, T>(metrics: Option<&M>, f: impl FnOnce() -> T) -> T {
+    match metrics {
+        Some(metrics) => {
+            let start = Instant::now();
+            let result = f();
+            let elapsed = start.elapsed();
+            metrics.checksum_time(elapsed);
+            metrics.stage_time(Stage::Checksum, elapsed);
+            result
+        }
+        None => f(),
+    }
+}
+
+/// Runs `f` and reports its duration as time spent in `stage`, but only if anyone is listening.
+pub(crate) fn time_stage<M: Metrics + ?Sized, T>(metrics: Option<&M>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    match metrics {
+        Some(metrics) => {
+            let start = Instant::now();
+            let result = f();
+            metrics.stage_time(stage, start.elapsed());
+            result
+        }
+        None => f(),
+    }
+}
+
+/// A reader or writer that reports the time spent in every call as `stage`.
+pub(crate) struct Timed<'m, T> {
+    inner: T,
+    metrics: Option<&'m (dyn Metrics + Send + Sync)>,
+    stage: Stage,
+}
+impl<'m, T> Timed<'m, T> {
+    pub(crate) fn new(inner: T, metrics: Option<&'m (dyn Metrics + Send + Sync)>, stage: Stage) -> Self {
+        Timed { inner, metrics, stage }
+    }
+}
+impl<T: Read> Read for Timed<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        time_stage(self.metrics, self.stage, || self.inner.read(buf))
+    }
+}
+impl<T: Write> Write for Timed<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        time_stage(self.metrics, self.stage, || self.inner.write(buf))
+    }
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        time_stage(self.metrics, self.stage, || self.inner.write_all(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        time_stage(self.metrics, self.stage, || self.inner.flush())
+    }
+}
@@ -8,18 +8,88 @@
 //! See `CompressionSettings` for the features and flexibility that the format offers.
 
 
+#[cfg(feature = "async-futures")]
+mod async_io;
+#[cfg(feature = "async-futures")]
+mod async_parallel;
+mod batch;
+mod buffered;
+mod cancel;
+mod checksum;
+mod chunker;
+mod compare;
 mod compress;
+mod decoder;
 mod decompress;
-mod header;
+mod dictionary;
+mod file;
+#[cfg(feature = "arbitrary")]
+mod fuzzing;
+mod gear;
+pub(crate) mod header;
+#[cfg(feature = "http")]
+mod http;
+mod join;
+mod lowmem;
+mod metrics;
+mod nonblocking;
+mod pages;
+mod records;
+mod rolling;
+mod sparse;
+mod spawn;
+mod tee;
+mod warnings;
+mod yielding;
 
 /// The four magic bytes at the start of every LZ4 frame (little endian).
 pub const MAGIC: u32 = 0x184D2204;
+/// Skippable frames start with any magic number in 0x184D2A50..=0x184D2A5F, followed by a 32-bit length.
+pub const SKIPPABLE_MAGIC: u32 = 0x184D2A50;
+/// Mask to apply to a magic number before comparing it against `SKIPPABLE_MAGIC`.
+pub const SKIPPABLE_MAGIC_MASK: u32 = 0xFFFFFFF0;
 /// The frame format sets the high bit of every length field to indicate that the data was not compressed.
-const INCOMPRESSIBLE: u32 = 1 << 31;
+pub(crate) const INCOMPRESSIBLE: u32 = 1 << 31;
 /// The LZ4 raw format maintains a lookback window of exactly 64KiB.
 pub const WINDOW_SIZE: usize = 64 * 1024;
 
+/// The most memory that compressing or decompressing a stream with blocks of `block_size` bytes takes in bounded-memory mode
+/// (see `CompressionSettings::bounded_memory` and `LZ4FrameReader::with_memory_limit`).
+///
+/// That's two blocks (one going in and one coming out), the 64 KiB window and another 64 KiB for hash tables and
+/// bookkeeping. It doesn't include your dictionary or whatever the underlying reader and writer allocate.
+pub const fn memory_bound(block_size: usize) -> usize {
+    2 * block_size + 2 * WINDOW_SIZE
+}
 
+
+#[cfg(feature = "async-futures")]
+pub use async_io::*;
+pub use batch::*;
+pub use cancel::*;
+pub use checksum::*;
+pub use chunker::*;
+pub use compare::*;
 pub use compress::*;
+pub use decoder::*;
 pub use decompress::*;
+pub use dictionary::*;
+pub use file::*;
+#[cfg(feature = "arbitrary")]
+pub use fuzzing::*;
+#[cfg(feature = "http")]
+pub use http::*;
+pub use join::*;
+pub(crate) use file::Counting;
+pub use header::Flags;
+pub use metrics::{Metrics, Stage};
+pub use nonblocking::*;
+pub use pages::*;
+pub use records::*;
+pub use rolling::*;
+pub use sparse::*;
+pub use spawn::*;
+pub use tee::*;
+pub use warnings::{Warning, WarningHook};
+pub use yielding::*;
 
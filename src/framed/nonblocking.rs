@@ -0,0 +1,139 @@
+use std::io::{self, ErrorKind, Read, Write};
+use culpa::{throw, throws};
+
+use super::{CompressionError, CompressionSettings};
+use super::buffered::{FrameInput, FrameOutput};
+
+type Error = io::Error;
+
+/// Decompresses a single LZ4 frame from a non-blocking reader (e.g. a socket in non-blocking mode).
+///
+/// `LZ4FrameIoReader` gives up for good as soon as the underlying reader returns an error.
+/// This one keeps all of its state instead, so when the underlying reader says `WouldBlock`
+/// (or `Interrupted`), you get that error and can simply call `read` again once there is more input.
+///
+/// We read ahead in chunks, so the underlying reader may have been advanced past the end of the frame.
+pub struct NonBlockingFrameReader<'a, R: Read> {
+    reader: R,
+    input: FrameInput<'a>,
+}
+
+impl<R: Read> NonBlockingFrameReader<'static, R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_dictionary(reader, &[])
+    }
+}
+
+impl<'a, R: Read> NonBlockingFrameReader<'a, R> {
+    /// Decompress a frame that was compressed with this dictionary.
+    pub fn with_dictionary(reader: R, dictionary: &'a [u8]) -> Self {
+        NonBlockingFrameReader { reader, input: FrameInput::new(dictionary) }
+    }
+
+    /// Whether we have reached the end of the frame.
+    pub fn is_done(&self) -> bool {
+        self.input.is_done()
+    }
+
+    /// Get a reference to the underlying reader (e.g. to register it with your event loop).
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for NonBlockingFrameReader<'_, R> {
+    #[throws]
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        loop {
+            if let Some(n) = self.input.decode(buf)? {
+                break n;
+            }
+            // WouldBlock ends up here and is passed on to the caller, all of our state stays intact
+            let n = self.reader.read(self.input.spare())?;
+            self.input.filled(n)?;
+        }
+    }
+}
+
+/// Compresses into a non-blocking writer (e.g. a socket in non-blocking mode).
+///
+/// Compressed blocks are produced in memory and written out as the underlying writer accepts them.
+/// If the underlying writer says `WouldBlock`, so do we, and nothing is lost:
+/// `write` doesn't take any new data before the previous block is out, so just retry it later.
+///
+/// Use `close` instead of `LZ4FrameWriter::finish` to end the frame. It also returns `WouldBlock`
+/// until everything is out, so keep calling it until it succeeds.
+pub struct NonBlockingFrameWriter<'a, W: Write> {
+    writer: W,
+    output: FrameOutput<'a>,
+}
+
+impl<'a, W: Write> NonBlockingFrameWriter<'a, W> {
+    /// Start a new frame. The header is only written on the first write (or flush/close).
+    #[throws(CompressionError)]
+    pub fn new(writer: W, settings: &'a CompressionSettings<'a>) -> Self {
+        NonBlockingFrameWriter { writer, output: FrameOutput::new(settings)? }
+    }
+
+    /// Whether there is compressed data waiting for the underlying writer to become ready.
+    pub fn has_pending_output(&self) -> bool {
+        !self.output.pending().is_empty()
+    }
+
+    /// Write the end of the frame and flush.
+    ///
+    /// If this fails with `WouldBlock`, call it again once the underlying writer is ready.
+    /// If the frame itself couldn't be finished (e.g. because of `CompressionSettings::output_limit`),
+    /// this keeps failing.
+    #[throws]
+    pub fn close(&mut self) {
+        self.drain()?;
+        self.output.finish()?;
+        self.drain()?;
+        self.writer.flush()?;
+    }
+
+    /// Get a reference to the underlying writer (e.g. to register it with your event loop).
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Return the underlying writer.
+    ///
+    /// Unless `close` succeeded, the frame is incomplete.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write out everything we have compressed so far.
+    #[throws]
+    fn drain(&mut self) {
+        while !self.output.pending().is_empty() {
+            match self.writer.write(self.output.pending())? {
+                0 => throw!(io::Error::from(ErrorKind::WriteZero)),
+                n => self.output.advance(n),
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for NonBlockingFrameWriter<'_, W> {
+    #[throws]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        // a WouldBlock here never loses any of buf
+        self.drain()?;
+        self.output.write(buf)?
+    }
+
+    #[throws]
+    fn flush(&mut self) {
+        self.output.flush()?;
+        self.drain()?;
+        self.writer.flush()?;
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::{Read, Seek};
+use culpa::{throw, throws};
+
+use super::{DecompressionError, LZ4FrameReader};
+
+type Error = DecompressionError;
+
+/// Reads single pages from a frame of pages (see `CompressionSettings::page_size`), given its offset table.
+///
+/// This only ever reads the header and the blocks you ask for, so getting at a page costs one seek
+/// and decoding a single block, no matter where it is in the frame. The offset table is what
+/// `CompressionSettings::compress_pages` returned (or the `compressed_offset`s of the checkpoints).
+/// Since we never see the whole content, the content checksum isn't checked, but block checksums are.
+pub struct PageReader<R: Read + Seek> {
+    frame: LZ4FrameReader<R>,
+    /// Where the frame starts in the underlying reader.
+    start: u64,
+    offsets: Vec<u64>,
+}
+
+impl<R: Read + Seek> PageReader<R> {
+    /// Read the frame header. `reader` must be positioned at the start of the frame.
+    #[throws]
+    pub fn new(mut reader: R, offsets: Vec<u64>) -> Self {
+        let start = reader.stream_position()?;
+        let frame = LZ4FrameReader::new(reader)?;
+        if !frame.flags().independent_blocks() {
+            throw!(Error::DependentBlocks);
+        }
+        PageReader { frame, start, offsets }
+    }
+
+    /// How many pages there are, according to the offset table.
+    pub fn page_count(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// The reader for the frame, e.g. to look at its header.
+    pub fn frame_reader(&self) -> &LZ4FrameReader<R> {
+        &self.frame
+    }
+
+    /// Decode the `index`th page into `output`, replacing whatever was in there.
+    ///
+    /// The `dictionary` is the one the whole frame was compressed with (if any).
+    #[throws]
+    pub fn read_page(&mut self, index: usize, dictionary: &[u8], output: &mut Vec<u8>) {
+        let offset = *self.offsets.get(index).ok_or(Error::NoSuchPage(index))?;
+        self.frame.seek_to_block(self.start, offset, index == 0)?;
+        output.clear();
+        if self.frame.decode_block_internal(output, dictionary)?.is_none() {
+            // that's the end of the frame, not a page
+            throw!(Error::NoSuchPage(index));
+        }
+    }
+}
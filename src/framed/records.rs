@@ -0,0 +1,127 @@
+use std::io::{BufRead, Read, Write};
+use culpa::{throw, throws};
+
+use super::compress::{end_len, LoneBlocks};
+use super::header::Flags;
+use super::{CompressionError, CompressionSettings, DecompressionError, FrameBuffers, LZ4FrameReader};
+
+/// Writes every record into a frame of its own, with as little overhead as a frame can have.
+///
+/// That's for write-ahead logs and the like, where every record must be readable on its own (and a crash may only ever
+/// lose the record that was being written), but records are so small that a regular frame would be mostly header.
+/// Every frame has the smallest header there is: no content size, no content checksum (whatever the settings say),
+/// and only a dictionary id if you set one. So a record costs 15 bytes on top of its block (7 for the header,
+/// 4 for the block length and 4 for the end mark), plus 4 for every block checksum if you asked for those.
+///
+/// The settings, hash tables and buffers are reused for all records, and every frame goes to the writer
+/// in a single `write_all`. Read them back with `RecordReader`.
+pub struct RecordWriter<'a, W: Write> {
+    writer: W,
+    settings: &'a CompressionSettings<'a>,
+    flags: Flags,
+    blocks: LoneBlocks<'a>,
+    /// The frame for the current record is put together here.
+    frame: Vec<u8>,
+}
+
+impl<'a, W: Write> RecordWriter<'a, W> {
+    #[throws(CompressionError)]
+    pub fn new(writer: W, settings: &'a CompressionSettings<'a>) -> Self {
+        let flags = settings.flags(None) - Flags::ContentChecksum;
+        RecordWriter { writer, settings, flags, blocks: LoneBlocks::new(settings, flags)?, frame: Vec::new() }
+    }
+
+    /// Write `record` as a frame of its own and return how long that frame is.
+    ///
+    /// Records larger than the block size are split into several blocks, each compressed on its own.
+    #[throws(CompressionError)]
+    pub fn write_record(&mut self, record: &[u8]) -> usize {
+        let settings = self.settings;
+        if settings.is_cancelled() {
+            throw!(CompressionError::Cancelled);
+        }
+        self.frame.clear();
+        settings.write_header(self.flags, None, &mut self.frame)?;
+        for (i, block) in record.chunks(settings.block_size).enumerate() {
+            let room = settings.output_limit.map(|limit| limit.saturating_sub(self.frame.len() as u64 + end_len(self.flags)));
+            self.blocks.write(i == 0, block, room, &mut self.frame)?;
+        }
+        settings.write_end(None, &mut self.frame)?;
+        self.writer.write_all(&self.frame)?;
+        self.frame.len()
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Get a mutable reference to the underlying writer, e.g. to flush or sync it.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads records that were written by `RecordWriter` (or any other frames, really), one frame per record.
+///
+/// Unlike creating an `LZ4FrameReader` for every frame, this never allocates once its buffers have grown to the largest
+/// record, and blocks are decoded straight into your buffer. Since we need to tell whether there is another
+/// record at all, the underlying reader must be a `BufRead` (wrap it in a `BufReader`, which you want
+/// for lots of small frames anyway).
+pub struct RecordReader<R: BufRead> {
+    reader: R,
+    buffers: FrameBuffers,
+    /// Where the second and following blocks of a record are decoded to.
+    block: Vec<u8>,
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(reader: R) -> Self {
+        RecordReader { reader, buffers: FrameBuffers::new(), block: Vec::new() }
+    }
+
+    /// Decode the next record into `output` (replacing whatever was in there).
+    ///
+    /// Returns `false` if the input ends before another record starts. A record that is cut off (e.g. because we crashed
+    /// while writing it) fails with an `InputError` instead, and so does anything that isn't a frame.
+    #[throws(DecompressionError)]
+    pub fn read_record(&mut self, dictionary: &[u8], output: &mut Vec<u8>) -> bool {
+        if self.reader.fill_buf()?.is_empty() {
+            return false;
+        }
+        output.clear();
+        let mut frame = LZ4FrameReader::with_buffers(&mut self.reader, &mut self.buffers)?;
+        let result = decode_frame(&mut frame, dictionary, output, &mut self.block);
+        frame.return_buffers(&mut self.buffers);
+        result?;
+        true
+    }
+
+    /// Get a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Decode all blocks of `frame` into `output`, the first one directly and all others through `block`.
+#[throws(DecompressionError)]
+fn decode_frame<R: Read>(frame: &mut LZ4FrameReader<R>, dictionary: &[u8], output: &mut Vec<u8>, block: &mut Vec<u8>) {
+    if frame.decode_block_internal(output, dictionary)?.is_none() {
+        return;
+    }
+    loop {
+        block.clear();
+        if frame.decode_block_internal(block, dictionary)?.is_none() {
+            break;
+        }
+        output.extend_from_slice(block);
+    }
+}
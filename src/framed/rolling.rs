@@ -0,0 +1,133 @@
+use std::io::{self, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+use culpa::throws;
+
+use super::{CompressionError, CompressionSettings, LZ4FrameWriter};
+
+type Error = CompressionError;
+
+/// A writer that splits its output into a sequence of independent frames,
+/// starting a new frame whenever the current one gets too big or too old.
+///
+/// This is what you want for logs: every frame can be decompressed on its own,
+/// so a crash only ever loses the frame that was being written,
+/// and old segments can be shipped or deleted without touching the current one.
+///
+/// By default, all frames are written back to back into the same writer.
+/// Use `on_rotate` to switch to a new writer (e.g. a new file) for every frame.
+///
+/// Note that the thresholds are only checked when you write, so a quiet writer
+/// keeps its current frame open for longer than `max_frame_age`.
+pub struct RollingFrameWriter<'a, W: Write> {
+    settings: &'a CompressionSettings<'a>,
+    max_frame_len: Option<u64>,
+    max_frame_age: Option<Duration>,
+    rotate: Option<Box<dyn FnMut(W) -> io::Result<W> + 'a>>,
+    state: State<'a, W>,
+}
+
+enum State<'a, W: Write> {
+    /// No frame is open right now. We only start one once there is data to put in it.
+    Idle(W),
+    Frame { frame: Box<LZ4FrameWriter<'a, W>>, started: Option<Instant>, len: u64 },
+    /// Something failed while switching frames and we lost the writer, there is nothing we can do anymore.
+    Poisoned,
+}
+
+impl<'a, W: Write> RollingFrameWriter<'a, W> {
+    pub fn new(writer: W, settings: &'a CompressionSettings<'a>) -> Self {
+        RollingFrameWriter { settings, max_frame_len: None, max_frame_age: None, rotate: None, state: State::Idle(writer) }
+    }
+
+    /// Start a new frame once this many (uncompressed) bytes have been written into the current one.
+    pub fn max_frame_len(&mut self, len: u64) -> &mut Self {
+        self.max_frame_len = Some(len.max(1));
+        self
+    }
+
+    /// Start a new frame when writing to a frame that was started longer ago than this.
+    pub fn max_frame_age(&mut self, age: Duration) -> &mut Self {
+        self.max_frame_age = Some(age);
+        self
+    }
+
+    /// Called with the underlying writer whenever a frame is finished, returning the writer for the next frame.
+    pub fn on_rotate(&mut self, rotate: impl FnMut(W) -> io::Result<W> + 'a) -> &mut Self {
+        self.rotate = Some(Box::new(rotate));
+        self
+    }
+
+    /// Finish the current frame now (if there is one) and rotate.
+    #[throws]
+    pub fn roll(&mut self) {
+        self.state = match mem::replace(&mut self.state, State::Poisoned) {
+            State::Frame { frame, .. } => {
+                let writer = frame.finish()?;
+                State::Idle(match self.rotate.as_mut() {
+                    Some(rotate) => rotate(writer)?,
+                    None => writer,
+                })
+            }
+            other => other,
+        };
+    }
+
+    /// Finish the current frame (without rotating) and return the underlying writer.
+    #[throws]
+    pub fn finish(self) -> W {
+        match self.state {
+            State::Idle(writer) => writer,
+            State::Frame { frame, .. } => frame.finish()?,
+            State::Poisoned => poisoned()?,
+        }
+    }
+
+    fn is_due(&self, started: Option<Instant>, len: u64) -> bool {
+        self.max_frame_len.is_some_and(|max| len >= max)
+            || self.max_frame_age.zip(started).is_some_and(|(max, started)| started.elapsed() >= max)
+    }
+}
+
+fn poisoned<T>() -> io::Result<T> {
+    Err(io::Error::other("an earlier attempt to start a new frame failed"))
+}
+
+impl<W: Write> Write for RollingFrameWriter<'_, W> {
+    #[throws(io::Error)]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        if let State::Frame { started, len, .. } = self.state {
+            if self.is_due(started, len) {
+                self.roll()?;
+            }
+        }
+        self.state = match mem::replace(&mut self.state, State::Poisoned) {
+            State::Idle(writer) => State::Frame {
+                frame: Box::new(LZ4FrameWriter::new(writer, self.settings)?),
+                // Instant::now() panics on wasm32-unknown-unknown, so only touch the clock if we need it
+                started: self.max_frame_age.map(|_| Instant::now()),
+                len: 0,
+            },
+            other => other,
+        };
+
+        match &mut self.state {
+            State::Frame { frame, len, .. } => {
+                let remaining = self.max_frame_len.map_or(u64::MAX, |max| max - *len);
+                let n = frame.write(&buf[..buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX))])?;
+                *len += n as u64;
+                n
+            }
+            _ => poisoned()?,
+        }
+    }
+
+    #[throws(io::Error)]
+    fn flush(&mut self) {
+        match &mut self.state {
+            State::Idle(writer) => writer.flush()?,
+            State::Frame { frame, .. } => frame.flush()?,
+            State::Poisoned => poisoned()?,
+        }
+    }
+}
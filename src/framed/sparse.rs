@@ -0,0 +1,120 @@
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write, ErrorKind};
+use culpa::{throw, throws};
+
+type Error = io::Error;
+
+/// A writer that seeks over long runs of zeros instead of writing them, which makes for sparse files.
+///
+/// Disk and VM images are mostly zeros, and so is their decompressed content. Put this around the output
+/// file (e.g. `decompress_pipe(input, &mut SparseWriter::new(file), &[])`) and those zeros never hit the disk.
+/// Only runs of at least `min_run` zeros are skipped, shorter ones are written like everything else.
+///
+/// Seeking over a region only leaves zeros behind if there was nothing there before, so the writer should be
+/// at the end of a new (or freshly truncated) file. Zeros at the end are only taken care of by `flush`
+/// (which writes the very last byte, so the file gets its full length), so don't forget to call it.
+pub struct SparseWriter<W> {
+    writer: W,
+    min_run: usize,
+    /// Zeros that were written to us, but not yet passed on.
+    pending: u64,
+    skipped: u64,
+}
+
+impl<W: Write + Seek> SparseWriter<W> {
+    pub fn new(writer: W) -> Self {
+        SparseWriter { writer, min_run: 4096, pending: 0, skipped: 0 }
+    }
+
+    /// How many zeros in a row it takes for us to skip them (4 KiB by default, the block size of most filesystems).
+    pub fn min_run(&mut self, len: usize) -> &mut Self {
+        self.min_run = len.max(1);
+        self
+    }
+
+    /// How many bytes were skipped instead of written so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Flush and return the underlying writer.
+    #[throws]
+    pub fn into_inner(mut self) -> W {
+        self.flush()?;
+        self.writer
+    }
+
+    /// Where the data in `buf` ends: at the first run of zeros that is long enough to skip,
+    /// or at the trailing zeros (which might turn out to be the start of a long run).
+    fn data_len(&self, buf: &[u8]) -> usize {
+        let mut zeros = 0;
+        for (i, &byte) in buf.iter().enumerate() {
+            if byte != 0 {
+                zeros = 0;
+            } else {
+                zeros += 1;
+                if zeros == self.min_run {
+                    return i + 1 - zeros;
+                }
+            }
+        }
+        buf.len() - zeros
+    }
+
+    /// Pass on the pending zeros, except for `keep` of them.
+    #[throws]
+    fn settle(&mut self, keep: u64) {
+        let zeros = self.pending - keep;
+        if zeros >= self.min_run as u64 {
+            let offset = i64::try_from(zeros).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "too many zeros to seek over"))?;
+            self.writer.seek(SeekFrom::Current(offset))?;
+            self.skipped += zeros;
+        } else if io::copy(&mut io::repeat(0).take(zeros), &mut self.writer)? != zeros {
+            throw!(io::Error::from(ErrorKind::WriteZero));
+        }
+        self.pending = keep;
+    }
+}
+
+impl<W: Write + Seek> Write for SparseWriter<W> {
+    #[throws]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let mut rest = buf;
+        loop {
+            let zeros = rest.iter().position(|&byte| byte != 0).unwrap_or(rest.len());
+            self.pending += zeros as u64;
+            rest = &rest[zeros..];
+            if rest.is_empty() {
+                break;
+            }
+            let len = self.data_len(rest);
+            self.settle(0)?;
+            self.writer.write_all(&rest[..len])?;
+            rest = &rest[len..];
+        }
+        buf.len()
+    }
+
+    #[throws]
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            // seeking doesn't make the file any longer, writing the last zero does
+            self.settle(1)?;
+            self.writer.write_all(&[0])?;
+            self.pending = 0;
+        }
+        self.writer.flush()?;
+    }
+}
+
+impl<W> fmt::Debug for SparseWriter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SparseWriter").field("min_run", &self.min_run).field("pending", &self.pending)
+            .field("skipped", &self.skipped).finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,25 @@
+/// Runs tasks on threads that you manage, so we don't start threads of our own.
+///
+/// If your application already sizes its thread pools, hand us one of them and our parallel modes
+/// (see `AsyncLZ4FrameReader::set_spawner`) stay within your limits. Every task is short (it decodes a single block)
+/// and never blocks, so a pool for CPU-bound work is the right place for it. Don't run the task right away on the
+/// calling thread, though: that's usually an async task, which is exactly what we're trying not to hold up.
+///
+/// With the `rayon` feature (which `mmap` enables, too), a `rayon::ThreadPool` is a `Spawn`.
+/// So is any function that takes a task, e.g. `|task| drop(tokio::task::spawn_blocking(task))`.
+pub trait Spawn: Send + Sync {
+    fn spawn(&self, task: Box<dyn FnOnce() + Send>);
+}
+
+impl<F: Fn(Box<dyn FnOnce() + Send>) + Send + Sync> Spawn for F {
+    fn spawn(&self, task: Box<dyn FnOnce() + Send>) {
+        self(task)
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl Spawn for rayon::ThreadPool {
+    fn spawn(&self, task: Box<dyn FnOnce() + Send>) {
+        rayon::ThreadPool::spawn(self, task)
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+use std::io::{self, Write};
+use culpa::throws;
+
+type Error = io::Error;
+
+type Observer<'a> = Box<dyn FnMut(&[u8]) + 'a>;
+
+/// A writer that passes everything on to another writer and shows it to any number of observers on the way.
+///
+/// This is how you compute e.g. a SHA-256 of the decompressed data for an integrity manifest while it's being
+/// written out (`LZ4FrameIoReader::copy_to` and `LZ4FrameReader::decode_to_writer` take any writer),
+/// instead of reading the output a second time. Observers only ever see bytes that the inner writer accepted,
+/// so they always agree with what actually ended up in the output, even if a write fails halfway.
+pub struct Tee<'a, W> {
+    writer: W,
+    observers: Vec<Observer<'a>>,
+    written: u64,
+}
+
+impl<'a, W: Write> Tee<'a, W> {
+    pub fn new(writer: W) -> Self {
+        Tee { writer, observers: Vec::new(), written: 0 }
+    }
+
+    /// Call `observer` with every chunk of data that was written, in order.
+    pub fn observe(&mut self, observer: impl FnMut(&[u8]) + 'a) -> &mut Self {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// How many bytes were written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for Tee<'_, W> {
+    #[throws]
+    fn write(&mut self, buf: &[u8]) -> usize {
+        let len = self.writer.write(buf)?;
+        for observer in &mut self.observers {
+            observer(&buf[..len]);
+        }
+        self.written += len as u64;
+        len
+    }
+
+    #[throws]
+    fn flush(&mut self) {
+        self.writer.flush()?;
+    }
+}
+
+impl<W> fmt::Debug for Tee<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tee").field("observers", &self.observers.len()).field("written", &self.written).finish_non_exhaustive()
+    }
+}
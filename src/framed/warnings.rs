@@ -0,0 +1,87 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::raw::{self, EncoderTable, U16Table, U32Table, DEFAULT_ACCELERATION};
+
+/// Something about a frame that isn't wrong, but suspicious enough that you may want to know about it.
+///
+/// None of these stop decompression, the frame decodes just fine. They usually point at a bug or a misconfiguration
+/// in whatever wrote the frame, or in how you're reading it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Warning {
+    /// The frame says it was compressed with this dictionary, but we're decoding it without one.
+    ///
+    /// That's fine if the compressor set the id without using the dictionary, but if it did use it, you'll get garbage
+    /// (or an error, if you're lucky).
+    MissingDictionary(u32),
+    /// The header doesn't say how large the content is, so nobody can preallocate or check it.
+    NoContentSize,
+    /// A block that isn't the last one decoded to less than the block size that the header claims.
+    ///
+    /// The format allows that, but encoders don't do it, so this might be a frame glued together from pieces.
+    ShortBlock {
+        /// Where the block starts in the decompressed content.
+        offset: u64,
+        /// What it decoded to.
+        len: usize,
+    },
+    /// A block was stored uncompressed, even though compressing it would have made it smaller.
+    CompressibleStoredBlock {
+        /// Where the block starts in the decompressed content.
+        offset: u64,
+        len: usize,
+        /// How large the block is when compressed on its own (and it might have been even smaller with the preceding blocks).
+        compressed_len: usize,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::MissingDictionary(id) => write!(f, "the frame needs dictionary {:08x}, but we don't have any", id),
+            Warning::NoContentSize => write!(f, "the frame doesn't say how large its content is"),
+            Warning::ShortBlock { offset, len } => write!(f, "the block at {} is only {} bytes, but isn't the last one", offset, len),
+            Warning::CompressibleStoredBlock { offset, len, compressed_len } =>
+                write!(f, "the block at {} was stored as {} bytes, but compresses to {}", offset, len, compressed_len),
+        }
+    }
+}
+
+/// Gets told about every `Warning` while we decompress (see `LZ4FrameReader::set_warning_hook`).
+///
+/// Unless you set one of these, we don't even look for anything suspicious, so it costs nothing.
+/// Checking whether stored blocks would have compressed means compressing them, so that's not free.
+#[derive(Clone)]
+pub struct WarningHook {
+    hook: Arc<dyn Fn(Warning) + Send + Sync>,
+}
+impl WarningHook {
+    pub fn new(hook: impl Fn(Warning) + Send + Sync + 'static) -> Self {
+        WarningHook { hook: Arc::new(hook) }
+    }
+
+    /// Collect all warnings in a list instead of handling them as they come in.
+    pub fn collect_into(list: Arc<Mutex<Vec<Warning>>>) -> Self {
+        WarningHook::new(move |warning| list.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(warning))
+    }
+
+    pub(crate) fn warn(&self, warning: Warning) {
+        (self.hook)(warning)
+    }
+}
+impl fmt::Debug for WarningHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarningHook").finish_non_exhaustive()
+    }
+}
+
+/// How large `block` gets when compressed on its own, if that's smaller than storing it.
+pub(crate) fn compressed_len(block: &[u8]) -> Option<usize> {
+    let limit = block.len().checked_sub(1)?;
+    if block.len() <= U16Table::default().payload_size_limit() {
+        raw::compressed_len(block, 0, &mut U16Table::default(), DEFAULT_ACCELERATION, None, limit)
+    } else {
+        raw::compressed_len(block, 0, &mut U32Table::default(), DEFAULT_ACCELERATION, None, limit)
+    }.ok()
+}
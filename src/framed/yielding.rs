@@ -0,0 +1,54 @@
+use std::fmt;
+use std::sync::Arc;
+
+/// Calls a function every so often while we compress or decompress, so you can yield to your executor.
+///
+/// Compressing a 4 MiB block takes a while, and if you do it inline in a task on a single-threaded runtime,
+/// nothing else gets to run in the meantime. The hook is called from the middle of a block, roughly every `every`
+/// bytes (of input when compressing, of output when decompressing), which is your chance to run other tasks,
+/// pump an event loop, or at least notice that you're hogging the thread.
+///
+/// The hook is only called between two sequences of a block, so a single huge match or literal run can exceed `every`.
+#[derive(Clone)]
+pub struct YieldHook {
+    every: usize,
+    hook: Arc<dyn Fn() + Send + Sync>,
+}
+impl YieldHook {
+    pub fn new(every: usize, hook: impl Fn() + Send + Sync + 'static) -> Self {
+        YieldHook { every: every.max(1), hook: Arc::new(hook) }
+    }
+}
+impl fmt::Debug for YieldHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("YieldHook").field("every", &self.every).finish_non_exhaustive()
+    }
+}
+
+/// Keeps track of how many bytes were processed since the hook was last called.
+#[derive(Clone, Debug)]
+pub(crate) struct YieldCounter {
+    hook: YieldHook,
+    pending: usize,
+}
+impl YieldCounter {
+    pub(crate) fn new(hook: YieldHook) -> Self {
+        YieldCounter { hook, pending: 0 }
+    }
+
+    pub(crate) fn advance(&mut self, bytes: usize) {
+        self.pending += bytes;
+        if self.pending >= self.hook.every {
+            self.pending %= self.hook.every;
+            (self.hook.hook)();
+        }
+    }
+
+    /// Returns a callback for the raw codec, which reports absolute positions that start at `start`.
+    pub(crate) fn progress(&mut self, mut start: usize) -> impl FnMut(usize) + '_ {
+        move |position| {
+            self.advance(position - start);
+            start = position;
+        }
+    }
+}
@@ -1,5 +1,7 @@
-#![forbid(unsafe_code)]
-//! A fast pure-rust no-unsafe implementation of LZ4 compression and decompression.
+#![cfg_attr(not(any(feature = "ffi", feature = "mmap")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "ffi", feature = "mmap"), deny(unsafe_code))]
+//! A fast pure-rust implementation of LZ4 compression and decompression,
+//! with no unsafe code except behind the `ffi` and `mmap` features.
 //!
 //! Hey you! Yes you! Are you unhappy with these docs?
 //! Would you like to see more examples?
@@ -9,8 +11,20 @@
 
 pub mod raw;
 pub mod framed;
+pub mod analysis;
+pub mod archive;
+pub mod mux;
+pub mod lz4net;
+pub mod validate;
+#[cfg(feature = "self-test")]
+pub mod self_test;
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+pub mod ffi;
 
 pub use framed::{LZ4FrameReader, CompressionSettings};
+#[cfg(feature = "self-test")]
+pub use self_test::self_test;
 
 
 
@@ -18,22 +32,13 @@ pub use framed::{LZ4FrameReader, CompressionSettings};
 #[cfg(test)]
 mod tests {
     use std::str;
-    use crate::raw::compress2;
+    use crate::raw::{compress_block, compress_independent_block as compress, compress_into, compress_to_vec, decompress_raw, OutputTooSmall};
     use crate::raw::test::decompress;
 
-    fn compress(input: &[u8]) -> Vec<u8> {
-        let mut buf = Vec::new();
-        if input.len() <= 0xFFFF {
-            compress2(input, 0, &mut crate::raw::U16Table::default(), &mut buf).unwrap();
-        } else {
-            compress2(input, 0, &mut crate::raw::U32Table::default(), &mut buf).unwrap();
-        }
-        buf
-    }
-
     /// Test that the compressed string decompresses to the original string.
     fn inverse(s: &str) {
         let compressed = compress(s.as_bytes());
+        assert_eq!(crate::raw::check_conformance(&compressed, 0), []);
         println!("Compressed '{}' into {:?}", s, compressed);
         let decompressed = decompress(&compressed).unwrap();
         println!("Decompressed it into {:?}", str::from_utf8(&decompressed).unwrap());
@@ -94,6 +99,54 @@ mod tests {
         assert!(compress(s.as_bytes()).len() < s.len());
     }
 
+    #[test]
+    fn prefix() {
+        let prefix = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day.";
+        let s = b"The average panda eats as much bamboo as it can.";
+        let compressed = compress_block(s, prefix);
+        assert!(compressed.len() < compress(s).len());
+        let mut decompressed = Vec::new();
+        decompress_raw(&compressed, prefix, &mut decompressed, usize::MAX).unwrap();
+        assert_eq!(decompressed, s);
+    }
+
+    #[test]
+    fn into_slice() {
+        let s = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(10);
+        let expected = compress(&s);
+        let mut buf = vec![0; expected.len()];
+        assert_eq!(compress_into(&s, &mut buf), Ok(expected.len()));
+        assert_eq!(buf, expected);
+        assert_eq!(compress_into(&s, &mut buf[..expected.len() - 1]), Err(OutputTooSmall));
+    }
+
+    #[test]
+    fn into_vec() {
+        let prefix = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day.";
+        let s = b"The average panda eats as much bamboo as it can. ".repeat(10);
+        let mut output = b"header".to_vec();
+        let len = compress_to_vec(&s, prefix, &mut output);
+        assert_eq!(&output[..6], b"header");
+        assert_eq!(output[6..], compress_block(&s, prefix));
+        assert_eq!(len, output.len() - 6);
+        let mut decompressed = Vec::new();
+        decompress_raw(&output[6..], prefix, &mut decompressed, usize::MAX).unwrap();
+        assert_eq!(decompressed, s);
+    }
+
+    #[test]
+    fn long_lengths() {
+        // around the places where the length encoding needs another byte (and where the run of 0xFF gets long)
+        let noise: Vec<u8> = (0..2_000_000u64).map(|i| (i.wrapping_mul(6364136223846793005) >> 56) as u8).collect();
+        for len in [14, 15, 16, 15 + 254, 15 + 255, 15 + 256, 15 + 4 * 255, 15 + 4096 * 255, 15 + 4097 * 255 + 1] {
+            // a long literal followed by a long match
+            let s = [&noise[..len], &vec![0; len + 20][..]].concat();
+            let compressed = compress(&s);
+            assert_eq!(crate::raw::check_conformance(&compressed, 0), []);
+            assert!(decompress(&compressed).unwrap() == s);
+        }
+    }
+
     #[test]
     fn big_compression() {
         let mut s = Vec::with_capacity(80_000000);
@@ -0,0 +1,260 @@
+//! The chunked stream format of the old lz4net library (`LZ4Stream` in .NET), which is not an LZ4 frame at all.
+//!
+//! A lot of Windows tooling still produces it, so this lets you read (and write) it. The stream is just a sequence
+//! of chunks with no header and no end mark; every chunk is a raw LZ4 block of its own (or stored, if compressing
+//! didn't help). There are no checksums, so corruption is only noticed if it breaks the block.
+//!
+//! Layout of a chunk, where every number is a varint (7 bits at a time, least significant first,
+//! with the high bit set on every byte but the last):
+//!
+//! ```text
+//! flags (1 = compressed, 2 = compressed with the high compression codec, (passes - 1) << 2)
+//! uncompressed length
+//! compressed length (only if compressed)
+//! the data
+//! ```
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io::{self, BufRead, ErrorKind, Read, Write};
+use thiserror::Error;
+use culpa::{throw, throws};
+
+use crate::raw;
+
+/// lz4net writes chunks of 1 MiB unless you tell it otherwise.
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+/// lz4net keeps lengths in an `int`, so nothing larger can come from there.
+const MAX_CHUNK_SIZE: u64 = i32::MAX as u64;
+
+const COMPRESSED: u64 = 1;
+const HIGH_COMPRESSION: u64 = 2;
+
+/// Errors when reading an lz4net stream.
+#[derive(Error, Debug)]
+pub enum LZ4NetError {
+    #[error("error reading from the input you gave me")]
+    Io(#[from] io::Error),
+    #[error("the raw LZ4 decompression failed (data corruption?)")]
+    CodecError(#[from] raw::DecodeError),
+    #[error("a chunk was compressed in several passes, which lz4net itself can't read either")]
+    MultiplePasses,
+    #[error("a chunk header is corrupted")]
+    CorruptChunk,
+}
+type Error = LZ4NetError; // do it this way for better docs
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Decompresses an lz4net stream.
+///
+/// This implements `Read` and `BufRead`. The stream simply ends where the input ends.
+pub struct LZ4NetReader<R: Read> {
+    reader: R,
+    /// The current chunk, decompressed.
+    buffer: Vec<u8>,
+    pos: usize,
+    /// The current chunk as it was in the stream.
+    chunk: Vec<u8>,
+}
+
+impl<R: Read> LZ4NetReader<R> {
+    pub fn new(reader: R) -> Self {
+        LZ4NetReader { reader, buffer: Vec::new(), pos: 0, chunk: Vec::new() }
+    }
+
+    /// Decompress the next chunk into `buffer`. Returns `false` at the end of the input.
+    #[throws]
+    fn next_chunk(&mut self) -> bool {
+        let flags = match read_varint(&mut self.reader)? {
+            Some(flags) => flags,
+            None => return false,
+        };
+        if flags > (COMPRESSED | HIGH_COMPRESSION) {
+            throw!(Error::MultiplePasses);
+        }
+        let len = read_length(&mut self.reader)?;
+        let compressed_len = if flags & COMPRESSED != 0 { read_length(&mut self.reader)? } else { len };
+        if compressed_len > len {
+            throw!(Error::CorruptChunk);
+        }
+
+        // the lengths could be garbage, so we don't allocate anything up front
+        self.chunk.clear();
+        if self.reader.by_ref().take(compressed_len as u64).read_to_end(&mut self.chunk)? != compressed_len {
+            throw!(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        self.buffer.clear();
+        self.pos = 0;
+        if flags & COMPRESSED != 0 {
+            raw::decompress_raw_strict(&self.chunk, &[], &mut self.buffer, len)?;
+            if self.buffer.len() != len {
+                throw!(Error::CorruptChunk);
+            }
+        } else {
+            std::mem::swap(&mut self.buffer, &mut self.chunk);
+        }
+        true
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for LZ4NetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for LZ4NetReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // empty chunks are allowed, so keep going until we have something
+        while self.pos == self.buffer.len() {
+            if !self.next_chunk()? {
+                break;
+            }
+        }
+        Ok(&self.buffer[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buffer.len());
+    }
+}
+
+/// Compresses into an lz4net stream that lz4net (and `LZ4NetReader`) can read.
+///
+/// Like a `BufWriter`, this collects a chunk before writing it. `flush` ends the current chunk early,
+/// so don't flush too often. Whatever is left is written when you call `finish` (or on drop, ignoring errors).
+pub struct LZ4NetWriter<W: Write> {
+    /// Only `None` once we're finished.
+    writer: Option<W>,
+    chunk_size: usize,
+    buffer: Vec<u8>,
+    compressed: Vec<u8>,
+}
+
+impl<W: Write> LZ4NetWriter<W> {
+    /// Write chunks of `DEFAULT_CHUNK_SIZE`, just like lz4net does.
+    pub fn new(writer: W) -> Self {
+        Self::with_chunk_size(writer, DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Write chunks of `chunk_size` bytes (before compression). lz4net can't read chunks larger than 2 GiB.
+    pub fn with_chunk_size(writer: W, chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE as usize);
+        LZ4NetWriter { writer: Some(writer), chunk_size, buffer: Vec::with_capacity(chunk_size), compressed: Vec::new() }
+    }
+
+    /// Compress and write whatever we collected so far as a chunk (unless that's nothing).
+    #[throws(io::Error)]
+    fn write_chunk(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let writer = self.writer.as_mut().unwrap();
+        self.compressed.clear();
+        let compressed_len = raw::compress_to_vec(&self.buffer, &[], &mut self.compressed);
+        if compressed_len < self.buffer.len() {
+            write_varint(&mut *writer, COMPRESSED)?;
+            write_varint(&mut *writer, self.buffer.len() as u64)?;
+            write_varint(&mut *writer, compressed_len as u64)?;
+            writer.write_all(&self.compressed)?;
+        } else {
+            // lz4net stores chunks that don't get any smaller, too
+            write_varint(&mut *writer, 0)?;
+            write_varint(&mut *writer, self.buffer.len() as u64)?;
+            writer.write_all(&self.buffer)?;
+        }
+        self.buffer.clear();
+    }
+
+    /// Write the last chunk and return the underlying writer.
+    #[throws(io::Error)]
+    pub fn finish(mut self) -> W {
+        self.write_chunk()?;
+        self.writer.take().unwrap()
+    }
+
+    /// Get a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        self.writer.as_ref().unwrap()
+    }
+}
+
+impl<W: Write> Write for LZ4NetWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.chunk_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.chunk_size {
+            self.write_chunk()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for LZ4NetWriter<W> {
+    fn drop(&mut self) {
+        if self.writer.is_some() {
+            let _ = self.write_chunk();
+        }
+    }
+}
+
+/// Read a varint, or `None` if the input ends right away.
+#[throws]
+fn read_varint<R: Read>(mut reader: R) -> Option<u64> {
+    let mut first = [0];
+    if reader.read(&mut first)? == 0 {
+        return None;
+    }
+    let mut byte = first[0];
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        byte = reader.read_u8()?;
+    }
+    throw!(Error::CorruptChunk);
+}
+
+/// Read a chunk length (which has to be there).
+#[throws]
+fn read_length<R: Read>(reader: R) -> usize {
+    match read_varint(reader)? {
+        Some(len) if len <= MAX_CHUNK_SIZE => len as usize,
+        Some(_) => throw!(Error::CorruptChunk),
+        None => throw!(io::Error::from(ErrorKind::UnexpectedEof)),
+    }
+}
+
+#[throws(io::Error)]
+fn write_varint<W: Write>(mut writer: W, mut value: u64) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            writer.write_u8(byte)?;
+            break;
+        }
+        writer.write_u8(byte | 0x80)?;
+    }
+}
@@ -0,0 +1,147 @@
+//! Several logical streams interleaved in one LZ4 file (e.g. stdout, stderr and a log of the same process).
+//!
+//! Every chunk is stored as its own LZ4 frame, preceded by a small skippable frame that says which stream
+//! the chunk belongs to. Any ordinary LZ4 decompressor (e.g. `lz4 -d`) can still decompress the file,
+//! it just sees all chunks of all streams in the order they were written.
+//!
+//! Layout of a chunk (all integers little endian):
+//!
+//! ```text
+//! u32 skippable magic (0x184D2A5D)    u32 payload length (4)    u32 stream id
+//! one LZ4 frame with the data
+//! ```
+
+use byteorder::{LE, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+use thiserror::Error;
+use culpa::{throw, throws};
+
+use crate::framed::{CompressionError, CompressionSettings, DecompressionError, LZ4FrameReader, MAGIC, SKIPPABLE_MAGIC, SKIPPABLE_MAGIC_MASK};
+
+/// The skippable frame magic number we use for stream tags.
+const TAG_MAGIC: u32 = SKIPPABLE_MAGIC | 0xD;
+
+/// Errors when multiplexing or demultiplexing streams.
+#[derive(Error, Debug)]
+pub enum MuxError {
+    #[error("I/O error")]
+    Io(#[from] io::Error),
+    #[error("error compressing a chunk")]
+    Compression(#[from] CompressionError),
+    #[error("error decompressing a chunk")]
+    Decompression(#[from] DecompressionError),
+    #[error("found an LZ4 frame that doesn't say which stream it belongs to")]
+    Untagged,
+    #[error("a stream tag is corrupted")]
+    CorruptTag,
+}
+type Error = MuxError; // do it this way for better docs
+
+/// Writes chunks of several streams into one output.
+///
+/// Every chunk becomes a frame of its own, so don't make them too small: each one costs a frame header,
+/// a tag and the end of the frame (about 25 bytes), and a fresh start for the compressor.
+pub struct MuxWriter<'a, W: Write> {
+    writer: W,
+    settings: CompressionSettings<'a>,
+}
+
+impl<'a, W: Write> MuxWriter<'a, W> {
+    /// All chunks are compressed with the given settings.
+    pub fn new(writer: W, settings: CompressionSettings<'a>) -> Self {
+        MuxWriter { writer, settings }
+    }
+
+    /// Compress `data` into a new chunk of `stream`.
+    #[throws]
+    pub fn write_chunk(&mut self, stream: u32, data: &[u8]) {
+        self.writer.write_u32::<LE>(TAG_MAGIC)?;
+        self.writer.write_u32::<LE>(4)?;
+        self.writer.write_u32::<LE>(stream)?;
+        self.settings.compress_with_size_unchecked(data, &mut self.writer, data.len() as u64)?;
+    }
+
+    /// Get a mutable reference to the underlying writer (e.g. to flush it).
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads the chunks that a `MuxWriter` wrote, one at a time.
+///
+/// Skippable frames other than our tags are skipped, so you can still put your own metadata in between.
+pub struct MuxReader<'a, R: Read> {
+    reader: R,
+    dictionary: &'a [u8],
+}
+
+impl<R: Read> MuxReader<'static, R> {
+    pub fn new(reader: R) -> Self {
+        MuxReader { reader, dictionary: &[] }
+    }
+}
+
+impl<'a, R: Read> MuxReader<'a, R> {
+    /// If the chunks were compressed with a dictionary, you have to supply it here.
+    pub fn with_dictionary(reader: R, dictionary: &'a [u8]) -> Self {
+        MuxReader { reader, dictionary }
+    }
+
+    /// Decompress the next chunk, append it to `output` and return the stream it belongs to.
+    ///
+    /// Returns `None` at the end of the input.
+    #[throws]
+    pub fn next_chunk(&mut self, output: &mut Vec<u8>) -> Option<u32> {
+        let stream = loop {
+            // the end of the input is only fine between chunks
+            let mut magic = [0; 4];
+            if self.reader.read(&mut magic[..1])? == 0 {
+                return None;
+            }
+            self.reader.read_exact(&mut magic[1..])?;
+            let magic = u32::from_le_bytes(magic);
+            if magic == MAGIC {
+                throw!(Error::Untagged);
+            }
+            if magic & SKIPPABLE_MAGIC_MASK != SKIPPABLE_MAGIC {
+                throw!(DecompressionError::WrongMagic(magic));
+            }
+            let len = self.reader.read_u32::<LE>()?;
+            if magic != TAG_MAGIC {
+                if io::copy(&mut self.reader.by_ref().take(u64::from(len)), &mut io::sink())? != u64::from(len) {
+                    throw!(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                continue;
+            }
+            if len != 4 {
+                throw!(Error::CorruptTag);
+            }
+            break self.reader.read_u32::<LE>()?;
+        };
+
+        LZ4FrameReader::new(&mut self.reader)?.into_read_with_dictionary(self.dictionary).read_to_end(output)?;
+        Some(stream)
+    }
+
+    /// Decompress all remaining chunks and hand each one to `sink`, together with the stream it belongs to.
+    ///
+    /// This is how you split the streams back apart, e.g. by writing every chunk to a file per stream.
+    #[throws]
+    pub fn for_each_chunk(&mut self, mut sink: impl FnMut(u32, &[u8]) -> io::Result<()>) {
+        let mut chunk = Vec::new();
+        while let Some(stream) = self.next_chunk(&mut chunk)? {
+            sink(stream, &chunk)?;
+            chunk.clear();
+        }
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
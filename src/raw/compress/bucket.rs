@@ -0,0 +1,79 @@
+use std::convert::TryInto;
+
+use super::{count_matching_bytes, hash_for_u32, EncoderTable, MINMATCH};
+
+/// How many positions fit into a bucket (one 64-byte cache line).
+const BUCKET_LEN: usize = 16;
+/// The largest distance a match can have in the LZ4 format.
+const MAX_DISTANCE: usize = 0xFFFF;
+
+#[derive(Clone, Copy)]
+#[repr(align(64))]
+struct Bucket([u32; BUCKET_LEN]);
+
+/// A hash table that remembers several positions per hash and returns the one with the longest match.
+///
+/// `U32Table` only remembers the most recent position for every hash, so it misses a lot of matches
+/// when there are collisions or when the most recent occurrence is a short match but an older one is long.
+/// This table keeps the last `depth` positions per hash in a bucket that is exactly one cache line large,
+/// so looking at all of them is still fairly cheap. It's slower than `U32Table` but usually compresses
+/// a few percent better. Think of it as a midpoint between the fast mode and a proper high-compression mode.
+#[derive(Clone)]
+pub struct BucketTable {
+    buckets: Box<[Bucket]>,
+    hash_log: usize,
+    depth: usize,
+    offset: usize,
+}
+impl BucketTable {
+    /// The most candidates we can check per position.
+    pub const MAX_DEPTH: usize = BUCKET_LEN;
+
+    /// A table with `1 << hash_log` buckets (taking up `64 << hash_log` bytes) that checks `depth` candidates per position.
+    ///
+    /// Returns `None` if `hash_log` is not between 8 and 20 or if `depth` is not between 1 and `MAX_DEPTH`.
+    pub fn new(hash_log: usize, depth: usize) -> Option<Self> {
+        if !(8..=20).contains(&hash_log) || !(1..=Self::MAX_DEPTH).contains(&depth) {
+            return None;
+        }
+        Some(BucketTable { buckets: vec![Bucket([0; BUCKET_LEN]); 1 << hash_log].into_boxed_slice(), hash_log, depth, offset: 0 })
+    }
+}
+impl Default for BucketTable {
+    /// 1024 buckets (64 KiB) with 8 candidates each.
+    fn default() -> Self {
+        Self::new(10, 8).unwrap()
+    }
+}
+
+impl EncoderTable for BucketTable {
+    fn replace(&mut self, input: &[u8], offset: usize) -> usize {
+        let o = offset + self.offset; // apply positive offset on input
+        let bucket = &mut self.buckets[hash_for_u32(&input[offset..], self.hash_log)].0;
+
+        // when there are no good candidates, we just return the most recent one (which is what U32Table would do)
+        let mut best = usize::try_from(bucket[0]).unwrap().saturating_sub(self.offset);
+        let mut best_len = MINMATCH - 1;
+        for &entry in &bucket[..self.depth] {
+            let candidate = usize::try_from(entry).unwrap().saturating_sub(self.offset);
+            if candidate >= offset || offset - candidate > MAX_DISTANCE {
+                // it fell out of the window, and so did everything after it because that's even older
+                break;
+            }
+            let len = count_matching_bytes(&input[offset..], &input[candidate..]);
+            if len > best_len {
+                best = candidate;
+                best_len = len;
+            }
+        }
+
+        // most recent first
+        bucket.copy_within(..self.depth - 1, 1);
+        bucket[0] = o.try_into().expect("EncoderTable contract violated");
+        best
+    }
+    fn offset(&mut self, offset: usize) {
+        self.offset += offset;
+    }
+    fn payload_size_limit(&self) -> usize { u32::MAX as usize }
+}
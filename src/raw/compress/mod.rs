@@ -1,9 +1,20 @@
 use std::mem;
 use std::cmp;
-use std::io::Write;
+use std::io::{self, ErrorKind, Write};
 use std::convert::{TryInto, TryFrom};
 use byteorder::{ByteOrder, NativeEndian, WriteBytesExt, LE};
 use culpa::{throws};
+use thiserror::Error;
+
+use crate::framed::WINDOW_SIZE;
+use crate::raw::{sequences, Sequence};
+
+mod bucket;
+mod sequence;
+mod stream;
+pub use bucket::*;
+pub use sequence::*;
+pub use stream::*;
 
 type Error = std::io::Error;
 
@@ -11,34 +22,193 @@ type Error = std::io::Error;
 ///
 /// Every four bytes is assigned an entry. When this number is lower, fewer entries exists, and
 /// thus collisions are more likely, hurting the compression ratio.
-const DICTIONARY_SIZE: usize = 1 << HASHLOG;
-const HASHLOG: usize = 12;
+const DICTIONARY_SIZE: usize = 1 << 12;
 const MINMATCH: usize = 4;
 
 
+/// The hash table that the compressor uses to find matches.
+///
+/// For every position, the compressor asks the table for an earlier position that starts with
+/// the same bytes (a match candidate) and tells it about the current position at the same time.
+/// A better table finds more and longer matches, a faster table makes compression faster.
+/// `U32Table` is what the framed compressor uses by default and what you should compare against.
+///
+/// The trait is object safe, so you can plug your own implementation into the framed compressor
+/// via `CompressionSettings::encoder_table`.
 pub trait EncoderTable {
-    fn payload_size_limit() -> usize;
-    // offset is declared as usize but must not be above payload_size_limit
+    /// The largest input (including any prefix before the cursor) this table can handle.
+    ///
+    /// `compress2` panics if you give it more than this.
+    fn payload_size_limit(&self) -> usize;
+
+    /// Remember that `input[offset..]` starts at `offset` and return a match candidate for it.
+    ///
+    /// The candidate must be a position before `offset` (if you don't have one, return anything before it, e.g. 0).
+    /// It doesn't have to be a real match: the compressor checks the bytes anyway,
+    /// so returning garbage only hurts the compression ratio, never correctness.
+    /// `offset` is never above `payload_size_limit`.
     fn replace(&mut self, input: &[u8], offset: usize) -> usize;
 
+    /// The input was shifted: everything that used to be at position `p` is now at `p - offset`.
+    ///
+    /// The framed compressor does this when it drops old data from its window.
+    /// Positions that would become negative are gone. They are useless as candidates,
+    /// but you may still return them as 0 (that's what `U32Table` does).
     fn offset(&mut self, offset: usize);
 }
 
+/// The default table: 4096 slots of 32 bits each (16 KiB), like the C implementation.
+pub type U32Table = FixedU32Table<DICTIONARY_SIZE>;
+/// The same amount of memory as `U32Table`, but twice as many slots. Only works for inputs up to 64 KiB.
+pub type U16Table = FixedU16Table<{ DICTIONARY_SIZE * 2 }>;
+
+/// A hash table with `SLOTS` entries of 32 bits each, where `SLOTS` is chosen at compile time.
+///
+/// Ideally this would be generic over the hash log, but stable Rust can't compute array sizes from
+/// generic parameters yet. So you give us the number of slots instead, which must be a power of two
+/// between 2^8 and 2^20 (e.g. `FixedU32Table<{ 1 << 16 }>`). Anything else fails to compile.
+/// Use `VarU32Table` if you need to pick the size at runtime.
 #[derive(Clone)]
-pub struct U32Table {
-    dict: [u32; DICTIONARY_SIZE],
+pub struct FixedU32Table<const SLOTS: usize> {
+    dict: [u32; SLOTS],
     offset: usize,
 }
-impl Default for U32Table {
+impl<const SLOTS: usize> Default for FixedU32Table<SLOTS> {
     fn default() -> Self {
-        U32Table { dict: [0; DICTIONARY_SIZE], offset: 0 }
+        FixedU32Table { dict: [0; SLOTS], offset: 0 }
+    }
+}
+impl<const SLOTS: usize> FixedU32Table<SLOTS> {
+    /// The number of bits that the hash function produces.
+    const HASH_LOG: usize = hash_log_for(SLOTS);
+
+    /// The raw table entries and the offset, for snapshots.
+    pub(crate) fn to_parts(&self) -> (Vec<u32>, usize) {
+        (self.dict.to_vec(), self.offset)
+    }
+
+    /// The opposite of `to_parts`. Fails if the entries point beyond `history_len` bytes of history.
+    pub(crate) fn from_parts(dict: &[u32], offset: usize, history_len: usize) -> Option<Self> {
+        if !entries_valid(dict, offset, history_len) {
+            return None;
+        }
+        Some(FixedU32Table { dict: dict.try_into().ok()?, offset })
+    }
+}
+
+/// Checks that `slots` is a valid table size (at compile time, if it's a const) and returns its log.
+const fn hash_log_for(slots: usize) -> usize {
+    assert!(slots.is_power_of_two() && slots >= 1 << 8 && slots <= 1 << 20, "the number of slots must be a power of two between 2^8 and 2^20");
+    slots.trailing_zeros() as usize
+}
+
+/// Whether all table entries point into the `history_len` bytes of history before `offset`.
+fn entries_valid(dict: &[u32], offset: usize, history_len: usize) -> bool {
+    match offset.checked_add(history_len) {
+        Some(limit) => dict.iter().all(|&entry| usize::try_from(entry).is_ok_and(|entry| entry <= limit)),
+        None => false,
+    }
+}
+
+/// Like `U32Table`, but you pick the size at runtime.
+///
+/// The table has `1 << hash_log` entries (so it takes up `4 << hash_log` bytes).
+/// Bigger tables have fewer collisions, which gives a better ratio on big blocks,
+/// smaller tables are nice when memory is tight.
+/// With a `hash_log` of 12, this behaves exactly like `U32Table` (but is a tiny bit slower).
+#[derive(Clone)]
+pub struct VarU32Table {
+    dict: Box<[u32]>,
+    hash_log: usize,
+    offset: usize,
+}
+impl VarU32Table {
+    /// The smallest `hash_log` we support.
+    pub const MIN_HASH_LOG: usize = 8;
+    /// The largest `hash_log` we support (a 4 MiB table).
+    pub const MAX_HASH_LOG: usize = 20;
+
+    /// Returns `None` if `hash_log` is not within `MIN_HASH_LOG..=MAX_HASH_LOG`.
+    pub fn new(hash_log: usize) -> Option<Self> {
+        if !(Self::MIN_HASH_LOG..=Self::MAX_HASH_LOG).contains(&hash_log) {
+            return None;
+        }
+        Some(VarU32Table { dict: vec![0; 1 << hash_log].into_boxed_slice(), hash_log, offset: 0 })
+    }
+
+    /// The `hash_log` this table was created with.
+    pub fn hash_log(&self) -> usize {
+        self.hash_log
+    }
+
+    /// The raw table entries and the offset, for snapshots.
+    pub(crate) fn to_parts(&self) -> (Vec<u32>, usize) {
+        (self.dict.to_vec(), self.offset)
+    }
+
+    /// Like `U32Table::from_parts`. The size of the table is implied by `dict`.
+    pub(crate) fn from_parts(dict: &[u32], offset: usize, history_len: usize) -> Option<Self> {
+        if !dict.len().is_power_of_two() || !entries_valid(dict, offset, history_len) {
+            return None;
+        }
+        let mut table = Self::new(dict.len().trailing_zeros() as usize)?;
+        table.dict.copy_from_slice(dict);
+        table.offset = offset;
+        Some(table)
     }
 }
 
 
+/// Inserts every position of `history` into the table, so blocks that follow it can refer back to it.
+pub(crate) fn prime_table<T: EncoderTable + ?Sized>(table: &mut T, history: &[u8]) {
+    for window in history.windows(mem::size_of::<usize>()).step_by(3) {
+        // this is a perfectly safe way to find out where our window is pointing
+        // we could do this manually by iterating with an index to avoid the scary-looking
+        // pointer math but this is way more convenient IMO
+        let offset = window.as_ptr() as usize - history.as_ptr() as usize;
+        table.replace(history, offset);
+    }
+}
+
+/// Like `prime_table`, but also fills every slot that is still empty afterwards, which is what liblz4 1.10 does
+/// when loading a dictionary (`LZ4_loadDictSlow`). Only the last 64 KiB of `history` are used, just like in liblz4.
+///
+/// `table` must be empty. The first pass is `prime_table` (every third position, later ones win),
+/// the second one visits every position, but only takes slots that nobody took yet (so earlier ones win).
+pub(crate) fn prime_table_thoroughly<T: EncoderTable + ?Sized>(table: &mut T, history: &[u8]) {
+    const HASH_UNIT: usize = mem::size_of::<usize>();
+    // liblz4 places the end of the dictionary at index 64 KiB and treats index 0 as an empty slot,
+    // which is the first position of a full dictionary. We need to see the same indices to take the same slots,
+    // so short dictionaries are moved to the end of a 64 KiB buffer first.
+    let padded;
+    let (buffer, start) = if history.len() >= WINDOW_SIZE {
+        (history, history.len() - WINDOW_SIZE)
+    } else {
+        padded = [&vec![0; WINDOW_SIZE - history.len()][..], history].concat();
+        (&padded[..], WINDOW_SIZE - history.len())
+    };
+    // the position that liblz4 sees at index 0
+    let zero = buffer.len() - WINDOW_SIZE;
+    if buffer.len() - start >= HASH_UNIT {
+        let end = buffer.len() - HASH_UNIT;
+        for position in (start..=end).step_by(3) {
+            table.replace(buffer, position);
+        }
+        for position in start..=end {
+            let previous = table.replace(buffer, position);
+            if previous != 0 && previous != zero {
+                // the slot was taken, so put the previous position back (it hashes to the same slot, after all)
+                table.replace(buffer, previous);
+            }
+        }
+    }
+    // back to the positions in `history`
+    table.offset(buffer.len() - history.len());
+}
+
 // on 64 bit systems, we read 64 bits and hash 5 bytes instead of 4
 #[cfg(target_pointer_width = "64")]
-fn hash_for_u32(input: &[u8]) -> usize {
+fn hash_for_u32(input: &[u8], hash_log: usize) -> usize {
     // read 64 bits if possible
     let v = input.get(..8).map(NativeEndian::read_u64).unwrap_or(0);
     // we end up only needing 5 bytes but the only case where this becomes
@@ -47,57 +217,78 @@ fn hash_for_u32(input: &[u8]) -> usize {
     // calculate a bad but very cheap checksum
     #[cfg(target_endian = "little")] fn checksum_u64(v: u64) -> u64 { (v << 24).wrapping_mul(889523592379) }
     #[cfg(target_endian = "big")] fn checksum_u64(v: u64) -> u64 { (v >> 24).wrapping_mul(11400714785074694791) }
-    (checksum_u64(v) >> (64 - HASHLOG)) as usize
+    (checksum_u64(v) >> (64 - hash_log)) as usize
 }
 // on all other systems we simply hash 4 bytes, borrowing the algorithm for the u16 table
 #[cfg(not(target_pointer_width = "64"))]
-fn hash_for_u32(input: &[u8]) -> usize {
-    hash_for_u16(input) >> 1 // shift by one more because we have half as many slots as the u16 table
+fn hash_for_u32(input: &[u8], hash_log: usize) -> usize {
+    hash_for_u16(input, hash_log)
 }
 
-fn hash_for_u16(input: &[u8]) -> usize {
+fn hash_for_u16(input: &[u8], hash_log: usize) -> usize {
     let v = NativeEndian::read_u32(input);
-    (v.wrapping_mul(2654435761) >> (32 - HASHLOG - 1)) as usize // shift by one less than hashlog because we have twice as many slots
+    (v.wrapping_mul(2654435761) >> (32 - hash_log)) as usize
 }
 
-impl EncoderTable for U32Table {
+impl<const SLOTS: usize> EncoderTable for FixedU32Table<SLOTS> {
     fn replace(&mut self, input: &[u8], offset: usize) -> usize {
         let o = offset + self.offset; // apply positive offset on input
 
         let mut value = o.try_into().expect("EncoderTable contract violated");
-        mem::swap(&mut self.dict[hash_for_u32(&input[offset..])], &mut value);
+        mem::swap(&mut self.dict[hash_for_u32(&input[offset..], Self::HASH_LOG)], &mut value);
         usize::try_from(value).expect("This code is not supposed to run on a 16-bit arch (let alone smaller)")
             .saturating_sub(self.offset) // apply negative offset on output
     }
     fn offset(&mut self, offset: usize) {
         self.offset += offset;
     }
-    fn payload_size_limit() -> usize { std::u32::MAX as usize }
+    fn payload_size_limit(&self) -> usize { std::u32::MAX as usize }
 }
 
+impl EncoderTable for VarU32Table {
+    fn replace(&mut self, input: &[u8], offset: usize) -> usize {
+        let o = offset + self.offset; // apply positive offset on input
+
+        let mut value = o.try_into().expect("EncoderTable contract violated");
+        mem::swap(&mut self.dict[hash_for_u32(&input[offset..], self.hash_log)], &mut value);
+        usize::try_from(value).expect("This code is not supposed to run on a 16-bit arch (let alone smaller)")
+            .saturating_sub(self.offset) // apply negative offset on output
+    }
+    fn offset(&mut self, offset: usize) {
+        self.offset += offset;
+    }
+    fn payload_size_limit(&self) -> usize { std::u32::MAX as usize }
+}
+
+/// Like `FixedU32Table`, but with 16-bit slots. That's half the memory per slot, but it only works for inputs up to 64 KiB.
 #[derive(Clone)]
-pub struct U16Table {
-    dict: [u16; DICTIONARY_SIZE*2], // u16 fits twice as many slots into the same amount of memory
+pub struct FixedU16Table<const SLOTS: usize> {
+    dict: [u16; SLOTS],
     offset: usize,
 }
-impl Default for U16Table {
+impl<const SLOTS: usize> FixedU16Table<SLOTS> {
+    /// The number of bits that the hash function produces.
+    const HASH_LOG: usize = hash_log_for(SLOTS);
+}
+impl<const SLOTS: usize> Default for FixedU16Table<SLOTS> {
     fn default() -> Self {
-        U16Table { dict: [0; DICTIONARY_SIZE*2], offset: 0 }
+        FixedU16Table { dict: [0; SLOTS], offset: 0 }
     }
 }
-impl EncoderTable for U16Table {
+impl<const SLOTS: usize> EncoderTable for FixedU16Table<SLOTS> {
     fn replace(&mut self, input: &[u8], offset: usize) -> usize {
         let o = offset + self.offset; // apply positive offset on input
 
         let mut value = o.try_into().expect("EncoderTable contract violated");
-        mem::swap(&mut self.dict[hash_for_u16(&input[offset..])], &mut value);
+        mem::swap(&mut self.dict[hash_for_u16(&input[offset..], Self::HASH_LOG)], &mut value);
         usize::try_from(value).expect("This code is not supposed to run on a 16-bit arch (let alone smaller)")
             .saturating_sub(self.offset) // apply negative offset on output
     }
     fn offset(&mut self, offset: usize) {
         self.offset += offset;
     }
-    fn payload_size_limit() -> usize { std::u16::MAX as usize }
+    // the last few bytes never end up in the table, so a full 64 KiB still fits
+    fn payload_size_limit(&self) -> usize { 1 << 16 }
 }
 
 
@@ -144,9 +335,13 @@ fn count_matching_bytes(a: &[u8], b: &[u8]) -> usize {
     matching_bytes + trailing_matches
 }
 
-const ACCELERATION: usize = 1;
+/// The default for `compress2_accelerated`, i.e. no acceleration at all.
+pub const DEFAULT_ACCELERATION: usize = 1;
 const SKIP_TRIGGER: usize = 6; // for each 64 steps, skip in bigger increments
 
+/// Most sequences (short literals, short matches) fit into this many bytes, so we can write them in one go.
+const GROUP_BUFFER: usize = 64;
+
 #[throws]
 fn write_group<W: Write>(mut writer: &mut W, literal: &[u8], duplicate: Duplicate) {
         let literal_len = literal.len();
@@ -155,6 +350,28 @@ fn write_group<W: Write>(mut writer: &mut W, literal: &[u8], duplicate: Duplicat
         write_lsic_head(&mut token, 4, literal_len);
         write_lsic_head(&mut token, 0, duplicate.extra_bytes);
 
+        // token, literal length, literal, offset, match length
+        if literal_len <= GROUP_BUFFER - 5 && duplicate.extra_bytes < 0xF + 0xFF {
+            // both lengths need at most one extra byte, so everything fits on the stack
+            let mut buf = [0; GROUP_BUFFER];
+            buf[0] = token;
+            let mut len = 1;
+            if literal_len >= 0xF {
+                buf[len] = (literal_len - 0xF) as u8;
+                len += 1;
+            }
+            buf[len..][..literal_len].copy_from_slice(literal);
+            len += literal_len;
+            LE::write_u16(&mut buf[len..], duplicate.offset);
+            len += 2;
+            if duplicate.extra_bytes >= 0xF {
+                buf[len] = (duplicate.extra_bytes - 0xF) as u8;
+                len += 1;
+            }
+            writer.write_all(&buf[..len])?;
+            return;
+        }
+
         writer.write_u8(token)?;
         write_lsic_tail(&mut writer, literal_len)?;
         writer.write_all(literal)?;
@@ -163,15 +380,33 @@ fn write_group<W: Write>(mut writer: &mut W, literal: &[u8], duplicate: Duplicat
 }
 
 #[throws]
-pub fn compress2<W: Write, T: EncoderTable>(input: &[u8], cursor: usize, table: &mut T, mut writer: W) {
-    assert!(input.len() <= T::payload_size_limit());
+pub fn compress2<W: Write, T: EncoderTable + ?Sized>(input: &[u8], cursor: usize, table: &mut T, writer: W) {
+    compress2_accelerated(input, cursor, table, DEFAULT_ACCELERATION, writer)?;
+}
+
+/// Like `compress2`, but trades compression ratio for speed.
+///
+/// Whenever we don't find a match, we skip ahead a bit further the longer the search has been unsuccessful.
+/// The acceleration makes us skip ahead faster, so higher values compress faster (and worse).
+/// This works just like the acceleration parameter of the C implementation's `LZ4_compress_fast`.
+#[throws]
+pub fn compress2_accelerated<W: Write, T: EncoderTable + ?Sized>(input: &[u8], cursor: usize, table: &mut T, acceleration: usize, writer: W) {
+    compress_with_progress(input, cursor, table, acceleration, None, writer)?;
+}
+
+/// Like `compress2_accelerated`, but tells `progress` how far we got (as a position in `input`) after every sequence.
+#[throws]
+pub(crate) fn compress_with_progress<W: Write, T: EncoderTable + ?Sized>(input: &[u8], cursor: usize, table: &mut T, acceleration: usize,
+                                                                        mut progress: Option<&mut dyn FnMut(usize)>, mut writer: W) {
+    assert!(input.len() <= table.payload_size_limit());
+    let acceleration = cmp::max(acceleration, DEFAULT_ACCELERATION);
 
     let init_cursor = cursor;
     let mut cursor = cursor;
     while cursor < input.len() {
         let literal_start = cursor;
 
-        let mut step_counter = ACCELERATION << SKIP_TRIGGER;
+        let mut step_counter = acceleration << SKIP_TRIGGER;
         let mut step = 1;
         // look for a duplicate
         let duplicate = loop {
@@ -234,28 +469,198 @@ pub fn compress2<W: Write, T: EncoderTable>(input: &[u8], cursor: usize, table:
         // cursor is now pointing past the match
         let literal_end = cursor - duplicate.extra_bytes - MINMATCH;
         write_group(&mut writer, &input[literal_start..literal_end], duplicate)?;
+        if let Some(progress) = progress.as_mut() {
+            progress(cursor);
+        }
    }
 }
+
+/// Compress `input` into a single raw block that may refer back to `prefix` (e.g. a dictionary or the previous block).
+///
+/// This is `compress2` without the plumbing: it picks the right table, primes it with the prefix
+/// and glues everything together. Only the last 64 KiB of `prefix` are used, because that's as far back
+/// as LZ4 can reference. Decompress with `decompress_raw`, passing the same prefix.
+pub fn compress_block(input: &[u8], prefix: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    compress_to_vec(input, prefix, &mut output);
+    output
+}
+
+/// Like `compress_block`, but appends the block to `output` and returns how many bytes that was.
+///
+/// A `Vec` never runs out of space, so unlike `compress2` with a writer of limited size (or `compress_into`),
+/// this can't fail: if all you want is the compressed bytes, there are no errors to deal with.
+/// Reuse `output` for many blocks (clearing it in between, or not) to save the allocations.
+pub fn compress_to_vec(input: &[u8], prefix: &[u8], output: &mut Vec<u8>) -> usize {
+    let prefix = &prefix[prefix.len().saturating_sub(WINDOW_SIZE)..];
+    let start = output.len();
+    // writing to a Vec can't fail
+    if prefix.is_empty() && input.len() <= U16Table::default().payload_size_limit() {
+        compress2(input, 0, &mut U16Table::default(), &mut *output).unwrap();
+    } else {
+        let mut table = U32Table::default();
+        prime_table(&mut table, prefix);
+        compress2(&[prefix, input].concat(), prefix.len(), &mut table, &mut *output).unwrap();
+    }
+    output.len() - start
+}
+
+/// Compress `input` into a single raw block that doesn't depend on anything else.
+pub fn compress_independent_block(input: &[u8]) -> Vec<u8> {
+    compress_block(input, &[])
+}
+
+/// The output buffer is too small for the compressed data.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
+#[error("the compressed data doesn't fit into the output buffer")]
+pub struct OutputTooSmall;
+
+/// How far the encoder got with a block before it ran out of space (see `framed::BlockStats::bailout`).
+///
+/// This tells you *why* data doesn't compress: if `consumed` is close to the size of the block, it almost fit,
+/// and a larger output (or block) might have worked. If it's tiny, there simply weren't any matches worth mentioning.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct Bailout {
+    /// How many bytes of input went into complete sequences before we gave up.
+    pub consumed: usize,
+    /// How many bytes of output those sequences took.
+    pub produced: usize,
+}
+
+impl Bailout {
+    /// Work out how far we got from the part of the block that we managed to write.
+    fn from_partial(block: &[u8]) -> Self {
+        let mut bailout = Bailout { consumed: 0, produced: 0 };
+        let mut sequences = sequences(block);
+        // the last sequence is usually cut off, so this stops at an error
+        while let Some(Ok(sequence)) = sequences.next() {
+            bailout.consumed += match sequence {
+                Sequence::Literal { len } | Sequence::Match { len, .. } => len,
+            };
+            bailout.produced = sequences.consumed();
+        }
+        bailout
+    }
+}
+
+/// Compress `input` into a single raw block in `output` and return how many bytes were written.
+///
+/// This is for when you already have a buffer, e.g. from an arena or across an FFI boundary.
+/// If the compressed block doesn't fit, you get `OutputTooSmall` and the contents of `output` are unspecified.
+/// A common trick is to pass a buffer as large as `input`: if compression fails, the data is incompressible
+/// and you can just store it as-is (that's what the frame format does).
+pub fn compress_into(input: &[u8], output: &mut [u8]) -> Result<usize, OutputTooSmall> {
+    if input.len() <= U16Table::default().payload_size_limit() {
+        compress_to_slice(input, 0, &mut U16Table::default(), DEFAULT_ACCELERATION, None, output)
+    } else {
+        compress_to_slice(input, 0, &mut U32Table::default(), DEFAULT_ACCELERATION, None, output)
+    }.or(Err(OutputTooSmall))
+}
+
+/// Like `compress_with_progress`, but writes into a slice and returns how many bytes were written
+/// (or how far we got, if they don't fit).
+pub(crate) fn compress_to_slice<T: EncoderTable + ?Sized>(input: &[u8], cursor: usize, table: &mut T, acceleration: usize,
+                                                          progress: Option<&mut dyn FnMut(usize)>, output: &mut [u8]) -> Result<usize, Bailout> {
+    let capacity = output.len();
+    // use a wrapper that forbids partial writes, so we don't write 32-bit integers
+    // as four individual bytes with four individual range checks
+    let mut writer = NoPartialWrites(output);
+    let result = compress_with_progress(input, cursor, table, acceleration, progress, &mut writer);
+    let written = capacity - writer.0.len();
+    match result {
+        Ok(()) => Ok(written),
+        Err(e) => {
+            // that's the only error NoPartialWrites can produce
+            assert_eq!(e.kind(), ErrorKind::ConnectionAborted);
+            Err(Bailout::from_partial(&output[..written]))
+        }
+    }
+}
+
+/// Like `compress_to_slice`, but only counts how many bytes would have been written (failing beyond `limit`).
+pub(crate) fn compressed_len<T: EncoderTable + ?Sized>(input: &[u8], cursor: usize, table: &mut T, acceleration: usize,
+                                                       progress: Option<&mut dyn FnMut(usize)>, limit: usize) -> Result<usize, OutputTooSmall> {
+    let mut writer = LimitedCount { count: 0, limit };
+    match compress_with_progress(input, cursor, table, acceleration, progress, &mut writer) {
+        Ok(()) => Ok(writer.count),
+        Err(e) => {
+            assert_eq!(e.kind(), ErrorKind::ConnectionAborted);
+            Err(OutputTooSmall)
+        }
+    }
+}
+
+/// A writer that throws everything away, but fails like `NoPartialWrites` once it would have run out of space.
+struct LimitedCount {
+    count: usize,
+    limit: usize,
+}
+impl Write for LimitedCount {
+    #[inline]
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.limit - self.count < data.len() {
+            return Err(ErrorKind::ConnectionAborted.into());
+        }
+        self.count += data.len();
+        Ok(data.len())
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Helper struct to allow more efficient code generation when using the Write trait on byte buffers.
+///
+/// The underlying problem is that the Write impl on [u8] (and everything similar, e.g. Cursor<[u8]>)
+/// is specified to write as many bytes as possible before returning an error.
+/// This is a problem because it forces e.g. a 32-bit write to compile to four 8-bit writes with a range
+/// check every time, rather than a single 32-bit write with a range check.
+///
+/// This wrapper aims to resolve the problem by simply not writing anything in case we fail the bounds check,
+/// as we throw away the entire buffer in that case anyway.
+struct NoPartialWrites<'a>(&'a mut [u8]);
+impl<'a> Write for NoPartialWrites<'a> {
+    #[inline]
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.0.len() < data.len() {
+            // quite frankly it doesn't matter what we specify here
+            return Err(ErrorKind::ConnectionAborted.into());
+        }
+
+        let amt = data.len();
+        let (a, b) = mem::take(&mut self.0).split_at_mut(data.len());
+        a.copy_from_slice(data);
+        self.0 = b;
+        Ok(amt)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
 fn write_lsic_head(token: &mut u8, shift: usize, value: usize) {
     let i = cmp::min(value, 0xF) as u8;
     *token |= i << shift;
 }
+/// Long literals and matches need lots of these (16 KiB of them for a 4 MiB run of zeros).
+static LSIC_RUN: [u8; 4096] = [0xFF; 4096];
+
 #[throws]
-fn write_lsic_tail<W: Write>(writer: &mut W, mut value: usize) {
+fn write_lsic_tail<W: Write>(writer: &mut W, value: usize) {
     if value < 0xF {
         return;
     }
 
-    value -= 0xF;
-
-    while value >= 4 * 0xFF {
-        writer.write_u32::<NativeEndian>(std::u32::MAX)?;
-        value -= 4 * 0xFF;
-    }
-    while value >= 0xFF {
-        writer.write_u8(0xFF)?;
-        value -= 0xFF;
+    let value = value - 0xF;
+    let mut run = value / 0xFF;
+    while run > 0 {
+        let len = cmp::min(run, LSIC_RUN.len());
+        writer.write_all(&LSIC_RUN[..len])?;
+        run -= len;
     }
-    writer.write_u8(value as u8)?;
+    writer.write_u8((value % 0xFF) as u8)?;
 }
 
@@ -0,0 +1,65 @@
+use std::io::{ErrorKind, Write};
+use byteorder::WriteBytesExt;
+use culpa::{throw, throws};
+
+use super::{write_group, write_lsic_head, write_lsic_tail, Duplicate, Error, MINMATCH};
+use crate::raw::Sequence;
+
+fn invalid(msg: &'static str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// Serialize a stream of sequences (e.g. from an external match finder) into a raw LZ4 block.
+///
+/// The sequences describe `input[cursor..]` from front to back: A `Literal` consumes `len` bytes of input as-is,
+/// a `Match` consumes `len` bytes that must be equal to the bytes `offset` positions earlier.
+/// Just like with `compress2`, the bytes before `cursor` may be referenced by matches but are not encoded.
+/// Any input left over after the last sequence is encoded as literals.
+///
+/// Matches are checked against the input, so the resulting block always decompresses correctly.
+/// However, it is your responsibility to honor the reference implementation's end-of-block rules
+/// (the last five bytes must be literals and the last match must start at least 12 bytes before the end)
+/// if you want the block to be readable by other decoders.
+#[throws]
+pub fn encode_sequences<W: Write, I: IntoIterator<Item = Sequence>>(input: &[u8], cursor: usize, sequences: I, mut writer: W) {
+    assert!(cursor <= input.len());
+
+    let mut cursor = cursor;
+    let mut literal_start = cursor;
+    for sequence in sequences {
+        match sequence {
+            Sequence::Literal { len } => {
+                if len > input.len() - cursor {
+                    throw!(invalid("literal runs past the end of the input"));
+                }
+                cursor += len;
+            }
+            Sequence::Match { offset, len } => {
+                let distance = usize::from(offset);
+                if distance == 0 || distance > cursor {
+                    throw!(invalid("match offset out of bounds"));
+                }
+                let extra_bytes = len.checked_sub(MINMATCH).ok_or_else(|| invalid("match is shorter than four bytes"))?;
+                if len > input.len() - cursor {
+                    throw!(invalid("match runs past the end of the input"));
+                }
+                // compare byte by byte because matches may overlap themselves
+                if (cursor..cursor + len).any(|i| input[i] != input[i - distance]) {
+                    throw!(invalid("match does not match the input"));
+                }
+
+                write_group(&mut writer, &input[literal_start..cursor], Duplicate { offset, extra_bytes })?;
+                cursor += len;
+                literal_start = cursor;
+            }
+        }
+    }
+
+    // every block ends with a literal-only sequence (even if it's empty)
+    let literal = &input[literal_start..];
+    let mut token = 0;
+    write_lsic_head(&mut token, 4, literal.len());
+    writer.write_u8(token)?;
+    write_lsic_tail(&mut writer, literal.len())?;
+    writer.write_all(literal)?;
+}
@@ -0,0 +1,82 @@
+use std::io::Write;
+use culpa::throws;
+
+use super::{compress2, prime_table, EncoderTable, Error, U32Table};
+use crate::framed::WINDOW_SIZE;
+
+/// Compresses a stream of messages into raw blocks that can refer back to earlier messages.
+///
+/// This is the equivalent of liblz4's `LZ4_compress_fast_continue`: every call to `compress` produces
+/// a standalone raw block (no framing whatsoever), but the compressor remembers the last 64 KiB
+/// of input, so repetitions across messages are compressed too. This is great for protocols that
+/// do their own framing and send lots of small, similar messages.
+///
+/// The other side needs a `StreamDecompressor` (or `LZ4_decompress_safe_continue`) that sees
+/// exactly the same blocks in exactly the same order.
+pub struct StreamCompressor {
+    table: U32Table,
+    /// The last (up to) 64 KiB of input, followed by the message that is currently being compressed.
+    buffer: Vec<u8>,
+    /// How much input the table has seen go by (positions in the table are relative to this).
+    forgotten: usize,
+}
+
+impl Default for StreamCompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamCompressor {
+    pub fn new() -> Self {
+        StreamCompressor { table: U32Table::default(), buffer: Vec::with_capacity(2 * WINDOW_SIZE), forgotten: 0 }
+    }
+
+    /// Start with a dictionary, i.e. the first message can already refer back to it.
+    ///
+    /// Only the last 64 KiB of the dictionary are used.
+    pub fn with_dictionary(dictionary: &[u8]) -> Self {
+        let mut compressor = Self::new();
+        compressor.reset_with_dictionary(dictionary);
+        compressor
+    }
+
+    /// Compress `input` into a single raw block.
+    ///
+    /// The block can reference any of the previous 64 KiB of input (across messages).
+    /// If this fails (i.e. `writer` fails), the stream is broken and you have to `reset` both sides.
+    #[throws]
+    pub fn compress<W: Write>(&mut self, input: &[u8], writer: W) {
+        if self.buffer.len() > WINDOW_SIZE {
+            let how_much_to_forget = self.buffer.len() - WINDOW_SIZE;
+            self.table.offset(how_much_to_forget);
+            self.forgotten += how_much_to_forget;
+            self.buffer.drain(..how_much_to_forget);
+        }
+
+        let window_offset = self.buffer.len();
+        if self.forgotten + window_offset + input.len() > self.table.payload_size_limit() {
+            // the table stores positions in 32 bits, so every 4 GiB we have to rebuild it from the window
+            self.table = U32Table::default();
+            prime_table(&mut self.table, &self.buffer);
+            self.forgotten = 0;
+        }
+        self.buffer.extend_from_slice(input);
+        compress2(&self.buffer, window_offset, &mut self.table, writer)?;
+    }
+
+    /// Forget all history, so the next block doesn't depend on anything.
+    pub fn reset(&mut self) {
+        self.table = U32Table::default();
+        self.buffer.clear();
+        self.forgotten = 0;
+    }
+
+    /// Forget all history, then continue as if `dictionary` had been the last message.
+    pub fn reset_with_dictionary(&mut self, dictionary: &[u8]) {
+        self.reset();
+        let dictionary = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        prime_table(&mut self.table, dictionary);
+        self.buffer.extend_from_slice(dictionary);
+    }
+}
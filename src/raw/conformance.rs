@@ -0,0 +1,108 @@
+use thiserror::Error;
+
+use super::{sequences, DecodeError, Sequence};
+
+/// The last match must start at least this many bytes before the end of the block.
+const MFLIMIT: usize = 12;
+/// The last this many bytes of a block must be literals.
+const LASTLITERALS: usize = 5;
+
+/// The ways in which a raw block can violate the rules of the reference implementation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
+pub enum ViolationKind {
+    #[error("the block can not be decoded: {0}")]
+    Malformed(DecodeError),
+    #[error("the match offset points before the start of the available history")]
+    OffsetOutOfWindow,
+    #[error("the last match starts less than {MFLIMIT} bytes before the end of the block")]
+    MatchTooCloseToEnd,
+    #[error("the block does not end with at least {LASTLITERALS} literal bytes")]
+    LastLiteralsTooShort,
+}
+
+/// A single rule violation found by `check_conformance`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
+#[error("{kind} (at input offset {input_offset}, output offset {output_offset})")]
+pub struct Violation {
+    pub kind: ViolationKind,
+    /// Position in the compressed block where the offending sequence starts.
+    pub input_offset: usize,
+    /// Position in the decompressed output where the offending sequence starts.
+    pub output_offset: usize,
+}
+
+/// Check whether a raw block follows all the rules of the LZ4 block format, including the ones that
+/// our own decoder doesn't care about (but other decoders might).
+///
+/// `prefix_len` is the amount of history (dictionary or previous blocks) that is available to the decoder.
+/// Returns all violations that were found, so an empty result means the block is conformant.
+/// Parsing stops at the first `Malformed` violation.
+pub fn check_conformance(input: &[u8], prefix_len: usize) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let mut iter = sequences(input);
+    let mut output_offset = 0;
+    let mut trailing_literals = 0;
+    let mut last_match = None;
+
+    loop {
+        let input_offset = iter.consumed();
+        let violation = |kind| Violation { kind, input_offset, output_offset };
+        match iter.next() {
+            None => break,
+            Some(Err(e)) => {
+                violations.push(violation(ViolationKind::Malformed(e)));
+                return violations;
+            }
+            Some(Ok(Sequence::Literal { len })) => {
+                output_offset += len;
+                trailing_literals += len;
+            }
+            Some(Ok(Sequence::Match { offset, len })) => {
+                if usize::from(offset) > output_offset + prefix_len {
+                    violations.push(violation(ViolationKind::OffsetOutOfWindow));
+                }
+                last_match = Some(violation(ViolationKind::MatchTooCloseToEnd));
+                output_offset += len;
+                trailing_literals = 0;
+            }
+        }
+    }
+
+    if let Some(last_match) = last_match {
+        if output_offset - last_match.output_offset < MFLIMIT {
+            violations.push(last_match);
+        }
+        if trailing_literals < LASTLITERALS {
+            violations.push(Violation {
+                kind: ViolationKind::LastLiteralsTooShort,
+                input_offset: input.len(),
+                output_offset: output_offset - trailing_literals,
+            });
+        }
+    }
+    violations
+}
+
+/// Check that a raw block can be decoded and return its decompressed size, without decompressing anything.
+///
+/// This walks the token stream and checks every length and offset, exactly like `decompress_raw_strict` would,
+/// so you can reject malformed blocks (or blocks that would decompress to more than `output_limit` bytes)
+/// before you allocate a single byte for the output. `prefix_len` is the amount of history (dictionary or previous
+/// blocks) that the decoder is going to have. Unlike `check_conformance`, this only cares about whether
+/// the block can be decoded, not about the rules of the reference encoder.
+pub fn validate_block(input: &[u8], prefix_len: usize, output_limit: usize) -> Result<usize, DecodeError> {
+    let mut output_len = 0usize;
+    for sequence in sequences(input) {
+        let len = match sequence? {
+            Sequence::Literal { len } => len,
+            Sequence::Match { offset, len } => {
+                if usize::from(offset) > output_len.saturating_add(prefix_len) {
+                    return Err(DecodeError::InvalidDeduplicationOffset);
+                }
+                len
+            }
+        };
+        output_len = output_len.checked_add(len).filter(|&len| len <= output_limit).ok_or(DecodeError::MemoryLimitExceeded)?;
+    }
+    Ok(output_len)
+}
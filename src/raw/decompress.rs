@@ -3,6 +3,10 @@ use std::io::{self, Cursor, Read, ErrorKind};
 use thiserror::Error;
 use culpa::{throws, throw};
 
+use super::{check_conformance, Violation, ViolationKind};
+
+use crate::framed::WINDOW_SIZE;
+
 /// Errors when decoding a raw LZ4 block.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
 pub enum DecodeError {
@@ -14,6 +18,8 @@ pub enum DecodeError {
     ZeroDeduplicationOffset,
     #[error("The offset for a deduplication is out of bounds. This may be caused by a missing or incomplete dictionary.")]
     InvalidDeduplicationOffset,
+    #[error("The block can be decoded, but it breaks the rules of the LZ4 block format (and you asked for strict conformance).")]
+    NonConforming,
 }
 type Error = DecodeError; // do it this way for better docs
 
@@ -28,7 +34,7 @@ impl From<io::Error> for Error {
 /// This is how LZ4 encodes varints.
 /// Just keep reading and adding while it's all F
 #[throws]
-fn read_lsic(initial: u8, cursor: &mut Cursor<&[u8]>) -> usize {
+pub(super) fn read_lsic(initial: u8, cursor: &mut Cursor<&[u8]>) -> usize {
     let mut value: usize = initial.into();
     if value == 0xF {
         loop {
@@ -55,12 +61,129 @@ fn read_lsic(initial: u8, cursor: &mut Cursor<&[u8]>) -> usize {
 /// `output_limit` specifies a soft upper limit for the size of `output` (including
 /// the data you passed on input). Note that this is only a measure to protect from
 /// DoS attacks and in the worst case, we may exceed it by up to `input.len()` bytes.
+/// Use `decompress_raw_strict` if you need a hard limit.
 #[throws]
 pub fn decompress_raw(input: &[u8], prefix: &[u8], output: &mut Vec<u8>, output_limit: usize) {
+    decompress_raw_internal(input, prefix, output, output_limit, false, None)?;
+}
+
+/// Like `decompress_raw` (or `decompress_raw_strict`), but tells `progress` how large `output` is after every sequence.
+#[throws]
+pub(crate) fn decompress_raw_with_progress(input: &[u8], prefix: &[u8], output: &mut Vec<u8>, output_limit: usize, strict: bool,
+                                           progress: &mut dyn FnMut(usize)) {
+    decompress_raw_internal(input, prefix, output, output_limit, strict, Some(progress))?;
+}
+
+/// Like `decompress_raw`, but `output` never grows beyond `output_limit`, not even by a single byte.
+///
+/// This makes the limit a real allocation bound for untrusted input: if you reserve `output_limit` bytes
+/// up front, `output` never reallocates. We also refuse literal runs that are longer than the rest of the input
+/// before allocating anything for them. If the block doesn't fit, you get `MemoryLimitExceeded`
+/// and `output` contains everything up to the literal run or match that didn't fit.
+#[throws]
+pub fn decompress_raw_strict(input: &[u8], prefix: &[u8], output: &mut Vec<u8>, output_limit: usize) {
+    decompress_raw_internal(input, prefix, output, output_limit, true, None)?;
+}
+
+/// A builder-style struct that configures how raw blocks are decoded.
+///
+/// This bundles all the knobs of `decompress_raw` and friends, so you don't have to care about the ones you don't need.
+/// Create it using `Default::default()`.
+#[derive(Clone, Debug)]
+pub struct DecodeOptions<'a> {
+    prefix: &'a [u8],
+    output_limit: usize,
+    strict_limit: bool,
+    max_expansion: Option<usize>,
+    conformance: bool,
+}
+impl Default for DecodeOptions<'_> {
+    fn default() -> Self {
+        DecodeOptions { prefix: &[], output_limit: usize::MAX, strict_limit: false, max_expansion: None, conformance: false }
+    }
+}
+impl<'a> DecodeOptions<'a> {
+    /// History that the block can refer back to (a dictionary or the previous blocks), see `decompress_raw`.
+    ///
+    /// By default, there is no prefix.
+    pub fn prefix(&mut self, prefix: &'a [u8]) -> &mut Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// An upper limit for the size of the output (including whatever was in there before).
+    ///
+    /// By default, there is no limit.
+    pub fn output_limit(&mut self, limit: usize) -> &mut Self {
+        self.output_limit = limit;
+        self
+    }
+
+    /// Whether the output limit is hard (see `decompress_raw_strict`) or may be exceeded a bit (see `decompress_raw`).
+    ///
+    /// The limit is soft by default.
+    pub fn strict_limit(&mut self, v: bool) -> &mut Self {
+        self.strict_limit = v;
+        self
+    }
+
+    /// Refuse to produce more than `ratio` bytes of output per byte of input.
+    ///
+    /// LZ4 can expand data by a factor of about 250, so a tiny malicious block can still ask for a lot of memory.
+    /// If you know what kind of data to expect, this is an easy way to turn that into an error early on.
+    /// Exceeding the ratio fails with `MemoryLimitExceeded`, just like the output limit.
+    ///
+    /// By default, there is no such limit.
+    pub fn max_expansion(&mut self, ratio: usize) -> &mut Self {
+        self.max_expansion = Some(ratio);
+        self
+    }
+
+    /// Reject blocks that break the rules of the block format (see `check_conformance`) with `NonConforming`,
+    /// even though we could decode them. This is useful if the data is going to be decoded by other implementations as well.
+    ///
+    /// This is off by default. Note that it requires an extra pass over the block.
+    pub fn conformance(&mut self, v: bool) -> &mut Self {
+        self.conformance = v;
+        self
+    }
+
+    /// Decode a raw block and append it to `output`.
+    ///
+    /// Whatever is already in `output` counts as history, just like with `decompress_raw`.
+    #[throws]
+    pub fn decode(&self, input: &[u8], output: &mut Vec<u8>) {
+        if self.conformance {
+            match check_conformance(input, self.prefix.len() + output.len()).first() {
+                None => (),
+                Some(Violation { kind: ViolationKind::Malformed(e), .. }) => throw!(*e),
+                Some(_) => throw!(Error::NonConforming),
+            }
+        }
+        let mut output_limit = self.output_limit;
+        if let Some(ratio) = self.max_expansion {
+            output_limit = output_limit.min(output.len().saturating_add(input.len().saturating_mul(ratio)));
+        }
+        decompress_raw_internal(input, self.prefix, output, output_limit, self.strict_limit, None)?;
+    }
+}
+
+#[throws]
+fn decompress_raw_internal(input: &[u8], prefix: &[u8], output: &mut Vec<u8>, output_limit: usize, strict: bool,
+                           mut progress: Option<&mut dyn FnMut(usize)>) {
     let mut reader = Cursor::new(input);
     while let Ok(token) = reader.read_u8() {
         // read literals
         let literal_length = read_lsic(token >> 4, &mut reader)?;
+        if strict {
+            let remaining = input.len() - reader.position() as usize;
+            if literal_length > remaining {
+                throw!(Error::UnexpectedEnd);
+            }
+            if output.len() + literal_length > output_limit {
+                throw!(Error::MemoryLimitExceeded);
+            }
+        }
 
         let output_pos_pre_literal = output.len();
         output.resize(output_pos_pre_literal + literal_length, 0);
@@ -74,30 +197,36 @@ pub fn decompress_raw(input: &[u8], prefix: &[u8], output: &mut Vec<u8>, output_
             }
             copy_overlapping(offset.into(), match_len, prefix, output)?;
         }
+        if let Some(progress) = progress.as_mut() {
+            progress(output.len());
+        }
     }
 }
 
-fn copy_overlapping(offset: usize, match_len: usize, prefix: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
-    let old_len = output.len();
-    match offset {
-        0 => return Err(Error::ZeroDeduplicationOffset),
-        i if i > old_len => {
-            // need prefix for this
-            let prefix_needed = i - old_len;
-            if prefix_needed > prefix.len() {
-                return Err(Error::InvalidDeduplicationOffset);
-            }
-            let how_many_bytes_from_prefix = std::cmp::min(prefix_needed, match_len);
-            output.extend_from_slice(
-                &prefix[prefix.len() - prefix_needed..][..how_many_bytes_from_prefix],
-            );
-            let remaining_len = match_len - how_many_bytes_from_prefix;
-            if remaining_len != 0 {
-                // offset stays the same because our curser moved forward by the amount of bytes we took from prefix
-                return copy_overlapping(offset, remaining_len, &[], output);
-            }
+fn copy_overlapping(offset: usize, mut match_len: usize, prefix: &[u8], output: &mut Vec<u8>) -> Result<(), Error> {
+    if offset == 0 {
+        return Err(Error::ZeroDeduplicationOffset);
+    }
+    if offset > output.len() {
+        // need prefix for this
+        let prefix_needed = offset - output.len();
+        if prefix_needed > prefix.len() {
+            return Err(Error::InvalidDeduplicationOffset);
         }
+        let how_many_bytes_from_prefix = std::cmp::min(prefix_needed, match_len);
+        output.extend_from_slice(
+            &prefix[prefix.len() - prefix_needed..][..how_many_bytes_from_prefix],
+        );
+        match_len -= how_many_bytes_from_prefix;
+        if match_len == 0 {
+            return Ok(());
+        }
+        // the rest comes from the output itself, with the same offset
+        // because our cursor moved forward by the amount of bytes we took from prefix
+    }
 
+    let old_len = output.len();
+    match offset {
         // fastpath: memset if we repeat the same byte forever
         1 => output.resize(old_len + match_len, output[old_len - 1]),
 
@@ -138,10 +267,73 @@ fn copy_overlapping(offset: usize, match_len: usize, prefix: &[u8], output: &mut
 }
 
 
+/// Decompresses a stream of raw blocks that refer back to earlier blocks.
+///
+/// This is the counterpart to `StreamCompressor` and the equivalent of liblz4's `LZ4_decompress_safe_continue`:
+/// it keeps the last 64 KiB of output around, so every block can reference the blocks before it.
+/// You have to feed it exactly the same blocks in exactly the same order as they were compressed.
+pub struct StreamDecompressor {
+    /// The last (up to) 64 KiB of output, followed by the most recent block.
+    buffer: Vec<u8>,
+    /// Where the most recent block starts.
+    start: usize,
+}
+
+impl Default for StreamDecompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamDecompressor {
+    pub fn new() -> Self {
+        StreamDecompressor { buffer: Vec::with_capacity(2 * WINDOW_SIZE), start: 0 }
+    }
+
+    /// Start with a dictionary, i.e. the first block can already refer back to it.
+    pub fn with_dictionary(dictionary: &[u8]) -> Self {
+        let mut decompressor = Self::new();
+        decompressor.reset_with_dictionary(dictionary);
+        decompressor
+    }
+
+    /// Decompress a single block and return its contents.
+    ///
+    /// `output_limit` is a soft upper limit for the size of this block (see `decompress_raw`).
+    /// If this fails, the stream is broken and you have to `reset` both sides.
+    #[throws]
+    pub fn decompress(&mut self, input: &[u8], output_limit: usize) -> &[u8] {
+        if self.buffer.len() > WINDOW_SIZE {
+            self.buffer.drain(..self.buffer.len() - WINDOW_SIZE);
+        }
+        self.start = self.buffer.len();
+        if let Err(e) = decompress_raw(input, &[], &mut self.buffer, self.start.saturating_add(output_limit)) {
+            // at least don't leave garbage in the history
+            self.buffer.truncate(self.start);
+            throw!(e);
+        }
+        &self.buffer[self.start..]
+    }
+
+    /// Forget all history, so the next block must not depend on anything.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.start = 0;
+    }
+
+    /// Forget all history, then continue as if `dictionary` had been the last block.
+    pub fn reset_with_dictionary(&mut self, dictionary: &[u8]) {
+        self.reset();
+        self.buffer.extend_from_slice(&dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..]);
+        self.start = self.buffer.len();
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use culpa::throws;
-    use super::{decompress_raw, Error};
+    use super::{decompress_raw, decompress_raw_strict, DecodeOptions, Error};
+    use crate::raw::{check_conformance, encode_sequences, sequences, validate_block, Sequence, ViolationKind};
 
     #[throws]
     pub fn decompress(input: &[u8]) -> Vec<u8> {
@@ -168,9 +360,139 @@ pub mod test {
         assert_eq!(decompress(&[0x30, b'a', b'4', b'9']).unwrap(), b"a49");
     }
 
+    #[test]
+    fn strict_limit() {
+        let block = [0x11, b'a', 1, 0, 0x22, b'b', b'c', 2, 0];
+        for limit in 0..14 {
+            let mut output = Vec::new();
+            assert_eq!(decompress_raw_strict(&block, &[], &mut output, limit), Err(Error::MemoryLimitExceeded));
+            assert!(output.len() <= limit);
+        }
+        let mut output = Vec::new();
+        decompress_raw_strict(&block, &[], &mut output, 14).unwrap();
+        assert_eq!(output, b"aaaaaabcbcbcbc");
+
+        // a literal run that claims to be longer than the input doesn't allocate anything
+        let mut output = Vec::new();
+        assert_eq!(decompress_raw_strict(&[0xF0, 0xFF, 0xFF, 0xFF, b'a'], &[], &mut output, usize::MAX), Err(Error::UnexpectedEnd));
+        assert_eq!(output.capacity(), 0);
+    }
+
+    #[test]
+    fn options() {
+        let block = [0x11, b'a', 1, 0, 0x22, b'b', b'c', 2, 0];
+        let mut output = Vec::new();
+        DecodeOptions::default().decode(&block, &mut output).unwrap();
+        assert_eq!(output, b"aaaaaabcbcbcbc");
+
+        let mut output = Vec::new();
+        let err = DecodeOptions::default().max_expansion(1).strict_limit(true).decode(&block, &mut output).unwrap_err();
+        assert_eq!(err, Error::MemoryLimitExceeded);
+        assert!(output.len() <= block.len());
+
+        // valid, but the last bytes aren't literals
+        let err = DecodeOptions::default().conformance(true).decode(&block, &mut Vec::new()).unwrap_err();
+        assert_eq!(err, Error::NonConforming);
+        let err = DecodeOptions::default().conformance(true).decode(&[0x40, b'a'], &mut Vec::new()).unwrap_err();
+        assert_eq!(err, Error::UnexpectedEnd);
+
+        let mut output = Vec::new();
+        DecodeOptions::default().prefix(b"xyz").decode(&[0x00, 3, 0], &mut output).unwrap();
+        assert_eq!(output, b"xyzx");
+    }
+
+    #[test]
+    fn many_prefix_crossing_matches() {
+        // every match takes two bytes from the prefix and two from its own output
+        let prefix: Vec<u8> = (0..=255).collect();
+        let mut block = Vec::new();
+        let mut expected: Vec<u8> = Vec::new();
+        for i in 0..10_000 {
+            let offset = expected.len() + 2;
+            block.push(0x00);
+            block.extend_from_slice(&(offset as u16).to_le_bytes());
+            for _ in 0..4 {
+                let b = if offset > expected.len() { prefix[prefix.len() + expected.len() - offset] } else { expected[expected.len() - offset] };
+                expected.push(b);
+            }
+            assert_eq!(expected.len(), 4 * (i + 1));
+        }
+
+        let mut output = Vec::new();
+        decompress_raw(&block, &prefix, &mut output, usize::MAX).unwrap();
+        assert_eq!(output, expected);
+    }
+
     #[test]
     fn offset_oob() {
         decompress(&[0x10, b'a', 2, 0]).unwrap_err();
         decompress(&[0x40, b'a', 1, 0]).unwrap_err();
     }
+
+    #[test]
+    fn sequence_events() {
+        let events: Result<Vec<_>, _> = sequences(&[0x11, b'a', 1, 0, 0x02, 2, 0, 0x20, b'b', b'c']).collect();
+        assert_eq!(events.unwrap(), [
+            Sequence::Literal { len: 1 },
+            Sequence::Match { offset: 1, len: 5 },
+            Sequence::Match { offset: 2, len: 6 },
+            Sequence::Literal { len: 2 },
+        ]);
+
+        let mut events = sequences(&[0x40, b'a']);
+        assert_eq!(events.next(), Some(Err(Error::UnexpectedEnd)));
+        assert_eq!(events.next(), None);
+    }
+
+    #[test]
+    fn encoded_sequences_roundtrip() {
+        let input = b"abcabcabcabcxyzxyzxyz!";
+        let events = [
+            Sequence::Literal { len: 3 },
+            Sequence::Match { offset: 3, len: 9 },
+            Sequence::Literal { len: 3 },
+            Sequence::Match { offset: 3, len: 6 },
+        ];
+        let mut block = Vec::new();
+        encode_sequences(input, 0, events.iter().copied(), &mut block).unwrap();
+        assert_eq!(decompress(&block).unwrap(), input);
+
+        let parsed: Vec<_> = sequences(&block).collect::<Result<_, _>>().unwrap();
+        assert_eq!(parsed[..4], events);
+        assert_eq!(parsed[4], Sequence::Literal { len: 1 });
+
+        encode_sequences(input, 0, [Sequence::Match { offset: 1, len: 4 }], &mut Vec::new()).unwrap_err();
+    }
+
+    #[test]
+    fn conformance() {
+        assert!(check_conformance(&[0x30, b'a', b'4', b'9'], 0).is_empty());
+
+        let violations = check_conformance(&[0x11, b'a', 2, 0], 0);
+        let kinds: Vec<_> = violations.iter().map(|v| v.kind).collect();
+        assert_eq!(kinds, [ViolationKind::OffsetOutOfWindow, ViolationKind::MatchTooCloseToEnd, ViolationKind::LastLiteralsTooShort]);
+        assert!(check_conformance(&[0x11, b'a', 2, 0], 1).iter().all(|v| v.kind != ViolationKind::OffsetOutOfWindow));
+
+        let violations = check_conformance(&[0x40, b'a'], 0);
+        assert_eq!(violations[0].kind, ViolationKind::Malformed(Error::UnexpectedEnd));
+    }
+
+    #[test]
+    fn validate_block_without_output() {
+        let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(100);
+        let block = crate::raw::compress_independent_block(&input);
+        assert_eq!(validate_block(&block, 0, input.len()), Ok(input.len()));
+        assert_eq!(validate_block(&block, 0, input.len() - 1), Err(Error::MemoryLimitExceeded));
+
+        // the match reaches one byte into the prefix
+        assert_eq!(validate_block(&[0x11, b'a', 2, 0], 0, 100), Err(Error::InvalidDeduplicationOffset));
+        assert_eq!(validate_block(&[0x11, b'a', 2, 0], 1, 100), Ok(6));
+        assert_eq!(validate_block(&[0x11, b'a', 0, 0], 0, 100), Err(Error::ZeroDeduplicationOffset));
+        assert_eq!(validate_block(&[0x40, b'a'], 0, 100), Err(Error::UnexpectedEnd));
+        for end in 0..block.len() {
+            let mut output = Vec::new();
+            let expected = decompress_raw_strict(&block[..end], &[], &mut output, usize::MAX).map(|()| output.len());
+            assert_eq!(validate_block(&block[..end], 0, usize::MAX), expected);
+        }
+    }
 }
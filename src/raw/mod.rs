@@ -10,8 +10,14 @@
 //! (but does get you lots of nice features).
 
 mod compress;
+mod conformance;
 mod decompress;
+mod pages;
+mod sequence;
 
 pub use compress::*;
+pub use conformance::*;
 pub use decompress::*;
+pub use pages::*;
+pub use sequence::*;
 
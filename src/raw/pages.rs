@@ -0,0 +1,138 @@
+use super::{compress_to_slice, decompress_raw_strict, DecodeError, EncoderTable, U16Table, U32Table, DEFAULT_ACCELERATION};
+
+/// Compresses and decompresses raw blocks of a fixed size, like the pages of a filesystem image or a pak file.
+///
+/// Containers like that (squashfs, for example) split their data into pages of the same uncompressed size,
+/// compress every page into a raw block of its own and keep the compressed sizes in an index of their own.
+/// They also store a page as-is if compressing it doesn't make it any smaller, and tell the two apart by the size:
+/// a block that is as large as its page is stored. `PageCodec` follows that convention, so all you need
+/// to keep track of is where every block starts and how long it is.
+///
+/// Compared to gluing this together from `compress_into` and `decompress_raw_strict` by hand,
+/// the hash table is shared by all pages (instead of setting up a new one every time),
+/// and decoding never goes beyond the page it knows it's decoding.
+/// Every block is exactly what `compress_into` would have produced for the page, so blocks are independent of each other.
+pub struct PageCodec {
+    page_size: usize,
+    table: PageTable,
+}
+
+/// Pages that fit into a `U16Table` get one, just like in `compress_into`.
+enum PageTable {
+    Small(Box<U16Table>),
+    Large(Box<U32Table>),
+}
+
+impl PageCodec {
+    /// Pages of `page_size` bytes (only the last one may be shorter).
+    pub fn new(page_size: usize) -> Self {
+        assert!(page_size > 0, "pages can't be empty");
+        let table = if page_size <= U16Table::default().payload_size_limit() {
+            PageTable::Small(Box::default())
+        } else {
+            PageTable::Large(Box::default())
+        };
+        PageCodec { page_size, table }
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Compress a single page into `output` and return how many bytes were written.
+    ///
+    /// If that's `page.len()`, the page didn't compress, so it was stored as-is.
+    /// `output` must be at least as large as `page`, which must not be larger than a page.
+    pub fn compress_page(&mut self, page: &[u8], output: &mut [u8]) -> usize {
+        assert!(page.len() <= self.page_size, "a page of {} bytes doesn't fit into pages of {}", page.len(), self.page_size);
+        assert!(output.len() >= page.len(), "the output needs room for at least the whole page");
+
+        // a compressed block has to be smaller than the page, otherwise it would look stored
+        let limit = &mut output[..page.len().saturating_sub(1)];
+        let compressed = match &mut self.table {
+            PageTable::Small(table) => {
+                **table = U16Table::default();
+                compress_to_slice(page, 0, &mut **table, DEFAULT_ACCELERATION, None, limit)
+            }
+            PageTable::Large(table) => {
+                **table = U32Table::default();
+                compress_to_slice(page, 0, &mut **table, DEFAULT_ACCELERATION, None, limit)
+            }
+        };
+        match compressed {
+            Ok(len) => len,
+            Err(_) => {
+                output[..page.len()].copy_from_slice(page);
+                page.len()
+            }
+        }
+    }
+
+    /// Decompress a single block (compressed or stored) that holds a page of `page_len` bytes, and append it to `output`.
+    ///
+    /// `page_len` is usually the page size, except for the last page. It's an error if the block
+    /// decodes to anything other than exactly `page_len` bytes, and `output` never grows beyond that.
+    pub fn decompress_page(&self, block: &[u8], page_len: usize, output: &mut Vec<u8>) -> Result<(), DecodeError> {
+        assert!(page_len <= self.page_size, "a page of {} bytes doesn't fit into pages of {}", page_len, self.page_size);
+        if block.len() == page_len {
+            output.extend_from_slice(block);
+            return Ok(());
+        }
+        if block.len() > page_len {
+            // too large to be compressed, and stored pages are exactly the page
+            return Err(DecodeError::NonConforming);
+        }
+
+        let start = output.len();
+        output.reserve(page_len);
+        let result = decompress_raw_strict(block, &[], output, start + page_len);
+        if result.is_ok() && output.len() == start + page_len {
+            return Ok(());
+        }
+        output.truncate(start);
+        // if the block decoded fine but was too short, it must have been cut off
+        Err(result.err().unwrap_or(DecodeError::UnexpectedEnd))
+    }
+
+    /// Compress all of `input` page by page, append the blocks to `output` and return their sizes.
+    ///
+    /// The sizes are what you'd keep in your index (block `n` starts after the sum of the first `n` sizes).
+    pub fn compress_pages(&mut self, input: &[u8], output: &mut Vec<u8>) -> Vec<usize> {
+        let mut sizes = Vec::with_capacity(input.len().div_ceil(self.page_size));
+        for page in input.chunks(self.page_size) {
+            let start = output.len();
+            output.resize(start + page.len(), 0);
+            let len = self.compress_page(page, &mut output[start..]);
+            output.truncate(start + len);
+            sizes.push(len);
+        }
+        sizes
+    }
+
+    /// Decompress the blocks of `compress_pages` back into `len` bytes and append them to `output`.
+    ///
+    /// `blocks` are the blocks one after another, and `sizes` how long every one of them is.
+    /// The input is split into pages based on `len` alone, so a missing or extra block is an error.
+    pub fn decompress_pages(&self, blocks: &[u8], sizes: &[usize], len: usize, output: &mut Vec<u8>) -> Result<(), DecodeError> {
+        if sizes.len() != len.div_ceil(self.page_size) {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let start = output.len();
+        output.reserve(len);
+        let mut blocks = blocks;
+        for (i, &size) in sizes.iter().enumerate() {
+            if size > blocks.len() {
+                output.truncate(start);
+                return Err(DecodeError::UnexpectedEnd);
+            }
+            let (block, rest) = blocks.split_at(size);
+            blocks = rest;
+            let page_len = (len - i * self.page_size).min(self.page_size);
+            if let Err(e) = self.decompress_page(block, page_len, output) {
+                output.truncate(start);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use byteorder::{LE, ReadBytesExt};
+use std::io::Cursor;
+
+use super::{read_lsic, DecodeError};
+type Error = DecodeError;
+
+/// A single event in the token stream of a raw LZ4 block.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Sequence {
+    /// `len` bytes are copied verbatim from the compressed block.
+    Literal { len: usize },
+    /// `len` bytes are copied from `offset` bytes before the current output position.
+    Match { offset: u16, len: usize },
+}
+
+/// Iterator over the token stream of a raw LZ4 block, created by `sequences`.
+pub struct Sequences<'a> {
+    reader: Cursor<&'a [u8]>,
+    pending_match: Option<u8>,
+    failed: bool,
+}
+
+/// Parse a raw LZ4 block into its literal and match events without decompressing anything.
+///
+/// This is useful for debugging and for research on the token stream itself.
+/// Note that match offsets are not checked against the output position (or any prefix), as this would require
+/// knowing how much history the decoder is going to have.
+/// The iterator stops after the first error.
+pub fn sequences(input: &[u8]) -> Sequences<'_> {
+    Sequences { reader: Cursor::new(input), pending_match: None, failed: false }
+}
+
+impl Sequences<'_> {
+    /// Number of input bytes consumed so far.
+    pub fn consumed(&self) -> usize {
+        self.reader.position() as usize
+    }
+
+    fn next_sequence(&mut self) -> Result<Option<Sequence>, Error> {
+        loop {
+            if let Some(nibble) = self.pending_match.take() {
+                // the final sequence has no match part, just like in decompress_raw
+                if let Ok(offset) = self.reader.read_u16::<LE>() {
+                    if offset == 0 {
+                        return Err(Error::ZeroDeduplicationOffset);
+                    }
+                    let len = 4 + read_lsic(nibble, &mut self.reader)?;
+                    return Ok(Some(Sequence::Match { offset, len }));
+                }
+            }
+
+            let token = match self.reader.read_u8() {
+                Ok(token) => token,
+                Err(_) => return Ok(None),
+            };
+            let len = read_lsic(token >> 4, &mut self.reader)?;
+            let remaining = self.reader.get_ref().len() - self.consumed();
+            if len > remaining {
+                return Err(Error::UnexpectedEnd);
+            }
+            self.reader.set_position((self.consumed() + len) as u64);
+            self.pending_match = Some(token & 0xf);
+
+            if len != 0 {
+                return Ok(Some(Sequence::Literal { len }));
+            }
+        }
+    }
+}
+
+impl Iterator for Sequences<'_> {
+    type Item = Result<Sequence, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let result = self.next_sequence().transpose();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
@@ -0,0 +1,240 @@
+//! Checks the codec against reference frames that were made by the lz4 CLI, at runtime.
+//!
+//! The test suite makes sure we match the C implementation, but it runs on the machine that built us.
+//! If you cross-compile to an unusual target (or with an unusual toolchain), `self_test` lets you check
+//! on the target itself that everything still works: it decompresses every reference frame and compresses its input again,
+//! which has to give exactly the same frame. The frames take up about 150 KiB, and checking them takes a few milliseconds.
+//!
+//! The frames were made on a 64-bit little-endian machine. liblz4 (just like us) hashes differently everywhere else,
+//! so compressing gives different (but just as valid) frames there. On those targets, we decompress what we compressed instead.
+
+use std::fmt;
+use thiserror::Error;
+
+use crate::framed::{CompressionError, CompressionSettings, DecompressionError, LZ4FrameReader};
+
+/// Errors for a single reference frame.
+#[derive(Error, Debug)]
+pub enum SelfTestError {
+    #[error("decompression failed")]
+    Decompression(#[from] DecompressionError),
+    #[error("decompression gave the wrong output")]
+    WrongOutput,
+    #[error("compressing the input failed")]
+    Compression(#[from] CompressionError),
+    #[error("compressing the input gave a different frame than the reference")]
+    DifferentFrame,
+}
+
+/// What `self_test` found out about a single reference frame.
+#[derive(Debug)]
+pub struct SelfTestCase {
+    /// The flags that the lz4 CLI made the frame with, e.g. `-B4 -BD --no-frame-crc`.
+    pub args: &'static str,
+    /// How long the input is.
+    pub len: usize,
+    /// Whether decompressing the frame gave us the input.
+    pub decompression: Result<(), SelfTestError>,
+    /// Whether compressing the input gave us the frame (or at least something that decompresses to the input,
+    /// on targets where liblz4 would give us something else, too).
+    pub compression: Result<(), SelfTestError>,
+}
+
+impl SelfTestCase {
+    pub fn passed(&self) -> bool {
+        self.decompression.is_ok() && self.compression.is_ok()
+    }
+}
+
+/// The result of `self_test`: one case per reference frame.
+#[derive(Debug)]
+pub struct SelfTestReport {
+    pub cases: Vec<SelfTestCase>,
+}
+
+impl SelfTestReport {
+    /// Whether everything worked.
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(SelfTestCase::passed)
+    }
+
+    /// The cases that didn't.
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestCase> {
+        self.cases.iter().filter(|case| !case.passed())
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let failed = self.failures().count();
+        writeln!(f, "{} of {} reference frames passed", self.cases.len() - failed, self.cases.len())?;
+        for case in self.failures() {
+            for result in [&case.decompression, &case.compression] {
+                if let Err(e) = result {
+                    writeln!(f, "lz4 {} ({} bytes): {}", case.args, case.len, e)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run the codec against the embedded reference frames and report how it went.
+///
+/// Every flag of the frame format is covered (in all combinations where that makes sense), and so are
+/// dictionaries, all block sizes, frames with several blocks (including a stored one) and an empty frame.
+/// This never panics: anything that goes wrong ends up in the report.
+pub fn self_test() -> SelfTestReport {
+    let dictionary = dictionary();
+    let cases = VECTORS.iter().map(|vector| {
+        let input = input(vector.len);
+        let dictionary = if vector.args.split(' ').any(|arg| arg == "-D") { &dictionary[..] } else { &[] };
+        SelfTestCase {
+            args: vector.args,
+            len: vector.len,
+            decompression: check_decompression(vector, &input, dictionary),
+            compression: check_compression(vector, &input, dictionary),
+        }
+    }).collect();
+    SelfTestReport { cases }
+}
+
+fn check_decompression(vector: &Vector, input: &[u8], dictionary: &[u8]) -> Result<(), SelfTestError> {
+    let mut output = Vec::new();
+    LZ4FrameReader::new(vector.frame)?.decode_to_writer(dictionary, &mut output)?;
+    if output != input {
+        return Err(SelfTestError::WrongOutput);
+    }
+    Ok(())
+}
+
+fn check_compression(vector: &Vector, input: &[u8], dictionary: &[u8]) -> Result<(), SelfTestError> {
+    let mut settings = CompressionSettings::default();
+    let mut content_size = false;
+    for arg in vector.args.split(' ') {
+        match arg {
+            "-B4" => { settings.block_size(64 * 1024); }
+            "-B5" => { settings.block_size(256 * 1024); }
+            "-B6" => { settings.block_size(1024 * 1024); }
+            "-B7" => { settings.block_size(4 * 1024 * 1024); }
+            "-BD" => { settings.independent_blocks(false); }
+            "-BX" => { settings.block_checksums(true); }
+            "--no-frame-crc" => { settings.content_checksum(false); }
+            "--content-size" => content_size = true,
+            // the CLI never writes a dictionary id
+            "-D" => { settings.dictionary(0, dictionary).dictionary_id_nonsense_override(None); }
+            _ => unreachable!("unknown flag {}", arg),
+        }
+    }
+
+    let mut frame = Vec::new();
+    if content_size {
+        settings.compress_with_size_unchecked(input, &mut frame, input.len() as u64)?;
+    } else {
+        settings.compress(input, &mut frame)?;
+    }
+    if cfg!(all(target_pointer_width = "64", target_endian = "little")) {
+        if frame != vector.frame {
+            return Err(SelfTestError::DifferentFrame);
+        }
+    } else {
+        let mut output = Vec::new();
+        LZ4FrameReader::new(&frame[..])?.decode_to_writer(dictionary, &mut output)?;
+        if output != input {
+            return Err(SelfTestError::WrongOutput);
+        }
+    }
+    Ok(())
+}
+
+/// A reference frame, made with `lz4 <args> input` (and lz4 1.9.4), where the input is `input(len)`.
+///
+/// `-D` is short for `-D dictionary`, with the dictionary from `dictionary()`.
+struct Vector {
+    args: &'static str,
+    len: usize,
+    frame: &'static [u8],
+}
+
+/// Fits into one block of any size.
+///
+/// Note that the CLI makes frames with a single block independent (even with `-BD`), because there's nothing to link.
+const SMALL: usize = 5000;
+/// One full block of 64 KiB and a short one.
+const MEDIUM: usize = 72 * 1024;
+/// Two full blocks of 64 KiB and a short one that doesn't compress.
+const LARGE: usize = 2 * 64 * 1024 + NOISE;
+/// How much noise there is at the end of the input.
+const NOISE: usize = 1000;
+
+static VECTORS: &[Vector] = &[
+    Vector { args: "-B4", len: MEDIUM, frame: include_bytes!("vectors/B4.lz4") },
+    Vector { args: "-B4 -BD", len: MEDIUM, frame: include_bytes!("vectors/B4_BD.lz4") },
+    Vector { args: "-B4 -BX", len: MEDIUM, frame: include_bytes!("vectors/B4_BX.lz4") },
+    Vector { args: "-B4 -BD -BX", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_BX.lz4") },
+    Vector { args: "-B4 --no-frame-crc", len: MEDIUM, frame: include_bytes!("vectors/B4_no-frame-crc.lz4") },
+    Vector { args: "-B4 -BD --no-frame-crc", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_no-frame-crc.lz4") },
+    Vector { args: "-B4 -BX --no-frame-crc", len: MEDIUM, frame: include_bytes!("vectors/B4_BX_no-frame-crc.lz4") },
+    Vector { args: "-B4 -BD -BX --no-frame-crc", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_BX_no-frame-crc.lz4") },
+    Vector { args: "-B4 --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_content-size.lz4") },
+    Vector { args: "-B4 -BD --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_content-size.lz4") },
+    Vector { args: "-B4 -BX --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_BX_content-size.lz4") },
+    Vector { args: "-B4 -BD -BX --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_BX_content-size.lz4") },
+    Vector { args: "-B4 --no-frame-crc --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_no-frame-crc_content-size.lz4") },
+    Vector { args: "-B4 -BD --no-frame-crc --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_no-frame-crc_content-size.lz4") },
+    Vector { args: "-B4 -BX --no-frame-crc --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_BX_no-frame-crc_content-size.lz4") },
+    Vector { args: "-B4 -BD -BX --no-frame-crc --content-size", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_BX_no-frame-crc_content-size.lz4") },
+    // the CLI picks the smallest block size that fits a frame with a single block
+    Vector { args: "-B5", len: MEDIUM, frame: include_bytes!("vectors/B5.lz4") },
+    Vector { args: "-B6", len: 256 * 1024 + 1, frame: include_bytes!("vectors/B6.lz4") },
+    Vector { args: "-B7", len: 1024 * 1024 + 1, frame: include_bytes!("vectors/B7.lz4") },
+    Vector { args: "-B4 -D", len: SMALL, frame: include_bytes!("vectors/small_B4_D.lz4") },
+    Vector { args: "-B4 -BX --content-size -D", len: SMALL, frame: include_bytes!("vectors/small_B4_BX_content-size_D.lz4") },
+    Vector { args: "-B4 -BD -D", len: MEDIUM, frame: include_bytes!("vectors/B4_BD_D.lz4") },
+    Vector { args: "-B4 -BD", len: LARGE, frame: include_bytes!("vectors/large_B4_BD.lz4") },
+    // the CLI doesn't write a content size of 0
+    Vector { args: "-B4", len: 0, frame: include_bytes!("vectors/empty_B4.lz4") },
+];
+
+/// The input of the reference frames: a few sentences in random order with numbers in between, repeating every 32 KiB
+/// (so even the large inputs compress to almost nothing), then some noise. Always the same, of course.
+fn input(len: usize) -> Vec<u8> {
+    let text = sentences(1, 32 * 1024);
+    let mut input: Vec<u8> = text.iter().copied().cycle().take(len.saturating_sub(NOISE)).collect();
+    let mut state = 2;
+    while input.len() < len {
+        input.push((next(&mut state) >> 56) as u8);
+    }
+    input
+}
+
+/// The dictionary of the reference frames that use one.
+fn dictionary() -> Vec<u8> {
+    sentences(3, 4096)
+}
+
+fn sentences(mut state: u64, len: usize) -> Vec<u8> {
+    const SENTENCES: [&str; 8] = [
+        "The red panda eats bamboo. ",
+        "Red pandas sleep in trees for most of the day. ",
+        "A giant panda eats as much as 9 to 14 kg of bamboo shoots a day. ",
+        "Save the red panda! ",
+        "The panda bear has an amazing black-and-white fur. ",
+        "Pandas are born pink, blind and about as heavy as a stick of butter. ",
+        "There are fewer than 10000 red pandas left in the wild. ",
+        "Pandas spend up to 14 hours a day eating. ",
+    ];
+    let mut text = Vec::with_capacity(len);
+    while text.len() < len {
+        let random = next(&mut state);
+        text.extend_from_slice(SENTENCES[(random >> 61) as usize].as_bytes());
+        text.extend_from_slice(format!("{} ", random >> 32 & 0x3FF).as_bytes());
+    }
+    text.truncate(len);
+    text
+}
+
+fn next(state: &mut u64) -> u64 {
+    *state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+    *state
+}
@@ -0,0 +1,211 @@
+//! Checking frames produced by other encoders against the frame format.
+//!
+//! Our decoder is lenient wherever the format allows it and stops at the first error, which is what you want
+//! when decompressing. If you're writing an encoder, you want the opposite: a referee that tells you about
+//! every single thing your frame gets wrong, including the things that only stricter decoders would reject.
+
+use byteorder::{ByteOrder, LE};
+use std::hash::Hasher;
+use std::convert::TryFrom;
+use twox_hash::XxHash32;
+use thiserror::Error;
+use culpa::{throw, throws};
+
+use crate::framed::{block_checksum, header_checksum, INCOMPRESSIBLE, MAGIC, WINDOW_SIZE};
+use crate::framed::header::{BlockDescriptor, Flags};
+use crate::raw::{self, DecodeError, ViolationKind};
+
+/// The ways in which a frame can violate the frame format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
+pub enum FrameViolationKind {
+    #[error("wrong magic number: {0:08x}")]
+    WrongMagic(u32),
+    #[error("unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("reserved bits in the flags are set")]
+    ReservedFlagBitsSet,
+    #[error("reserved bits in the block descriptor are set")]
+    ReservedBdBitsSet,
+    #[error("block size value {0} is reserved")]
+    InvalidBlockSize(u8),
+    #[error("the header checksum is {actual:02x}, but should be {expected:02x}")]
+    HeaderChecksum { expected: u8, actual: u8 },
+    #[error("the frame ends prematurely")]
+    Truncated,
+    #[error("the block is larger than the block size from the header")]
+    BlockTooLarge,
+    #[error("the block decompresses to more than the block size from the header")]
+    BlockDecompressesTooLarge,
+    #[error("the block can not be decoded: {0}")]
+    Malformed(DecodeError),
+    #[error("the block breaks the rules of the reference encoder: {0}")]
+    BlockRules(ViolationKind),
+    #[error("the block checksum is wrong")]
+    BlockChecksum,
+    #[error("the content checksum is wrong")]
+    ContentChecksum,
+    #[error("the header says the content is {declared} bytes, but it's actually {actual}")]
+    ContentSize { declared: u64, actual: u64 },
+}
+
+/// A single violation found by `FrameValidator`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Error)]
+#[error("{kind} (at offset {offset})")]
+pub struct FrameViolation {
+    pub kind: FrameViolationKind,
+    /// Position in the frame where the offending part starts (for blocks, that's the length field).
+    pub offset: usize,
+}
+
+/// A builder-style struct that checks frames for conformance. Create it using `Default::default()`.
+///
+/// This checks the header (magic, version, reserved bits, block size and header checksum), that every block
+/// fits within the declared block size (both compressed and decompressed), that the frame is properly terminated,
+/// and that all the checksums and the content size that the flags promise are present and correct.
+#[derive(Clone, Debug, Default)]
+pub struct FrameValidator<'a> {
+    dictionary: &'a [u8],
+    block_rules: bool,
+}
+impl<'a> FrameValidator<'a> {
+    /// The dictionary the frame was compressed with.
+    pub fn dictionary(&mut self, dictionary: &'a [u8]) -> &mut Self {
+        self.dictionary = dictionary;
+        self
+    }
+
+    /// Also check every compressed block against the rules of the reference encoder (see `raw::check_conformance`).
+    ///
+    /// These rules are not strictly part of the format, but some decoders rely on them. Disabled by default.
+    pub fn block_rules(&mut self, v: bool) -> &mut Self {
+        self.block_rules = v;
+        self
+    }
+
+    /// Check a single frame. An empty result means the frame is fine.
+    ///
+    /// We keep going after most violations so you get to see all of them at once. Only when we can't
+    /// make sense of the rest of the frame (a broken header, a block that can't be decoded, or the end of the input)
+    /// do we stop. Anything after the end of the frame is ignored.
+    pub fn validate(&self, frame: &[u8]) -> Vec<FrameViolation> {
+        let mut violations = Vec::new();
+        // the only thing that can stop us early is a fatal violation
+        if let Err(violation) = self.validate_internal(frame, &mut violations) {
+            violations.push(violation);
+        }
+        violations
+    }
+
+    #[throws(FrameViolation)]
+    fn validate_internal(&self, frame: &[u8], violations: &mut Vec<FrameViolation>) {
+        let violation = |kind, offset| FrameViolation { kind, offset };
+        let take = |pos: usize, len: usize| frame.get(pos..pos + len).ok_or(violation(FrameViolationKind::Truncated, frame.len()));
+
+        let magic = LE::read_u32(take(0, 4)?);
+        if magic != MAGIC {
+            throw!(violation(FrameViolationKind::WrongMagic(magic), 0));
+        }
+
+        let descriptor = take(4, 2)?;
+        let (flags_byte, bd_byte) = (descriptor[0], descriptor[1]);
+        let version = flags_byte >> 6;
+        if version != 1 {
+            throw!(violation(FrameViolationKind::UnsupportedVersion(version), 4));
+        }
+        if flags_byte & 0b10 != 0 {
+            violations.push(violation(FrameViolationKind::ReservedFlagBitsSet, 4));
+        }
+        if bd_byte & 0b10001111 != 0 {
+            violations.push(violation(FrameViolationKind::ReservedBdBitsSet, 5));
+        }
+        let flags = Flags::from_bits_truncate(flags_byte);
+        let block_maxsize = BlockDescriptor(bd_byte & 0b01110000).block_maxsize()
+            .map_err(|_| violation(FrameViolationKind::InvalidBlockSize((bd_byte >> 4) & 0b111), 5))?;
+
+        let mut pos = 6;
+        let content_size = if flags.content_size() {
+            pos += 8;
+            Some(LE::read_u64(take(pos - 8, 8)?))
+        } else {
+            None
+        };
+        if flags.dictionary_id() {
+            take(pos, 4)?;
+            pos += 4;
+        }
+        let expected = header_checksum(&frame[4..pos]);
+        let actual = take(pos, 1)?[0];
+        if actual != expected {
+            violations.push(violation(FrameViolationKind::HeaderChecksum { expected, actual }, pos));
+        }
+        pos += 1;
+
+        let mut content_hasher = XxHash32::with_seed(0);
+        let mut content_len = 0u64;
+        let mut window = self.dictionary[self.dictionary.len().saturating_sub(WINDOW_SIZE)..].to_vec();
+        let mut output = Vec::with_capacity(block_maxsize);
+        loop {
+            let block_start = pos;
+            let block_length = LE::read_u32(take(pos, 4)?);
+            pos += 4;
+            if block_length == 0 {
+                break;
+            }
+
+            let is_compressed = block_length & INCOMPRESSIBLE == 0;
+            let len = usize::try_from(block_length & !INCOMPRESSIBLE).unwrap();
+            if len > block_maxsize {
+                violations.push(violation(FrameViolationKind::BlockTooLarge, block_start));
+            }
+            let block = take(pos, len)?;
+            pos += len;
+            if flags.block_checksums() {
+                let checksum = LE::read_u32(take(pos, 4)?);
+                pos += 4;
+                if block_checksum(block) != checksum {
+                    violations.push(violation(FrameViolationKind::BlockChecksum, block_start));
+                }
+            }
+
+            let prefix = if flags.independent_blocks() { self.dictionary } else { &window };
+            output.clear();
+            if is_compressed {
+                match raw::decompress_raw_strict(block, prefix, &mut output, block_maxsize) {
+                    Ok(()) => (),
+                    Err(DecodeError::MemoryLimitExceeded) => {
+                        // we don't know what the rest of the block would have looked like, so we can't go on
+                        throw!(violation(FrameViolationKind::BlockDecompressesTooLarge, block_start));
+                    }
+                    Err(e) => throw!(violation(FrameViolationKind::Malformed(e), block_start)),
+                }
+                if self.block_rules {
+                    for v in raw::check_conformance(block, prefix.len()) {
+                        violations.push(violation(FrameViolationKind::BlockRules(v.kind), block_start));
+                    }
+                }
+            } else {
+                if len > block_maxsize {
+                    violations.push(violation(FrameViolationKind::BlockDecompressesTooLarge, block_start));
+                }
+                output.extend_from_slice(block);
+            }
+
+            content_hasher.write(&output);
+            content_len += output.len() as u64;
+            if !flags.independent_blocks() {
+                window.extend_from_slice(&output);
+                window.drain(..window.len().saturating_sub(WINDOW_SIZE));
+            }
+        }
+
+        if flags.content_checksum() {
+            let checksum = LE::read_u32(take(pos, 4)?);
+            if content_hasher.finish() != checksum.into() {
+                violations.push(violation(FrameViolationKind::ContentChecksum, pos));
+            }
+        }
+        if let Some(declared) = content_size.filter(|&declared| declared != content_len) {
+            violations.push(violation(FrameViolationKind::ContentSize { declared, actual: content_len }, 6));
+        }
+    }
+}
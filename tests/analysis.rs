@@ -0,0 +1,24 @@
+use lz_fear::analysis::analyze_frame;
+use lz_fear::framed::CompressionSettings;
+
+#[test]
+fn analyze_repetitive_frame() {
+    let input: Vec<u8> = b"hello world, hello lz4! ".iter().copied().cycle().take(100_000).collect();
+    let mut compressed = Vec::new();
+    CompressionSettings::default()
+        .block_size(64 * 1024)
+        .compress_with_size_unchecked(&input[..], &mut compressed, input.len() as u64)
+        .unwrap();
+
+    let analysis = analyze_frame(&compressed[..], &[]).unwrap();
+    assert_eq!(analysis.block_size, 64 * 1024);
+    assert_eq!(analysis.content_size, Some(input.len() as u64));
+    assert_eq!(analysis.blocks.len(), 2);
+
+    let total = analysis.total();
+    assert!(!total.stored);
+    assert_eq!(total.decompressed_len, input.len());
+    assert_eq!(total.literal_bytes + total.match_bytes, input.len());
+    assert!(total.ratio() > 10.0);
+    assert_eq!(total.match_lengths.count(), total.match_offsets.count());
+}
@@ -0,0 +1,72 @@
+use lz_fear::archive::{ArchiveError, ArchiveReader, ArchiveWriter};
+use lz_fear::framed::{decompress_slice, CompressionSettings};
+use std::io::{Cursor, Read};
+
+fn build() -> Vec<u8> {
+    let mut writer = ArchiveWriter::new(Vec::new(), CompressionSettings::default());
+    writer.add("a.txt", &b"to live or not to live"[..]).unwrap();
+    writer.add("empty", &b""[..]).unwrap();
+    let member = writer.add("dir/pandas.txt", &b"Save the red panda! ".repeat(1000)[..]).unwrap();
+    assert_eq!(member.uncompressed_size, 20_000);
+    writer.finish().unwrap()
+}
+
+#[test]
+fn lookup_by_name() {
+    let mut reader = ArchiveReader::new(Cursor::new(build())).unwrap();
+    let names: Vec<_> = reader.members().iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["a.txt", "empty", "dir/pandas.txt"]);
+
+    assert_eq!(reader.read("dir/pandas.txt").unwrap(), b"Save the red panda! ".repeat(1000));
+    assert_eq!(reader.read("a.txt").unwrap(), b"to live or not to live");
+    let mut empty = Vec::new();
+    reader.open("empty").unwrap().read_to_end(&mut empty).unwrap();
+    assert!(empty.is_empty());
+    assert!(matches!(reader.read("missing"), Err(ArchiveError::NotFound(_))));
+}
+
+#[test]
+fn plain_decompressors_see_concatenated_members() {
+    let mut expected = b"to live or not to live".to_vec();
+    expected.extend(b"Save the red panda! ".repeat(1000));
+    assert_eq!(decompress_slice(&build(), &[]).unwrap(), expected);
+}
+
+#[test]
+fn not_an_archive() {
+    let frame = CompressionSettings::default().compress_slice(b"just a frame").unwrap();
+    assert!(matches!(ArchiveReader::new(Cursor::new(frame)), Err(ArchiveError::NoTableOfContents)));
+    assert!(matches!(ArchiveReader::new(Cursor::new(b"LZFA")), Err(ArchiveError::NoTableOfContents)));
+
+    let mut corrupt = build();
+    let len = corrupt.len();
+    corrupt[len - 20] ^= 0xFF; // somewhere in the last member's sizes
+    assert!(matches!(ArchiveReader::new(Cursor::new(corrupt)), Err(ArchiveError::CorruptTableOfContents)));
+}
+
+/// Gives up with an error once the data runs out.
+struct Failing<'a>(&'a [u8]);
+
+impl Read for Failing<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.0.read(buf)? {
+            0 => Err(std::io::Error::other("the disk is on fire")),
+            n => Ok(n),
+        }
+    }
+}
+
+#[test]
+fn broken_member() {
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024);
+    let mut writer = ArchiveWriter::new(Vec::new(), settings);
+    writer.add("good", &b"all is well"[..]).unwrap();
+    let torn = b"half a member ".repeat(20_000);
+    assert!(matches!(writer.add("torn", Failing(&torn)), Err(ArchiveError::Compression(_))));
+
+    // the offsets would be off from here on
+    assert!(matches!(writer.add("late", &b"too late"[..]), Err(ArchiveError::Broken)));
+    assert_eq!(writer.members().len(), 1);
+    assert!(matches!(writer.finish(), Err(ArchiveError::Broken)));
+}
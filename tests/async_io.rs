@@ -0,0 +1,344 @@
+#![cfg(feature = "async-futures")]
+
+use futures_lite::future::{block_on, poll_once};
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncSeekExt, AsyncWriteExt, Cursor};
+use lz_fear::framed::{decompress_slice, AsyncLZ4FrameReader, AsyncLZ4FrameWriter, AsyncLZ4RandomAccessReader, CancellationToken,
+                      CompressionSettings, DecoderStatus, DecompressionError, FrameDecoder, LZ4FrameWriter};
+use std::io::{self, SeekFrom, Write};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+fn sample() -> Vec<u8> {
+    b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3000)
+}
+
+#[test]
+fn roundtrip() {
+    let input = sample();
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).independent_blocks(false).content_checksum(true);
+
+    let compressed = block_on(async {
+        let mut writer = AsyncLZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+        for chunk in input.chunks(1000) {
+            writer.write_all(chunk).await.unwrap();
+        }
+        writer.close().await.unwrap();
+        writer.into_inner()
+    });
+    assert_eq!(decompress_slice(&compressed, &[]).unwrap(), input);
+
+    let output = block_on(async {
+        let mut output = Vec::new();
+        AsyncLZ4FrameReader::new(&compressed[..]).read_to_end(&mut output).await.unwrap();
+        output
+    });
+    assert_eq!(output, input);
+}
+
+#[test]
+fn skippable_frame() {
+    let input = sample();
+    let mut stream = vec![0x50, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3];
+    CompressionSettings::default().independent_blocks(false).compress(&input[..], &mut stream).unwrap();
+
+    // the end of the skippable frame isn't the end of the data
+    let output = block_on(async {
+        let mut output = Vec::new();
+        AsyncLZ4FrameReader::new(&stream[..]).read_to_end(&mut output).await.unwrap();
+        output
+    });
+    assert_eq!(output, input);
+}
+
+#[test]
+fn truncated() {
+    let mut compressed = Vec::new();
+    CompressionSettings::default().compress(&sample()[..], &mut compressed).unwrap();
+    compressed.truncate(compressed.len() - 1);
+
+    let result = block_on(AsyncLZ4FrameReader::new(&compressed[..]).read_to_end(&mut Vec::new()));
+    assert!(result.is_err());
+}
+
+#[test]
+fn decoder_byte_by_byte() {
+    let input = sample();
+    let mut compressed = Vec::new();
+    CompressionSettings::default().block_checksums(true).compress(&input[..], &mut compressed).unwrap();
+    // a skippable frame in front must be ignored
+    let mut stream = vec![0x50, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3];
+    stream.extend_from_slice(&compressed);
+
+    let mut decoder = FrameDecoder::new();
+    let mut output = Vec::new();
+    let mut buf = [0; 100];
+    let mut frames = 0;
+    for byte in stream.chunks(1) {
+        let mut byte = byte;
+        loop {
+            let progress = decoder.decode(byte, &mut buf).unwrap();
+            byte = &byte[progress.consumed..];
+            output.extend_from_slice(&buf[..progress.written]);
+            match progress.status {
+                DecoderStatus::NeedInput(n) => {
+                    assert!(n > 0);
+                    break;
+                }
+                DecoderStatus::OutputFull => (),
+                DecoderStatus::FrameEnd => frames += 1,
+            }
+        }
+        assert!(byte.is_empty());
+    }
+    assert_eq!(frames, 2);
+    assert!(decoder.is_idle());
+    assert_eq!(output, input);
+}
+
+#[test]
+fn decoder_oversized_block() {
+    let frame = CompressionSettings::default().content_checksum(false).compress_slice(b"").unwrap();
+    // the header without the end mark, then a block that claims to be almost 2 GiB
+    let mut stream = frame[..frame.len() - 4].to_vec();
+    stream.extend_from_slice(&0x7FFF_FFFFu32.to_le_bytes());
+
+    let mut decoder = FrameDecoder::new();
+    let result = decoder.decode(&stream, &mut [0; 100]);
+    assert!(matches!(result, Err(DecompressionError::BlockSizeOverflow)));
+    // and we didn't wait for the block to show up first
+    assert!(decoder.is_idle());
+}
+
+#[test]
+fn random_access() {
+    let input: Vec<u8> = (0..1_000_000u64).map(|i| (i * i % 251) as u8 ^ (i / 5000) as u8).collect();
+    let dictionary = &input[1234..20_000].to_vec();
+    let mut settings = CompressionSettings::default();
+    settings.independent_blocks(false).block_size(64 * 1024).dictionary(0, dictionary).content_checksum(true).checkpoint_interval(3);
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_all(&input).unwrap();
+    let (compressed, checkpoints) = writer.finish_with_checkpoints().unwrap();
+    // the frame doesn't have to be at the start of the reader
+    let stream = [&b"junk"[..], &compressed].concat();
+
+    for checkpoints in [checkpoints, Vec::new()] {
+        block_on(async {
+            let mut cursor = Cursor::new(&stream[..]);
+            cursor.seek(SeekFrom::Start(4)).await.unwrap();
+            let mut reader = AsyncLZ4RandomAccessReader::with_dictionary(cursor, checkpoints, dictionary);
+            let mut buf = vec![0; 1000];
+            // forwards, backwards, within a block and across checkpoints
+            for pos in [500_000, 10, 10_000, 600_000, 999_500, 400_000, 0] {
+                assert_eq!(reader.seek(SeekFrom::Start(pos)).await.unwrap(), pos);
+                let end = (pos as usize + buf.len()).min(input.len());
+                reader.read_exact(&mut buf[..end - pos as usize]).await.unwrap();
+                assert!(buf[..end - pos as usize] == input[pos as usize..end]);
+            }
+            assert_eq!(reader.seek(SeekFrom::Current(-500)).await.unwrap(), 500);
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).await.unwrap();
+            assert!(rest[..] == input[500..]);
+            // past the end is fine, there is just nothing there
+            reader.seek(SeekFrom::Start(2_000_000)).await.unwrap();
+            assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+            // without a content size, we don't know where the end is
+            assert!(reader.seek(SeekFrom::End(0)).await.is_err());
+        });
+    }
+
+    let mut compressed = Vec::new();
+    CompressionSettings::default().compress_with_size_unchecked(&input[..], &mut compressed, input.len() as u64).unwrap();
+    block_on(async {
+        let mut reader = AsyncLZ4RandomAccessReader::new(Cursor::new(&compressed[..]), Vec::new());
+        assert_eq!(reader.seek(SeekFrom::End(-100)).await.unwrap(), input.len() as u64 - 100);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).await.unwrap();
+        assert!(tail[..] == input[input.len() - 100..]);
+        assert!(reader.seek(SeekFrom::Current(-2_000_000)).await.is_err());
+    });
+}
+
+/// Hands out the data in small pieces, and only every other time it's asked.
+struct Stuttering<'a> {
+    data: &'a [u8],
+    ready: bool,
+}
+
+impl AsyncRead for Stuttering<'_> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.ready = !self.ready;
+        if !self.ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let n = buf.len().min(self.data.len()).min(10_000);
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Poll::Ready(Ok(n))
+    }
+}
+
+#[test]
+fn parallel_blocks() {
+    let input: Vec<u8> = (0..1_000_000u64).map(|i| (i * i % 251) as u8 ^ (i / 5000) as u8).collect();
+    let dictionary = &input[1234..100_000].to_vec();
+    let read = |compressed: &[u8], dictionary| block_on(async {
+        let mut reader = AsyncLZ4FrameReader::with_dictionary(Stuttering { data: compressed, ready: false }, dictionary);
+        reader.set_parallel_blocks(4);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.map(|_| output)
+    });
+
+    for independent in [true, false] {
+        let mut settings = CompressionSettings::default();
+        settings.independent_blocks(independent).block_size(64 * 1024).block_checksums(true).content_checksum(true).dictionary(1, dictionary);
+        let mut compressed = Vec::new();
+        settings.compress_with_size_unchecked(&input[..], &mut compressed, input.len() as u64).unwrap();
+        assert!(read(&compressed, dictionary).unwrap() == input);
+
+        // a bad block, a bad content checksum and a frame that's cut short
+        let mut corrupt = compressed.clone();
+        corrupt[compressed.len() / 2] ^= 1;
+        assert!(read(&corrupt, dictionary).is_err());
+        let mut corrupt = compressed.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(read(&corrupt, dictionary).is_err());
+        assert!(read(&compressed[..compressed.len() - 5], dictionary).is_err());
+    }
+
+    // stored blocks, without any checksums
+    let noise: Vec<u8> = (0..300_000u64).map(|i| (i.wrapping_mul(6364136223846793005) >> 56) as u8).collect();
+    let compressed = CompressionSettings::default().block_size(64 * 1024).compress_slice(&noise).unwrap();
+    assert!(read(&compressed, &[]).unwrap() == noise);
+}
+
+#[test]
+fn parallel_blocks_on_spawner() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let input = sample();
+    let compressed = CompressionSettings::default().block_size(64 * 1024).compress_slice(&input).unwrap();
+    let tasks = Arc::new(AtomicUsize::new(0));
+    let counter = tasks.clone();
+    let output = block_on(async {
+        let mut reader = AsyncLZ4FrameReader::new(Stuttering { data: &compressed, ready: false });
+        reader.set_parallel_blocks(2);
+        reader.set_spawner(Arc::new(move |task: Box<dyn FnOnce() + Send>| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            std::thread::spawn(task);
+        }));
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await.map(|_| output)
+    });
+    assert!(output.unwrap() == input);
+    assert_eq!(tasks.load(Ordering::Relaxed), input.len().div_ceil(64 * 1024));
+}
+
+#[test]
+fn reorder_window() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    let input: Vec<u8> = (0..1_000_000u64).map(|i| (i * i % 251) as u8 ^ (i / 5000) as u8).collect();
+    let compressed = CompressionSettings::default().block_size(64 * 1024).compress_slice(&input).unwrap();
+    for window in [1, 3, 100] {
+        // every fifth block takes a while, so the blocks after it are done first
+        let tasks = AtomicUsize::new(0);
+        let spawner = move |task: Box<dyn FnOnce() + Send>| {
+            let slow = tasks.fetch_add(1, Ordering::Relaxed).is_multiple_of(5);
+            std::thread::spawn(move || {
+                if slow {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                task()
+            });
+        };
+        let output = block_on(async {
+            let mut reader = AsyncLZ4FrameReader::new(Stuttering { data: &compressed, ready: false });
+            reader.set_parallel_blocks(2);
+            reader.set_reorder_window(window);
+            reader.set_spawner(Arc::new(spawner));
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).await.map(|_| output)
+        });
+        assert!(output.unwrap() == input);
+    }
+}
+
+/// Takes small pieces, and only every other time it's asked.
+#[derive(Default)]
+struct StutteringWriter {
+    data: Vec<u8>,
+    ready: bool,
+    closed: bool,
+}
+
+impl StutteringWriter {
+    fn stutter(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.ready = !self.ready;
+        if self.ready {
+            Poll::Ready(())
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncWrite for StutteringWriter {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.stutter(cx));
+        let n = buf.len().min(1000);
+        self.data.extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stutter(cx).map(Ok)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.stutter(cx));
+        self.closed = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn cancelled_close() {
+    let input = sample();
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).content_checksum(true);
+
+    // give up on closing after every step, and start over
+    let mut writer = AsyncLZ4FrameWriter::new(StutteringWriter::default(), &settings).unwrap();
+    block_on(writer.write_all(&input)).unwrap();
+    let mut attempts = 0;
+    while block_on(poll_once(writer.close())).is_none() {
+        attempts += 1;
+    }
+    assert!(attempts > 1);
+    let output = writer.into_inner();
+    assert!(output.closed);
+    assert_eq!(decompress_slice(&output.data, &[]).unwrap(), input);
+
+    // giving up on the frame leaves something that can't be decompressed
+    let mut writer = AsyncLZ4FrameWriter::new(StutteringWriter::default(), &settings).unwrap();
+    block_on(writer.write_all(&input)).unwrap();
+    let output = writer.abort();
+    assert!(!output.data.is_empty());
+    assert!(decompress_slice(&output.data, &[]).is_err());
+
+    // if the end of the frame can't be written, trying again doesn't make it look complete
+    let token = CancellationToken::new();
+    settings.cancellation_token(token.clone());
+    let mut writer = AsyncLZ4FrameWriter::new(StutteringWriter::default(), &settings).unwrap();
+    block_on(writer.write_all(&input[..1000])).unwrap();
+    token.cancel();
+    assert!(block_on(writer.close()).is_err());
+    assert!(block_on(writer.close()).is_err());
+    assert!(!writer.into_inner().closed);
+}
@@ -0,0 +1,34 @@
+use lz_fear::framed::{compress_batch, decompress_batch, decompress_frame, CompressionSettings};
+
+#[test]
+fn roundtrip() {
+    let items: Vec<Vec<u8>> = (0..100u32).map(|i| format!("column value {} ", i).repeat(i as usize * 50).into_bytes()).collect();
+    let items: Vec<&[u8]> = items.iter().map(|item| &item[..]).collect();
+    let settings = CompressionSettings::default();
+    let frames = compress_batch(&items, &settings).unwrap();
+    assert_eq!(frames.len(), items.len());
+    for (frame, item) in frames.iter().zip(&items) {
+        assert_eq!(decompress_frame(&frame[..]).unwrap(), *item);
+    }
+
+    let frames: Vec<&[u8]> = frames.iter().map(|frame| &frame[..]).collect();
+    assert_eq!(decompress_batch(&frames, &[]).unwrap(), items);
+    assert!(compress_batch(&[], &settings).unwrap().is_empty());
+}
+
+#[test]
+fn same_as_compress_slice() {
+    let dictionary = b"column value ".repeat(10);
+    let mut settings = CompressionSettings::default();
+    settings.dictionary(7, &dictionary).block_size(64 * 1024).content_checksum(false);
+    let items = [&b"column value 1 column value 2"[..], &[], &b"column value 3 ".repeat(10_000)];
+    let frames = compress_batch(&items, &settings).unwrap();
+    for (frame, item) in frames.iter().zip(items) {
+        assert_eq!(*frame, settings.compress_slice(item).unwrap());
+    }
+
+    let frames: Vec<&[u8]> = frames.iter().map(|frame| &frame[..]).collect();
+    assert_eq!(decompress_batch(&frames, &dictionary).unwrap(), items);
+    // a broken frame fails the whole batch
+    assert!(decompress_batch(&[frames[0], &frames[2][..100]], &dictionary).is_err());
+}
@@ -0,0 +1,28 @@
+use lz_fear::framed::{CancellationToken, CompressionError, CompressionSettings, DecompressionError, LZ4FrameReader};
+
+#[test]
+fn cancelled_compression() {
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let mut output = Vec::new();
+    let result = CompressionSettings::default()
+        .cancellation_token(token)
+        .compress(&b"never compressed"[..], &mut output);
+    assert!(matches!(result, Err(CompressionError::Cancelled)));
+}
+
+#[test]
+fn cancelled_decompression() {
+    let mut compressed = Vec::new();
+    CompressionSettings::default().compress(&b"never decompressed"[..], &mut compressed).unwrap();
+
+    let token = CancellationToken::new();
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap();
+    reader.set_cancellation_token(token.clone());
+    token.cancel();
+
+    let mut output = Vec::new();
+    let result = reader.decode_block(&mut output, &[]);
+    assert!(matches!(result, Err(DecompressionError::Cancelled)));
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use lz_fear::framed::{decompress_frame, decompress_slice, ChunkFormat, Chunker, CompressionSettings};
+use std::collections::HashSet;
+use common::words;
+
+#[test]
+fn frames() {
+    let input = words(1_000_000, 0);
+    let settings = CompressionSettings::default();
+    let mut chunker = Chunker::new(&input[..], &settings, ChunkFormat::Frame).unwrap();
+    chunker.average_len(16 * 1024);
+    let chunks: Vec<_> = chunker.collect::<Result<_, _>>().unwrap();
+
+    let mut offset = 0;
+    for chunk in &chunks[..chunks.len() - 1] {
+        assert_eq!(chunk.offset, offset);
+        assert!((4 * 1024..=64 * 1024).contains(&chunk.len));
+        let content = decompress_frame(&chunk.data[..]).unwrap();
+        assert_eq!(content, input[offset as usize..][..chunk.len]);
+        offset += chunk.len as u64;
+    }
+    assert!(chunks.len() > 30 && chunks.len() < 120);
+    let frames: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.data.iter().copied()).collect();
+    assert_eq!(decompress_slice(&frames, &[]).unwrap(), input);
+}
+
+#[test]
+fn dedup() {
+    let input = words(1_000_000, 0);
+    let mut edited = words(5000, 1);
+    edited.extend_from_slice(&input[..500_000]);
+    edited.extend_from_slice(b"the panda was here");
+    edited.extend_from_slice(&input[500_000..]);
+
+    let settings = CompressionSettings::default();
+    let chunks = |input: &[u8]| -> Vec<_> {
+        Chunker::new(input, &settings, ChunkFormat::Frame).unwrap().map(Result::unwrap).collect()
+    };
+    let before = chunks(&input);
+    let after = chunks(&edited);
+    let known: HashSet<_> = before.iter().map(|chunk| chunk.hash).collect();
+    let new: Vec<_> = after.iter().filter(|chunk| !known.contains(&chunk.hash)).collect();
+    // the chunks at the start and around the edit are new, and that's it
+    assert!(new.len() <= 4);
+    // the same content compresses to the same frame
+    for chunk in &after {
+        if let Some(old) = before.iter().find(|old| old.hash == chunk.hash) {
+            assert_eq!(old.data, chunk.data);
+        }
+    }
+}
+
+#[test]
+fn blocks() {
+    let input = words(300_000, 7);
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).block_checksums(true);
+    let mut chunker = Chunker::new(&input[..], &settings, ChunkFormat::Block).unwrap();
+    let mut frame = chunker.header().unwrap();
+    for chunk in chunker.by_ref() {
+        frame.extend_from_slice(&chunk.unwrap().data);
+    }
+    frame.extend_from_slice(&[0; 4]);
+    assert_eq!(decompress_frame(&frame[..]).unwrap(), input);
+}
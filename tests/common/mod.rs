@@ -0,0 +1,35 @@
+//! Test data that several test files need.
+// every test file only uses some of this
+#![allow(dead_code)]
+
+use rand::{Rng, RngCore, SeedableRng, rngs::StdRng};
+
+/// Random bytes, which don't compress at all.
+pub fn noise(len: usize) -> Vec<u8> {
+    let mut noise = vec![0; len];
+    StdRng::seed_from_u64(0).fill_bytes(&mut noise);
+    noise
+}
+
+/// Text that compresses, but never repeats itself exactly.
+pub fn words(len: usize, seed: u64) -> Vec<u8> {
+    let words = ["panda ", "bamboo ", "eats ", "the ", "red ", "bear ", "sleeps ", "all ", "day ", "long "];
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut text = Vec::new();
+    while text.len() < len {
+        text.extend_from_slice(words[rng.gen_range(0..words.len())].as_bytes());
+    }
+    text.truncate(len);
+    text
+}
+
+/// Like `words`, but with thousands of different words, so a hash table has lots of different matches to remember.
+pub fn many_words(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut text = Vec::new();
+    while text.len() < len {
+        text.extend_from_slice(format!("w{} ", rng.gen_range(0..3000)).as_bytes());
+    }
+    text.truncate(len);
+    text
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use lz_fear::framed::{trim_dictionary, CompatProfile, CompressionSettings, DecompressionError, DictionaryScope, FrameDecoder, LZ4FrameReader};
+use std::collections::HashMap;
+use std::io::Read;
+
+const INPUT: &[u8] = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day.";
+
+fn compress(dictionary: &[u8], id: u32) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    CompressionSettings::default().dictionary(id, dictionary).compress(INPUT, &mut compressed).unwrap();
+    compressed
+}
+
+#[test]
+fn provider() {
+    let dictionaries: HashMap<u32, Vec<u8>> = [(1, b"panda bamboo".to_vec()), (2, b"average eats".to_vec())].into_iter().collect();
+    for (&id, dictionary) in &dictionaries {
+        let compressed = compress(dictionary, id);
+        let mut output = Vec::new();
+        LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_provider(&dictionaries).unwrap().read_to_end(&mut output).unwrap();
+        assert_eq!(output, INPUT);
+    }
+
+    let compressed = compress(b"something else", 3);
+    let result = LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_provider(&dictionaries);
+    assert!(matches!(result, Err(DecompressionError::UnknownDictionary(3))));
+
+    // a plain slice is used for everything
+    let mut output = Vec::new();
+    LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_provider(&b"something else"[..]).unwrap().read_to_end(&mut output).unwrap();
+    assert_eq!(output, INPUT);
+}
+
+/// The dictionary is loaded inside the helper, so it must be owned by the reader.
+fn open_with_dictionary(compressed: &[u8]) -> impl Read + '_ {
+    let dictionary = b"panda bamboo".to_vec();
+    LZ4FrameReader::new(compressed).unwrap().into_read_with_owned_dictionary(dictionary)
+}
+
+#[test]
+fn owned() {
+    let compressed = compress(b"panda bamboo", 0);
+    let mut output = Vec::new();
+    open_with_dictionary(&compressed).read_to_end(&mut output).unwrap();
+    assert_eq!(output, INPUT);
+}
+
+#[test]
+fn first_block_scope() {
+    let dictionary = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day.";
+    let input = dictionary.repeat(4000);
+    let compress = |scope| {
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).dictionary(0, dictionary).dictionary_scope(scope);
+        let mut compressed = Vec::new();
+        settings.compress(&input[..], &mut compressed).unwrap();
+        compressed
+    };
+    let decompress = |compressed: &[u8], scope| {
+        let mut reader = LZ4FrameReader::new(compressed).unwrap();
+        reader.set_dictionary_scope(scope);
+        let mut output = Vec::new();
+        reader.into_read_with_dictionary(dictionary).read_to_end(&mut output).map(|_| output)
+    };
+
+    let every_block = compress(DictionaryScope::EveryBlock);
+    let first_block = compress(DictionaryScope::FirstBlock);
+    assert_ne!(every_block, first_block);
+    assert_eq!(decompress(&first_block, DictionaryScope::FirstBlock).unwrap(), input);
+    // later blocks reference the dictionary, which isn't there with the wrong scope
+    assert!(decompress(&every_block, DictionaryScope::FirstBlock).map_or(true, |output| output != input));
+
+    let mut decoder = FrameDecoder::with_dictionary(dictionary);
+    decoder.dictionary_scope(DictionaryScope::FirstBlock);
+    let mut output = vec![0; input.len()];
+    let progress = decoder.decode(&first_block, &mut output).unwrap();
+    assert_eq!(progress.written, input.len());
+    assert_eq!(output, input);
+}
+
+#[test]
+fn oversized() {
+    let dictionary: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8 ^ (i / 3000) as u8).collect();
+    let input = &dictionary[900_000..][..50_000];
+    let mut settings = CompressionSettings::default();
+    settings.dictionary(0, &dictionary);
+    let info = settings.dictionary_info().unwrap();
+    assert!(info.truncated());
+    assert_eq!((info.original_size, info.effective_size), (1_000_000, 64 * 1024));
+
+    // everything in front of the last 64 KiB was useless anyway
+    let compressed = settings.compress_slice(input).unwrap();
+    let (tail, _) = trim_dictionary(&dictionary);
+    assert_eq!(compressed, CompressionSettings::default().dictionary(0, tail).compress_slice(input).unwrap());
+    let mut output = Vec::new();
+    LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_dictionary(tail).read_to_end(&mut output).unwrap();
+    assert_eq!(output, input);
+}
+
+#[test]
+fn chain() {
+    let base = b"The average panda eats as much as".repeat(3000);
+    let tenant = b" 9 to 14 kg of bamboo shoots a day.";
+    let concatenated = [&base[..], tenant].concat();
+    let (tail, _) = trim_dictionary(&concatenated);
+
+    let mut settings = CompressionSettings::default();
+    settings.dictionary_chain(7, &[&base, tenant]);
+    assert_eq!(settings.dictionary_info().unwrap().original_size, concatenated.len());
+    let compressed = settings.compress_slice(INPUT).unwrap();
+    assert_eq!(compressed, CompressionSettings::default().dictionary(7, tail).compress_slice(INPUT).unwrap());
+
+    let mut output = Vec::new();
+    LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_dictionary_chain(&[&base, tenant]).read_to_end(&mut output).unwrap();
+    assert_eq!(output, INPUT);
+}
+
+#[test]
+fn compat_profiles() {
+    let noise = common::noise(64 * 1024);
+    // short pieces of the dictionary are only found if (almost) every position of it is in the table
+    let pieces = |dictionary: &[u8]| -> Vec<u8> {
+        (0..500).flat_map(|i| dictionary[i * 7919 % (dictionary.len() - 7)..][..7].to_vec()).collect()
+    };
+    let compress = |dictionary: Option<&[u8]>, input: &[u8], profile| {
+        let mut settings = CompressionSettings::default();
+        settings.compat_profile(profile);
+        if let Some(dictionary) = dictionary {
+            settings.dictionary(0, dictionary);
+        }
+        settings.compress_slice(input).unwrap()
+    };
+
+    // the versions only differ in how they load dictionaries
+    let input = pieces(&noise);
+    assert_eq!(compress(None, &input, CompatProfile::Liblz4_1_9), CompressionSettings::default().compress_slice(&input).unwrap());
+    assert_eq!(compress(None, &input, CompatProfile::Liblz4_1_9), compress(None, &input, CompatProfile::Liblz4_1_10));
+    assert_eq!(compress(Some(b"panda"), &input, CompatProfile::Liblz4_1_9), compress(Some(b"panda"), &input, CompatProfile::Liblz4_1_10));
+
+    // a short dictionary and a full one (which starts at index 0 for liblz4)
+    for dictionary in [&noise[..3000], &noise[..]] {
+        let input = pieces(dictionary);
+        let old = compress(Some(dictionary), &input, CompatProfile::Liblz4_1_9);
+        let new = compress(Some(dictionary), &input, CompatProfile::Liblz4_1_10);
+        assert!(new.len() < old.len());
+        for compressed in [old, new] {
+            let mut output = Vec::new();
+            LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_dictionary(dictionary).read_to_end(&mut output).unwrap();
+            assert_eq!(output, input);
+        }
+    }
+}
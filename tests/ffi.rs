@@ -0,0 +1,74 @@
+#![cfg(feature = "ffi")]
+use lz_fear::ffi::*;
+use std::ptr;
+
+fn compress(input: &[u8], prefs: &LZ4F_preferences_t) -> Vec<u8> {
+    unsafe {
+        let mut output = vec![0u8; LZ4F_compressFrameBound(input.len(), prefs)];
+        let len = LZ4F_compressFrame(output.as_mut_ptr().cast(), output.len(), input.as_ptr().cast(), input.len(), prefs);
+        assert_eq!(LZ4F_isError(len), 0);
+        output.truncate(len);
+        output
+    }
+}
+
+/// Feed the input in chunks of `chunk` bytes and drain output in chunks of `chunk` bytes.
+fn decompress(input: &[u8], chunk: usize) -> Vec<u8> {
+    unsafe {
+        let mut dctx = ptr::null_mut();
+        assert_eq!(LZ4F_createDecompressionContext(&mut dctx, LZ4F_VERSION), 0);
+
+        let mut output = Vec::new();
+        let mut pos = 0;
+        loop {
+            let mut buf = vec![0u8; chunk];
+            let mut dst_size = buf.len();
+            let mut src_size = chunk.min(input.len() - pos);
+            let hint = LZ4F_decompress(dctx, buf.as_mut_ptr().cast(), &mut dst_size, input[pos..].as_ptr().cast(), &mut src_size, ptr::null());
+            assert_eq!(LZ4F_isError(hint), 0);
+            output.extend_from_slice(&buf[..dst_size]);
+            pos += src_size;
+            if hint == 0 {
+                break;
+            }
+        }
+        assert_eq!(pos, input.len());
+        LZ4F_freeDecompressionContext(dctx);
+        output
+    }
+}
+
+#[test]
+fn roundtrip() {
+    let input: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8 ^ (i / 1000) as u8).collect();
+    let mut prefs = LZ4F_preferences_t::default();
+    prefs.frameInfo.contentChecksumFlag = 1;
+    prefs.frameInfo.blockChecksumFlag = 1;
+    prefs.frameInfo.contentSize = 1;
+    let compressed = compress(&input, &prefs);
+
+    assert_eq!(decompress(&compressed, 1 << 20), input);
+    assert_eq!(decompress(&compressed, 7), input);
+}
+
+#[test]
+fn errors() {
+    let mut garbage = compress(b"hello hello hello hello", &Default::default());
+    garbage[0] ^= 1;
+    unsafe {
+        let mut dctx = ptr::null_mut();
+        LZ4F_createDecompressionContext(&mut dctx, LZ4F_VERSION);
+        let mut buf = [0u8; 64];
+        let mut dst_size = buf.len();
+        let mut src_size = garbage.len();
+        let code = LZ4F_decompress(dctx, buf.as_mut_ptr().cast(), &mut dst_size, garbage.as_ptr().cast(), &mut src_size, ptr::null());
+        assert_eq!(LZ4F_isError(code), 1);
+        let name = std::ffi::CStr::from_ptr(LZ4F_getErrorName(code));
+        assert_eq!(name.to_str().unwrap(), "ERROR_frameType_unknown");
+        LZ4F_freeDecompressionContext(dctx);
+
+        let mut small = [0u8; 4];
+        let code = LZ4F_compressFrame(small.as_mut_ptr().cast(), small.len(), b"abc".as_ptr().cast(), 3, ptr::null());
+        assert_eq!(LZ4F_isError(code), 1);
+    }
+}
@@ -0,0 +1,135 @@
+use lz_fear::framed::{decompress_file, decompress_slice, CompressionSettings, FileSizes};
+use std::fs;
+use tempfile::tempdir;
+
+#[test]
+fn roundtrip() {
+    let dir = tempdir().unwrap();
+    let input = b"Save water, it doesn't grow on trees. Save water, it doesn't grow on trees.".repeat(1000);
+    fs::write(dir.path().join("plain"), &input).unwrap();
+
+    let compressed = CompressionSettings::default().compress_file(dir.path().join("plain"), dir.path().join("plain.lz4")).unwrap();
+    assert_eq!(compressed.input, input.len() as u64);
+    assert_eq!(compressed.output, fs::metadata(dir.path().join("plain.lz4")).unwrap().len());
+
+    let decompressed = decompress_file(dir.path().join("plain.lz4"), dir.path().join("restored"), &[]).unwrap();
+    assert_eq!(decompressed, FileSizes { input: compressed.output, output: compressed.input });
+    assert_eq!(fs::read(dir.path().join("restored")).unwrap(), input);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn no_partial_output() {
+    let dir = tempdir().unwrap();
+    let mut compressed = CompressionSettings::default().compress_slice(&b"truncated ".repeat(100_000)).unwrap();
+    compressed.truncate(compressed.len() / 2);
+    fs::write(dir.path().join("truncated.lz4"), &compressed).unwrap();
+    fs::write(dir.path().join("existing"), b"untouched").unwrap();
+
+    assert!(decompress_file(dir.path().join("truncated.lz4"), dir.path().join("new"), &[]).is_err());
+    assert!(decompress_file(dir.path().join("truncated.lz4"), dir.path().join("existing"), &[]).is_err());
+    assert_eq!(fs::read(dir.path().join("existing")).unwrap(), b"untouched");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn path_with_size() {
+    let dir = tempdir().unwrap();
+    let input = b"There is nothing either good or bad, but thinking makes it so.".repeat(100);
+    fs::write(dir.path().join("plain"), &input).unwrap();
+
+    let mut compressed = Vec::new();
+    let size = CompressionSettings::default().compress_path_with_size(dir.path().join("plain"), &mut compressed).unwrap();
+    assert_eq!(size, input.len() as u64);
+    let reader = lz_fear::LZ4FrameReader::new(&compressed[..]).unwrap();
+    assert_eq!(reader.frame_size(), Some(size));
+    assert_eq!(decompress_slice(&compressed, &[]).unwrap(), input);
+}
+
+#[test]
+fn pipes() {
+    use lz_fear::framed::decompress_pipe;
+
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(10_000);
+    let (compressed, sizes) = std::thread::scope(|s| s.spawn(|| {
+        let mut compressed = Vec::new();
+        let sizes = CompressionSettings::default().block_size(64 * 1024).compress_pipe(&input[..], &mut compressed).unwrap();
+        (compressed, sizes)
+    }).join().unwrap());
+    assert_eq!(sizes, FileSizes { input: input.len() as u64, output: compressed.len() as u64 });
+
+    // a skippable frame and a second frame in between
+    let mut stream = compressed.clone();
+    stream.extend_from_slice(&[0x50, 0x2a, 0x4d, 0x18, 2, 0, 0, 0, 7, 7]);
+    stream.extend_from_slice(&compressed);
+    let mut output = Vec::new();
+    let sizes = decompress_pipe(&stream[..], &mut output, &[]).unwrap();
+    assert_eq!(sizes, FileSizes { input: stream.len() as u64, output: 2 * input.len() as u64 });
+    assert!(output == [&input[..], &input[..]].concat());
+}
+
+#[test]
+fn many_files() {
+    use std::cell::Cell;
+
+    let dir = tempdir().unwrap();
+    let mut files = Vec::new();
+    for i in 0..8 {
+        fs::write(dir.path().join(format!("{}", i)), format!("file number {} ", i).repeat(i * 20_000)).unwrap();
+        files.push((dir.path().join(format!("{}", i)), dir.path().join(format!("{}.lz4", i))));
+    }
+    files.insert(3, (dir.path().join("missing"), dir.path().join("missing.lz4")));
+
+    let blocks = Cell::new(0);
+    let callback = |_: &_| blocks.set(blocks.get() + 1);
+    let results = CompressionSettings::default().block_size(64 * 1024).block_callback(&callback).compress_files(&files, 3);
+    assert_eq!(results.len(), files.len());
+    assert_eq!(results[3].as_ref().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert!(!dir.path().join("missing.lz4").exists());
+
+    let mut expected_blocks = 0;
+    for (result, (input, output)) in results.iter().zip(&files).filter(|(result, _)| result.is_ok()) {
+        let plain = fs::read(input).unwrap();
+        let compressed = fs::read(output).unwrap();
+        assert_eq!(*result.as_ref().unwrap(), FileSizes { input: plain.len() as u64, output: compressed.len() as u64 });
+        assert_eq!(decompress_slice(&compressed, &[]).unwrap(), plain);
+        expected_blocks += plain.len().div_ceil(64 * 1024);
+    }
+    assert_eq!(blocks.get(), expected_blocks);
+}
+
+#[test]
+fn many_streams() {
+    let inputs: Vec<_> = (0..5).map(|i| format!("stream {} ", i).repeat(1000).into_bytes()).collect();
+    let mut outputs = vec![Vec::new(); inputs.len()];
+    let jobs: Vec<_> = inputs.iter().zip(&mut outputs).map(|(input, output)| move || Ok((&input[..], output))).collect();
+    let results = CompressionSettings::default().compress_streams(jobs, 0);
+    for ((result, input), output) in results.into_iter().zip(&inputs).zip(&outputs) {
+        assert_eq!(result.unwrap().output, output.len() as u64);
+        assert_eq!(decompress_slice(output, &[]).unwrap(), *input);
+    }
+}
+
+#[test]
+fn preallocated() {
+    let dir = tempdir().unwrap();
+    let input = b"Who controls the past controls the future. ".repeat(10_000);
+    let mut compressed = Vec::new();
+    CompressionSettings::default().compress_with_size(std::io::Cursor::new(&input), &mut compressed).unwrap();
+    fs::write(dir.path().join("sized.lz4"), &compressed).unwrap();
+    let sizes = decompress_file(dir.path().join("sized.lz4"), dir.path().join("sized"), &[]).unwrap();
+    assert_eq!(sizes.output, input.len() as u64);
+    assert_eq!(fs::read(dir.path().join("sized")).unwrap(), input);
+
+    // only the first frame has a size, the file ends up longer than that
+    compressed.extend(CompressionSettings::default().compress_slice(b"and then some").unwrap());
+    fs::write(dir.path().join("more.lz4"), &compressed).unwrap();
+    decompress_file(dir.path().join("more.lz4"), dir.path().join("more"), &[]).unwrap();
+    assert_eq!(fs::read(dir.path().join("more")).unwrap(), [&input[..], b"and then some"].concat());
+
+    // a truncated frame still leaves nothing behind
+    compressed.truncate(compressed.len() / 2);
+    fs::write(dir.path().join("truncated.lz4"), &compressed).unwrap();
+    assert!(decompress_file(dir.path().join("truncated.lz4"), dir.path().join("truncated"), &[]).is_err());
+    assert!(!dir.path().join("truncated").exists());
+}
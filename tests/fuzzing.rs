@@ -0,0 +1,24 @@
+#![cfg(feature = "arbitrary")]
+
+use arbitrary::{Arbitrary, Unstructured};
+use lz_fear::framed::FrameSpec;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+#[test]
+fn random_frame_specs() {
+    let mut rng = StdRng::seed_from_u64(1337);
+    let mut seen_dictionary = false;
+    for _ in 0..200 {
+        let mut raw = vec![0u8; rng.gen_range(0..4096)];
+        rng.fill(&mut raw[..]);
+        // make the content a bit compressible
+        let half = raw.len() / 2;
+        raw.copy_within(..half / 2, half);
+
+        let spec = FrameSpec::arbitrary(&mut Unstructured::new(&raw)).unwrap();
+        seen_dictionary |= format!("{:?}", spec.settings).contains("dictionary_id: Some");
+        let frame = spec.compress().unwrap();
+        assert_eq!(spec.decompress(&frame).unwrap(), spec.content, "{:?}", spec);
+    }
+    assert!(seen_dictionary);
+}
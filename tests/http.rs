@@ -0,0 +1,87 @@
+#![cfg(feature = "http")]
+
+use lz_fear::framed::{decompress_slice, CompressingReader, CompressionSettings, DecompressingReader, FrameDecoder};
+use std::io::{self, ErrorKind, Read};
+
+/// Returns the chunks one by one, like a streaming response body.
+struct Chunks(Vec<&'static [u8]>);
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Ok(0);
+        }
+        let chunk = self.0.remove(0);
+        buf[..chunk.len()].copy_from_slice(chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[test]
+fn compress_response() {
+    let settings = CompressionSettings::default();
+    for sync_flush in [false, true] {
+        let mut compressed = Vec::new();
+        CompressingReader::new(Chunks(vec![b"event: hello\n\n", b"event: world\n\n"]), &settings).unwrap()
+            .sync_flush(sync_flush)
+            .read_to_end(&mut compressed).unwrap();
+        assert_eq!(decompress_slice(&compressed, &[]).unwrap(), b"event: hello\n\nevent: world\n\n");
+    }
+}
+
+#[test]
+fn sync_flush_after_every_chunk() {
+    let settings = CompressionSettings::default();
+    let mut reader = CompressingReader::new(Chunks(vec![b"first chunk", b"second chunk"]), &settings).unwrap();
+    // the header and the first chunk come out before we even look at the second one
+    let mut buf = [0; 1024];
+    let mut compressed = Vec::new();
+    while compressed.len() < 7 + 4 + 11 {
+        let n = reader.read(&mut buf).unwrap();
+        compressed.extend_from_slice(&buf[..n]);
+    }
+    let mut output = [0; 1024];
+    let progress = FrameDecoder::new().decode(&compressed, &mut output).unwrap();
+    assert_eq!(&output[..progress.written], b"first chunk");
+}
+
+#[test]
+fn decompress_request() {
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(1000);
+    let compressed = CompressionSettings::default().compress_slice(&input).unwrap();
+
+    let mut output = Vec::new();
+    DecompressingReader::new(&compressed[..]).limit(input.len() as u64).read_to_end(&mut output).unwrap();
+    assert_eq!(output, input);
+
+    let result = DecompressingReader::new(&compressed[..]).limit(input.len() as u64 - 1).read_to_end(&mut Vec::new());
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+/// A body that never ends, and how much of it was read.
+struct Endless {
+    start: Vec<u8>,
+    read: usize,
+}
+impl Read for Endless {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = self.start.get(self.read..).unwrap_or(&[]);
+        let n = start.len().min(buf.len());
+        buf[..n].copy_from_slice(&start[..n]);
+        buf[n..].fill(0);
+        self.read += buf.len();
+        Ok(buf.len())
+    }
+}
+
+#[test]
+fn oversized_block() {
+    let frame = CompressionSettings::default().content_checksum(false).compress_slice(b"").unwrap();
+    // a block that claims to be almost 2 GiB, far more than any frame allows
+    let mut start = frame[..frame.len() - 4].to_vec();
+    start.extend_from_slice(&0x7FFF_FFFFu32.to_le_bytes());
+
+    let mut reader = DecompressingReader::new(Endless { start, read: 0 });
+    assert!(reader.read(&mut [0; 1000]).is_err());
+    // we gave up right away instead of collecting the block first
+    assert!(reader.into_inner().read <= 64 * 1024);
+}
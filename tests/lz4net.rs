@@ -0,0 +1,64 @@
+mod common;
+
+use lz_fear::lz4net::{LZ4NetError, LZ4NetReader, LZ4NetWriter};
+use lz_fear::raw::compress_independent_block;
+use std::io::{self, Read, Write};
+
+fn read_all(stream: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    LZ4NetReader::new(stream).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+fn error(stream: &[u8]) -> LZ4NetError {
+    *read_all(stream).unwrap_err().into_inner().unwrap().downcast().unwrap()
+}
+
+#[test]
+fn chunks_by_hand() {
+    let panda = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(10);
+    let block = compress_independent_block(&panda);
+    assert!(panda.len() > 127 && block.len() < 127);
+
+    // a stored chunk, a compressed one (with the flag for the high compression codec, which doesn't matter to us)
+    // and an empty one
+    let mut stream = vec![0, 5];
+    stream.extend_from_slice(b"hello");
+    stream.extend_from_slice(&[3, (panda.len() & 0x7F) as u8 | 0x80, (panda.len() >> 7) as u8, block.len() as u8]);
+    stream.extend_from_slice(&block);
+    stream.extend_from_slice(&[0, 0]);
+    assert_eq!(read_all(&stream).unwrap(), [&b"hello"[..], &panda].concat());
+    assert!(read_all(&[]).unwrap().is_empty());
+
+    // several passes, a compressed chunk that's larger than what it decompresses to,
+    // and chunks that end too early
+    assert!(matches!(error(&[1 | 1 << 2, 5, 5, 0, 0, 0, 0, 0]), LZ4NetError::MultiplePasses));
+    assert!(matches!(error(&[1, 5, 6, 0, 0, 0, 0, 0, 0]), LZ4NetError::CorruptChunk));
+    assert!(matches!(error(&[0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]), LZ4NetError::CorruptChunk));
+    assert_eq!(read_all(&stream[..stream.len() - 3]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(read_all(&[0, 0x85]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn roundtrip() {
+    let text = b"The panda bear has an amazing black-and-white fur. ".repeat(2000);
+    let noise = common::noise(50_000);
+    let input = [&text[..], &noise, &text].concat();
+
+    for chunk_size in [1, 1000, 64 * 1024, lz_fear::lz4net::DEFAULT_CHUNK_SIZE] {
+        let mut writer = LZ4NetWriter::with_chunk_size(Vec::new(), chunk_size);
+        for piece in input.chunks(7777) {
+            writer.write_all(piece).unwrap();
+        }
+        let stream = writer.finish().unwrap();
+        assert!(read_all(&stream).unwrap() == input);
+    }
+
+    // the writer gives up on compressing noise, and flushing ends a chunk
+    let mut writer = LZ4NetWriter::new(Vec::new());
+    writer.write_all(&noise).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(writer.get_ref()[..4], [0, 0xD0, 0x86, 0x03]);
+    writer.write_all(b"bye").unwrap();
+    drop(writer);
+}
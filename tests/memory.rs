@@ -0,0 +1,129 @@
+use lz_fear::framed::{memory_bound, CompressionError, CompressionSettings, DecompressionError, LZ4FrameReader, LZ4FrameWriter};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, BufRead, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+/// Keeps track of how much is allocated, and the most that was allocated at once.
+struct PeakAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), SeqCst) + layout.size();
+        PEAK.fetch_max(current, SeqCst);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), SeqCst);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+/// How many bytes of heap `f` needed at most (on top of what was already allocated).
+fn peak_heap(f: impl FnOnce()) -> usize {
+    let start = CURRENT.load(SeqCst);
+    PEAK.store(start, SeqCst);
+    f();
+    PEAK.load(SeqCst) - start
+}
+
+/// Generates `left` bytes on the fly (so the input isn't on the heap): a few letters with some noise, or just noise.
+struct Input {
+    state: u64,
+    left: usize,
+    noise: bool,
+}
+
+impl Input {
+    fn new(len: usize, noise: bool) -> Self {
+        Input { state: 0, left: len, noise }
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.left);
+        for b in &mut buf[..len] {
+            self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            *b = if self.noise || self.state >> 60 == 0 { (self.state >> 32) as u8 } else { b'a' + (self.state >> 61) as u8 };
+        }
+        self.left -= len;
+        Ok(len)
+    }
+}
+
+// everything in one test, because the allocator doesn't know which test allocated what
+#[test]
+fn bounded_memory() {
+    let cases = [(64 * 1024, false), (64 * 1024, true), (256 * 1024, false), (1024 * 1024, true), (4 * 1024 * 1024, true)];
+    for (block_size, independent) in cases {
+        for noise in [false, true] {
+            let len = block_size * 7 / 2;
+            let bound = memory_bound(block_size);
+            let mut settings = CompressionSettings::default();
+            settings.block_size(block_size).independent_blocks(independent).block_checksums(true).bounded_memory(true);
+
+            let peak = peak_heap(|| settings.compress(Input::new(len, noise), io::sink()).unwrap());
+            assert!(peak <= bound, "compressing {} bytes in blocks of {} took {} bytes", len, block_size, peak);
+
+            // blocks of all sizes (which would make buffers grow step by step)
+            let peak = peak_heap(|| {
+                let mut writer = LZ4FrameWriter::new(io::sink(), &settings).unwrap();
+                let mut input = Input::new(len, noise);
+                let mut chunk = 1000;
+                while io::copy(&mut input.by_ref().take(chunk), &mut writer).unwrap() > 0 {
+                    writer.flush().unwrap();
+                    chunk = chunk * 3 / 2;
+                }
+                writer.finish().unwrap();
+            });
+            assert!(peak <= bound, "writing {} bytes in blocks of {} took {} bytes", len, block_size, peak);
+
+            let mut compressed = Vec::new();
+            settings.compress(Input::new(len, noise), &mut compressed).unwrap();
+            let peak = peak_heap(|| {
+                let mut reader = LZ4FrameReader::with_memory_limit(&compressed[..], bound).unwrap().into_read();
+                let mut total = 0;
+                loop {
+                    let available = reader.fill_buf().unwrap().len();
+                    if available == 0 {
+                        break;
+                    }
+                    reader.consume(available);
+                    total += available;
+                }
+                assert_eq!(total, len);
+            });
+            assert!(peak <= bound, "reading {} bytes in blocks of {} took {} bytes", len, block_size, peak);
+
+            let peak = peak_heap(|| {
+                let mut reader = LZ4FrameReader::with_memory_limit(&compressed[..], bound).unwrap();
+                assert_eq!(reader.decode_to_writer(&[], io::sink()).unwrap(), len as u64);
+            });
+            assert!(peak <= bound, "decoding {} bytes in blocks of {} took {} bytes", len, block_size, peak);
+        }
+    }
+
+    // settings that can't keep the promise fail up front
+    let mut settings = CompressionSettings::default();
+    settings.bounded_memory(true).hash_log(16);
+    assert!(matches!(LZ4FrameWriter::new(Vec::new(), &settings), Err(CompressionError::Unbounded)));
+    let mut settings = CompressionSettings::default();
+    settings.bounded_memory(true).checkpoint_interval(10);
+    assert!(matches!(settings.compress(Input::new(10, false), io::sink()), Err(CompressionError::Unbounded)));
+    let mut settings = CompressionSettings::default();
+    settings.bounded_memory(true);
+    assert!(matches!(settings.compress_read_ahead(Input::new(10, false), io::sink()), Err(CompressionError::Unbounded)));
+
+    // and so do frames with blocks that are too large
+    let compressed = CompressionSettings::default().compress_slice(b"Save the red panda!").unwrap();
+    let result = LZ4FrameReader::with_memory_limit(&compressed[..], memory_bound(1024 * 1024));
+    assert!(matches!(result, Err(DecompressionError::MemoryLimit(needed)) if needed == memory_bound(4 * 1024 * 1024)));
+    assert!(LZ4FrameReader::with_memory_limit(&compressed[..], memory_bound(4 * 1024 * 1024)).is_ok());
+}
@@ -0,0 +1,111 @@
+use lz_fear::framed::{CompressionSettings, LZ4FrameReader, Metrics, Stage};
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Default)]
+struct Counters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    blocks: AtomicU64,
+    stored: AtomicU64,
+}
+impl Metrics for Counters {
+    fn bytes_in(&self, bytes: u64) { self.bytes_in.fetch_add(bytes, Ordering::Relaxed); }
+    fn bytes_out(&self, bytes: u64) { self.bytes_out.fetch_add(bytes, Ordering::Relaxed); }
+    fn block_emitted(&self) { self.blocks.fetch_add(1, Ordering::Relaxed); }
+    fn block_stored(&self) { self.stored.fetch_add(1, Ordering::Relaxed); }
+}
+
+#[test]
+fn counts_match_output() {
+    let input = vec![7u8; 200 * 1024];
+    let counters = Arc::new(Counters::default());
+    let mut compressed = Vec::new();
+    CompressionSettings::default()
+        .block_size(64 * 1024)
+        .metrics(counters.clone())
+        .compress(&input[..], &mut compressed)
+        .unwrap();
+
+    assert_eq!(counters.bytes_in.load(Ordering::Relaxed), input.len() as u64);
+    assert_eq!(counters.bytes_out.load(Ordering::Relaxed), compressed.len() as u64);
+    assert_eq!(counters.blocks.load(Ordering::Relaxed), 4);
+    assert_eq!(counters.stored.load(Ordering::Relaxed), 0);
+
+    let counters = Arc::new(Counters::default());
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap();
+    reader.set_metrics(counters.clone());
+    let mut decompressed = Vec::new();
+    reader.into_read().read_to_end(&mut decompressed).unwrap();
+
+    assert_eq!(decompressed, input);
+    assert_eq!(counters.bytes_out.load(Ordering::Relaxed), input.len() as u64);
+    assert_eq!(counters.blocks.load(Ordering::Relaxed), 4);
+}
+
+#[test]
+fn block_callback_sees_every_block() {
+    use lz_fear::framed::BlockStats;
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+    use std::cell::RefCell;
+
+    let mut input = vec![0u8; 64 * 1024];
+    StdRng::seed_from_u64(0).fill(&mut input[..]);
+    input.extend_from_slice(&[b'x'; 1000]);
+
+    let seen = RefCell::new(Vec::new());
+    let callback = |stats: &BlockStats| seen.borrow_mut().push(*stats);
+    let mut compressed = Vec::new();
+    CompressionSettings::default()
+        .block_size(64 * 1024)
+        .block_callback(&callback)
+        .compress(&input[..], &mut compressed)
+        .unwrap();
+
+    let seen = seen.into_inner();
+    assert_eq!(seen.len(), 2);
+    assert!(seen[0].stored);
+    assert_eq!(seen[0].uncompressed_len, 64 * 1024);
+    assert!(!seen[1].stored);
+    assert_eq!(seen[1].uncompressed_len, 1000);
+    assert!(seen[1].compressed_len < 1000);
+}
+
+#[derive(Default)]
+struct Stages(Mutex<HashMap<Stage, u32>>);
+impl Metrics for Stages {
+    fn stage_time(&self, stage: Stage, _elapsed: Duration) { *self.0.lock().unwrap().entry(stage).or_default() += 1; }
+}
+impl Stages {
+    fn seen(&self, stage: Stage) -> bool { self.0.lock().unwrap().contains_key(&stage) }
+}
+
+#[test]
+fn stage_times() {
+    let input = b"The quick brown fox jumps over the lazy dog. ".repeat(5000);
+    let stages = Arc::new(Stages::default());
+    let mut compressed = Vec::new();
+    CompressionSettings::default()
+        .content_checksum(true)
+        .metrics(stages.clone())
+        .compress(&input[..], &mut compressed)
+        .unwrap();
+    for stage in [Stage::Read, Stage::Compress, Stage::Checksum, Stage::Write] {
+        assert!(stages.seen(stage), "{:?}", stage);
+    }
+    assert!(!stages.seen(Stage::Decompress));
+
+    let stages = Arc::new(Stages::default());
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap();
+    reader.set_metrics(stages.clone());
+    let mut decompressed = Vec::new();
+    reader.into_read().copy_to(&mut decompressed).unwrap();
+    assert_eq!(decompressed, input);
+    for stage in [Stage::Read, Stage::Decompress, Stage::Checksum, Stage::Write] {
+        assert!(stages.seen(stage), "{:?}", stage);
+    }
+    assert!(!stages.seen(Stage::Compress));
+}
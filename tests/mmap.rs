@@ -0,0 +1,56 @@
+#![cfg(feature = "mmap")]
+use lz_fear::framed::{CompressionSettings, DictionaryScope, LZ4FrameReader};
+use std::io::{Read, Write};
+use tempfile::NamedTempFile;
+
+fn roundtrip(input: &[u8], settings: &CompressionSettings, dictionary: &[u8]) {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(input).unwrap();
+
+    let mut compressed = Vec::new();
+    settings.compress_mmap(file.path(), &mut compressed).unwrap();
+    let mut output = Vec::new();
+    LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_dictionary(dictionary).read_to_end(&mut output).unwrap();
+    assert_eq!(output, input);
+}
+
+#[test]
+fn parallel_blocks() {
+    let input: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8 ^ (i / 5000) as u8).collect();
+    let dictionary = &input[1234..80_000];
+    roundtrip(&input, &CompressionSettings::default(), &[]);
+    roundtrip(&input, CompressionSettings::default().block_checksums(true).block_size(64 * 1024), &[]);
+    roundtrip(&input, CompressionSettings::default().independent_blocks(false).block_size(64 * 1024), &[]);
+    roundtrip(&input, CompressionSettings::default().block_size(64 * 1024).blocks_in_flight(1), &[]);
+    roundtrip(&input, CompressionSettings::default().dictionary(0, dictionary), dictionary);
+    roundtrip(&input, CompressionSettings::default().independent_blocks(false).dictionary(0, dictionary), dictionary);
+    // blocks that don't use the dictionary decode just fine with it
+    roundtrip(&input, CompressionSettings::default().block_size(64 * 1024).dictionary(0, dictionary).dictionary_scope(DictionaryScope::FirstBlock), dictionary);
+}
+
+#[test]
+fn thread_pool() {
+    let input: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8 ^ (i / 5000) as u8).collect();
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+    roundtrip(&input, CompressionSettings::default().block_size(64 * 1024).thread_pool(&pool), &[]);
+}
+
+#[test]
+fn empty_file() {
+    roundtrip(b"", &CompressionSettings::default(), &[]);
+}
+
+#[test]
+fn stored_blocks() {
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+    use std::cell::Cell;
+
+    let mut input = vec![0; 1_000_000];
+    StdRng::seed_from_u64(0).fill_bytes(&mut input[300_000..]);
+    let stored = Cell::new(0);
+    let callback = |stats: &lz_fear::framed::BlockStats| stored.set(stored.get() + stats.stored as usize);
+    let mut settings = CompressionSettings::default();
+    settings.block_checksums(true).block_size(64 * 1024).block_callback(&callback);
+    roundtrip(&input, &settings, &[]);
+    assert_eq!(stored.get(), 11);
+}
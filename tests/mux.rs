@@ -0,0 +1,37 @@
+use lz_fear::framed::{decompress_slice, CompressionSettings};
+use lz_fear::mux::{MuxError, MuxReader, MuxWriter};
+use std::collections::BTreeMap;
+
+#[test]
+fn split_streams_apart() {
+    let mut writer = MuxWriter::new(Vec::new(), CompressionSettings::default());
+    writer.write_chunk(1, b"starting up\n").unwrap();
+    writer.write_chunk(2, b"warning: the panda is hungry\n").unwrap();
+    writer.write_chunk(1, &b"eating bamboo\n".repeat(1000)).unwrap();
+    writer.write_chunk(3, b"").unwrap();
+    writer.write_chunk(2, b"warning: the panda is sleepy\n").unwrap();
+    let mut muxed = writer.into_inner();
+    // somebody else's metadata
+    muxed.extend_from_slice(&[0x5f, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3]);
+
+    let mut streams = BTreeMap::<u32, Vec<u8>>::new();
+    MuxReader::new(&muxed[..]).for_each_chunk(|stream, chunk| {
+        streams.entry(stream).or_default().extend_from_slice(chunk);
+        Ok(())
+    }).unwrap();
+    assert_eq!(streams.len(), 3);
+    assert_eq!(streams[&1], [&b"starting up\n"[..], &b"eating bamboo\n".repeat(1000)].concat());
+    assert_eq!(streams[&2], b"warning: the panda is hungry\nwarning: the panda is sleepy\n");
+    assert!(streams[&3].is_empty());
+
+    // to everybody else, it's just a bunch of frames
+    assert_eq!(decompress_slice(&muxed, &[]).unwrap().len(), 12 + 14_000 + 29 * 2);
+}
+
+#[test]
+fn untagged_frame() {
+    let frame = CompressionSettings::default().compress_slice(b"who am I?").unwrap();
+    let mut chunk = Vec::new();
+    assert!(matches!(MuxReader::new(&frame[..]).next_chunk(&mut chunk), Err(MuxError::Untagged)));
+    assert!(matches!(MuxReader::new(&[][..]).next_chunk(&mut chunk), Ok(None)));
+}
@@ -0,0 +1,107 @@
+use lz_fear::framed::{decompress_slice, CompressionSettings, NonBlockingFrameReader, NonBlockingFrameWriter};
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Alternates between WouldBlock and accepting/returning at most a few bytes, like a congested socket.
+struct Congested<T> {
+    inner: T,
+    ready: bool,
+}
+
+impl<T> Congested<T> {
+    fn new(inner: T) -> Self {
+        Congested { inner, ready: false }
+    }
+
+    fn poll(&mut self) -> io::Result<()> {
+        self.ready = !self.ready;
+        if self.ready { Ok(()) } else { Err(ErrorKind::WouldBlock.into()) }
+    }
+}
+
+impl<R: Read> Read for Congested<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.poll()?;
+        let len = buf.len().min(333);
+        self.inner.read(&mut buf[..len])
+    }
+}
+
+impl<W: Write> Write for Congested<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.poll()?;
+        self.inner.write(&buf[..buf.len().min(333)])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.poll()
+    }
+}
+
+fn retry<T>(mut f: impl FnMut() -> io::Result<T>) -> T {
+    loop {
+        match f() {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            result => return result.unwrap(),
+        }
+    }
+}
+
+#[test]
+fn roundtrip() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3000);
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).content_checksum(true);
+
+    let mut writer = NonBlockingFrameWriter::new(Congested::new(Vec::new()), &settings).unwrap();
+    for mut chunk in input.chunks(1000) {
+        while !chunk.is_empty() {
+            let n = retry(|| writer.write(chunk));
+            chunk = &chunk[n..];
+        }
+    }
+    retry(|| writer.flush());
+    retry(|| writer.close());
+    assert!(!writer.has_pending_output());
+    let compressed = writer.into_inner().inner;
+    assert_eq!(decompress_slice(&compressed, &[]).unwrap(), input);
+
+    let mut reader = NonBlockingFrameReader::new(Congested::new(&compressed[..]));
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+    while !reader.is_done() {
+        let n = retry(|| reader.read(&mut buf));
+        output.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(output, input);
+}
+
+#[test]
+fn skippable_frame() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3000);
+    let mut stream = vec![0x50, 0x2A, 0x4D, 0x18, 3, 0, 0, 0, 1, 2, 3];
+    CompressionSettings::default().compress(&input[..], &mut stream).unwrap();
+
+    // the end of the skippable frame isn't the end of the data
+    let mut reader = NonBlockingFrameReader::new(Congested::new(&stream[..]));
+    let mut output = Vec::new();
+    let mut buf = [0; 4096];
+    while !reader.is_done() {
+        let n = retry(|| reader.read(&mut buf));
+        output.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(output, input);
+}
+
+#[test]
+fn close_after_failure() {
+    let input: Vec<u8> = (0..1000u32).flat_map(|i| i.to_le_bytes()).collect();
+    let mut settings = CompressionSettings::default();
+    settings.output_limit(32);
+
+    let mut writer = NonBlockingFrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_all(&input).unwrap();
+    assert!(writer.close().is_err());
+    // the frame is torn, so retrying must not look like success
+    assert!(writer.close().is_err());
+    assert!(writer.write(b"more").is_err());
+}
@@ -101,3 +101,14 @@ fn run_test() {
     assert!(failed_runs.is_empty());
 }
 
+
+#[test]
+fn small_blocks() {
+    // 64 KiB blocks go through the u16 table, just like in the C implementation
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024);
+    let input = std::fs::File::open(env::current_exe().unwrap()).unwrap();
+    let mut output = Vec::new();
+    settings.compress(input, &mut output).expect("CompressionSettings::compress failed");
+    assert!(output == run_cmd(&["-B4"]));
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use lz_fear::framed::{decompress_frame, CompressionError, CompressionSettings, DecompressionError, PageReader};
+use lz_fear::raw::{compress_into, DecodeError, PageCodec};
+use std::io::Cursor;
+use common::noise;
+
+#[test]
+fn roundtrip() {
+    // compressible pages, incompressible ones and a short last page
+    let mut input = b"The panda bear has an amazing black-and-white fur. ".repeat(300);
+    input.extend(noise(10_000));
+    input.extend(b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(100));
+    for page_size in [4096, 128 * 1024] {
+        let mut codec = PageCodec::new(page_size);
+        let mut compressed = Vec::new();
+        let sizes = codec.compress_pages(&input, &mut compressed);
+        assert_eq!(sizes.iter().sum::<usize>(), compressed.len());
+        assert!(compressed.len() < input.len());
+
+        let mut output = b"header".to_vec();
+        codec.decompress_pages(&compressed, &sizes, input.len(), &mut output).unwrap();
+        assert_eq!(&output[..6], b"header");
+        assert_eq!(output[6..], input);
+    }
+}
+
+#[test]
+fn same_as_compress_into() {
+    let input = [b"Save the red panda! ".repeat(500), noise(4096)].concat();
+    let mut codec = PageCodec::new(4096);
+    for page in input.chunks(4096) {
+        let mut block = vec![0; page.len()];
+        let len = codec.compress_page(page, &mut block);
+        let mut expected = vec![0; page.len()];
+        match compress_into(page, &mut expected[..page.len() - 1]) {
+            Ok(expected_len) => assert_eq!(block[..len], expected[..expected_len]),
+            Err(_) => assert_eq!(block[..len], *page),
+        }
+    }
+}
+
+#[test]
+fn wrong_sizes() {
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(300);
+    let mut codec = PageCodec::new(4096);
+    let mut compressed = Vec::new();
+    let sizes = codec.compress_pages(&input, &mut compressed);
+
+    // a page that is longer than the block says
+    let mut output = Vec::new();
+    let result = codec.decompress_page(&compressed[..sizes[0]], 4096 - 1, &mut output);
+    assert_eq!(result, Err(DecodeError::MemoryLimitExceeded));
+    assert!(output.is_empty());
+    // a truncated block
+    assert!(codec.decompress_page(&compressed[..sizes[0] - 1], 4096, &mut output).is_err());
+    assert!(output.is_empty());
+    // a missing block
+    let result = codec.decompress_pages(&compressed, &sizes[1..], input.len(), &mut output);
+    assert_eq!(result, Err(DecodeError::UnexpectedEnd));
+    assert!(output.is_empty());
+}
+
+#[test]
+fn frame_of_pages() {
+    let mut input = b"The panda bear has an amazing black-and-white fur. ".repeat(1000);
+    input.extend(noise(20_000));
+    let mut settings = CompressionSettings::default();
+    settings.page_size(8192).block_checksums(true);
+    let mut frame = b"prefix".to_vec();
+    let offsets = settings.compress_pages(&input, &mut frame).unwrap();
+    assert_eq!(offsets.len(), input.len().div_ceil(8192));
+    // it's still a regular frame
+    assert_eq!(decompress_frame(&frame[6..]).unwrap(), input);
+
+    let mut cursor = Cursor::new(&frame[..]);
+    cursor.set_position(6);
+    let mut pages = PageReader::new(cursor, offsets).unwrap();
+    assert_eq!(pages.frame_reader().block_size(), 64 * 1024);
+    let mut page = Vec::new();
+    for index in (0..pages.page_count()).rev() {
+        pages.read_page(index, &[], &mut page).unwrap();
+        assert_eq!(page, input.chunks(8192).nth(index).unwrap());
+    }
+    assert!(matches!(pages.read_page(pages.page_count(), &[], &mut page), Err(DecompressionError::NoSuchPage(_))));
+}
+
+#[test]
+fn replace_page() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(1000);
+    let mut settings = CompressionSettings::default();
+    settings.page_size(16 * 1024).dictionary(0, b"panda bamboo shoots");
+    let mut frame = Vec::new();
+    let mut offsets = settings.compress_pages(&input, &mut frame).unwrap();
+
+    // the same page comes out the same on its own
+    let mut block = Vec::new();
+    let len = settings.compress_page(1, &input[16 * 1024..32 * 1024], &mut block).unwrap();
+    assert_eq!(block, frame[offsets[1] as usize..offsets[2] as usize]);
+    assert_eq!(len, block.len() as u64);
+
+    // append the new page and point the offset table at it
+    let new_page = b"Red pandas are not bears. ".repeat(500);
+    offsets[1] = frame.len() as u64;
+    settings.compress_page(1, &new_page, &mut frame).unwrap();
+    let mut pages = PageReader::new(Cursor::new(&frame), offsets).unwrap();
+    let mut page = Vec::new();
+    pages.read_page(1, b"panda bamboo shoots", &mut page).unwrap();
+    assert_eq!(page, new_page);
+    pages.read_page(2, b"panda bamboo shoots", &mut page).unwrap();
+    assert_eq!(page, input[32 * 1024..48 * 1024]);
+
+    assert!(matches!(settings.compress_page(0, &vec![0; 16 * 1024 + 1], &mut block), Err(CompressionError::InvalidPageLength(_))));
+    assert!(matches!(CompressionSettings::default().compress_pages(&input, &mut block), Err(CompressionError::NotPaged)));
+}
+
+#[test]
+fn dependent_frames() {
+    let frame = CompressionSettings::default().independent_blocks(false).compress_slice(b"not pages").unwrap();
+    assert!(matches!(PageReader::new(Cursor::new(&frame), vec![7]), Err(DecompressionError::DependentBlocks)));
+}
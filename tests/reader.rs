@@ -0,0 +1,398 @@
+use lz_fear::framed::{BufferCapacity, CompressionSettings, LZ4FrameReader, LZ4FrameWriter};
+use std::io::{ErrorKind, Read, Write};
+
+#[test]
+fn small_buffers() {
+    let input: Vec<u8> = (0..300_000u64).map(|i| (i * i % 251) as u8).collect();
+    for independent in [false, true] {
+        let compressed = CompressionSettings::default().independent_blocks(independent).block_size(64 * 1024).compress_slice(&input).unwrap();
+        let mut output = Vec::new();
+        LZ4FrameReader::with_capacity(&compressed[..], BufferCapacity::at_most(1024)).unwrap().into_read().read_to_end(&mut output).unwrap();
+        assert_eq!(output, input);
+    }
+}
+
+#[test]
+fn remaining() {
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(10_000);
+    let mut compressed = Vec::new();
+    CompressionSettings::default().block_size(64 * 1024).compress_with_size(std::io::Cursor::new(&input), &mut compressed).unwrap();
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap().into_read();
+    let mut buf = vec![0; 12345];
+    let mut read = 0;
+    loop {
+        assert_eq!(reader.remaining(), Some((input.len() - read) as u64));
+        match reader.read(&mut buf).unwrap() {
+            0 => break,
+            n => read += n,
+        }
+    }
+    assert_eq!(read, input.len());
+
+    let compressed = CompressionSettings::default().compress_slice(&input).unwrap();
+    assert_eq!(LZ4FrameReader::new(&compressed[..]).unwrap().remaining(), None);
+}
+
+#[test]
+fn copy_to() {
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(10_000);
+    let compressed = CompressionSettings::default().block_size(64 * 1024).compress_slice(&input).unwrap();
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap().into_read();
+    let mut output = vec![0; 100];
+    reader.read_exact(&mut output).unwrap();
+    assert_eq!(reader.copy_to(&mut output).unwrap(), input.len() as u64 - 100);
+    assert_eq!(output, input);
+}
+
+#[test]
+fn block_per_read() {
+    let settings = CompressionSettings::default();
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    let records: Vec<Vec<u8>> = (1..50).map(|i| format!("record {} ", i).repeat(i).into_bytes()).collect();
+    for record in &records {
+        writer.write_all(record).unwrap();
+        writer.flush().unwrap();
+    }
+    let compressed = writer.finish().unwrap();
+
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap().into_read();
+    reader.block_per_read(true);
+    let mut buf = vec![0; 1000];
+    for record in &records {
+        // too small, but we can try again
+        if record.len() > 10 {
+            assert_eq!(reader.read(&mut buf[..10]).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+        let n = reader.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &record[..]);
+    }
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+}
+
+#[test]
+fn stored_blocks() {
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+    // alternate between compressible and incompressible blocks, so dependent blocks refer back across stored ones
+    let mut input = b"The panda bear has an amazing black-and-white fur. ".repeat(6000);
+    StdRng::seed_from_u64(0).fill_bytes(&mut input[64 * 1024..128 * 1024]);
+    StdRng::seed_from_u64(1).fill_bytes(&mut input[192 * 1024..256 * 1024]);
+    let dictionary = b"The panda bear has an amazing appetite.";
+    for (independent, checksums) in [(false, false), (true, false), (false, true)] {
+        let compressed = CompressionSettings::default().independent_blocks(independent).block_checksums(checksums)
+            .block_size(64 * 1024).dictionary(0, dictionary).compress_slice(&input).unwrap();
+        for chunk in [100, 64 * 1024, 1 << 20] {
+            let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap().into_read_with_dictionary(dictionary);
+            let mut output = Vec::new();
+            let mut buf = vec![0; chunk];
+            loop {
+                match reader.read(&mut buf).unwrap() {
+                    0 => break,
+                    n => output.extend_from_slice(&buf[..n]),
+                }
+            }
+            assert!(output == input);
+        }
+    }
+}
+
+#[test]
+fn decode_to_writer() {
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+    let mut input: Vec<u8> = (0..3_000_000u64).map(|i| (i * i % 251) as u8 ^ (i / 5000) as u8).collect();
+    StdRng::seed_from_u64(0).fill_bytes(&mut input[100_000..200_000]);
+    let dictionary = &input[1234..80_000].to_vec();
+    for (independent, checksums, block_size) in [(true, false, 4 << 20), (false, true, 4 << 20), (false, false, 64 * 1024), (true, true, 64 * 1024)] {
+        let compressed = CompressionSettings::default().independent_blocks(independent).block_checksums(checksums)
+            .block_size(block_size).dictionary(0, dictionary).compress_slice(&input).unwrap();
+        let mut output = Vec::new();
+        let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap();
+        assert_eq!(reader.decode_to_writer(dictionary, &mut output).unwrap(), input.len() as u64);
+        assert!(output == input);
+    }
+}
+
+#[test]
+fn reader_survives_header_errors() {
+    use lz_fear::framed::DecompressionError;
+    use std::io::Cursor;
+
+    let gzip = Cursor::new(b"\x1f\x8b\x08\x00\x00\x00\x00\x00".to_vec());
+    let error = LZ4FrameReader::try_new(gzip).err().unwrap();
+    assert!(matches!(error.error, DecompressionError::WrongMagic(0x00088b1f)));
+    assert_eq!(error.consumed, 4);
+    assert_eq!(error.reader.position(), 4);
+
+    let compressed = CompressionSettings::default().compress_slice(b"hello").unwrap();
+    let error = LZ4FrameReader::try_new(&compressed[..5]).err().unwrap();
+    assert!(matches!(error.error, DecompressionError::InputError(_)));
+    assert_eq!(error.consumed, 5);
+}
+
+#[test]
+fn trailing_data() {
+    use lz_fear::framed::{DecompressionError, TrailingData};
+
+    let mut compressed = CompressionSettings::default().compress_slice(b"hello").unwrap();
+    let read = |input: &[u8], policy| {
+        let mut reader = LZ4FrameReader::new(input).unwrap();
+        reader.set_trailing_data(policy);
+        let mut reader = reader.into_read();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).map(|_| (output, reader.frame_reader().trailing_bytes()))
+    };
+    assert_eq!(read(&compressed, TrailingData::Reject).unwrap(), (b"hello".to_vec(), None));
+    assert_eq!(read(&compressed, TrailingData::Count).unwrap(), (b"hello".to_vec(), Some(0)));
+
+    compressed.extend_from_slice(b"junk");
+    assert_eq!(read(&compressed, TrailingData::Ignore).unwrap(), (b"hello".to_vec(), None));
+    assert_eq!(read(&compressed, TrailingData::Count).unwrap(), (b"hello".to_vec(), Some(4)));
+    let error = read(&compressed, TrailingData::Reject).unwrap_err();
+    assert!(matches!(error.into_inner().unwrap().downcast::<DecompressionError>().as_deref(), Ok(DecompressionError::TrailingData)));
+}
+
+#[test]
+fn header_bytes() {
+    use lz_fear::framed::Flags;
+    use std::io::Cursor;
+
+    let mut compressed = Vec::new();
+    CompressionSettings::default().block_size(64 * 1024).dictionary_id_nonsense_override(Some(7))
+        .compress_with_size(Cursor::new(b"hello"), &mut compressed).unwrap();
+    let reader = LZ4FrameReader::new(&compressed[..]).unwrap();
+    assert_eq!(reader.header_bytes(), &compressed[..4 + 2 + 8 + 4 + 1]);
+    assert_eq!(reader.block_descriptor(), 0x40);
+    assert_eq!(reader.flags(), Flags::IndependentBlocks | Flags::ContentChecksum | Flags::ContentSize | Flags::DictionaryId);
+}
+
+#[test]
+fn lenient_header() {
+    use lz_fear::framed::{DecompressionError, FrameDecoder};
+    use std::hash::Hasher;
+    use twox_hash::XxHash32;
+
+    // set a reserved bit in both the flags and the block descriptor, but keep the checksum valid
+    let mut compressed = CompressionSettings::default().compress_slice(b"hello hello hello").unwrap();
+    compressed[4] |= 0b10;
+    compressed[5] |= 0b1;
+    let mut hasher = XxHash32::with_seed(0);
+    hasher.write(&compressed[4..6]);
+    compressed[6] = (hasher.finish() >> 8) as u8;
+
+    assert!(matches!(LZ4FrameReader::new(&compressed[..]).err().unwrap(), DecompressionError::HeaderParseError(_)));
+    let reader = LZ4FrameReader::new_lenient(&compressed[..]).unwrap();
+    assert_eq!(reader.header_bytes(), &compressed[..7]);
+    assert_eq!(reader.block_size(), 4 * 1024 * 1024);
+    let mut output = Vec::new();
+    reader.into_read().read_to_end(&mut output).unwrap();
+    assert_eq!(output, b"hello hello hello");
+
+    let mut output = [0; 64];
+    let progress = FrameDecoder::new().lenient_header(true).decode(&compressed, &mut output).unwrap();
+    assert_eq!(&output[..progress.written], b"hello hello hello");
+
+    // the checksum still has to match
+    compressed[6] ^= 1;
+    assert!(matches!(LZ4FrameReader::new_lenient(&compressed[..]).err().unwrap(), DecompressionError::HeaderChecksumFail));
+}
+
+#[test]
+fn tee() {
+    use lz_fear::framed::Tee;
+    use std::cell::Cell;
+    use std::hash::Hasher;
+    use twox_hash::XxHash64;
+
+    /// Only ever accepts a few bytes at a time, like a congested socket.
+    struct Trickle(Vec<u8>);
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    let input: Vec<u8> = (0..100_000u64).map(|i| (i * i % 251) as u8).collect();
+    let compressed = CompressionSettings::default().compress_slice(&input).unwrap();
+    let mut hasher = XxHash64::with_seed(0);
+    let chunks = Cell::new(0);
+    let mut tee = Tee::new(Trickle(Vec::new()));
+    tee.observe(|data| hasher.write(data)).observe(|_| chunks.set(chunks.get() + 1));
+    assert_eq!(LZ4FrameReader::new(&compressed[..]).unwrap().into_read().copy_to(&mut tee).unwrap(), input.len() as u64);
+    assert_eq!(tee.written(), input.len() as u64);
+    assert!(tee.into_inner().0 == input);
+    assert_eq!(chunks.get(), input.len().div_ceil(3));
+
+    let mut expected = XxHash64::with_seed(0);
+    expected.write(&input);
+    assert_eq!(hasher.finish(), expected.finish());
+}
+
+#[test]
+fn compare_frames() {
+    use lz_fear::framed::{compare_frames, FrameComparison};
+
+    let input: Vec<u8> = (0..300_000u64).map(|i| (i * i % 251) as u8).collect();
+    let a = CompressionSettings::default().compress_slice(&input).unwrap();
+    // different settings, same plaintext
+    let b = CompressionSettings::default().independent_blocks(false).block_size(64 * 1024).compress_slice(&input).unwrap();
+    assert_eq!(compare_frames(&a[..], &b[..], &[]).unwrap(), FrameComparison::Identical(input.len() as u64));
+
+    let mut changed = input.clone();
+    changed[200_000] ^= 1;
+    let c = CompressionSettings::default().block_size(64 * 1024).compress_slice(&changed).unwrap();
+    assert_eq!(compare_frames(&a[..], &c[..], &[]).unwrap(), FrameComparison::Differ(200_000));
+
+    let d = CompressionSettings::default().compress_slice(&input[..250_000]).unwrap();
+    assert_eq!(compare_frames(&d[..], &b[..], &[]).unwrap(), FrameComparison::Differ(250_000));
+}
+
+#[test]
+fn checkpoints() {
+    use lz_fear::framed::{Checkpoint, DecompressionError};
+    use std::io::Cursor;
+
+    let input: Vec<u8> = (0..1_000_000u64).map(|i| (i * i % 251) as u8 ^ (i / 5000) as u8).collect();
+    let dictionary = &input[1234..20_000].to_vec();
+    let mut settings = CompressionSettings::default();
+    settings.independent_blocks(false).block_size(64 * 1024).dictionary(0, dictionary).checkpoint_interval(3);
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_all(&input).unwrap();
+    let (compressed, checkpoints) = writer.finish_with_checkpoints().unwrap();
+    assert_eq!(checkpoints.len(), 6);
+    let header_len = LZ4FrameReader::new(&compressed[..]).unwrap().header_bytes().len() as u64;
+    assert_eq!(checkpoints[0], Checkpoint { compressed_offset: header_len, uncompressed_offset: 0 });
+    assert!(lz_fear::framed::decompress_slice(&compressed, dictionary).unwrap() == input);
+
+    for checkpoint in &checkpoints {
+        assert_eq!(checkpoint.uncompressed_offset % (3 * 64 * 1024), 0);
+        let reader = LZ4FrameReader::from_checkpoint(Cursor::new(&compressed), checkpoint).unwrap();
+        let mut output = Vec::new();
+        reader.into_read_with_dictionary(dictionary).read_to_end(&mut output).unwrap();
+        assert!(output[..] == input[checkpoint.uncompressed_offset as usize..]);
+    }
+
+    let bogus = Checkpoint { compressed_offset: 2, uncompressed_offset: 0 };
+    assert!(matches!(LZ4FrameReader::from_checkpoint(Cursor::new(&compressed), &bogus).err().unwrap(), DecompressionError::InvalidCheckpoint));
+}
+
+#[test]
+fn join_frames() {
+    use lz_fear::framed::{decompress_slice, join_frames, DecompressionError, Flags};
+
+    let segments: Vec<Vec<u8>> = (0..3u64).map(|hour| (0..100_000u64).map(|i| (i * i % 251) as u8 ^ hour as u8).collect()).collect();
+    let dictionary = &segments[0][..5000];
+    for (independent, checksums, dictionary) in [(true, false, &[][..]), (true, true, dictionary), (false, true, &[][..])] {
+        let mut settings = CompressionSettings::default();
+        settings.independent_blocks(independent).block_checksums(checksums).block_size(64 * 1024);
+        if !dictionary.is_empty() {
+            settings.dictionary(7, dictionary);
+        }
+        let frames: Vec<Vec<u8>> = segments.iter().map(|segment| settings.compress_slice(segment).unwrap()).collect();
+        for content_checksum in [false, true] {
+            let mut joined = Vec::new();
+            let written = join_frames(frames.iter().map(|f| &f[..]), &mut joined, dictionary, content_checksum).unwrap();
+            assert_eq!(written, joined.len() as u64);
+            let reader = LZ4FrameReader::new(&joined[..]).unwrap();
+            assert_eq!(reader.flags().contains(Flags::ContentChecksum), content_checksum);
+            let mut output = Vec::new();
+            reader.into_read_with_dictionary(dictionary).read_to_end(&mut output).unwrap();
+            assert!(output == segments.concat());
+            assert!(decompress_slice(&joined, dictionary).unwrap() == output);
+        }
+    }
+
+    let other = CompressionSettings::default().block_checksums(true).compress_slice(b"hello").unwrap();
+    let frames = [CompressionSettings::default().compress_slice(b"hello").unwrap(), other];
+    let error = join_frames(frames.iter().map(|f| &f[..]), Vec::new(), &[], false).unwrap_err();
+    assert!(matches!(error, DecompressionError::IncompatibleFrames));
+}
+
+#[test]
+fn checksum_utilities() {
+    use lz_fear::framed::{block_checksum, header_checksum, ContentChecksum};
+
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(100);
+    let compressed = CompressionSettings::default().block_checksums(true).compress_slice(&input).unwrap();
+    assert_eq!(header_checksum(&compressed[4..6]), compressed[6]);
+
+    let block_len = u32::from_le_bytes(compressed[7..11].try_into().unwrap()) as usize;
+    let block = &compressed[11..11 + block_len];
+    assert_eq!(block_checksum(block).to_le_bytes(), compressed[11 + block_len..15 + block_len]);
+
+    let mut content = ContentChecksum::new();
+    for chunk in input.chunks(1000) {
+        content.update(chunk);
+    }
+    assert_eq!(content.finish().to_le_bytes(), compressed[compressed.len() - 4..]);
+}
+
+#[test]
+fn frame_buffers() {
+    use lz_fear::framed::FrameBuffers;
+
+    let messages: Vec<Vec<u8>> = (0..500).map(|i| format!("message {} ", i).repeat(i % 50 + 1).into_bytes()).collect();
+    let mut concatenated = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        concatenated.extend(CompressionSettings::default().independent_blocks(i % 2 == 0).compress_slice(message).unwrap());
+    }
+
+    let mut buffers = FrameBuffers::new();
+    let mut input = &concatenated[..];
+    let mut capacity = 0;
+    for message in &messages {
+        let mut frame = LZ4FrameReader::with_buffers(&mut input, &mut buffers).unwrap().into_read();
+        let mut output = Vec::new();
+        frame.read_to_end(&mut output).unwrap();
+        assert_eq!(&output, message);
+        frame.return_buffers(&mut buffers);
+        assert!(buffers.capacity() >= capacity);
+        capacity = buffers.capacity();
+    }
+    assert!(input.is_empty());
+    // only as large as the largest message needs, not a full 4 MiB block
+    assert!(capacity > 0 && capacity < 1 << 20);
+}
+
+#[test]
+fn background_checksums() {
+    use lz_fear::framed::DecompressionError;
+
+    let input: Vec<u8> = (0..300_000u64).map(|i| (i * i % 251) as u8).collect();
+    for (independent, block_checksums, content_checksum) in [(true, true, true), (false, true, false), (false, false, true), (true, false, false)] {
+        let mut settings = CompressionSettings::default();
+        settings.independent_blocks(independent).block_checksums(block_checksums).content_checksum(content_checksum).block_size(64 * 1024);
+        let compressed = settings.compress_slice(&input).unwrap();
+        let mut output = Vec::new();
+        for blocks_in_flight in [1, 4] {
+            output.clear();
+            let mut frame = LZ4FrameReader::new(&compressed[..]).unwrap();
+            frame.set_blocks_in_flight(blocks_in_flight);
+            assert_eq!(frame.decode_with_background_checksums(&[], &mut output).unwrap(), input.len() as u64);
+            assert_eq!(output, input);
+            // we're at the end of the frame now
+            assert_eq!(frame.decode_with_background_checksums(&[], &mut output).unwrap(), 0);
+        }
+    }
+
+    let mut settings = CompressionSettings::default();
+    settings.block_checksums(true).block_size(64 * 1024);
+    let compressed = settings.compress_slice(&input).unwrap();
+    // a bad byte in the third block, and a bad content checksum
+    let header_len = LZ4FrameReader::new(&compressed[..]).unwrap().header_bytes().len();
+    let mut offset = header_len;
+    for _ in 0..2 {
+        let len = u32::from_le_bytes(compressed[offset..][..4].try_into().unwrap()) & !(1 << 31);
+        offset += 4 + len as usize + 4;
+    }
+    let mut bad_block = compressed.clone();
+    bad_block[offset + 10] ^= 1;
+    let mut bad_content = compressed.clone();
+    *bad_content.last_mut().unwrap() ^= 1;
+    let decode = |frame: &[u8]| LZ4FrameReader::new(frame).unwrap().decode_with_background_checksums(&[], std::io::sink());
+    assert!(matches!(decode(&bad_block), Err(DecompressionError::BlockChecksumFail)));
+    assert!(matches!(decode(&bad_content), Err(DecompressionError::FrameChecksumFail)));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use lz_fear::framed::{decompress_slice, CompressionError, CompressionSettings, DecompressionError, RecordReader, RecordWriter};
+use std::io::BufReader;
+
+#[test]
+fn roundtrip() {
+    let records: Vec<Vec<u8>> = (0..1000u32).map(|i| format!("SET key{} = value{}", i, i * i).into_bytes()).collect();
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024);
+    let mut writer = RecordWriter::new(Vec::new(), &settings).unwrap();
+    for record in &records {
+        // header, block length, end mark and the block (which is stored, it's too short to compress)
+        assert_eq!(writer.write_record(record).unwrap(), 7 + 4 + 4 + record.len());
+    }
+    // an empty record and one that takes several blocks
+    assert_eq!(writer.write_record(&[]).unwrap(), 11);
+    let large = b"All work and no play makes Jack a dull boy. ".repeat(5000);
+    writer.write_record(&large).unwrap();
+    let log = writer.into_inner();
+
+    // every record is a regular frame
+    let mut all = records.concat();
+    all.extend_from_slice(&large);
+    assert_eq!(decompress_slice(&log, &[]).unwrap(), all);
+
+    let mut reader = RecordReader::new(BufReader::new(&log[..]));
+    let mut record = b"garbage".to_vec();
+    for expected in &records {
+        assert!(reader.read_record(&[], &mut record).unwrap());
+        assert_eq!(record, *expected);
+    }
+    assert!(reader.read_record(&[], &mut record).unwrap());
+    assert_eq!(record, []);
+    assert!(reader.read_record(&[], &mut record).unwrap());
+    assert_eq!(record, large);
+    assert!(!reader.read_record(&[], &mut record).unwrap());
+}
+
+#[test]
+fn torn_record() {
+    let settings = CompressionSettings::default();
+    let mut writer = RecordWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_record(b"first").unwrap();
+    writer.write_record(b"second").unwrap();
+    let mut log = writer.into_inner();
+    log.truncate(log.len() - 1);
+
+    let mut reader = RecordReader::new(&log[..]);
+    let mut record = Vec::new();
+    assert!(reader.read_record(&[], &mut record).unwrap());
+    assert_eq!(record, b"first");
+    assert!(matches!(reader.read_record(&[], &mut record), Err(DecompressionError::InputError(_))));
+}
+
+#[test]
+fn dictionary() {
+    let dictionary = b"INSERT INTO users (name, email) VALUES ";
+    let mut settings = CompressionSettings::default();
+    settings.dictionary(1, dictionary).block_checksums(true);
+    let mut writer = RecordWriter::new(Vec::new(), &settings).unwrap();
+    let record = b"INSERT INTO users (name, email) VALUES ('panda', 'panda@example.com')";
+    // header with dictionary id, block length, end mark and block checksum, but the block is compressed
+    assert!(writer.write_record(record).unwrap() < 11 + 4 + 4 + 4 + record.len());
+    let log = writer.into_inner();
+
+    let mut reader = RecordReader::new(&log[..]);
+    let mut output = Vec::new();
+    assert!(reader.read_record(dictionary, &mut output).unwrap());
+    assert_eq!(output, record);
+}
+
+#[test]
+fn output_limit() {
+    let mut settings = CompressionSettings::default();
+    settings.output_limit(1500);
+    let mut writer = RecordWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_record(&[b'x'; 1400]).unwrap();
+    let len = writer.get_ref().len();
+    let noise = common::noise(1500);
+    assert!(matches!(writer.write_record(&noise), Err(CompressionError::OutputLimit)));
+    // nothing of it was written
+    assert_eq!(writer.get_ref().len(), len);
+}
@@ -0,0 +1,10 @@
+#![cfg(feature = "self-test")]
+
+#[test]
+fn reference_frames() {
+    let report = lz_fear::self_test();
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.failures().count(), 0);
+    assert!(report.cases.iter().any(|case| case.args.contains("-BD") && case.args.contains("-D")));
+    assert_eq!(report.to_string(), format!("{0} of {0} reference frames passed\n", report.cases.len()));
+}
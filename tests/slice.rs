@@ -0,0 +1,33 @@
+use lz_fear::framed::{decompress_slice, CompressionSettings, DecompressionError};
+
+#[test]
+fn roundtrip() {
+    let input = b"The panda bear has an amazing black-and-white fur. The panda bear has an amazing appetite.".repeat(100);
+    let compressed = CompressionSettings::default().compress_slice(&input).unwrap();
+    assert_eq!(decompress_slice(&compressed, &[]).unwrap(), input);
+}
+
+#[test]
+fn concatenated_and_skippable_frames() {
+    let mut input = CompressionSettings::default().compress_slice(b"first frame, ").unwrap();
+    input.extend_from_slice(&0x184D2A5Au32.to_le_bytes());
+    input.extend_from_slice(&3u32.to_le_bytes());
+    input.extend_from_slice(b"xyz");
+    input.extend(CompressionSettings::default().compress_slice(b"second frame").unwrap());
+
+    assert_eq!(decompress_slice(&input, &[]).unwrap(), b"first frame, second frame");
+}
+
+#[test]
+fn truncated() {
+    let compressed = CompressionSettings::default().compress_slice(b"truncated truncated truncated").unwrap();
+    let result = decompress_slice(&compressed[..compressed.len() - 1], &[]);
+    assert!(matches!(result, Err(DecompressionError::InputError(_))));
+
+    let skippable = [0x50, 0x2A, 0x4D, 0x18, 10, 0, 0, 0, 1, 2, 3];
+    assert!(matches!(decompress_slice(&skippable, &[]), Err(DecompressionError::InputError(_))));
+
+    // this length used to wrap around on 32 bit targets, so we never got past the frame
+    let huge = [0x50, 0x2A, 0x4D, 0x18, 0xF8, 0xFF, 0xFF, 0xFF, 1, 2, 3, 4, 5, 6, 7, 8];
+    assert!(matches!(decompress_slice(&huge, &[]), Err(DecompressionError::InputError(_))));
+}
@@ -0,0 +1,92 @@
+use lz_fear::framed::{CompressionSettings, FrameDecoder, LZ4FrameWriter};
+use std::io::Write;
+
+/// Pretend we crashed and came back up: the snapshot has to survive a trip through bytes.
+#[cfg(feature = "serde")]
+fn persist<T: serde::Serialize + serde::de::DeserializeOwned>(snapshot: T) -> T {
+    let bytes = serde_json::to_vec(&snapshot).unwrap();
+    serde_json::from_slice(&bytes).unwrap()
+}
+
+#[cfg(not(feature = "serde"))]
+fn persist<T>(snapshot: T) -> T {
+    snapshot
+}
+
+#[test]
+fn suspend_and_resume() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(5000);
+    let dictionary = b"The average panda";
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).independent_blocks(false).content_checksum(true).dictionary(0, dictionary);
+    let mut compressed = Vec::new();
+    settings.compress(&input[..], &mut compressed).unwrap();
+
+    let mut output = Vec::new();
+    let mut buf = [0; 7000];
+    let mut decoder = FrameDecoder::with_dictionary(dictionary);
+    let mut snapshots = 0;
+    loop {
+        let position = decoder.total_in() as usize;
+        let end = (position + 3001).min(compressed.len());
+        let progress = decoder.decode(&compressed[position..end], &mut buf).unwrap();
+        output.extend_from_slice(&buf[..progress.written]);
+        if decoder.is_idle() && position + progress.consumed == compressed.len() {
+            break;
+        }
+
+        // throw the decoder away every now and then and carry on from a snapshot
+        let snapshot = persist(decoder.snapshot());
+        assert_eq!(snapshot.total_out(), output.len() as u64);
+        decoder = FrameDecoder::restore(snapshot, dictionary).unwrap();
+        snapshots += 1;
+    }
+    assert!(snapshots > 10);
+    assert_eq!(output, input);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn reject_garbage() {
+    let mut compressed = Vec::new();
+    CompressionSettings::default().compress(&b"hello"[..], &mut compressed).unwrap();
+    let mut decoder = FrameDecoder::new();
+    decoder.decode(&compressed[..10], &mut []).unwrap();
+
+    let mut json: serde_json::Value = serde_json::to_value(decoder.snapshot()).unwrap();
+    json["stage"]["Blocks"]["block_maxsize"] = 12345.into();
+    let snapshot = serde_json::from_value(json).unwrap();
+    assert!(matches!(FrameDecoder::restore(snapshot, &[]), Err(lz_fear::framed::DecompressionError::InvalidSnapshot)));
+}
+
+#[test]
+fn encoder_resume() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(5000);
+    let dictionary = b"The average panda";
+    for (independent, hash_log) in [(false, None), (true, None), (false, Some(14))] {
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).independent_blocks(independent).content_checksum(true).dictionary(0, dictionary);
+        if let Some(hash_log) = hash_log {
+            settings.hash_log(hash_log);
+        }
+        let mut expected = Vec::new();
+        settings.compress(&input[..], &mut expected).unwrap();
+
+        let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+        let mut crashes = 0;
+        for (i, chunk) in input.chunks(10_000).enumerate() {
+            writer.write_all(chunk).unwrap();
+            if i % 3 == 1 {
+                // crash, losing everything except the snapshot and the output written so far
+                let snapshot = persist(writer.snapshot().unwrap());
+                let mut output = writer.abort();
+                assert_eq!(snapshot.total_in(), input.len().min((i + 1) * 10_000) as u64);
+                output.truncate(snapshot.total_out() as usize);
+                writer = LZ4FrameWriter::restore(output, &settings, snapshot).unwrap();
+                crashes += 1;
+            }
+        }
+        assert!(crashes > 5);
+        assert_eq!(writer.finish().unwrap(), expected);
+    }
+}
@@ -0,0 +1,45 @@
+use lz_fear::framed::{decompress_pipe, CompressionSettings, SparseWriter};
+use std::fs;
+use std::io::{Cursor, Write};
+use tempfile::tempdir;
+
+/// Something like a disk image: a bit of data here and there, lots of zeros in between.
+fn image() -> Vec<u8> {
+    let mut image = vec![0; 1 << 20];
+    image[..3000].copy_from_slice(&b"boot sector ".repeat(250));
+    image[100_000..100_020].copy_from_slice(&[0, 1, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 5]);
+    image[500_000..600_000].fill(0xAA);
+    image
+}
+
+#[test]
+fn skips_zeros() {
+    let image = image();
+    let mut sparse = SparseWriter::new(Cursor::new(Vec::new()));
+    // in odd pieces, so that runs of zeros span several writes
+    for chunk in image.chunks(1000) {
+        sparse.write_all(chunk).unwrap();
+    }
+    sparse.flush().unwrap();
+    // the three long runs of zeros, except for the very last byte
+    assert_eq!(sparse.skipped(), 97_001 + 399_980 + 448_575);
+    assert_eq!(sparse.into_inner().unwrap().into_inner(), image);
+
+    let mut sparse = SparseWriter::new(Cursor::new(Vec::new()));
+    sparse.min_run(1 << 30);
+    sparse.write_all(&image).unwrap();
+    assert_eq!(sparse.skipped(), 0);
+    assert_eq!(sparse.into_inner().unwrap().into_inner(), image);
+}
+
+#[test]
+fn sparse_file() {
+    let dir = tempdir().unwrap();
+    let image = image();
+    let compressed = CompressionSettings::default().compress_slice(&image).unwrap();
+    let mut sparse = SparseWriter::new(fs::File::create(dir.path().join("image")).unwrap());
+    decompress_pipe(&compressed[..], &mut sparse, &[]).unwrap();
+    assert!(sparse.skipped() > 800_000);
+    drop(sparse);
+    assert_eq!(fs::read(dir.path().join("image")).unwrap(), image);
+}
@@ -0,0 +1,57 @@
+use lz_fear::raw::{compress2, decompress_raw, DecodeError, StreamCompressor, StreamDecompressor, U32Table};
+
+fn messages() -> Vec<Vec<u8>> {
+    (0..2000).map(|i| format!("{{\"id\": {}, \"type\": \"measurement\", \"sensor\": \"panda-cam-{}\", \"value\": {}}}", i, i % 7, i * 37 % 1000).into_bytes()).collect()
+}
+
+#[test]
+fn compress_continue() {
+    let dictionary = b"{\"id\": 0, \"type\": \"measurement\", \"sensor\": \"panda-cam-0\"";
+    let mut compressor = StreamCompressor::with_dictionary(dictionary);
+    let mut history = dictionary.to_vec();
+    let (mut streamed, mut independent) = (0, 0);
+    for message in messages() {
+        let mut block = Vec::new();
+        compressor.compress(&message, &mut block).unwrap();
+        streamed += block.len();
+
+        let mut output = Vec::new();
+        decompress_raw(&block, &history, &mut output, usize::MAX).unwrap();
+        assert_eq!(output, message);
+        history.extend_from_slice(&output);
+
+        let mut block = Vec::new();
+        compress2(&message, 0, &mut U32Table::default(), &mut block).unwrap();
+        independent += block.len();
+    }
+    assert!(streamed * 2 < independent, "{} vs {}", streamed, independent);
+
+    // after a reset, blocks can be decompressed on their own
+    compressor.reset();
+    let mut block = Vec::new();
+    compressor.compress(b"panda panda panda panda panda", &mut block).unwrap();
+    let mut output = Vec::new();
+    decompress_raw(&block, &[], &mut output, usize::MAX).unwrap();
+    assert_eq!(output, b"panda panda panda panda panda");
+}
+
+#[test]
+fn decompress_continue() {
+    let dictionary = b"\"type\": \"measurement\"";
+    let mut compressor = StreamCompressor::with_dictionary(dictionary);
+    let mut decompressor = StreamDecompressor::with_dictionary(dictionary);
+    // a few big messages in between, so the history wraps around in the middle of a message
+    let big = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(1500);
+    for (i, message) in messages().iter().enumerate() {
+        let message = if i % 500 == 250 { &big } else { message };
+        let mut block = Vec::new();
+        compressor.compress(message, &mut block).unwrap();
+        assert_eq!(decompressor.decompress(&block, message.len()).unwrap(), &message[..]);
+    }
+
+    // a decompressor that missed the history can't make sense of the next block
+    let mut block = Vec::new();
+    compressor.compress(&messages()[0], &mut block).unwrap();
+    let err = StreamDecompressor::new().decompress(&block, usize::MAX).unwrap_err();
+    assert_eq!(err, DecodeError::InvalidDeduplicationOffset);
+}
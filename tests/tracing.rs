@@ -0,0 +1,72 @@
+#![cfg(feature = "tracing")]
+use lz_fear::framed::{decompress_frame, CompressionSettings};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Remembers the message of every event.
+#[derive(Clone, Default)]
+struct Messages(Arc<Mutex<Vec<String>>>);
+
+impl Visit for &Messages {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0.lock().unwrap().push(format!("{:?}", value));
+        }
+    }
+}
+
+impl Subscriber for Messages {
+    fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+    fn new_span(&self, _: &Attributes<'_>) -> Id { Id::from_u64(1) }
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, event: &Event<'_>) { event.record(&mut &*self); }
+    fn enter(&self, _: &Id) {}
+    fn exit(&self, _: &Id) {}
+}
+
+impl Messages {
+    fn count(&self, message: &str) -> usize {
+        self.0.lock().unwrap().iter().filter(|m| *m == message).count()
+    }
+}
+
+#[test]
+fn frames_and_blocks() {
+    let input = b"The quick brown fox jumps over the lazy dog. ".repeat(5000);
+    let messages = Messages::default();
+    tracing::subscriber::with_default(messages.clone(), || {
+        let mut compressed = CompressionSettings::default()
+            .block_size(64 * 1024)
+            .block_checksums(true)
+            .content_checksum(true)
+            .compress_slice(&input)
+            .unwrap();
+        assert_eq!(decompress_frame(&compressed[..]).unwrap(), input);
+
+        // break the content checksum
+        let len = compressed.len();
+        compressed[len - 1] ^= 1;
+        assert!(decompress_frame(&compressed[..]).is_err());
+    });
+
+    let blocks = input.len().div_ceil(64 * 1024);
+    assert_eq!(messages.count("writing lz4 frame"), 1);
+    assert_eq!(messages.count("wrote lz4 block"), blocks);
+    assert_eq!(messages.count("finished writing lz4 frame"), 1);
+    assert_eq!(messages.count("reading lz4 frame"), 2);
+    assert_eq!(messages.count("read lz4 block"), 2 * blocks);
+    // every block checksum was fine, and so was the first content checksum
+    assert_eq!(messages.count("checksum verified"), 2 * blocks + 1);
+    assert_eq!(messages.count("checksum mismatch"), 1);
+    assert_eq!(messages.count("finished reading lz4 frame"), 1);
+
+    let messages = Messages::default();
+    tracing::subscriber::with_default(messages.clone(), || {
+        CompressionSettings::default().store_only(true).compress_slice(&input).unwrap();
+    });
+    assert_eq!(messages.count("storing the block without trying"), 1);
+}
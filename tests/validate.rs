@@ -0,0 +1,67 @@
+use lz_fear::framed::CompressionSettings;
+use lz_fear::raw::ViolationKind;
+use lz_fear::validate::{FrameValidator, FrameViolation, FrameViolationKind};
+
+fn frame(settings: &CompressionSettings) -> Vec<u8> {
+    let input = b"The panda bear has an amazing black-and-white fur. ".repeat(3000);
+    let mut compressed = Vec::new();
+    settings.compress_with_size_unchecked(&input[..], &mut compressed, input.len() as u64).unwrap();
+    compressed
+}
+
+#[test]
+fn our_frames_are_fine() {
+    let dictionary = b"The panda bear has an amazing appetite.";
+    for independent in [false, true] {
+        let mut settings = CompressionSettings::default();
+        settings.independent_blocks(independent).block_checksums(true).block_size(64 * 1024).dictionary(0, dictionary);
+        let violations = FrameValidator::default().dictionary(dictionary).block_rules(true).validate(&frame(&settings));
+        assert_eq!(violations, []);
+    }
+}
+
+#[test]
+fn broken_frames() {
+    let mut settings = CompressionSettings::default();
+    settings.block_checksums(true).block_size(64 * 1024);
+    let good = frame(&settings);
+    let header_len = 4 + 2 + 8 + 1;
+    let kinds = |frame: &[u8]| FrameValidator::default().validate(frame).into_iter().map(|v| v.kind).collect::<Vec<_>>();
+
+    let mut broken = good.clone();
+    broken[header_len - 1] ^= 1;
+    assert!(matches!(kinds(&broken)[..], [FrameViolationKind::HeaderChecksum { .. }]));
+
+    let mut broken = good.clone();
+    broken[header_len + 4] ^= 1;
+    assert!(kinds(&broken).contains(&FrameViolationKind::BlockChecksum));
+
+    let mut broken = good.clone();
+    let last = broken.len() - 1;
+    broken[last] ^= 1;
+    assert_eq!(kinds(&broken), [FrameViolationKind::ContentChecksum]);
+
+    let mut broken = good.clone();
+    broken[6] ^= 1;
+    assert!(kinds(&broken).contains(&FrameViolationKind::ContentSize { declared: 153001, actual: 153000 }));
+
+    // no end mark
+    assert_eq!(FrameValidator::default().validate(&good[..good.len() - 8]), [FrameViolation { kind: FrameViolationKind::Truncated, offset: good.len() - 8 }]);
+}
+
+#[test]
+fn block_rules() {
+    // a block that ends with a match, which our decoder accepts but the reference decoder may not
+    let mut settings = CompressionSettings::default();
+    settings.content_checksum(false).block_size(64 * 1024);
+    let mut frame = settings.compress_slice(b"").unwrap();
+    frame.truncate(7);
+    let block = [0x10, b'a', 0x01, 0x00];
+    frame.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&block);
+    frame.extend_from_slice(&[0; 4]);
+
+    assert_eq!(FrameValidator::default().validate(&frame), []);
+    let violations = FrameValidator::default().block_rules(true).validate(&frame);
+    assert!(violations.iter().any(|v| v.kind == FrameViolationKind::BlockRules(ViolationKind::LastLiteralsTooShort)));
+}
@@ -0,0 +1,55 @@
+use lz_fear::framed::{join_frames, CompressionSettings, FrameDecoder, LZ4FrameReader, Warning, WarningHook};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+fn warnings_for(frame: &[u8]) -> Vec<Warning> {
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let mut reader = LZ4FrameReader::new(frame).unwrap();
+    reader.set_warning_hook(WarningHook::collect_into(warnings.clone()));
+    reader.into_read().read_to_end(&mut Vec::new()).unwrap();
+    let warnings = warnings.lock().unwrap().clone();
+    warnings
+}
+
+#[test]
+fn clean_frame() {
+    let input = b"The quick brown fox jumps over the lazy dog. ".repeat(5000);
+    let mut frame = Vec::new();
+    CompressionSettings::default().block_size(64 * 1024).compress_with_size(std::io::Cursor::new(&input), &mut frame).unwrap();
+    assert_eq!(warnings_for(&frame), []);
+}
+
+#[test]
+fn suspicious_frames() {
+    let input = b"The quick brown fox jumps over the lazy dog. ".repeat(5000);
+
+    let frame = CompressionSettings::default().dictionary_id_nonsense_override(Some(0xCAFE)).compress_slice(&input).unwrap();
+    assert_eq!(warnings_for(&frame), [Warning::NoContentSize, Warning::MissingDictionary(0xCAFE)]);
+
+    let frame = CompressionSettings::default().block_size(64 * 1024).store_only(true).compress_slice(&input[..100_000]).unwrap();
+    let warnings = warnings_for(&frame);
+    assert_eq!(warnings.len(), 3);
+    assert!(matches!(warnings[1], Warning::CompressibleStoredBlock { offset: 0, len: 65536, compressed_len } if compressed_len < 1000));
+    assert!(matches!(warnings[2], Warning::CompressibleStoredBlock { offset: 65536, len: 34464, .. }));
+
+    // the last block of the first frame ends up in the middle
+    let first = CompressionSettings::default().block_size(64 * 1024).compress_slice(&input[..1000]).unwrap();
+    let second = CompressionSettings::default().block_size(64 * 1024).compress_slice(&input).unwrap();
+    let mut joined = Vec::new();
+    join_frames([&first[..], &second[..]], &mut joined, &[], false).unwrap();
+    let warnings = warnings_for(&joined);
+    assert_eq!(warnings, [Warning::NoContentSize, Warning::ShortBlock { offset: 0, len: 1000 }]);
+    assert_eq!(warnings[1].to_string(), "the block at 0 is only 1000 bytes, but isn't the last one");
+}
+
+#[test]
+fn decoder() {
+    let input = vec![0; 300_000];
+    let frame = CompressionSettings::default().block_size(64 * 1024).store_only(true).compress_slice(&input).unwrap();
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let mut decoder = FrameDecoder::new();
+    decoder.warning_hook(WarningHook::collect_into(warnings.clone()));
+    let mut output = vec![0; input.len()];
+    decoder.decode(&frame, &mut output).unwrap();
+    assert_eq!(warnings.lock().unwrap().len(), 1 + 5);
+}
@@ -0,0 +1,601 @@
+mod common;
+
+use lz_fear::framed::{decompress_slice, CompressionError, CompressionSettings, LZ4FrameReader, LZ4FrameWriter, RollingFrameWriter};
+use lz_fear::raw::{compress2, decompress_raw, BucketTable, EncoderTable, FixedU16Table, FixedU32Table, U16Table, U32Table, VarU32Table};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+#[test]
+fn frame_writer_matches_compress() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(2000);
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).independent_blocks(false).block_checksums(true);
+
+    let mut expected = Vec::new();
+    settings.compress(&input[..], &mut expected).unwrap();
+
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    for chunk in input.chunks(1000) {
+        writer.write_all(chunk).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), expected);
+}
+
+#[test]
+fn split_by_size() {
+    let settings = CompressionSettings::default();
+    let mut writer = RollingFrameWriter::new(Vec::new(), &settings);
+    writer.max_frame_len(1000);
+    for i in 0..500 {
+        writeln!(writer, "log line {}", i).unwrap();
+    }
+    let output = writer.finish().unwrap();
+
+    let expected: String = (0..500).map(|i| format!("log line {}\n", i)).collect();
+    assert_eq!(decompress_slice(&output, &[]).unwrap(), expected.as_bytes());
+
+    // every frame is at most 1000 bytes and can be decoded on its own
+    let mut rest = &output[..];
+    let mut frames = 0;
+    while !rest.is_empty() {
+        let mut plaintext = Vec::new();
+        LZ4FrameReader::new(&mut rest).unwrap().into_read().read_to_end(&mut plaintext).unwrap();
+        assert!(plaintext.len() <= 1000);
+        frames += 1;
+    }
+    assert_eq!(frames, expected.len().div_ceil(1000));
+}
+
+#[test]
+fn rotate_by_age() {
+    let segments = RefCell::new(Vec::new());
+    let settings = CompressionSettings::default();
+    let mut writer = RollingFrameWriter::new(Vec::new(), &settings);
+    writer.max_frame_age(Duration::ZERO).on_rotate(|finished| {
+        segments.borrow_mut().push(finished);
+        Ok(Vec::new())
+    });
+    writer.write_all(b"first").unwrap();
+    writer.write_all(b"second").unwrap();
+    writer.write_all(b"third").unwrap();
+    let last = writer.finish().unwrap();
+
+    let segments = segments.into_inner();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(decompress_slice(&segments[0], &[]).unwrap(), b"first");
+    assert_eq!(decompress_slice(&segments[1], &[]).unwrap(), b"second");
+    assert_eq!(decompress_slice(&last, &[]).unwrap(), b"third");
+}
+
+#[test]
+fn sync_flush() {
+    let settings = CompressionSettings::default();
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_all(b"ping").unwrap();
+    writer.flush().unwrap();
+    writer.flush().unwrap(); // nothing buffered, so this must not produce an (empty) block
+
+    // the receiver can already see the first message while the frame is still open
+    let mut reader = LZ4FrameReader::new(&writer.get_ref()[..]).unwrap();
+    let mut message = Vec::new();
+    reader.decode_block(&mut message, &[]).unwrap();
+    assert_eq!(message, b"ping");
+
+    writer.write_all(b"pong").unwrap();
+    let output = writer.finish().unwrap();
+    assert_eq!(decompress_slice(&output, &[]).unwrap(), b"pingpong");
+}
+
+#[test]
+fn auto_flush() {
+    let settings = CompressionSettings::default();
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.auto_flush_len(10).auto_flush_delay(Duration::from_millis(20));
+
+    writer.write_all(b"0123456789").unwrap();
+    let flushed_by_len = writer.get_ref().len();
+    assert!(flushed_by_len > 7, "header only");
+    assert_eq!(writer.time_until_auto_flush(), None);
+
+    writer.write_all(b"abc").unwrap();
+    assert_eq!(writer.get_ref().len(), flushed_by_len);
+    assert!(!writer.poll_auto_flush().unwrap());
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(writer.time_until_auto_flush(), Some(Duration::ZERO));
+    assert!(writer.poll_auto_flush().unwrap());
+    assert!(writer.get_ref().len() > flushed_by_len);
+
+    let output = writer.finish().unwrap();
+    assert_eq!(decompress_slice(&output, &[]).unwrap(), b"0123456789abc");
+}
+
+#[test]
+fn finish_on_drop() {
+    let settings = CompressionSettings::default();
+    let mut output = Vec::new();
+    {
+        let mut writer = LZ4FrameWriter::new(&mut output, &settings).unwrap();
+        writer.write_all(b"forgot to call finish").unwrap();
+    }
+    assert_eq!(decompress_slice(&output, &[]).unwrap(), b"forgot to call finish");
+}
+
+#[test]
+fn abort() {
+    let settings = CompressionSettings::default();
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_all(b"never written").unwrap();
+    let output = writer.abort();
+    assert_eq!(output.len(), 7); // just the header
+    assert!(decompress_slice(&output, &[]).is_err());
+}
+
+/// Takes `room` bytes, then says `WouldBlock` forever.
+struct Stuck {
+    written: Vec<u8>,
+    room: usize,
+}
+impl Write for Stuck {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.room - self.written.len());
+        if n == 0 {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        self.written.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn poisoned() {
+    let input = common::words(200_000, 0);
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024);
+    for finish in [true, false] {
+        let mut output = Stuck { written: Vec::new(), room: 1000 };
+        let mut writer = LZ4FrameWriter::new(&mut output, &settings).unwrap();
+        // the first block only makes it halfway
+        assert!(writer.write_all(&input).is_err());
+        // and trying again doesn't pretend it's fine
+        assert!(writer.write(b"more").is_err());
+        assert!(writer.flush().is_err());
+        if finish {
+            assert!(matches!(writer.finish(), Err(CompressionError::Poisoned)));
+        } else {
+            drop(writer);
+        }
+        // nothing was added after the torn block
+        assert_eq!(output.written.len(), 1000);
+    }
+}
+
+#[test]
+fn reuse() {
+    let settings = CompressionSettings::default();
+    let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+    writer.write_all(b"first message").unwrap();
+    let first = writer.reset(Vec::new()).unwrap();
+    writer.write_all(b"second message").unwrap();
+    writer.next_frame().unwrap();
+    writer.write_all(b"third message").unwrap();
+    let rest = writer.finish().unwrap();
+
+    assert_eq!(decompress_slice(&first, &[]).unwrap(), b"first message");
+    assert_eq!(decompress_slice(&rest, &[]).unwrap(), b"second messagethird message");
+    let mut rest = &rest[..];
+    let mut second = Vec::new();
+    LZ4FrameReader::new(&mut rest).unwrap().into_read().read_to_end(&mut second).unwrap();
+    assert_eq!(second, b"second message");
+    assert_eq!(decompress_slice(rest, &[]).unwrap(), b"third message");
+}
+
+#[test]
+fn patch_content_size() {
+    let input = b"Love is a wonderful terrible thing. ".repeat(500);
+    let mut output = std::io::Cursor::new(b"prefix".to_vec());
+    output.set_position(6);
+    let size = CompressionSettings::default().compress_patch_size(&input[..], &mut output).unwrap();
+    assert_eq!(size, input.len() as u64);
+    assert_eq!(output.position(), output.get_ref().len() as u64);
+
+    let frame = &output.get_ref()[6..];
+    let reader = LZ4FrameReader::new(frame).unwrap();
+    assert_eq!(reader.frame_size(), Some(input.len() as u64));
+    let mut plaintext = Vec::new();
+    reader.into_read().read_to_end(&mut plaintext).unwrap();
+    assert_eq!(plaintext, input);
+}
+
+#[test]
+fn chunks() {
+    let chunks: Vec<Vec<u8>> = (0..1000).map(|i| format!("chunk number {} ", i).into_bytes()).collect();
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024);
+
+    let mut output = Vec::new();
+    settings.compress_chunks(&chunks, &mut output).unwrap();
+    let mut expected = Vec::new();
+    settings.compress(&chunks.concat()[..], &mut expected).unwrap();
+    assert_eq!(output, expected);
+}
+
+/// Behaves exactly like `U32Table`, but goes through the trait object path.
+#[derive(Default)]
+struct Wrapped(U32Table);
+impl EncoderTable for Wrapped {
+    fn payload_size_limit(&self) -> usize { self.0.payload_size_limit() }
+    fn replace(&mut self, input: &[u8], offset: usize) -> usize { self.0.replace(input, offset) }
+    fn offset(&mut self, offset: usize) { self.0.offset(offset) }
+}
+
+#[test]
+fn custom_encoder_table() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(5000);
+    let factory = || Box::new(Wrapped::default()) as Box<dyn EncoderTable + Send + Sync>;
+    for independent in [true, false] {
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).independent_blocks(independent).dictionary(0, b"panda bamboo");
+        let mut expected = Vec::new();
+        settings.compress(&input[..], &mut expected).unwrap();
+
+        settings.encoder_table(&factory);
+        let mut output = Vec::new();
+        settings.compress(&input[..], &mut output).unwrap();
+        assert_eq!(output, expected);
+
+        let mut writer = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+        writer.write_all(&input).unwrap();
+        assert_eq!(writer.finish().unwrap(), expected);
+    }
+}
+
+#[test]
+fn encoder_table_too_small() {
+    let factory = || Box::new(U16Table::default()) as Box<dyn EncoderTable + Send + Sync>;
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).encoder_table(&factory);
+    let err = settings.compress(&b"hello"[..], Vec::new()).unwrap_err();
+    assert!(matches!(err, CompressionError::TableTooSmall));
+}
+
+#[test]
+fn hash_log() {
+    // pseudo-random words, so there are lots of different matches for the table to remember
+    let input = common::many_words(600_000, 0);
+
+    // the default size behaves exactly like U32Table
+    let mut expected = Vec::new();
+    compress2(&input, 0, &mut U32Table::default(), &mut expected).unwrap();
+    let mut output = Vec::new();
+    compress2(&input, 0, &mut VarU32Table::new(12).unwrap(), &mut output).unwrap();
+    assert_eq!(output, expected);
+
+    let mut sizes = Vec::new();
+    for hash_log in [8, 12, 16, 20] {
+        let mut settings = CompressionSettings::default();
+        settings.hash_log(hash_log);
+        let mut compressed = Vec::new();
+        settings.compress(&input[..], &mut compressed).unwrap();
+        assert_eq!(decompress_slice(&compressed, &[]).unwrap(), input);
+        sizes.push(compressed.len());
+    }
+    assert!(sizes.windows(2).all(|w| w[0] > w[1]), "{:?}", sizes);
+
+    let mut settings = CompressionSettings::default();
+    settings.hash_log(30);
+    let err = settings.compress(&input[..], Vec::new()).unwrap_err();
+    assert!(matches!(err, CompressionError::InvalidHashLog));
+}
+
+#[test]
+fn fixed_size_tables() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(500);
+
+    // a const-sized table behaves exactly like a runtime-sized table of the same size
+    let mut expected = Vec::new();
+    compress2(&input, 0, &mut VarU32Table::new(16).unwrap(), &mut expected).unwrap();
+    let mut output = Vec::new();
+    compress2(&input, 0, &mut FixedU32Table::<{ 1 << 16 }>::default(), &mut output).unwrap();
+    assert_eq!(output, expected);
+
+    let mut output = Vec::new();
+    compress2(&input, 0, &mut FixedU16Table::<{ 1 << 10 }>::default(), &mut output).unwrap();
+    let mut decompressed = Vec::new();
+    decompress_raw(&output, &[], &mut decompressed, usize::MAX).unwrap();
+    assert_eq!(decompressed, input);
+}
+
+#[test]
+fn bucket_table() {
+    let input = common::many_words(600_000, 0);
+
+    let mut expected = Vec::new();
+    CompressionSettings::default().compress(&input[..], &mut expected).unwrap();
+
+    let factory = || Box::new(BucketTable::default()) as Box<dyn EncoderTable + Send + Sync>;
+    for independent in [true, false] {
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).independent_blocks(independent).encoder_table(&factory);
+        let mut output = Vec::new();
+        settings.compress(&input[..], &mut output).unwrap();
+        assert_eq!(decompress_slice(&output, &[]).unwrap(), input);
+        assert!(output.len() < expected.len());
+    }
+
+    assert!(BucketTable::new(12, BucketTable::MAX_DEPTH + 1).is_none());
+}
+
+#[test]
+fn block_time_budget() {
+    use lz_fear::raw::compress2_accelerated;
+
+    let input = common::words(1_200_000, 0);
+
+    // acceleration trades ratio for speed, but the output is still valid
+    let mut sizes = Vec::new();
+    for acceleration in [1, 8, 64] {
+        let mut output = Vec::new();
+        compress2_accelerated(&input, 0, &mut U32Table::default(), acceleration, &mut output).unwrap();
+        let mut decompressed = Vec::new();
+        decompress_raw(&output, &[], &mut decompressed, usize::MAX).unwrap();
+        assert!(decompressed == input);
+        sizes.push(output.len());
+    }
+    assert!(sizes[0] < sizes[1] && sizes[1] < sizes[2], "{:?}", sizes);
+
+    let compress = |budget| {
+        let stored = RefCell::new(Vec::new());
+        let callback = |stats: &lz_fear::framed::BlockStats| stored.borrow_mut().push(stats.stored);
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).block_time_budget(budget).block_callback(&callback);
+        let compressed = settings.compress_slice(&input).unwrap();
+        assert!(decompress_slice(&compressed, &[]).unwrap() == input);
+        stored.into_inner()
+    };
+    // no block is ever fast enough, so we speed up until we give up on compression entirely
+    let stored = compress(Duration::ZERO);
+    assert_eq!(stored.iter().position(|&s| s), Some(7));
+    assert!(stored[7..].iter().all(|&s| s));
+    // plenty of time
+    assert!(compress(Duration::from_secs(3600)).iter().all(|&s| !s));
+}
+
+#[test]
+fn yield_hook() {
+    use lz_fear::framed::YieldHook;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let input = common::words(6_000_000, 0);
+    let expected = input.len() / (64 * 1024);
+    let counter = || {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let hook = YieldHook::new(64 * 1024, { let calls = calls.clone(); move || { calls.fetch_add(1, Ordering::Relaxed); } });
+        (calls, hook)
+    };
+
+    // the hook is called in the middle of a single large block, in both directions
+    let (compress_calls, hook) = counter();
+    let compressed = CompressionSettings::default().yield_hook(hook).compress_slice(&input).unwrap();
+    assert!((expected - 2..=expected).contains(&compress_calls.load(Ordering::Relaxed)), "{:?}", compress_calls);
+
+    let (decompress_calls, hook) = counter();
+    let mut reader = LZ4FrameReader::new(&compressed[..]).unwrap();
+    reader.set_yield_hook(hook);
+    let mut output = Vec::new();
+    reader.into_read().read_to_end(&mut output).unwrap();
+    assert!(output == input);
+    assert!((expected - 2..=expected).contains(&decompress_calls.load(Ordering::Relaxed)), "{:?}", decompress_calls);
+}
+
+#[test]
+fn compress_many() {
+    let inputs = [&b"first"[..], &b""[..], &b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(1000)[..]];
+    let mut output = Vec::new();
+    CompressionSettings::default().compress_many(inputs.iter().copied(), &mut output).unwrap();
+
+    let mut reader = &output[..];
+    for input in inputs {
+        let mut frame = Vec::new();
+        LZ4FrameReader::new(&mut reader).unwrap().into_read().read_to_end(&mut frame).unwrap();
+        assert_eq!(frame, input);
+    }
+    assert!(reader.is_empty());
+    assert!(decompress_slice(&output, &[]).unwrap() == inputs.concat());
+
+    let mut output = Vec::new();
+    CompressionSettings::default().compress_many(std::iter::empty::<&[u8]>(), &mut output).unwrap();
+    assert!(output.is_empty());
+}
+
+#[test]
+fn compressed_size() {
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+    // compressible, incompressible (stored) and a short final block
+    let mut input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3000);
+    StdRng::seed_from_u64(0).fill_bytes(&mut input[64 * 1024..128 * 1024]);
+    let dictionary = b"The average panda sleeps a lot.";
+    for (independent, checksums) in [(true, false), (false, true), (true, true)] {
+        let mut settings = CompressionSettings::default();
+        settings.independent_blocks(independent).block_checksums(checksums).block_size(64 * 1024).dictionary(0, dictionary);
+        let blocks = RefCell::new(0);
+        let callback = |_: &_| *blocks.borrow_mut() += 1;
+        settings.block_callback(&callback);
+        let expected = settings.compress_slice(&input).unwrap().len() as u64;
+        assert_eq!(settings.compressed_size(&input[..]).unwrap(), expected);
+        assert_eq!(*blocks.borrow(), 2 * 4);
+    }
+    assert_eq!(CompressionSettings::default().compressed_size(&b""[..]).unwrap(), CompressionSettings::default().compress_slice(b"").unwrap().len() as u64);
+}
+
+#[test]
+fn auto_store() {
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+    // a (very compressible) PNG, random data and plain text
+    let mut input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3000);
+    input[..8].copy_from_slice(b"\x89PNG\r\n\x1a\n");
+    StdRng::seed_from_u64(0).fill_bytes(&mut input[64 * 1024..128 * 1024]);
+    for (auto_store, expected) in [(false, [false, true, false, false]), (true, [true, true, false, false])] {
+        let stored = RefCell::new(Vec::new());
+        let callback = |stats: &lz_fear::framed::BlockStats| stored.borrow_mut().push(stats.stored);
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).auto_store(auto_store).block_callback(&callback);
+        let compressed = settings.compress_slice(&input).unwrap();
+        assert_eq!(*stored.borrow(), expected);
+        assert!(decompress_slice(&compressed, &[]).unwrap() == input);
+    }
+}
+
+#[test]
+fn read_ahead() {
+    /// Hands out data in small pieces and fails at some point, if asked to.
+    struct Trickle<'a>(&'a [u8], Option<usize>);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.1.is_some_and(|fail_at| self.0.len() <= fail_at) {
+                return Err(std::io::Error::other("broken pipe"));
+            }
+            let len = buf.len().min(self.0.len()).min(777);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(5000);
+    for (independent, ahead) in [(true, 1), (false, 1), (true, 3)] {
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).independent_blocks(independent).content_checksum(true).blocks_in_flight(ahead);
+        for len in [0, 1000, 64 * 1024, 5 * 64 * 1024, input.len()] {
+            let mut compressed = Vec::new();
+            settings.compress_read_ahead(Trickle(&input[..len], None), &mut compressed).unwrap();
+            assert_eq!(compressed, settings.compress_slice(&input[..len]).unwrap());
+        }
+        let result = settings.compress_read_ahead(Trickle(&input, Some(100_000)), Vec::new());
+        assert!(matches!(result, Err(CompressionError::ReadError(_))));
+    }
+}
+
+#[test]
+fn bailout() {
+    use rand::{RngCore, SeedableRng, rngs::StdRng};
+
+    // random data, random data with a short match every 275 bytes (which almost pays off) and plain text
+    let block = 64 * 1024;
+    let mut input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3 * block / 70 + 1);
+    let mut rng = StdRng::seed_from_u64(0);
+    rng.fill_bytes(&mut input[..2 * block]);
+    for chunk in input[block..2 * block].chunks_mut(275) {
+        let len = chunk.len();
+        chunk[len.saturating_sub(4)..].copy_from_slice(&b"abcd"[..len.min(4)]);
+    }
+    for dry_run in [false, true] {
+        let stats = RefCell::new(Vec::new());
+        let callback = |s: &lz_fear::framed::BlockStats| stats.borrow_mut().push(*s);
+        let mut settings = CompressionSettings::default();
+        settings.block_size(block).block_callback(&callback);
+        if dry_run {
+            settings.compressed_size(&input[..]).unwrap();
+            assert!(stats.borrow().iter().all(|s| s.bailout.is_none()));
+            continue;
+        }
+        settings.compress_slice(&input).unwrap();
+        let stats = stats.borrow();
+        assert!(stats[0].stored && stats[1].stored && !stats[2].stored, "{:?}", stats);
+        let (random, almost) = (stats[0].bailout.unwrap(), stats[1].bailout.unwrap());
+        assert!(random.consumed < 1000, "{:?}", random);
+        assert!(almost.consumed > block / 2 && almost.produced >= almost.consumed && almost.produced <= block, "{:?}", almost);
+        assert!(stats[2].bailout.is_none());
+    }
+}
+
+#[test]
+fn store_only() {
+    let input = b"The average panda eats as much as 9 to 14 kg of bamboo shoots a day. ".repeat(3000);
+    for independent in [true, false] {
+        let stored = RefCell::new(Vec::new());
+        let callback = |stats: &lz_fear::framed::BlockStats| stored.borrow_mut().push(stats.stored);
+        let mut settings = CompressionSettings::default();
+        settings.block_size(64 * 1024).independent_blocks(independent).block_checksums(true).store_only(true).block_callback(&callback);
+        let compressed = settings.compress_slice(&input).unwrap();
+        assert_eq!(*stored.borrow(), [true; 4]);
+        // the header, every block with its length and checksum, the end mark and the content checksum
+        let header_len = LZ4FrameReader::new(&compressed[..]).unwrap().header_bytes().len();
+        assert_eq!(compressed.len(), header_len + input.len() + 4 * 8 + 4 + 4);
+        assert!(decompress_slice(&compressed, &[]).unwrap() == input);
+        assert_eq!(settings.compressed_size(&input[..]).unwrap(), compressed.len() as u64);
+    }
+}
+
+#[test]
+fn output_limit() {
+    let input = b"The quick brown fox jumps over the lazy dog. ".repeat(5000);
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024);
+    let frame = settings.compress_slice(&input).unwrap();
+
+    // exactly enough room is fine
+    settings.output_limit(frame.len() as u64);
+    assert_eq!(settings.compress_slice(&input).unwrap(), frame);
+
+    // one byte less and the last block doesn't fit anymore, so it never gets written
+    settings.output_limit(frame.len() as u64 - 1);
+    let mut output = Vec::new();
+    assert!(matches!(settings.compress(&input[..], &mut output), Err(CompressionError::OutputLimit)));
+    assert!(output.len() < frame.len() - 10);
+
+    // noise never fits, so we give up after the first block
+    let noise = common::noise(1_000_000);
+    settings.output_limit(1500);
+    let mut output = Vec::new();
+    assert!(matches!(settings.compress(&noise[..], &mut output), Err(CompressionError::OutputLimit)));
+    assert_eq!(output.len(), 7);
+    settings.output_limit(10);
+    assert!(matches!(settings.compress_slice(&[]), Err(CompressionError::OutputLimit)));
+}
+
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn common_suffix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().rev().zip(b.iter().rev()).take_while(|(a, b)| a == b).count()
+}
+
+#[test]
+fn rsyncable() {
+    let input = common::words(2_000_000, 0);
+    let mut edited = input.clone();
+    edited.splice(1_000_000..1_000_000, b"the panda was here".iter().copied());
+
+    for independent in [true, false] {
+        let mut settings = CompressionSettings::default();
+        settings.independent_blocks(independent).content_checksum(false).rsyncable(true);
+        let before = settings.compress_slice(&input).unwrap();
+        let after = settings.compress_slice(&edited).unwrap();
+        assert_eq!(decompress_slice(&after, &[]).unwrap(), edited);
+        // only the blocks around the edit changed
+        assert!(common_prefix(&before, &after) + common_suffix(&before, &after) > before.len() - 200_000);
+
+        // writing in small pieces finds the same boundaries as reading whole blocks
+        let mut frame = LZ4FrameWriter::new(Vec::new(), &settings).unwrap();
+        for chunk in edited.chunks(1000) {
+            frame.write_all(chunk).unwrap();
+        }
+        assert_eq!(frame.finish().unwrap(), after);
+    }
+
+    // without it, everything after the edit moves to other blocks
+    let mut settings = CompressionSettings::default();
+    settings.block_size(64 * 1024).content_checksum(false);
+    let before = settings.compress_slice(&input).unwrap();
+    let after = settings.compress_slice(&edited).unwrap();
+    assert!(common_suffix(&before, &after) < before.len() / 20);
+}